@@ -0,0 +1,765 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use binrw::{BinRead, BinWrite, Endian};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{malformed, unsupported},
+    scoped_reader_pos,
+    util::pointer::Pointer,
+    write_at_pointer, Result,
+};
+
+use super::{
+    bcres::{CgfxCollectionValue, CgfxDict, WriteContext},
+    util::{
+        brw_read_string, check_count, write_list_placeholder, write_relative_pointer_here, write_string_pointer,
+        CgfxObjectHeader,
+    },
+};
+
+/// Describes which members of a node (bones, material colors, texture mappers, ...)
+/// can be animated and how the results of multiple animations get blended together.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimGroup {
+    pub flags: u32,
+    pub name: Option<String>,
+    pub member_type: u32,
+    
+    pub elements: Option<CgfxDict<AnimGroupElement>>,
+    pub blend_operations: Vec<AnimBlendOperation>,
+    pub evaluation_timing: AnimEvaluationTiming,
+}
+
+impl AnimGroup {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let flags = reader.read_u32::<LittleEndian>()?;
+        let name = brw_read_string(reader, Endian::Little, ())?;
+        let member_type = reader.read_u32::<LittleEndian>()?;
+        
+        // elements
+        let element_count = reader.read_u32::<LittleEndian>()?;
+        let element_ptr = Pointer::read_relative(reader)?;
+        
+        let elements = if let Some(element_ptr) = element_ptr {
+            scoped_reader_pos!(reader);
+            reader.seek(SeekFrom::Start(element_ptr.into()))?;
+            
+            let dict = CgfxDict::from_reader_with(reader, AnimGroupElement::from_reader)?;
+            
+            if dict.values_count != element_count {
+                return Err(malformed!("Anim group {:?} has {} elements, expected {}", name, dict.values_count, element_count));
+            }
+            
+            Some(dict)
+        } else {
+            None
+        };
+        
+        // blend operations
+        let blend_operation_count = reader.read_u32::<LittleEndian>()?;
+        let blend_operation_ptr = Pointer::read_relative(reader)?;
+        
+        let blend_operations = if let Some(blend_operation_ptr) = blend_operation_ptr {
+            scoped_reader_pos!(reader);
+            reader.seek(SeekFrom::Start(blend_operation_ptr.into()))?;
+            check_count(reader, blend_operation_count, 4)?;
+            
+            (0..blend_operation_count)
+                .map(|_| Ok(AnimBlendOperation::read(reader)?))
+                .collect::<Result<Vec<AnimBlendOperation>>>()?
+        } else {
+            Vec::new()
+        };
+        
+        let evaluation_timing = AnimEvaluationTiming::read(reader)?;
+        
+        Ok(Self {
+            flags,
+            name,
+            member_type,
+            elements,
+            blend_operations,
+            evaluation_timing,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        writer.write_u32::<LittleEndian>(self.flags)?;
+        write_string_pointer(writer, ctx, self.name.as_deref())?;
+        writer.write_u32::<LittleEndian>(self.member_type)?;
+        
+        // elements and blend operations are written after the group, patch pointers later
+        writer.write_u32::<LittleEndian>(self.elements.as_ref().map_or(0, |elements| elements.values_count))?;
+        let element_pointer_location = Pointer::try_from(&writer)?;
+        writer.write_u32::<LittleEndian>(0)?;
+        
+        writer.write_u32::<LittleEndian>(self.blend_operations.len().try_into()?)?;
+        let blend_operation_pointer_location = Pointer::try_from(&writer)?;
+        writer.write_u32::<LittleEndian>(0)?;
+        
+        self.evaluation_timing.write(writer)?;
+        
+        if !self.blend_operations.is_empty() {
+            let current_offset = Pointer::try_from(&writer)?;
+            write_at_pointer(writer, blend_operation_pointer_location, (current_offset - blend_operation_pointer_location).into())?;
+            
+            for blend_operation in &self.blend_operations {
+                blend_operation.write(writer)?;
+            }
+        }
+        
+        if let Some(elements) = &self.elements {
+            let current_offset = Pointer::try_from(&writer)?;
+            write_at_pointer(writer, element_pointer_location, (current_offset - element_pointer_location).into())?;
+            
+            elements.to_writer(writer, ctx)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Finds the element animating the member at `path`, e.g. the name of a bone.
+    pub fn find_element(&self, path: &str) -> Option<&AnimGroupElement> {
+        self.elements.as_ref()?.get(path)
+    }
+    
+    /// The blend operation used to combine multiple animations of `element`.
+    pub fn blend_operation(&self, element: &AnimGroupElement) -> Option<AnimBlendOperation> {
+        let index: usize = element.blend_operation_index.try_into().ok()?;
+        self.blend_operations.get(index).copied()
+    }
+}
+
+impl CgfxCollectionValue for AnimGroup {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u32)]
+pub enum AnimBlendOperation {
+    Bool,
+    Float,
+    Int,
+    Vector2,
+    Vector3,
+    Transform,
+    RgbaColor,
+    Texture,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u32)]
+pub enum AnimEvaluationTiming {
+    BeforeWorldUpdate,
+    AfterSceneCulling,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimGroupElement {
+    pub name: Option<String>,
+    pub member_offset: i32,
+    pub blend_operation_index: i32,
+    pub object_type: u32,
+    pub member_type: u32,
+    
+    pub member: AnimGroupMember,
+}
+
+/// The kind of object an anim group element targets, including
+/// the information needed to find the target in the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AnimGroupMember {
+    MeshNodeVisibility { node_name: Option<String> },
+    Mesh { mesh_index: u32 },
+    TextureSampler { material_name: Option<String>, sampler_index: u32 },
+    BlendOperation { material_name: Option<String> },
+    MaterialColor { material_name: Option<String> },
+    Model,
+    TextureMapper { material_name: Option<String>, mapper_index: u32 },
+    Bone { bone_name: Option<String> },
+    TextureCoordinator { material_name: Option<String>, coordinator_index: u32 },
+}
+
+impl AnimGroupMember {
+    fn discriminant(&self) -> u32 {
+        match self {
+            AnimGroupMember::MeshNodeVisibility { .. } => 0x00080000,
+            AnimGroupMember::Mesh { .. } => 0x01000000,
+            AnimGroupMember::TextureSampler { .. } => 0x02000000,
+            AnimGroupMember::BlendOperation { .. } => 0x04000000,
+            AnimGroupMember::MaterialColor { .. } => 0x08000000,
+            AnimGroupMember::Model => 0x10000000,
+            AnimGroupMember::TextureMapper { .. } => 0x20000000,
+            AnimGroupMember::Bone { .. } => 0x40000000,
+            AnimGroupMember::TextureCoordinator { .. } => 0x80000000,
+        }
+    }
+}
+
+impl AnimGroupElement {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        let name = brw_read_string(reader, Endian::Little, ())?;
+        let member_offset = reader.read_i32::<LittleEndian>()?;
+        let blend_operation_index = reader.read_i32::<LittleEndian>()?;
+        let object_type = reader.read_u32::<LittleEndian>()?;
+        let member_type = reader.read_u32::<LittleEndian>()?;
+        
+        let read_name = |reader: &mut R| brw_read_string(reader, Endian::Little, ());
+        
+        let member = match discriminant {
+            0x00080000 => AnimGroupMember::MeshNodeVisibility { node_name: read_name(reader)? },
+            0x01000000 => AnimGroupMember::Mesh { mesh_index: reader.read_u32::<LittleEndian>()? },
+            0x02000000 => AnimGroupMember::TextureSampler {
+                material_name: read_name(reader)?,
+                sampler_index: reader.read_u32::<LittleEndian>()?,
+            },
+            0x04000000 => AnimGroupMember::BlendOperation { material_name: read_name(reader)? },
+            0x08000000 => AnimGroupMember::MaterialColor { material_name: read_name(reader)? },
+            0x10000000 => AnimGroupMember::Model,
+            0x20000000 => AnimGroupMember::TextureMapper {
+                material_name: read_name(reader)?,
+                mapper_index: reader.read_u32::<LittleEndian>()?,
+            },
+            0x40000000 => AnimGroupMember::Bone { bone_name: read_name(reader)? },
+            0x80000000 => AnimGroupMember::TextureCoordinator {
+                material_name: read_name(reader)?,
+                coordinator_index: reader.read_u32::<LittleEndian>()?,
+            },
+            _ => return Err(malformed!("Invalid anim group element discriminant {:x}", discriminant)),
+        };
+        
+        Ok(Self {
+            name,
+            member_offset,
+            blend_operation_index,
+            object_type,
+            member_type,
+            member,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        writer.write_u32::<LittleEndian>(self.member.discriminant())?;
+        write_string_pointer(writer, ctx, self.name.as_deref())?;
+        writer.write_i32::<LittleEndian>(self.member_offset)?;
+        writer.write_i32::<LittleEndian>(self.blend_operation_index)?;
+        writer.write_u32::<LittleEndian>(self.object_type)?;
+        writer.write_u32::<LittleEndian>(self.member_type)?;
+        
+        match &self.member {
+            AnimGroupMember::MeshNodeVisibility { node_name: name }
+            | AnimGroupMember::BlendOperation { material_name: name }
+            | AnimGroupMember::MaterialColor { material_name: name }
+            | AnimGroupMember::Bone { bone_name: name } => {
+                write_string_pointer(writer, ctx, name.as_deref())?;
+            },
+            AnimGroupMember::TextureSampler { material_name: name, sampler_index: index }
+            | AnimGroupMember::TextureMapper { material_name: name, mapper_index: index }
+            | AnimGroupMember::TextureCoordinator { material_name: name, coordinator_index: index } => {
+                write_string_pointer(writer, ctx, name.as_deref())?;
+                writer.write_u32::<LittleEndian>(*index)?;
+            },
+            AnimGroupMember::Mesh { mesh_index } => writer.write_u32::<LittleEndian>(*mesh_index)?,
+            AnimGroupMember::Model => (),
+        }
+        
+        Ok(())
+    }
+}
+
+impl CgfxCollectionValue for AnimGroupElement {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
+
+/// Whether an animation stops at its last frame or starts over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u32)]
+pub enum AnimLoopMode {
+    Once,
+    Loop,
+}
+
+/// A skeletal animation, which animates the bones of a model through its "SkeletalAnimation" anim group.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CgfxAnimation {
+    pub cgfx_object_header: CgfxObjectHeader,
+    
+    pub target_anim_group_name: Option<String>,
+    pub loop_mode: AnimLoopMode,
+    pub frame_count: f32,
+    /// Animated bones, named like the bone they animate
+    pub elements: Option<CgfxDict<AnimElement>>,
+}
+
+impl CgfxAnimation {
+    /// Creates a skeletal animation without any animated bones.
+    pub fn new_skeletal(name: &str, frame_count: f32, loop_mode: AnimLoopMode) -> Self {
+        Self {
+            cgfx_object_header: CgfxObjectHeader {
+                magic: "CANM".to_string(),
+                revision: 0x5000000,
+                name: Some(name.to_string()),
+                metadata: None,
+            },
+            target_anim_group_name: Some("SkeletalAnimation".to_string()),
+            loop_mode,
+            frame_count,
+            elements: None,
+        }
+    }
+    
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let cgfx_object_header = CgfxObjectHeader::read(reader)?;
+        
+        if cgfx_object_header.magic != "CANM" {
+            return Err(malformed!("Invalid animation magic number {:?}", cgfx_object_header.magic));
+        }
+        
+        let target_anim_group_name = brw_read_string(reader, Endian::Little, ())?;
+        let loop_mode = AnimLoopMode::read(reader)?;
+        let frame_count = reader.read_f32::<LittleEndian>()?;
+        
+        let element_count = reader.read_u32::<LittleEndian>()?;
+        let element_ptr = Pointer::read_relative(reader)?;
+        
+        let elements = if let Some(element_ptr) = element_ptr {
+            scoped_reader_pos!(reader);
+            reader.seek(SeekFrom::Start(element_ptr.into()))?;
+            
+            let dict = CgfxDict::from_reader_with(reader, AnimElement::from_reader)?;
+            
+            if dict.values_count != element_count {
+                return Err(malformed!("Animation {:?} has {} elements, expected {}", cgfx_object_header.name, dict.values_count, element_count));
+            }
+            
+            Some(dict)
+        } else {
+            None
+        };
+        
+        Ok(Self {
+            cgfx_object_header,
+            target_anim_group_name,
+            loop_mode,
+            frame_count,
+            elements,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        let header_offset = Pointer::try_from(&writer)?;
+        
+        if let Some(name) = &self.cgfx_object_header.name {
+            ctx.add_string(name)?;
+            ctx.add_string_reference(header_offset + 8, name.clone());
+        }
+        
+        self.cgfx_object_header.write(writer)?;
+        write_string_pointer(writer, ctx, self.target_anim_group_name.as_deref())?;
+        self.loop_mode.write(writer)?;
+        writer.write_f32::<LittleEndian>(self.frame_count)?;
+        
+        let element_pointer_location = write_list_placeholder(writer,
+            self.elements.as_ref().map_or(0, |elements| elements.values_count as usize))?;
+        
+        if let Some(elements) = &self.elements {
+            write_relative_pointer_here(writer, element_pointer_location)?;
+            elements.to_writer(writer, ctx)?;
+        }
+        
+        self.cgfx_object_header.write_metadata(writer, ctx, header_offset)?;
+        
+        Ok(())
+    }
+}
+
+impl CgfxCollectionValue for CgfxAnimation {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
+
+/// Primitive type of elements animating a transform as separate scale, rotation and translation curves.
+const TRANSFORM_PRIMITIVE_TYPE: u32 = 5;
+
+/// The animation of a single bone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimElement {
+    pub name: Option<String>,
+    pub transform: AnimTransform,
+}
+
+/// Scale, rotation (as XYZ euler angles in radians) and translation of a bone over time.
+/// Components without a value keep the value of the bone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnimTransform {
+    pub scale: [Option<AnimValue>; 3],
+    pub rotation: [Option<AnimValue>; 3],
+    pub translation: [Option<AnimValue>; 3],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AnimValue {
+    Constant(f32),
+    Curve(AnimCurve),
+}
+
+impl AnimTransform {
+    /// The components in the order they are stored in, rotation also has an unused W component.
+    fn slots(&self) -> [Option<&AnimValue>; 10] {
+        let [scale_x, scale_y, scale_z] = &self.scale;
+        let [rotation_x, rotation_y, rotation_z] = &self.rotation;
+        let [translation_x, translation_y, translation_z] = &self.translation;
+        
+        [
+            scale_x.as_ref(), scale_y.as_ref(), scale_z.as_ref(),
+            rotation_x.as_ref(), rotation_y.as_ref(), rotation_z.as_ref(), None,
+            translation_x.as_ref(), translation_y.as_ref(), translation_z.as_ref(),
+        ]
+    }
+}
+
+impl AnimElement {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let name = brw_read_string(reader, Endian::Little, ())?;
+        let flags = reader.read_u32::<LittleEndian>()?;
+        let primitive_type = reader.read_u32::<LittleEndian>()?;
+        
+        if primitive_type != TRANSFORM_PRIMITIVE_TYPE {
+            return Err(unsupported!("Animation element {:?} has unsupported primitive type {}", name, primitive_type));
+        }
+        
+        let mut slots: [Option<AnimValue>; 10] = Default::default();
+        
+        for (i, slot) in slots.iter_mut().enumerate() {
+            let is_constant = flags & (1 << (6 + i)) != 0;
+            let exists = flags & (1 << (16 + i)) == 0;
+            
+            let value = if !exists {
+                reader.read_u32::<LittleEndian>()?;
+                None
+            } else if is_constant {
+                Some(AnimValue::Constant(reader.read_f32::<LittleEndian>()?))
+            } else {
+                let curve_ptr = Pointer::read_relative(reader)?
+                    .ok_or_else(|| malformed!("Animation element {:?} is missing curve {}", name, i))?;
+                
+                scoped_reader_pos!(reader);
+                reader.seek(SeekFrom::Start(curve_ptr.into()))?;
+                
+                Some(AnimValue::Curve(AnimCurve::from_reader(reader)?))
+            };
+            
+            *slot = value;
+        }
+        
+        // rotation W is unused by euler angles
+        let [scale_x, scale_y, scale_z, rotation_x, rotation_y, rotation_z, _, translation_x, translation_y, translation_z] = slots;
+        
+        Ok(Self {
+            name,
+            transform: AnimTransform {
+                scale: [scale_x, scale_y, scale_z],
+                rotation: [rotation_x, rotation_y, rotation_z],
+                translation: [translation_x, translation_y, translation_z],
+            },
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        let slots = self.transform.slots();
+        let mut flags = 0;
+        
+        for (i, slot) in slots.iter().enumerate() {
+            match slot {
+                Some(AnimValue::Constant(_)) => flags |= 1 << (6 + i),
+                Some(AnimValue::Curve(_)) => {},
+                None => flags |= 1 << (16 + i),
+            }
+        }
+        
+        write_string_pointer(writer, ctx, self.name.as_deref())?;
+        writer.write_u32::<LittleEndian>(flags)?;
+        writer.write_u32::<LittleEndian>(TRANSFORM_PRIMITIVE_TYPE)?;
+        
+        // curves are written after all components, patch their pointers later
+        let mut curve_pointer_locations = Vec::new();
+        
+        for slot in slots {
+            match slot {
+                Some(AnimValue::Constant(value)) => writer.write_f32::<LittleEndian>(*value)?,
+                Some(AnimValue::Curve(curve)) => {
+                    curve_pointer_locations.push((Pointer::try_from(&writer)?, curve));
+                    writer.write_u32::<LittleEndian>(0)?;
+                },
+                None => writer.write_u32::<LittleEndian>(0)?,
+            }
+        }
+        
+        for (pointer_location, curve) in curve_pointer_locations {
+            write_relative_pointer_here(writer, pointer_location)?;
+            curve.to_writer(writer)?;
+        }
+        
+        Ok(())
+    }
+}
+
+impl CgfxCollectionValue for AnimElement {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
+
+/// What happens before the first and after the last key frame of a curve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u8)]
+pub enum AnimRepeatMethod {
+    None,
+    Repeat,
+    Mirror,
+    RelativeRepeat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AnimInterpolation {
+    Step,
+    Linear,
+    Hermite,
+}
+
+/// A key frame, the slopes are only used by hermite interpolation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnimKeyFrame {
+    pub frame: f32,
+    pub value: f32,
+    pub in_slope: f32,
+    pub out_slope: f32,
+}
+
+/// Quantization of key frames storing frame, value and both slopes as floats.
+const QUANTIZATION_HERMITE_128: u32 = 0;
+/// Quantization of key frames storing frame and value as floats.
+const QUANTIZATION_STEP_LINEAR_64: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimCurve {
+    pub start_frame: f32,
+    pub end_frame: f32,
+    pub pre_repeat: AnimRepeatMethod,
+    pub post_repeat: AnimRepeatMethod,
+    
+    pub interpolation: AnimInterpolation,
+    /// Sorted by frame
+    pub key_frames: Vec<AnimKeyFrame>,
+}
+
+impl AnimCurve {
+    /// Creates a curve spanning from the first to the last of `key_frames`, which need to be sorted.
+    pub fn new(interpolation: AnimInterpolation, key_frames: Vec<AnimKeyFrame>) -> Self {
+        Self {
+            start_frame: key_frames.first().map_or(0.0, |key_frame| key_frame.frame),
+            end_frame: key_frames.last().map_or(0.0, |key_frame| key_frame.frame),
+            pre_repeat: AnimRepeatMethod::None,
+            post_repeat: AnimRepeatMethod::None,
+            interpolation,
+            key_frames,
+        }
+    }
+    
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let start_frame = reader.read_f32::<LittleEndian>()?;
+        let end_frame = reader.read_f32::<LittleEndian>()?;
+        let pre_repeat = AnimRepeatMethod::read(reader)?;
+        let post_repeat = AnimRepeatMethod::read(reader)?;
+        reader.read_u16::<LittleEndian>()?;
+        
+        let flags = reader.read_u32::<LittleEndian>()?;
+        let key_frame_count = reader.read_u32::<LittleEndian>()?;
+        // inverse of the duration, only needed by the game
+        reader.read_f32::<LittleEndian>()?;
+        
+        let interpolation = match (flags >> 2) & 3 {
+            0 => AnimInterpolation::Step,
+            1 => AnimInterpolation::Linear,
+            2 => AnimInterpolation::Hermite,
+            value => return Err(malformed!("Invalid curve interpolation {}", value)),
+        };
+        
+        let quantization = (flags >> 5) & 7;
+        check_count(reader, key_frame_count, 8)?;
+        
+        let key_frames = (0..key_frame_count)
+            .map(|_| match quantization {
+                QUANTIZATION_HERMITE_128 => Ok(AnimKeyFrame {
+                    frame: reader.read_f32::<LittleEndian>()?,
+                    value: reader.read_f32::<LittleEndian>()?,
+                    in_slope: reader.read_f32::<LittleEndian>()?,
+                    out_slope: reader.read_f32::<LittleEndian>()?,
+                }),
+                QUANTIZATION_STEP_LINEAR_64 => Ok(AnimKeyFrame {
+                    frame: reader.read_f32::<LittleEndian>()?,
+                    value: reader.read_f32::<LittleEndian>()?,
+                    ..Default::default()
+                }),
+                _ => Err(unsupported!("Unsupported key frame quantization {}", quantization)),
+            })
+            .collect::<Result<Vec<AnimKeyFrame>>>()?;
+        
+        Ok(Self {
+            start_frame,
+            end_frame,
+            pre_repeat,
+            post_repeat,
+            interpolation,
+            key_frames,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
+        let (interpolation, quantization) = match self.interpolation {
+            AnimInterpolation::Step => (0, QUANTIZATION_STEP_LINEAR_64),
+            AnimInterpolation::Linear => (1, QUANTIZATION_STEP_LINEAR_64),
+            AnimInterpolation::Hermite => (2, QUANTIZATION_HERMITE_128),
+        };
+        
+        let duration = self.end_frame - self.start_frame;
+        
+        writer.write_f32::<LittleEndian>(self.start_frame)?;
+        writer.write_f32::<LittleEndian>(self.end_frame)?;
+        self.pre_repeat.write(writer)?;
+        self.post_repeat.write(writer)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        
+        writer.write_u32::<LittleEndian>(interpolation << 2 | quantization << 5)?;
+        writer.write_u32::<LittleEndian>(self.key_frames.len().try_into()?)?;
+        writer.write_f32::<LittleEndian>(if duration > 0.0 { 1.0 / duration } else { 0.0 })?;
+        
+        for key_frame in &self.key_frames {
+            writer.write_f32::<LittleEndian>(key_frame.frame)?;
+            writer.write_f32::<LittleEndian>(key_frame.value)?;
+            
+            if quantization == QUANTIZATION_HERMITE_128 {
+                writer.write_f32::<LittleEndian>(key_frame.in_slope)?;
+                writer.write_f32::<LittleEndian>(key_frame.out_slope)?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// The value of the curve at `frame`, which is clamped to the first and last key frame.
+    pub fn evaluate(&self, frame: f32) -> f32 {
+        let Some(first) = self.key_frames.first() else {
+            return 0.0;
+        };
+        
+        let next_index = self.key_frames.partition_point(|key_frame| key_frame.frame <= frame);
+        
+        let (left, right) = match (next_index.checked_sub(1), self.key_frames.get(next_index)) {
+            (Some(left), Some(right)) => (&self.key_frames[left], right),
+            (Some(left), None) => return self.key_frames[left].value,
+            (None, _) => return first.value,
+        };
+        
+        let duration = right.frame - left.frame;
+        let t = (frame - left.frame) / duration;
+        
+        match self.interpolation {
+            AnimInterpolation::Step => left.value,
+            AnimInterpolation::Linear => left.value + (right.value - left.value) * t,
+            AnimInterpolation::Hermite => {
+                let (t2, t3) = (t * t, t * t * t);
+                
+                (2.0 * t3 - 3.0 * t2 + 1.0) * left.value
+                    + (t3 - 2.0 * t2 + t) * duration * left.out_slope
+                    + (-2.0 * t3 + 3.0 * t2) * right.value
+                    + (t3 - t2) * duration * right.in_slope
+            },
+        }
+    }
+    
+    /// The value of this curve if all of its key frames are within `tolerance` of the first one
+    /// (and it has no slopes either in case of hermite curves).
+    pub fn constant_value(&self, tolerance: f32) -> Option<f32> {
+        let first = self.key_frames.first()?;
+        let is_flat = |key_frame: &AnimKeyFrame| self.interpolation != AnimInterpolation::Hermite
+            || (key_frame.in_slope.abs() <= tolerance && key_frame.out_slope.abs() <= tolerance);
+        
+        self.key_frames.iter()
+            .all(|key_frame| (key_frame.value - first.value).abs() <= tolerance && is_flat(key_frame))
+            .then_some(first.value)
+    }
+    
+    /// Removes key frames which can be left out without the curve changing by more than `tolerance`
+    /// at any of the original key frames. Hermite curves are kept as they are, as removing a key frame
+    /// would require fitting the slopes of its neighbors.
+    pub fn reduce_key_frames(&mut self, tolerance: f32) {
+        match self.interpolation {
+            AnimInterpolation::Step => {
+                self.key_frames.dedup_by(|key_frame, previous| (key_frame.value - previous.value).abs() <= tolerance);
+            },
+            AnimInterpolation::Linear => {
+                if self.key_frames.len() <= 2 {
+                    return;
+                }
+                
+                let mut keep = vec![false; self.key_frames.len()];
+                keep[0] = true;
+                keep[self.key_frames.len() - 1] = true;
+                
+                // Ramer-Douglas-Peucker, keeping the key frame with the largest error until all are within tolerance
+                let mut ranges = vec![(0, self.key_frames.len() - 1)];
+                
+                while let Some((start, end)) = ranges.pop() {
+                    let (left, right) = (&self.key_frames[start], &self.key_frames[end]);
+                    
+                    let furthest = (start + 1..end)
+                        .map(|i| {
+                            let key_frame = &self.key_frames[i];
+                            let t = (key_frame.frame - left.frame) / (right.frame - left.frame);
+                            let error = (left.value + (right.value - left.value) * t - key_frame.value).abs();
+                            
+                            (i, error)
+                        })
+                        .max_by(|(_, a), (_, b)| a.total_cmp(b));
+                    
+                    if let Some((i, error)) = furthest {
+                        if error > tolerance {
+                            keep[i] = true;
+                            ranges.push((start, i));
+                            ranges.push((i, end));
+                        }
+                    }
+                }
+                
+                let mut keep = keep.into_iter();
+                self.key_frames.retain(|_| keep.next().unwrap());
+            },
+            AnimInterpolation::Hermite => {},
+        }
+    }
+}
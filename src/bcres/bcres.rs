@@ -1,18 +1,25 @@
 use std::{
     collections::HashMap,
+    fs,
     io::{Cursor, Read, Seek, SeekFrom, Write},
-    str::from_utf8,
+    path::Path,
 };
 
-use anyhow::Result;
 use binrw::{BinRead, BinWrite};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    assert_matching, get_4_byte_string, scoped_reader_pos, util::pointer::Pointer, write_at_pointer,
+    error::{invalid_input, malformed, unsupported, ResultExt},
+    get_4_byte_string, scoped_reader_pos,
+    util::pointer::Pointer,
+    write_at_pointer, Result,
 };
 
-use super::{model::CgfxModel, texture::CgfxTexture};
+use super::{
+    animation::CgfxAnimation, blob::with_blob_dir, emitter::CgfxEmitter, model::CgfxModel, texture::CgfxTexture,
+    util::{check_count, CgfxObjectHeader},
+};
 
 fn read_string(read: &mut impl Read) -> Result<String> {
 	let mut string_buffer = Vec::new();
@@ -30,8 +37,58 @@ fn read_string(read: &mut impl Read) -> Result<String> {
 	Ok(String::from_utf8(string_buffer)?)
 }
 
+/// Names of the 16 dicts of a container in file order, used for errors.
+const DICT_NAMES: [&str; 16] = [
+    "models", "textures", "luts", "materials", "shaders", "cameras", "lights", "fogs", "scenes",
+    "skeletal_animations", "material_animations", "visibility_animations", "camera_animations",
+    "light_animations", "fog_animations", "emitters",
+];
+
+/// Reads dict number `index` of the container if there is one and checks it against the count
+/// the container has for it.
+fn read_dict_at<T: CgfxCollectionValue, R: Read + Seek>(reader: &mut R, dict_references: &[(u32, Option<Pointer>); 16], index: usize) -> Result<Option<CgfxDict<T>>> {
+    let (count, pointer) = dict_references[index];
+    let name = DICT_NAMES[index];
+    
+    let dict = match pointer {
+        Some(pointer) => {
+            reader.seek(SeekFrom::Start(pointer.into()))?;
+            Some(CgfxDict::from_reader(reader).at(pointer.into(), name)?)
+        },
+        None => None,
+    };
+    
+    let dict_count = dict.as_ref().map_or(0, CgfxDict::len);
+    
+    if dict_count != count as usize {
+        return Err(malformed!("Container says it has {} {} but its dict has {}", count, name, dict_count))
+            .at(pointer.map_or(0, u64::from), name);
+    }
+    
+    Ok(dict)
+}
+
+/// Writes `dict` at the current position and fills in its entry in the
+/// dict pointer array of the container at `reference_offset`.
+fn write_dict_reference<T: CgfxCollectionValue>(writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext, reference_offset: Pointer, dict: &Option<CgfxDict<T>>) -> Result<()> {
+    if let Some(dict) = dict {
+        let current_offset: Pointer = Pointer::try_from(&writer)?;
+        let relative_offset: Pointer = current_offset - (reference_offset + 4);
+        let count = dict.len();
+        
+        write_at_pointer(writer, reference_offset, count.try_into()?)?;
+        write_at_pointer(writer, reference_offset + 4, relative_offset.into())?;
+        
+        dict.to_writer(writer, ctx)?;
+    }
+    
+    Ok(())
+}
+
 pub struct WriteContext {
     string_section: String,
+    // offsets of whole strings in the string section, so a name isn't matched inside another one
+    string_offsets: HashMap<String, Pointer>,
     string_references: HashMap<Pointer, String>,
     
     image_section: Vec<u8>,
@@ -44,6 +101,7 @@ impl WriteContext {
     pub fn new() -> Self {
         WriteContext {
             string_section: String::new(),
+            string_offsets: HashMap::new(),
             string_references: HashMap::new(),
             image_section: Vec::new(),
             image_references: HashMap::new(),
@@ -51,11 +109,12 @@ impl WriteContext {
     }
     
     pub fn add_string(&mut self, string: &str) -> Result<()> {
-        if self.string_section.find(string).is_some() {
+        if self.string_offsets.contains_key(string) {
             // string exists already, exiting early
             return Ok(());
         }
         
+        self.string_offsets.insert(string.to_string(), self.string_section.len().try_into()?);
         self.string_section.push_str(string);
         self.string_section.push('\0');
         Ok(())
@@ -80,8 +139,7 @@ impl WriteContext {
 }
 
 pub trait CgfxCollectionValue: Sized {
-    // TODO: migrate this to use impl Read + Seek instead of Cursor
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self>;
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self>;
     fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()>;
 }
 
@@ -91,7 +149,7 @@ where
     for<'a> <T as BinRead>::Args<'a>: Default,
     for<'a> <T as BinWrite>::Args<'a>: Default,
 {
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         Ok(Self::read_le(reader)?)
     }
 
@@ -101,7 +159,7 @@ where
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CgfxNode<T: CgfxCollectionValue> {
     pub reference_bit: u32,
     pub left_node_index: u16,
@@ -110,8 +168,11 @@ pub struct CgfxNode<T: CgfxCollectionValue> {
     pub name: Option<String>,
     pub value: Option<T>,
     
+    #[serde(skip)]
     file_offset: Pointer,
+    #[serde(skip)]
     name_pointer: Option<Pointer>,
+    #[serde(skip)]
     value_pointer: Option<Pointer>,
 }
 
@@ -160,7 +221,7 @@ impl<T: CgfxCollectionValue> CgfxNode<T> {
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CgfxDict<T: CgfxCollectionValue> {
     pub magic_number: String,
     pub tree_length: u32,
@@ -168,6 +229,26 @@ pub struct CgfxDict<T: CgfxCollectionValue> {
     pub nodes: Vec<CgfxNode<T>>,
 }
 
+/// Gets a bit of a dict name, counting from the least significant bit of its last character.
+/// Bits outside of the name are zero.
+fn name_bit(name: &str, bit: u32) -> bool {
+    let bytes = name.as_bytes();
+    let char_index = bytes.len() as i64 - i64::from(bit >> 3) - 1;
+    
+    usize::try_from(char_index).ok()
+        .and_then(|char_index| bytes.get(char_index))
+        .is_some_and(|char| (char >> (bit & 7)) & 1 == 1)
+}
+
+/// The child of `node` to follow when looking up `name`.
+fn node_child_index<T: CgfxCollectionValue>(node: &CgfxNode<T>, name: &str) -> u16 {
+    if name_bit(name, node.reference_bit) {
+        node.right_node_index
+    } else {
+        node.left_node_index
+    }
+}
+
 impl<T: CgfxCollectionValue> CgfxDict<T> {
     pub fn from_buffer(buffer: &[u8], start_position: Pointer) -> Result<Self> {
         let mut cursor = Cursor::new(buffer);
@@ -176,25 +257,35 @@ impl<T: CgfxCollectionValue> CgfxDict<T> {
         Self::from_reader(&mut cursor)
     }
     
-    pub fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader_with(reader, T::read_dict_value)
+    }
+    
+    /// Like `from_reader`, but reads every value with `read_value` instead of
+    /// `CgfxCollectionValue::read_dict_value`, e.g. to collect where the values were read from.
+    pub fn from_reader_with<R: Read + Seek>(reader: &mut R, mut read_value: impl FnMut(&mut R) -> Result<T>) -> Result<Self> {
         let magic_number = get_4_byte_string(reader)?;
         let tree_length = reader.read_u32::<LittleEndian>()?;
         let values_count = reader.read_u32::<LittleEndian>()?;
+        check_count(reader, values_count, 16)?;
         
-        let nodes_result: Result<Vec<CgfxNode<T>>> = (0..values_count + 1)
-            .map(|_| CgfxNode::from_reader(reader, Pointer::try_from(&reader)?))
+        let nodes_result: Result<Vec<CgfxNode<T>>> = (0..=values_count)
+            .map(|_| {
+                let file_offset = Pointer::try_from(reader.stream_position()?)?;
+                CgfxNode::from_reader(reader, file_offset)
+            })
             .collect();
         
         let mut nodes = nodes_result?;
         
-        for node in &mut nodes {
+        for (i, node) in nodes.iter_mut().enumerate() {
             if let Some(name_pointer) = node.name_pointer {
                 scoped_reader_pos!(reader);
                 
                 let string_offset: Pointer = node.file_offset + 8 + name_pointer;
                 reader.seek(SeekFrom::Start(string_offset.into()))?;
                 
-                node.name = Some(read_string(reader)?);
+                node.name = Some(read_string(reader).at(string_offset.into(), &format!("[{}]", i))?);
             }
             
             if let Some(value_pointer) = node.value_pointer {
@@ -203,7 +294,12 @@ impl<T: CgfxCollectionValue> CgfxDict<T> {
                 let value_offset: Pointer = node.file_offset + 12 + value_pointer;
                 reader.seek(SeekFrom::Start(value_offset.into()))?;
                 
-                node.value = Some(T::read_dict_value(reader)?);
+                let segment = match &node.name {
+                    Some(name) => name.clone(),
+                    None => format!("[{}]", i),
+                };
+                
+                node.value = Some(read_value(reader).at(value_offset.into(), &segment)?);
             }
         }
         
@@ -215,17 +311,253 @@ impl<T: CgfxCollectionValue> CgfxDict<T> {
         })
     }
     
+    /// An empty dict, only consisting of the root node of its patricia tree.
+    pub fn new(magic_number: &str) -> Self {
+        CgfxDict {
+            magic_number: magic_number.to_string(),
+            tree_length: 0x1C,
+            values_count: 0,
+            nodes: vec![CgfxNode::<T> {
+                reference_bit: 0xFFFFFFFF,
+                left_node_index: 0,
+                right_node_index: 0,
+                name: None,
+                value: None,
+                file_offset: Pointer(0),
+                name_pointer: None,
+                value_pointer: None,
+            }],
+        }
+    }
+    
+    /// Builds a dict from named values, constructing the patricia tree used for lookups.
+    /// The values keep their order, which matters for values referencing each other by index.
+    pub fn from_values(magic_number: &str, values: Vec<(String, T)>) -> Result<Self> {
+        let mut dict = Self::new(magic_number);
+        
+        for (name, value) in values {
+            dict.insert_node(name, value)?;
+        }
+        
+        Ok(dict)
+    }
+    
+    /// Appends a node for a new name and links it into the patricia tree.
+    fn insert_node(&mut self, name: String, value: T) -> Result<()> {
+        let nodes = &mut self.nodes;
+        
+        if nodes.iter().any(|node| node.name.as_deref() == Some(name.as_str())) {
+            return Err(invalid_input!("Dict already contains a value named {:?}", name));
+        }
+        
+        let node_index = u16::try_from(nodes.len())?;
+        
+        // find the name closest to the new one to get the first bit they differ in
+        let mut parent = &nodes[0];
+        let mut current = &nodes[usize::from(parent.left_node_index)];
+        
+        while parent.reference_bit > current.reference_bit {
+            parent = current;
+            current = &nodes[usize::from(node_child_index(current, &name))];
+        }
+        
+        let closest_name = current.name.as_deref().unwrap_or("");
+        let max_bit = (name.len().max(closest_name.len()) * 8) as u32;
+        let reference_bit = (0..max_bit).rev()
+            .find(|bit| name_bit(&name, *bit) != name_bit(closest_name, *bit))
+            .ok_or_else(|| invalid_input!("Dict names {:?} and {:?} can't be told apart", name, closest_name))?;
+        
+        // find where the new node has to be inserted
+        let mut parent_index = 0;
+        let mut current_index = nodes[0].left_node_index;
+        
+        loop {
+            let (parent, current) = (&nodes[parent_index], &nodes[usize::from(current_index)]);
+            
+            if parent.reference_bit <= current.reference_bit || current.reference_bit <= reference_bit {
+                break;
+            }
+            
+            parent_index = usize::from(current_index);
+            current_index = node_child_index(current, &name);
+        }
+        
+        let (left_node_index, right_node_index) = if name_bit(&name, reference_bit) {
+            (current_index, node_index)
+        } else {
+            (node_index, current_index)
+        };
+        
+        let parent = &mut nodes[parent_index];
+        
+        if name_bit(&name, parent.reference_bit) {
+            parent.right_node_index = node_index;
+        } else {
+            parent.left_node_index = node_index;
+        }
+        
+        nodes.push(CgfxNode {
+            reference_bit,
+            left_node_index,
+            right_node_index,
+            name: Some(name),
+            value: Some(value),
+            file_offset: Pointer(0),
+            name_pointer: None,
+            value_pointer: None,
+        });
+        
+        self.values_count = u32::try_from(nodes.len() - 1)?;
+        self.tree_length = 0x1C + 0x10 * self.values_count;
+        Ok(())
+    }
+    
+    /// Rebuilding the tree needs every node to have a name and value, which is checked before
+    /// editing so a failure doesn't leave the dict half edited.
+    fn check_rebuildable(&self) -> Result<()> {
+        match self.nodes.iter().skip(1).find(|node| node.name.is_none() || node.value.is_none()) {
+            Some(node) => Err(invalid_input!("Dict node {:?} has no name or value, so the dict can't be edited", node.name)),
+            None => Ok(()),
+        }
+    }
+    
+    /// Rebuilds the patricia tree from scratch, keeping the order of the values.
+    fn rebuild_tree(&mut self) -> Result<()> {
+        let values = self.nodes.drain(1..)
+            .filter_map(|node| Some((node.name?, node.value?)))
+            .collect();
+        
+        *self = Self::from_values(&self.magic_number, values)?;
+        Ok(())
+    }
+    
+    /// Looks up `name` in the patricia tree, returning the index of its node.
+    fn find_node_index(&self, name: &str) -> Option<usize> {
+        let mut parent = self.nodes.first()?;
+        let mut current_index = usize::from(parent.left_node_index);
+        let mut current = self.nodes.get(current_index)?;
+        
+        while parent.reference_bit > current.reference_bit {
+            parent = current;
+            current_index = usize::from(node_child_index(current, name));
+            current = self.nodes.get(current_index)?;
+        }
+        
+        (current.name.as_deref() == Some(name)).then_some(current_index)
+    }
+    
+    pub fn len(&self) -> usize {
+        self.nodes.len().saturating_sub(1)
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.find_node_index(name).is_some()
+    }
+    
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.nodes[self.find_node_index(name)?].value.as_ref()
+    }
+    
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut T> {
+        let index = self.find_node_index(name)?;
+        self.nodes[index].value.as_mut()
+    }
+    
+    /// The position of `name` among the values, which is how values like bones reference each other.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        Some(self.find_node_index(name)? - 1)
+    }
+    
+    /// Adds a value under `name` after all other ones, or replaces the value which already has that
+    /// name, keeping its position. Returns the replaced value.
+    pub fn insert(&mut self, name: &str, value: T) -> Result<Option<T>> {
+        if let Some(index) = self.find_node_index(name) {
+            return Ok(self.nodes[index].value.replace(value));
+        }
+        
+        self.insert_node(name.to_string(), value)?;
+        Ok(None)
+    }
+    
+    /// Removes the value called `name`. The values after it move up by one position, so indices
+    /// referring to them (e.g. `CgfxBone::parent`) have to be updated by the caller.
+    pub fn remove(&mut self, name: &str) -> Result<Option<T>> {
+        let Some(index) = self.find_node_index(name) else {
+            return Ok(None);
+        };
+        
+        self.check_rebuildable()?;
+        let node = self.nodes.remove(index);
+        self.rebuild_tree()?;
+        
+        Ok(node.value)
+    }
+    
+    /// Renames a value, keeping its position.
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<()> {
+        let index = self.find_node_index(name)
+            .ok_or_else(|| invalid_input!("Dict doesn't contain a value named {:?}", name))?;
+        
+        if name == new_name {
+            return Ok(());
+        }
+        
+        if self.contains_key(new_name) {
+            return Err(invalid_input!("Dict already contains a value named {:?}", new_name));
+        }
+        
+        self.check_rebuildable()?;
+        self.nodes[index].name = Some(new_name.to_string());
+        self.rebuild_tree()
+    }
+    
+    /// The names and values in order, without the root node.
+    pub fn iter(&self) -> impl Iterator<Item = (Option<&str>, &T)> {
+        self.nodes.iter().skip(1)
+            .filter_map(|node| Some((node.name.as_deref(), node.value.as_ref()?)))
+    }
+    
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Option<&str>, &mut T)> {
+        self.nodes.iter_mut().skip(1)
+            .filter_map(|node| Some((node.name.as_deref(), node.value.as_mut()?)))
+    }
+    
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.iter().map(|(_, value)| value)
+    }
+    
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.iter_mut().map(|(_, value)| value)
+    }
+    
     pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
-        assert!(self.values_count + 1 == self.nodes.len() as u32, "values_count does not match node count");
+        self.to_writer_with_offsets(writer, ctx)?;
+        Ok(())
+    }
+    
+    /// Like `to_writer`, but also returns the offset every value got written to, in node order.
+    /// Useful for values which reference each other, like the bones of a skeleton.
+    pub fn to_writer_with_offsets(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<Vec<Pointer>> {
+        if self.values_count as usize + 1 != self.nodes.len() {
+            return Err(invalid_input!("values_count does not match node count"));
+        }
         
         write!(writer, "{}", self.magic_number)?;
         writer.write_u32::<LittleEndian>(self.tree_length)?;
         writer.write_u32::<LittleEndian>(self.values_count)?;
         
-        for node in &self.nodes {
-            let value_pointer_location = node.to_writer(writer, ctx)?;
-            
-            // TODO: when are the values serialized? here or in a separate loop
+        // all nodes come first, followed by the values they point to
+        let value_pointer_locations = self.nodes.iter()
+            .map(|node| node.to_writer(writer, ctx))
+            .collect::<Result<Vec<Pointer>>>()?;
+        
+        let mut value_offsets = Vec::new();
+        
+        for (node, value_pointer_location) in self.nodes.iter().zip(value_pointer_locations) {
             if let Some(value) = &node.value {
                 // update value pointer to point to current location
                 let current_offset = Pointer::try_from(&writer)?;
@@ -235,14 +567,15 @@ impl<T: CgfxCollectionValue> CgfxDict<T> {
                 
                 // write value
                 value.write_dict_value(writer, ctx)?;
+                value_offsets.push(current_offset);
             }
         }
         
-        Ok(())
+        Ok(value_offsets)
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Default, BinRead, BinWrite)]
+#[derive(Clone, Debug, PartialEq, Eq, Default, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little, magic = b"CGFX")]
 pub struct CgfxHeader {
     pub byte_order_mark: u16,
@@ -253,12 +586,48 @@ pub struct CgfxHeader {
     
     #[br(assert(content_magic_number == 0x41544144u32,
         "Invalid magic number for data, expected 'DATA' but got '{}'",
-        from_utf8(&content_magic_number.to_le_bytes()).unwrap()))]
+        String::from_utf8_lossy(&content_magic_number.to_le_bytes())))]
     pub content_magic_number: u32,
     pub content_length: u32,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl CgfxHeader {
+    /// Fails with the first length of `written` which differs from the one in this header.
+    fn verify_lengths(&self, written: &CgfxHeader) -> Result<()> {
+        let lengths = [
+            ("file_length", self.file_length, written.file_length),
+            ("sections_count", self.sections_count, written.sections_count),
+            ("content_length", self.content_length, written.content_length),
+        ];
+        
+        for (field, expected, actual) in lengths {
+            if expected != actual {
+                return Err(invalid_input!("Written {} is {:#x} but the header says {:#x}", field, actual, expected))
+                    .at(0, "header");
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+/// Fails with the offset of the first byte of `written` which differs from `original`, if there is one.
+fn verify_matching(written: &[u8], original: Option<&[u8]>) -> Result<()> {
+    let Some(original) = original else {
+        return Ok(());
+    };
+    
+    let mismatch = written.iter().zip(original)
+        .position(|(written, original)| written != original)
+        .or_else(|| (written.len() > original.len()).then_some(original.len()));
+    
+    match mismatch {
+        Some(offset) => Err(invalid_input!("Written bytes don't match the original ones")).at(offset as u64, "file"),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CgfxContainer {
     pub header: CgfxHeader,
     
@@ -271,97 +640,143 @@ pub struct CgfxContainer {
     pub lights: Option<CgfxDict<()>>,
     pub fogs: Option<CgfxDict<()>>,
     pub scenes: Option<CgfxDict<()>>,
-    pub skeletal_animations: Option<CgfxDict<()>>,
+    pub skeletal_animations: Option<CgfxDict<CgfxAnimation>>,
     pub material_animations: Option<CgfxDict<()>>,
     pub visibility_animations: Option<CgfxDict<()>>,
     pub camera_animations: Option<CgfxDict<()>>,
     pub light_animations: Option<CgfxDict<()>>,
     pub fog_animations: Option<CgfxDict<()>>,
-    pub emitters: Option<CgfxDict<()>>,
+    pub emitters: Option<CgfxDict<CgfxEmitter>>,
 }
 
 impl CgfxContainer {
     pub fn new(buffer: &[u8]) -> Result<Self> {
-        let mut cursor = Cursor::new(buffer);
-        
-        let header = CgfxHeader::read(&mut cursor)?;
+        Self::from_reader(&mut Cursor::new(buffer))
+    }
+    
+    /// Starts a brand-new container, see `CgfxContainerBuilder`.
+    pub fn builder() -> CgfxContainerBuilder {
+        CgfxContainerBuilder::default()
+    }
+    
+    /// Reads a container from any seekable reader (e.g. a buffered file), which doesn't
+    /// need the whole file to be loaded into memory first. `reader` has to start at the header.
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let header = CgfxHeader::read(reader).at(0, "header")?;
         let mut dict_references: [(u32, Option<Pointer>); 16] = [Default::default(); 16];
         
         for i in 0..16 {
-            let position = Pointer::try_from(&cursor)?;
+            let position = Pointer::try_from(reader.stream_position()?)?;
             
             dict_references[i] = (
-                cursor.read_u32::<LittleEndian>()?,
-                Pointer::read(&mut cursor)?.map(|pointer| pointer + position + 4),
+                reader.read_u32::<LittleEndian>()?,
+                Pointer::read(reader)?.map(|pointer| pointer + position + 4),
             );
         }
         
-        let mut unit_dicts: [Option<CgfxDict<()>>; 16] = Default::default();
-        
-        for (i, (count, offset)) in dict_references.into_iter().enumerate() {
-            // textures
-            if i == 1 {
-                continue;
-            }
-            
-            let dict = match offset {
-                Some(value) => Some(CgfxDict::from_buffer(buffer, value)?),
-                None => None,
-            };
-            
-            if let Some(dict) = &dict {
-                assert_eq!(dict.nodes.len(), (count + 1).try_into().unwrap());
-            } else {
-                assert_eq!(count, 0);
-            }
-            
-            unit_dicts[i] = dict;
-        }
-        
-        let mut unit_dicts_iter = unit_dicts.into_iter();
-        
-        let models = match dict_references[0].1 {
-            Some(pointer) => Some(CgfxDict::<CgfxModel>::from_buffer(buffer, pointer)?),
-            None => None,
-        };
-        
-        let textures = match dict_references[1].1 {
-            Some(pointer) => Some(CgfxDict::<CgfxTexture>::from_buffer(buffer, pointer)?),
-            None => None,
-        };
-        
         Ok(CgfxContainer {
             header,
             
-            models,
-            textures,
-            luts: unit_dicts_iter.nth(2).unwrap(),
-            materials: unit_dicts_iter.next().unwrap(),
-            shaders: unit_dicts_iter.next().unwrap(),
-            cameras: unit_dicts_iter.next().unwrap(),
-            lights: unit_dicts_iter.next().unwrap(),
-            fogs: unit_dicts_iter.next().unwrap(),
-            scenes: unit_dicts_iter.next().unwrap(),
-            skeletal_animations: unit_dicts_iter.next().unwrap(),
-            material_animations: unit_dicts_iter.next().unwrap(),
-            visibility_animations: unit_dicts_iter.next().unwrap(),
-            camera_animations: unit_dicts_iter.next().unwrap(),
-            light_animations: unit_dicts_iter.next().unwrap(),
-            fog_animations: unit_dicts_iter.next().unwrap(),
-            emitters: unit_dicts_iter.next().unwrap(),
+            models: read_dict_at(reader, &dict_references, 0)?,
+            textures: read_dict_at(reader, &dict_references, 1)?,
+            luts: read_dict_at(reader, &dict_references, 2)?,
+            materials: read_dict_at(reader, &dict_references, 3)?,
+            shaders: read_dict_at(reader, &dict_references, 4)?,
+            cameras: read_dict_at(reader, &dict_references, 5)?,
+            lights: read_dict_at(reader, &dict_references, 6)?,
+            fogs: read_dict_at(reader, &dict_references, 7)?,
+            scenes: read_dict_at(reader, &dict_references, 8)?,
+            skeletal_animations: read_dict_at(reader, &dict_references, 9)?,
+            material_animations: read_dict_at(reader, &dict_references, 10)?,
+            visibility_animations: read_dict_at(reader, &dict_references, 11)?,
+            camera_animations: read_dict_at(reader, &dict_references, 12)?,
+            light_animations: read_dict_at(reader, &dict_references, 13)?,
+            fog_animations: read_dict_at(reader, &dict_references, 14)?,
+            emitters: read_dict_at(reader, &dict_references, 15)?,
         })
     }
     
+    /// Writes the container. The lengths in the header are computed from what gets written,
+    /// so the ones in `header` don't need to be updated after editing.
     pub fn to_buffer(&self) -> Result<Vec<u8>> {
-        self.to_buffer_debug(None)
+        Ok(self.write_buffer(None)?.0)
+    }
+    
+    /// Like `to_buffer`, but fails if the written lengths don't match the ones in `header`,
+    /// e.g. to make sure a file which was only read gets written back the same way.
+    pub fn to_buffer_verified(&self) -> Result<Vec<u8>> {
+        let (out, written_header) = self.write_buffer(None)?;
+        self.header.verify_lengths(&written_header)?;
+        
+        Ok(out)
     }
     
+    /// Like `to_buffer_verified`, but also fails with the offset of the first written byte which differs from `original`.
     pub fn to_buffer_debug(&self, original: Option<&[u8]>) -> Result<Vec<u8>> {
+        let (out, written_header) = self.write_buffer(original)?;
+        
+        if original.is_some() {
+            self.header.verify_lengths(&written_header)?;
+        }
+        
+        Ok(out)
+    }
+    
+    /// Sets the lengths in `header` to the ones of the file `to_buffer` writes.
+    pub fn update_header(&mut self) -> Result<()> {
+        self.header = self.write_buffer(None)?.1;
+        Ok(())
+    }
+    
+    /// Dumps the whole container to YAML, which can be edited and turned back into the same
+    /// container with `from_yaml`. Image data and vertex buffers are written to files in `blob_dir`.
+    pub fn to_yaml(&self, blob_dir: &Path) -> Result<String> {
+        fs::create_dir_all(blob_dir)?;
+        Ok(with_blob_dir(blob_dir, || serde_yaml::to_string(self))?)
+    }
+    
+    pub fn from_yaml(yaml: &str, blob_dir: &Path) -> Result<Self> {
+        Ok(with_blob_dir(blob_dir, || serde_yaml::from_str(yaml))?)
+    }
+    
+    /// Like `to_yaml`, but as JSON. NaN and infinite floats can't be stored in JSON.
+    pub fn to_json(&self, blob_dir: &Path) -> Result<String> {
+        fs::create_dir_all(blob_dir)?;
+        Ok(with_blob_dir(blob_dir, || serde_json::to_string_pretty(self))?)
+    }
+    
+    pub fn from_json(json: &str, blob_dir: &Path) -> Result<Self> {
+        Ok(with_blob_dir(blob_dir, || serde_json::from_str(json))?)
+    }
+    
+    /// The dicts whose contents aren't parsed, so they can't be written without losing them.
+    fn unparsed_dicts(&self) -> [(&'static str, &Option<CgfxDict<()>>); 12] {
+        [
+            (DICT_NAMES[2], &self.luts),
+            (DICT_NAMES[3], &self.materials),
+            (DICT_NAMES[4], &self.shaders),
+            (DICT_NAMES[5], &self.cameras),
+            (DICT_NAMES[6], &self.lights),
+            (DICT_NAMES[7], &self.fogs),
+            (DICT_NAMES[8], &self.scenes),
+            (DICT_NAMES[10], &self.material_animations),
+            (DICT_NAMES[11], &self.visibility_animations),
+            (DICT_NAMES[12], &self.camera_animations),
+            (DICT_NAMES[13], &self.light_animations),
+            (DICT_NAMES[14], &self.fog_animations),
+        ]
+    }
+    
+    fn write_buffer(&self, original: Option<&[u8]>) -> Result<(Vec<u8>, CgfxHeader)> {
+        if let Some((name, _)) = self.unparsed_dicts().into_iter().find(|(_, dict)| dict.is_some()) {
+            return Err(unsupported!("Writing containers with {} is not implemented yet", name));
+        }
+        
         let mut out = Vec::new();
         let mut writer = Cursor::new(&mut out);
         
         self.header.write(&mut writer)?;
-        assert_matching!(writer, original);
+        verify_matching(writer.get_ref(), original)?;
         
         // write zeroes for all dicts for now and patch them later
         let dict_pointers_location = Pointer::try_from(&writer)?;
@@ -374,27 +789,17 @@ impl CgfxContainer {
         // write main content
         let mut ctx = WriteContext::new();
         
-        if let Some(textures) = &self.textures {
-            // write reference in dict pointer array above
-            let reference_offset: Pointer = dict_pointers_location + 8;
-            
-            let current_offset: Pointer = Pointer::try_from(&writer)?;
-            let relative_offset: Pointer = current_offset - (reference_offset + 4);
-            let count = textures.nodes.len() - 1;
-            
-            write_at_pointer(&mut writer, reference_offset, count.try_into()?)?;
-            write_at_pointer(&mut writer, reference_offset + 4, relative_offset.into())?;
-            
-            // write dict
-            textures.to_writer(&mut writer, &mut ctx)?;
-        }
+        write_dict_reference(&mut writer, &mut ctx, dict_pointers_location, &self.models)?;
+        write_dict_reference(&mut writer, &mut ctx, dict_pointers_location + 8, &self.textures)?;
+        write_dict_reference(&mut writer, &mut ctx, dict_pointers_location + 72, &self.skeletal_animations)?;
+        write_dict_reference(&mut writer, &mut ctx, dict_pointers_location + 120, &self.emitters)?;
         
         // apply string references
         let string_section_start = Pointer::try_from(&writer)?;
         
         for (location, target_string) in ctx.string_references {
-            if let Some(string_offset_usize) = ctx.string_section.find(&target_string) {
-                let string_offset = Pointer::from(string_offset_usize) + string_section_start;
+            if let Some(&string_offset) = ctx.string_offsets.get(&target_string) {
+                let string_offset = string_offset + string_section_start;
                 let relative_offset = string_offset - location;
                 
                 write_at_pointer(&mut writer, location, relative_offset.into())?;
@@ -402,14 +807,17 @@ impl CgfxContainer {
         }
         
         // write strings
-        writer.write(ctx.string_section.as_bytes())?;
+        writer.write_all(ctx.string_section.as_bytes())?;
         
         // apply padding
         let alignment: i32 = 128;
         let buffer_size: i32 = writer.position().try_into()?;
         let padding_size = ((-buffer_size - 8) % alignment + alignment) % alignment; // weird padding calculation
         
-        writer.write(&vec![0u8; padding_size.try_into()?])?;
+        writer.write_all(&vec![0u8; padding_size.try_into()?])?;
+        
+        // the DATA section ends right before the IMAG section, including the padding
+        let data_section_end: u32 = writer.position().try_into()?;
         
         // apply image section references
         let image_section_offset: Pointer = Pointer::try_from(&writer)? + 8;
@@ -421,74 +829,110 @@ impl CgfxContainer {
             write_at_pointer(&mut writer, location, relative_offset.into())?;
         }
         
-        assert_matching!(writer, original);
+        verify_matching(writer.get_ref(), original)?;
         
-        // write image data section
-        let image_section_length: u32 = ctx.image_section.len().try_into()?;
+        // write image data section, files which had one keep it even without any images
+        let write_image_section = !ctx.image_section.is_empty() || self.header.sections_count >= 2;
         
-        writer.write(b"IMAG")?;
-        writer.write_u32::<LittleEndian>(image_section_length + 8)?;
+        if write_image_section {
+            let image_section_length: u32 = ctx.image_section.len().try_into()?;
+            
+            writer.write_all(b"IMAG")?;
+            writer.write_u32::<LittleEndian>(image_section_length + 8)?;
+            
+            writer.write_all(&ctx.image_section)?;
+            
+            verify_matching(writer.get_ref(), original)?;
+        }
         
-        writer.write(&ctx.image_section)?;
+        // edited files can change in size, so the lengths can't just be taken over
+        let header = CgfxHeader {
+            file_length: writer.get_ref().len().try_into()?,
+            // DATA and, if it was written, IMAG
+            sections_count: if write_image_section { 2 } else { 1 },
+            content_length: data_section_end.checked_sub(self.header.header_length.into())
+                .ok_or_else(|| invalid_input!("Header length {:#x} is longer than the header", self.header.header_length))?,
+            ..self.header.clone()
+        };
         
-        assert_matching!(writer, original);
-        assert!(writer.get_ref().len() == self.header.file_length as usize,
-            "Written file size does not match expected file size, expected 0x{:x} bytes but got 0x{:x} bytes",
-            self.header.file_length,
-            writer.get_ref().len());
+        write_at_pointer(&mut writer, Pointer::from(12u32), header.file_length)?;
+        write_at_pointer(&mut writer, Pointer::from(16u32), header.sections_count)?;
+        write_at_pointer(&mut writer, Pointer::from(24u32), header.content_length)?;
         
-        Ok(out)
+        Ok((out, header))
     }
     
-    pub fn from_single_texture(name: String, orig_reference_bit: u32, texture: CgfxTexture) -> CgfxContainer {
-        let header = CgfxHeader {
-            byte_order_mark: 0xfeff,
-            header_length: 20,
-            revision: 0x5000000,
-            file_length: 0x180 + texture.size(),
-            sections_count: 2,
-            content_magic_number: 0x41544144,
-            content_length: 356,
-        };
+    /// A container with `texture` as its only content, stored under `name`.
+    pub fn from_single_texture(name: String, texture: CgfxTexture) -> Result<CgfxContainer> {
+        let mut container = CgfxContainer::builder().build()?;
         
-        let name_len =  texture.metadata().cgfx_object_header.name.as_ref()
-            .map_or(0, |name| name.len());
-        
-        // println!("{}: {} {}", texture.metadata().name.as_ref().unwrap_or(&"None".to_string()), (name_len << 3) - 2, orig_reference_bit);
-        
-        let textures = CgfxDict::<CgfxTexture> {
-            magic_number: "DICT".to_string(),
-            tree_length: 44,
-            values_count: 1,
-            nodes: vec![
-                CgfxNode::<CgfxTexture> {
-                    reference_bit: 0xFFFFFFFF,
-                    left_node_index: 1,
-                    right_node_index: 0,
-                    name: None,
-                    value: None,
-                    file_offset: Pointer(0),
-                    name_pointer: None,
-                    value_pointer: None,
-                },
-                CgfxNode::<CgfxTexture> {
-                    reference_bit: ((name_len << 3) - 2).try_into().unwrap(),
-                    left_node_index: 0,
-                    right_node_index: 1,
-                    name: Some(name),
-                    value: Some(texture),
-                    file_offset: Pointer(0),
-                    name_pointer: None,
-                    value_pointer: None,
-                },
-            ],
-        };
+        container.textures = Some(CgfxDict::from_values("DICT", vec![(name, texture)])?);
+        container.update_header()?;
         
-        CgfxContainer {
-            header,
+        Ok(container)
+    }
+}
+
+/// Collects the objects of a new container. Every object needs a name, which becomes its key in the dict.
+///
+/// ```no_run
+/// # use nw_tex::bcres::{bcres::CgfxContainer, image_codec::RgbaColor, texture::{CgfxTexture, PicaTextureFormat}};
+/// # fn main() -> nw_tex::Result<()> {
+/// let pixels = vec![RgbaColor { r: 255, g: 0, b: 0, a: 255 }; 64 * 64];
+/// let texture = CgfxTexture::from_rgba("red", 64, 64, PicaTextureFormat::RGBA8, &pixels)?;
+/// let bcres = CgfxContainer::builder().texture(texture).build()?.to_buffer()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CgfxContainerBuilder {
+    models: Vec<CgfxModel>,
+    textures: Vec<CgfxTexture>,
+    skeletal_animations: Vec<CgfxAnimation>,
+    emitters: Vec<CgfxEmitter>,
+}
+
+impl CgfxContainerBuilder {
+    pub fn model(mut self, model: CgfxModel) -> Self {
+        self.models.push(model);
+        self
+    }
+    
+    pub fn texture(mut self, texture: CgfxTexture) -> Self {
+        self.textures.push(texture);
+        self
+    }
+    
+    pub fn skeletal_animation(mut self, animation: CgfxAnimation) -> Self {
+        self.skeletal_animations.push(animation);
+        self
+    }
+    
+    pub fn emitter(mut self, emitter: CgfxEmitter) -> Self {
+        self.emitters.push(emitter);
+        self
+    }
+    
+    /// Builds the dicts and fills in the header, including the lengths of the file and its sections.
+    pub fn build(self) -> Result<CgfxContainer> {
+        let models = named_dict(self.models, |model| &model.common().cgfx_object_header)?;
+        let textures = named_dict(self.textures, |texture| &texture.common().cgfx_object_header)?;
+        let skeletal_animations = named_dict(self.skeletal_animations, |animation| &animation.cgfx_object_header)?;
+        let emitters = named_dict(self.emitters, |emitter| &emitter.cgfx_object_header)?;
+        
+        let mut container = CgfxContainer {
+            header: CgfxHeader {
+                byte_order_mark: 0xFEFF,
+                header_length: 0x14,
+                revision: 0x5000000,
+                file_length: 0,
+                sections_count: 0,
+                content_magic_number: 0x41544144,
+                content_length: 0,
+            },
             
-            models: None,
-            textures: Some(textures),
+            models,
+            textures,
             luts: None,
             materials: None,
             shaders: None,
@@ -496,13 +940,33 @@ impl CgfxContainer {
             lights: None,
             fogs: None,
             scenes: None,
-            skeletal_animations: None,
+            skeletal_animations,
             material_animations: None,
             visibility_animations: None,
             camera_animations: None,
             light_animations: None,
             fog_animations: None,
-            emitters: None,
-        }
+            emitters,
+        };
+        
+        container.update_header()?;
+        Ok(container)
     }
 }
+
+fn named_dict<T: CgfxCollectionValue>(values: Vec<T>, header: impl Fn(&T) -> &CgfxObjectHeader) -> Result<Option<CgfxDict<T>>> {
+    if values.is_empty() {
+        return Ok(None);
+    }
+    
+    let values = values.into_iter()
+        .map(|value| {
+            let name = header(&value).name.clone()
+                .ok_or_else(|| invalid_input!("Objects need a name to be added to a container"))?;
+            
+            Ok((name, value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    
+    Ok(Some(CgfxDict::from_values("DICT", values)?))
+}
@@ -0,0 +1,121 @@
+//! Serde helpers for large binary data like image data and vertex buffers, so text dumps
+//! of a container stay readable. Inside of `with_blob_dir`, every blob gets written to its
+//! own file and only the file name ends up in the text, otherwise blobs are embedded as base64.
+
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    result,
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+struct BlobDir {
+    path: PathBuf,
+    next_index: usize,
+}
+
+thread_local! {
+    static BLOB_DIR: RefCell<Option<BlobDir>> = const { RefCell::new(None) };
+}
+
+/// Restores the previous blob directory, even if serializing panics.
+struct BlobDirGuard(Option<BlobDir>);
+
+impl Drop for BlobDirGuard {
+    fn drop(&mut self) {
+        BLOB_DIR.set(self.0.take());
+    }
+}
+
+/// Runs `f`, which (de)serializes something, with blobs being stored in files in `dir`.
+/// The files are numbered in the order they are serialized in, so the same data always
+/// ends up in the same files.
+pub fn with_blob_dir<T>(dir: &Path, f: impl FnOnce() -> T) -> T {
+    let _guard = BlobDirGuard(BLOB_DIR.replace(Some(BlobDir {
+        path: dir.to_path_buf(),
+        next_index: 0,
+    })));
+    
+    f()
+}
+
+/// How a blob is stored in the text, e.g. `image_bytes: !file 0003.bin`
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum StoredBlob {
+    File(String),
+    Base64(String),
+}
+
+struct BlobRef<'a>(&'a [u8]);
+
+impl Serialize for BlobRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        serialize(self.0, serializer)
+    }
+}
+
+struct Blob(Vec<u8>);
+
+impl<'de> Deserialize<'de> for Blob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        deserialize(deserializer).map(Blob)
+    }
+}
+
+pub(crate) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> result::Result<S::Ok, S::Error> {
+    let stored = BLOB_DIR.with_borrow_mut(|blob_dir| {
+        let Some(blob_dir) = blob_dir else {
+            return Ok(StoredBlob::Base64(BASE64_STANDARD.encode(bytes)));
+        };
+        
+        let file_name = format!("{:04}.bin", blob_dir.next_index);
+        blob_dir.next_index += 1;
+        
+        fs::write(blob_dir.path.join(&file_name), bytes)
+            .map_err(|error| ser::Error::custom(format!("Can't write blob {}: {}", file_name, error)))?;
+        
+        Ok(StoredBlob::File(file_name))
+    })?;
+    
+    stored.serialize(serializer)
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<Vec<u8>, D::Error> {
+    let file_name = match StoredBlob::deserialize(deserializer)? {
+        StoredBlob::Base64(encoded) => return BASE64_STANDARD.decode(encoded)
+            .map_err(|error| de::Error::custom(format!("Invalid base64 blob: {}", error))),
+        StoredBlob::File(file_name) => file_name,
+    };
+    
+    // blobs are always written directly into the blob directory
+    if Path::new(&file_name).file_name().and_then(|name| name.to_str()) != Some(file_name.as_str()) {
+        return Err(de::Error::custom(format!("Blob file {:?} has to be inside of the blob directory", file_name)));
+    }
+    
+    let path = BLOB_DIR.with_borrow(|blob_dir| blob_dir.as_ref().map(|blob_dir| blob_dir.path.join(&file_name)))
+        .ok_or_else(|| de::Error::custom(format!("Blob {} is stored in a file, but there is no blob directory", file_name)))?;
+    
+    fs::read(&path)
+        .map_err(|error| de::Error::custom(format!("Can't read blob {}: {}", path.display(), error)))
+}
+
+/// The same for optional blobs, like the raw bytes of vertex buffers.
+pub(crate) mod option {
+    use std::result;
+    
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    
+    use super::{Blob, BlobRef};
+    
+    pub(crate) fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> result::Result<S::Ok, S::Error> {
+        bytes.as_deref().map(BlobRef).serialize(serializer)
+    }
+    
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<Option<Vec<u8>>, D::Error> {
+        Ok(Option::<Blob>::deserialize(deserializer)?.map(|blob| blob.0))
+    }
+}
@@ -0,0 +1,449 @@
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+use binrw::{BinRead, BinWrite, Endian};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::{malformed, ResultExt}, scoped_reader_pos, util::{math::Vec3, pointer::Pointer}, Result};
+
+use super::{
+    bcres::{CgfxCollectionValue, WriteContext},
+    image_codec::RgbaColor,
+    util::{
+        brw_read_string, read_pointer_table, write_list_placeholder, write_pointer_list, write_string_pointer,
+        CgfxNodeHeader, CgfxObjectHeader, CgfxTransform,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CgfxEmitter {
+    /// Type flags of the emitter, which include the flag 0x40000000 marking it as a node
+    pub discriminant: u32,
+    
+    // header stuff
+    pub cgfx_object_header: CgfxObjectHeader,
+    pub cgfx_node_header: CgfxNodeHeader,
+    pub transform_node_header: CgfxTransform,
+    
+    // emitter data
+    pub particle_set_path: Option<String>,
+    pub emitter_type: EmitterType,
+    pub parameters: EmitterParameters,
+    pub shape: EmitterShape,
+    
+    pub initializers: Option<Vec<ParticleInitializer>>,
+    pub updaters: Option<Vec<ParticleUpdater>>,
+}
+
+impl CgfxEmitter {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let header_offset = reader.stream_position()? + 4;
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        
+        // emitters are transform nodes, so they carry the node flag like models do
+        if discriminant & 0x40000000 == 0 {
+            return Err(malformed!("Invalid emitter type discriminant {:x}", discriminant));
+        }
+        
+        let cgfx_object_header = CgfxObjectHeader::read(reader)?;
+        let cgfx_node_header = CgfxNodeHeader::read(reader)?;
+        let transform_node_header = CgfxTransform::read(reader)?;
+        
+        let particle_set_path = brw_read_string(reader, Endian::Little, ())?;
+        let emitter_type = EmitterType::read(reader)?;
+        let parameters = EmitterParameters::read(reader)?;
+        let shape = EmitterShape::from_reader(reader)?;
+        
+        let initializer_table = read_pointer_table(reader)?;
+        let updater_table = read_pointer_table(reader)?;
+        
+        // unknown initializers and updaters are kept as raw bytes, which end
+        // where the next list, object or dict the emitter points to starts
+        let mut boundaries: Vec<u64> = initializer_table.iter().chain(&updater_table)
+            .flat_map(|(table_offset, offsets)| offsets.iter().copied().chain([*table_offset]))
+            .collect();
+        
+        // metadata and anim group pointers of the object and node headers
+        for pointer_location in [header_offset + 16, header_offset + 40] {
+            scoped_reader_pos!(reader);
+            reader.seek(SeekFrom::Start(pointer_location))?;
+            boundaries.extend(Pointer::read_relative(reader)?.map(u64::from));
+        }
+        
+        let end_of = |offset: u64| boundaries.iter().copied().filter(|&boundary| boundary > offset).min();
+        
+        let initializers = initializer_table
+            .map(|(_, offsets)| read_objects(reader, &offsets, "initializers",
+                |reader, offset| ParticleInitializer::from_reader_until(reader, end_of(offset))))
+            .transpose()?;
+        
+        let updaters = updater_table
+            .map(|(_, offsets)| read_objects(reader, &offsets, "updaters",
+                |reader, offset| ParticleUpdater::from_reader_until(reader, end_of(offset))))
+            .transpose()?;
+        
+        Ok(Self {
+            discriminant,
+            cgfx_object_header,
+            cgfx_node_header,
+            transform_node_header,
+            particle_set_path,
+            emitter_type,
+            parameters,
+            shape,
+            initializers,
+            updaters,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        writer.write_u32::<LittleEndian>(self.discriminant)?;
+        
+        let header_offset = Pointer::try_from(&writer)?;
+        
+        if let Some(name) = &self.cgfx_object_header.name {
+            ctx.add_string(name)?;
+            ctx.add_string_reference(header_offset + 8, name.clone());
+        }
+        
+        self.cgfx_object_header.write(writer)?;
+        
+        let node_header_offset = Pointer::try_from(&writer)?;
+        self.cgfx_node_header.write(writer)?;
+        self.transform_node_header.write(writer)?;
+        
+        write_string_pointer(writer, ctx, self.particle_set_path.as_deref())?;
+        self.emitter_type.write(writer)?;
+        self.parameters.write(writer)?;
+        self.shape.to_writer(writer)?;
+        
+        // initializers and updaters are written after the emitter, patch pointers later
+        let initializer_pointer_location = write_list_placeholder(writer, self.initializers.as_ref().map_or(0, Vec::len))?;
+        let updater_pointer_location = write_list_placeholder(writer, self.updaters.as_ref().map_or(0, Vec::len))?;
+        
+        if let Some(initializers) = &self.initializers {
+            write_pointer_list(writer, ctx, initializer_pointer_location, initializers,
+                |initializer, writer, _| initializer.to_writer(writer))?;
+        }
+        
+        if let Some(updaters) = &self.updaters {
+            write_pointer_list(writer, ctx, updater_pointer_location, updaters,
+                |updater, writer, _| updater.to_writer(writer))?;
+        }
+        
+        self.cgfx_object_header.write_metadata(writer, ctx, header_offset)?;
+        self.cgfx_node_header.write_anim_groups(writer, ctx, node_header_offset)?;
+        
+        Ok(())
+    }
+}
+
+/// Reads the objects at `offsets` with `read_object`, keeping the position of `reader`.
+fn read_objects<R: Read + Seek, T>(
+    reader: &mut R,
+    offsets: &[u64],
+    segment: &str,
+    mut read_object: impl FnMut(&mut R, u64) -> Result<T>,
+) -> Result<Vec<T>> {
+    scoped_reader_pos!(reader);
+    
+    offsets.iter().enumerate()
+        .map(|(i, &offset)| {
+            reader.seek(SeekFrom::Start(offset))?;
+            read_object(reader, offset).at(offset, &format!("{}[{}]", segment, i))
+        })
+        .collect()
+}
+
+/// Size of the largest payload of the known initializer and updater types.
+const MAX_KNOWN_PAYLOAD: u64 = 24;
+
+/// Reads the payload of an unknown initializer or updater, which ends at `end`. If nothing
+/// is known to follow it, it is assumed to be no larger than the largest known payload.
+fn read_unknown_payload<R: Read + Seek>(reader: &mut R, end: Option<u64>) -> Result<Vec<u8>> {
+    let position = reader.stream_position()?;
+    let length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(position))?;
+    
+    let end = end.unwrap_or(position + MAX_KNOWN_PAYLOAD).min(length);
+    let mut payload = vec![0; usize::try_from(end.saturating_sub(position))?];
+    reader.read_exact(&mut payload)?;
+    
+    Ok(payload)
+}
+
+impl CgfxCollectionValue for CgfxEmitter {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u32)]
+pub enum EmitterType {
+    Discharge,
+    Constant,
+}
+
+#[derive(Clone, Debug, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little)]
+pub struct EmitterParameters {
+    // emission timing, in frames
+    pub emission_start: f32,
+    pub emission_end: f32,
+    pub emission_interval: f32,
+    pub emission_interval_random: f32,
+    pub emission_ratio: f32,
+    
+    // particle lifetime, in frames
+    pub life_span: f32,
+    pub life_span_random: f32,
+    
+    // initial motion of emitted particles
+    pub direction: Vec3,
+    pub dispersion_angle: f32,
+    pub initial_velocity: f32,
+    pub initial_velocity_random: f32,
+    pub directional_velocity: f32,
+    pub inherit_parent_velocity: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EmitterShape {
+    Point,
+    Sphere { radius: f32, inner_radius_ratio: f32 },
+    SphereSurface { radius: f32 },
+    Cylinder { radius: f32, height: f32, inner_radius_ratio: f32 },
+    CylinderSurface { radius: f32, height: f32 },
+    Box { size: Vec3 },
+    BoxSurface { size: Vec3 },
+    Rectangle { width: f32, height: f32 },
+    Disc { radius: f32, inner_radius_ratio: f32 },
+    Line { length: f32 },
+}
+
+impl EmitterShape {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        
+        // every shape reserves the same amount of space, unused dimensions are zero
+        let dimensions = Vec3::read(reader)?;
+        let inner_radius_ratio = reader.read_f32::<LittleEndian>()?;
+        
+        let shape = match discriminant {
+            0 => Self::Point,
+            1 => Self::Sphere { radius: dimensions.x, inner_radius_ratio },
+            2 => Self::SphereSurface { radius: dimensions.x },
+            3 => Self::Cylinder { radius: dimensions.x, height: dimensions.y, inner_radius_ratio },
+            4 => Self::CylinderSurface { radius: dimensions.x, height: dimensions.y },
+            5 => Self::Box { size: dimensions },
+            6 => Self::BoxSurface { size: dimensions },
+            7 => Self::Rectangle { width: dimensions.x, height: dimensions.y },
+            8 => Self::Disc { radius: dimensions.x, inner_radius_ratio },
+            9 => Self::Line { length: dimensions.x },
+            _ => return Err(malformed!("Invalid emitter shape discriminant {:x}", discriminant)),
+        };
+        
+        Ok(shape)
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
+        let (discriminant, dimensions, inner_radius_ratio) = match *self {
+            Self::Point => (0, Vec3::default(), 0.0),
+            Self::Sphere { radius, inner_radius_ratio } => (1, Vec3::new(radius, 0.0, 0.0), inner_radius_ratio),
+            Self::SphereSurface { radius } => (2, Vec3::new(radius, 0.0, 0.0), 0.0),
+            Self::Cylinder { radius, height, inner_radius_ratio } => (3, Vec3::new(radius, height, 0.0), inner_radius_ratio),
+            Self::CylinderSurface { radius, height } => (4, Vec3::new(radius, height, 0.0), 0.0),
+            Self::Box { size } => (5, size, 0.0),
+            Self::BoxSurface { size } => (6, size, 0.0),
+            Self::Rectangle { width, height } => (7, Vec3::new(width, height, 0.0), 0.0),
+            Self::Disc { radius, inner_radius_ratio } => (8, Vec3::new(radius, 0.0, 0.0), inner_radius_ratio),
+            Self::Line { length } => (9, Vec3::new(length, 0.0, 0.0), 0.0),
+        };
+        
+        writer.write_u32::<LittleEndian>(discriminant)?;
+        dimensions.write(writer)?;
+        writer.write_f32::<LittleEndian>(inner_radius_ratio)?;
+        
+        Ok(())
+    }
+}
+
+/// The per-particle value an initializer or updater operates on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u32)]
+pub enum ParticleStream {
+    Translation,
+    Velocity,
+    Color,
+    Alpha,
+    Scale,
+    Rotation,
+    TexturePattern,
+    LifeSpan,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ParticleInitializer {
+    SingleValue { target: ParticleStream, value: Vec3 },
+    RandomValue { target: ParticleStream, value: Vec3, random: Vec3 },
+    Color { target: ParticleStream, color: RgbaColor },
+    /// A type whose layout isn't known yet, with everything after the target kept as is
+    Unknown { discriminant: u32, target: ParticleStream, payload: Vec<u8> },
+}
+
+impl ParticleInitializer {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader_until(reader, None)
+    }
+    
+    /// Like `from_reader`, but the payload of unknown types ends at `end`.
+    pub fn from_reader_until<R: Read + Seek>(reader: &mut R, end: Option<u64>) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        let target = ParticleStream::read(reader)?;
+        
+        let initializer = match discriminant {
+            0x00100001 => Self::SingleValue { target, value: Vec3::read(reader)? },
+            0x00100002 => Self::RandomValue { target, value: Vec3::read(reader)?, random: Vec3::read(reader)? },
+            0x00100004 => Self::Color { target, color: RgbaColor::read(reader)? },
+            // the game has a couple more initializer types whose layout isn't known yet,
+            // so keep their bytes instead of failing the entire emitter
+            _ => Self::Unknown { discriminant, target, payload: read_unknown_payload(reader, end)? },
+        };
+        
+        Ok(initializer)
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
+        match self {
+            Self::SingleValue { target, value } => {
+                writer.write_u32::<LittleEndian>(0x00100001)?;
+                target.write(writer)?;
+                value.write(writer)?;
+            },
+            Self::RandomValue { target, value, random } => {
+                writer.write_u32::<LittleEndian>(0x00100002)?;
+                target.write(writer)?;
+                value.write(writer)?;
+                random.write(writer)?;
+            },
+            Self::Color { target, color } => {
+                writer.write_u32::<LittleEndian>(0x00100004)?;
+                target.write(writer)?;
+                color.write(writer)?;
+            },
+            Self::Unknown { discriminant, target, payload } => {
+                writer.write_u32::<LittleEndian>(*discriminant)?;
+                target.write(writer)?;
+                writer.write_all(payload)?;
+            },
+        }
+        
+        Ok(())
+    }
+}
+
+impl CgfxCollectionValue for ParticleInitializer {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, _ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ParticleUpdater {
+    ConstantRate { target: ParticleStream, rate: Vec3 },
+    Gravity { target: ParticleStream, direction: Vec3, strength: f32 },
+    Spin { target: ParticleStream, axis: Vec3, speed: f32 },
+    Attraction { target: ParticleStream, position: Vec3, strength: f32 },
+    LinearInterpolation { target: ParticleStream, start: Vec3, end: Vec3 },
+    /// A type whose layout isn't known yet, with everything after the target kept as is
+    Unknown { discriminant: u32, target: ParticleStream, payload: Vec<u8> },
+}
+
+impl ParticleUpdater {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader_until(reader, None)
+    }
+    
+    /// Like `from_reader`, but the payload of unknown types ends at `end`.
+    pub fn from_reader_until<R: Read + Seek>(reader: &mut R, end: Option<u64>) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        let target = ParticleStream::read(reader)?;
+        
+        let updater = match discriminant {
+            0x00200001 => Self::ConstantRate { target, rate: Vec3::read(reader)? },
+            0x00200002 => Self::Gravity {
+                target,
+                direction: Vec3::read(reader)?,
+                strength: reader.read_f32::<LittleEndian>()?,
+            },
+            0x00200004 => Self::Spin {
+                target,
+                axis: Vec3::read(reader)?,
+                speed: reader.read_f32::<LittleEndian>()?,
+            },
+            0x00200008 => Self::Attraction {
+                target,
+                position: Vec3::read(reader)?,
+                strength: reader.read_f32::<LittleEndian>()?,
+            },
+            0x00200010 => Self::LinearInterpolation {
+                target,
+                start: Vec3::read(reader)?,
+                end: Vec3::read(reader)?,
+            },
+            _ => Self::Unknown { discriminant, target, payload: read_unknown_payload(reader, end)? },
+        };
+        
+        Ok(updater)
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
+        let (discriminant, target) = match self {
+            Self::ConstantRate { target, .. } => (0x00200001, target),
+            Self::Gravity { target, .. } => (0x00200002, target),
+            Self::Spin { target, .. } => (0x00200004, target),
+            Self::Attraction { target, .. } => (0x00200008, target),
+            Self::LinearInterpolation { target, .. } => (0x00200010, target),
+            Self::Unknown { discriminant, target, .. } => (*discriminant, target),
+        };
+        
+        writer.write_u32::<LittleEndian>(discriminant)?;
+        target.write(writer)?;
+        
+        match self {
+            Self::ConstantRate { rate, .. } => rate.write(writer)?,
+            Self::Gravity { direction: vector, strength: scalar, .. }
+            | Self::Spin { axis: vector, speed: scalar, .. }
+            | Self::Attraction { position: vector, strength: scalar, .. } => {
+                vector.write(writer)?;
+                writer.write_f32::<LittleEndian>(*scalar)?;
+            },
+            Self::LinearInterpolation { start, end, .. } => {
+                start.write(writer)?;
+                end.write(writer)?;
+            },
+            Self::Unknown { payload, .. } => writer.write_all(payload)?,
+        }
+        
+        Ok(())
+    }
+}
+
+impl CgfxCollectionValue for ParticleUpdater {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, _ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer)
+    }
+}
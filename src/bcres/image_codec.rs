@@ -1,13 +1,15 @@
 use std::{cmp::max, io::Cursor, slice::from_raw_parts};
 
-use anyhow::{anyhow, Result};
 use binrw::{BinRead, BinWrite};
 use byteorder::{LittleEndian, ReadBytesExt};
-use png::{BitDepth, ColorType, Encoder, ScaledFloat, SourceChromaticities};
+use png::{BitDepth, ColorType, Decoder, Encoder, ScaledFloat, SourceChromaticities, Transformations};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::{invalid_input, malformed, unsupported}, Result};
 
 use super::texture::PicaTextureFormat;
 
-#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, BinRead, BinWrite)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little)]
 #[repr(C)]
 pub struct RgbaColor {
@@ -81,7 +83,7 @@ pub fn to_png(image_buffer: &[RgbaColor], width: u32, height: u32) -> Result<Vec
             (0.15000, 0.06000),
         );
         encoder.set_source_chromaticities(source_chromaticities);
-        let mut writer = encoder.write_header().unwrap();
+        let mut writer = encoder.write_header()?;
         
         // write png
         writer.write_image_data(bytes)?;
@@ -90,6 +92,35 @@ pub fn to_png(image_buffer: &[RgbaColor], width: u32, height: u32) -> Result<Vec
     Ok(out)
 }
 
+/// Decodes a PNG file of any color type into its colors, width and height.
+pub fn from_png(png: &[u8]) -> Result<(Vec<RgbaColor>, u32, u32)> {
+    let mut decoder = Decoder::new(png);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let bytes = &buffer[..info.buffer_size()];
+    
+    let colors: Vec<RgbaColor> = match info.color_type {
+        ColorType::Rgba => bytes.chunks_exact(4)
+            .map(|pixel| RgbaColor { r: pixel[0], g: pixel[1], b: pixel[2], a: pixel[3] })
+            .collect(),
+        ColorType::Rgb => bytes.chunks_exact(3)
+            .map(|pixel| RgbaColor { r: pixel[0], g: pixel[1], b: pixel[2], a: 0xFF })
+            .collect(),
+        ColorType::GrayscaleAlpha => bytes.chunks_exact(2)
+            .map(|pixel| RgbaColor::grayscale_alpha(pixel[0], pixel[1]))
+            .collect(),
+        ColorType::Grayscale => bytes.iter()
+            .map(|pixel| RgbaColor::grayscale(*pixel))
+            .collect(),
+        ColorType::Indexed => return Err(invalid_input!("Indexed PNG files should have been expanded")),
+    };
+    
+    Ok((colors, info.width, info.height))
+}
+
 pub const ENCODABLE_FORMATS: [PicaTextureFormat; 0] = [
     // PicaTextureFormat::RGBA5551,
 ];
@@ -109,6 +140,17 @@ const SWIZZLE_LUT: [u32; 64] = [
 ];
 
 pub fn decode_swizzled_buffer(image_buffer: &[u8], input_format: PicaTextureFormat, width: u32, height: u32) -> Result<Vec<RgbaColor>> {
+    if !width.is_multiple_of(8) || !height.is_multiple_of(8) {
+        return Err(malformed!("Texture size has to be a multiple of 8, got {}x{}", width, height));
+    }
+    
+    let expected_length = u64::from(width) * u64::from(height) * u64::from(input_format.get_bpp()) / 8;
+    
+    if (image_buffer.len() as u64) < expected_length {
+        return Err(malformed!("Expected {:#x} bytes of image data for a {}x{} {:?} texture, got {:#x}",
+            expected_length, width, height, input_format, image_buffer.len()));
+    }
+    
     if input_format == PicaTextureFormat::ETC1A4 || input_format == PicaTextureFormat::ETC1 {
         return decode_etc1(image_buffer, width, height, input_format == PicaTextureFormat::ETC1A4);
     }
@@ -227,7 +269,7 @@ pub fn decode_swizzled_buffer(image_buffer: &[u8], input_format: PicaTextureForm
                         }
                     },
                     _ => {
-                        return Err(anyhow!("Format {:?} not implemented yet", input_format));
+                        return Err(unsupported!("Format {:?} not implemented yet", input_format));
                     }
                 }
                 
@@ -240,6 +282,96 @@ pub fn decode_swizzled_buffer(image_buffer: &[u8], input_format: PicaTextureForm
     Ok(output)
 }
 
+/// Luminance of a color for the grayscale formats
+fn luminance(color: RgbaColor) -> u8 {
+    ((u32::from(color.r) * 77 + u32::from(color.g) * 150 + u32::from(color.b) * 29) >> 8) as u8
+}
+
+/// Counterpart of `decode_swizzled_buffer`, ETC1 compression is not supported yet.
+/// `width` and `height` have to be multiples of 8.
+pub fn encode_swizzled_buffer(colors: &[RgbaColor], output_format: PicaTextureFormat, width: u32, height: u32) -> Result<Vec<u8>> {
+    if !width.is_multiple_of(8) || !height.is_multiple_of(8) {
+        return Err(invalid_input!("Texture size has to be a multiple of 8, got {}x{}", width, height));
+    }
+    
+    if colors.len() != (width * height) as usize {
+        return Err(invalid_input!("Expected {} colors for a {}x{} texture, got {}", width * height, width, height, colors.len()));
+    }
+    
+    let bytes_per_pixel = max(output_format.get_bpp() / 8, 1);
+    let mut output_offset: usize = 0;
+    let mut output: Vec<u8> = vec![0; (width * height * output_format.get_bpp() / 8).try_into()?];
+    
+    // iterate over every 8x8px chunk
+    for y in (0..height).step_by(8) {
+        for x in (0..width).step_by(8) {
+            
+            // iterate over every pixel in the current chunk
+            for p in SWIZZLE_LUT {
+                let local_x = p & 7;
+                let local_y = (p - local_x) >> 3;
+                
+                let input_offset: usize = (x + local_x + (y + local_y) * width).try_into()?;
+                let color = colors[input_offset];
+                
+                match output_format {
+                    PicaTextureFormat::RGBA8 => {
+                        output[output_offset..output_offset + 4].copy_from_slice(&[color.a, color.b, color.g, color.r]);
+                    },
+                    PicaTextureFormat::RGBA4 => {
+                        let raw = (u16::from(color.r >> 4) << 12) | (u16::from(color.g >> 4) << 8)
+                            | (u16::from(color.b >> 4) << 4) | u16::from(color.a >> 4);
+                        
+                        output[output_offset..output_offset + 2].copy_from_slice(&raw.to_le_bytes());
+                    },
+                    PicaTextureFormat::RGB565 => {
+                        let raw = (u16::from(color.r >> 3) << 11) | (u16::from(color.g >> 2) << 5) | u16::from(color.b >> 3);
+                        
+                        output[output_offset..output_offset + 2].copy_from_slice(&raw.to_le_bytes());
+                    },
+                    PicaTextureFormat::RGBA5551 => {
+                        let raw = (u16::from(color.r >> 3) << 11) | (u16::from(color.g >> 3) << 6)
+                            | (u16::from(color.b >> 3) << 1) | u16::from(color.a >> 7);
+                        
+                        output[output_offset..output_offset + 2].copy_from_slice(&raw.to_le_bytes());
+                    },
+                    PicaTextureFormat::L8 => {
+                        output[output_offset] = luminance(color);
+                    },
+                    PicaTextureFormat::L4 => {
+                        let value = luminance(color) >> 4;
+                        
+                        output[output_offset / 2] |= if output_offset.is_multiple_of(2) { value } else { value << 4 };
+                    },
+                    PicaTextureFormat::A8 => {
+                        output[output_offset] = color.a;
+                    },
+                    PicaTextureFormat::A4 => {
+                        let value = color.a >> 4;
+                        
+                        output[output_offset / 2] |= if output_offset.is_multiple_of(2) { value } else { value << 4 };
+                    },
+                    PicaTextureFormat::LA8 => {
+                        output[output_offset] = color.a;
+                        output[output_offset + 1] = luminance(color);
+                    },
+                    PicaTextureFormat::LA4 => {
+                        output[output_offset] = (luminance(color) & 0xF0) | (color.a >> 4);
+                    },
+                    _ => {
+                        return Err(unsupported!("Encoding format {:?} is not implemented yet", output_format));
+                    }
+                }
+                
+                output_offset += bytes_per_pixel as usize;
+            }
+        
+        }
+    }
+    
+    Ok(output)
+}
+
 const ETC1_X: [u32; 4] = [ 0, 4, 0, 4 ];
 const ETC1_Y: [u32; 4] = [ 0, 0, 4, 4 ];
 
@@ -0,0 +1,840 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use binrw::{BinRead, BinWrite, Endian};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use na::Matrix3x4;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{invalid_input, malformed, unsupported},
+    scoped_reader_pos,
+    util::{
+        math::{SerializableMatrix, Vec4},
+        pointer::Pointer,
+    },
+    Result,
+};
+
+use super::{
+    bcres::{CgfxCollectionValue, WriteContext},
+    image_codec::RgbaColor,
+    util::{brw_read_string, write_relative_pointer_here, write_string_pointer, CgfxObjectHeader},
+};
+
+/// How many texture mappers (and texture coordinators) a material has, one per texture unit.
+pub const TEXTURE_MAPPER_COUNT: usize = 3;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    pub cgfx_object_header: CgfxObjectHeader,
+    
+    // material stuff
+    pub flags: u32,
+    pub tex_coord_config: u32,
+    pub render_layer: u32,
+    pub colors: MaterialColors,
+    pub rasterization: Rasterization,
+    pub fragment_operation: FragmentOperation,
+    
+    pub used_texture_coordinators: u32,
+    pub texture_coordinators: [TextureCoordinator; TEXTURE_MAPPER_COUNT],
+    pub texture_mappers: [Option<TextureMapper>; TEXTURE_MAPPER_COUNT],
+    
+    pub shader: Option<ShaderReference>,
+    pub fragment_shader: Option<FragmentShader>,
+    pub shader_program_description_index: i32,
+    pub light_set_index: i32,
+    pub fog_index: i32,
+    
+    /// Hashes of the single parts of the material, which the game uses to
+    /// skip sending render state that didn't change between two materials.
+    pub hashes: [u32; 13],
+    pub unique_id: u32,
+}
+
+impl Material {
+    /// A white, untextured material using the default shader.
+    ///
+    /// The PICA command caches of the render state are left empty, so materials copied from an
+    /// existing model are closer to what the game expects if there is one.
+    pub fn new(name: &str) -> Self {
+        Self {
+            cgfx_object_header: CgfxObjectHeader {
+                magic: "MTOB".to_string(),
+                revision: 0x6000000,
+                name: Some(name.to_string()),
+                metadata: None,
+            },
+            flags: 0,
+            tex_coord_config: 0,
+            render_layer: 0,
+            colors: MaterialColors::default(),
+            rasterization: Rasterization {
+                flags: 0,
+                face_culling: FaceCulling::BackFace,
+                polygon_offset_unit: 0.0,
+                commands: [0; 3],
+            },
+            fragment_operation: FragmentOperation {
+                // depth test and depth writes enabled
+                depth_flags: 3,
+                depth_commands: [0; 4],
+                blend_mode: BlendMode::None,
+                blend_color: Vec4::new(0.0, 0.0, 0.0, 0.0),
+                blend_commands: [0; 6],
+                stencil_commands: [0; 4],
+            },
+            used_texture_coordinators: 0,
+            texture_coordinators: [0, 1, 2].map(TextureCoordinator::new),
+            texture_mappers: [None, None, None],
+            shader: Some(ShaderReference::new("DefaultShader")),
+            fragment_shader: Some(FragmentShader::default()),
+            shader_program_description_index: 0,
+            light_set_index: 0,
+            fog_index: 0,
+            hashes: [0; 13],
+            unique_id: 0,
+        }
+    }
+    
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let magic = reader.read_u32::<LittleEndian>()?;
+        
+        if magic != 0x8000000 {
+            return Err(malformed!("Invalid material magic number {:x}", magic));
+        }
+        
+        let cgfx_object_header = CgfxObjectHeader::read(reader)?;
+        let flags = reader.read_u32::<LittleEndian>()?;
+        let tex_coord_config = reader.read_u32::<LittleEndian>()?;
+        let render_layer = reader.read_u32::<LittleEndian>()?;
+        let colors = MaterialColors::read(reader)?;
+        let rasterization = Rasterization::read(reader)?;
+        let fragment_operation = FragmentOperation::read(reader)?;
+        
+        let used_texture_coordinators = reader.read_u32::<LittleEndian>()?;
+        let texture_coordinators = <[TextureCoordinator; TEXTURE_MAPPER_COUNT]>::read(reader)?;
+        
+        let mut texture_mappers: [Option<TextureMapper>; TEXTURE_MAPPER_COUNT] = [None, None, None];
+        
+        for texture_mapper in &mut texture_mappers {
+            *texture_mapper = read_pointed(reader, TextureMapper::from_reader)?;
+        }
+        
+        if Pointer::read_relative(reader)?.is_some() {
+            return Err(unsupported!("Material {:?} has a procedural texture mapper", cgfx_object_header.name));
+        }
+        
+        let shader = read_pointed(reader, ShaderReference::from_reader)?;
+        let fragment_shader = read_pointed(reader, FragmentShader::from_reader)?;
+        let shader_program_description_index = reader.read_i32::<LittleEndian>()?;
+        
+        let shader_parameter_count = reader.read_u32::<LittleEndian>()?;
+        if Pointer::read_relative(reader)?.is_some() && shader_parameter_count != 0 {
+            return Err(unsupported!("Material {:?} has {} shader parameters", cgfx_object_header.name, shader_parameter_count));
+        }
+        
+        let light_set_index = reader.read_i32::<LittleEndian>()?;
+        let fog_index = reader.read_i32::<LittleEndian>()?;
+        let hashes = <[u32; 13]>::read_le(reader)?;
+        let unique_id = reader.read_u32::<LittleEndian>()?;
+        
+        Ok(Self {
+            cgfx_object_header,
+            flags,
+            tex_coord_config,
+            render_layer,
+            colors,
+            rasterization,
+            fragment_operation,
+            used_texture_coordinators,
+            texture_coordinators,
+            texture_mappers,
+            shader,
+            fragment_shader,
+            shader_program_description_index,
+            light_set_index,
+            fog_index,
+            hashes,
+            unique_id,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        writer.write_u32::<LittleEndian>(0x8000000)?;
+        
+        let header_offset = Pointer::try_from(&writer)?;
+        
+        if let Some(name) = &self.cgfx_object_header.name {
+            ctx.add_string(name)?;
+            ctx.add_string_reference(header_offset + 8, name.clone());
+        }
+        
+        self.cgfx_object_header.write(writer)?;
+        writer.write_u32::<LittleEndian>(self.flags)?;
+        writer.write_u32::<LittleEndian>(self.tex_coord_config)?;
+        writer.write_u32::<LittleEndian>(self.render_layer)?;
+        self.colors.write(writer)?;
+        self.rasterization.write(writer)?;
+        self.fragment_operation.write(writer)?;
+        
+        writer.write_u32::<LittleEndian>(self.used_texture_coordinators)?;
+        self.texture_coordinators.write_le(writer)?;
+        
+        // texture mappers, procedural texture mapper, shader and fragment shader are written after the material
+        let texture_mapper_pointer_locations: Vec<Pointer> = (0..TEXTURE_MAPPER_COUNT + 1)
+            .map(|_| write_pointer_placeholder(writer))
+            .collect::<Result<_>>()?;
+        
+        let shader_pointer_location = write_pointer_placeholder(writer)?;
+        let fragment_shader_pointer_location = write_pointer_placeholder(writer)?;
+        writer.write_i32::<LittleEndian>(self.shader_program_description_index)?;
+        
+        // no shader parameters
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_u32::<LittleEndian>(0)?;
+        
+        writer.write_i32::<LittleEndian>(self.light_set_index)?;
+        writer.write_i32::<LittleEndian>(self.fog_index)?;
+        self.hashes.write_le(writer)?;
+        writer.write_u32::<LittleEndian>(self.unique_id)?;
+        
+        for (texture_mapper, pointer_location) in self.texture_mappers.iter().zip(texture_mapper_pointer_locations) {
+            if let Some(texture_mapper) = texture_mapper {
+                write_relative_pointer_here(writer, pointer_location)?;
+                texture_mapper.to_writer(writer, ctx)?;
+            }
+        }
+        
+        if let Some(shader) = &self.shader {
+            write_relative_pointer_here(writer, shader_pointer_location)?;
+            shader.to_writer(writer, ctx)?;
+        }
+        
+        if let Some(fragment_shader) = &self.fragment_shader {
+            write_relative_pointer_here(writer, fragment_shader_pointer_location)?;
+            fragment_shader.to_writer(writer, ctx)?;
+        }
+        
+        self.cgfx_object_header.write_metadata(writer, ctx, header_offset)?;
+        
+        Ok(())
+    }
+    
+    /// Names of the textures used by the texture mappers, in the order of the texture units.
+    pub fn texture_names(&self) -> impl Iterator<Item = Option<&str>> {
+        self.texture_mappers.iter()
+            .map(|texture_mapper| texture_mapper.as_ref()
+                .and_then(|texture_mapper| texture_mapper.texture.as_ref())
+                .and_then(|texture| texture.path.as_deref()))
+    }
+    
+    /// The texture of the first texture unit, which holds the base color in most materials.
+    pub fn base_texture_name(&self) -> Option<&str> {
+        self.texture_names().next().flatten()
+    }
+    
+    /// Binds the texture `texture_name` to texture unit `unit`, creating a texture mapper
+    /// for it if there isn't one yet. Pass `None` to remove the texture mapper.
+    pub fn set_texture(&mut self, unit: usize, texture_name: Option<&str>) -> Result<()> {
+        let texture_mapper = self.texture_mappers.get_mut(unit)
+            .ok_or_else(|| invalid_input!("Materials only have {} texture units, got unit {}", TEXTURE_MAPPER_COUNT, unit))?;
+        
+        match (texture_mapper, texture_name) {
+            (Some(texture_mapper), Some(texture_name)) => texture_mapper.texture = Some(TextureReference::new(texture_name)),
+            (texture_mapper, Some(texture_name)) => *texture_mapper = Some(TextureMapper::new(texture_name)),
+            (texture_mapper, None) => *texture_mapper = None,
+        }
+        
+        self.used_texture_coordinators = self.texture_mappers.iter()
+            .rposition(Option::is_some)
+            .map_or(0, |last_unit| last_unit as u32 + 1);
+        
+        Ok(())
+    }
+}
+
+impl CgfxCollectionValue for Material {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
+
+/// Reads the relative pointer at the current position and the object it points to, if it isn't null.
+fn read_pointed<R: Read + Seek, T>(reader: &mut R, read_value: impl FnOnce(&mut R) -> Result<T>) -> Result<Option<T>> {
+    let Some(pointer) = Pointer::read_relative(reader)? else {
+        return Ok(None);
+    };
+    
+    scoped_reader_pos!(reader);
+    reader.seek(SeekFrom::Start(pointer.into()))?;
+    
+    Ok(Some(read_value(reader)?))
+}
+
+fn write_pointer_placeholder(writer: &mut Cursor<&mut Vec<u8>>) -> Result<Pointer> {
+    let location = Pointer::try_from(&writer)?;
+    writer.write_u32::<LittleEndian>(0)?;
+    
+    Ok(location)
+}
+
+/// Links to other objects, like the texture of a texture reference, are resolved by the
+/// game when loading the file, so they are expected to be null in the file.
+fn read_runtime_link(reader: &mut impl Read, kind: &str, path: &Option<String>) -> Result<()> {
+    let link = reader.read_u32::<LittleEndian>()?;
+    
+    if link != 0 {
+        return Err(unsupported!("{} {:?} is already linked to {:#x}", kind, path, link));
+    }
+    
+    Ok(())
+}
+
+/// Writes a reference object like `TextureReference`, which is an object header
+/// followed by the path of the object it references and a null link to it.
+fn write_reference(writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext, discriminant: u32, header: &CgfxObjectHeader, path: Option<&str>) -> Result<()> {
+    writer.write_u32::<LittleEndian>(discriminant)?;
+    
+    let header_offset = Pointer::try_from(&writer)?;
+    
+    if let Some(name) = &header.name {
+        ctx.add_string(name)?;
+        ctx.add_string_reference(header_offset + 8, name.clone());
+    }
+    
+    header.write(writer)?;
+    write_string_pointer(writer, ctx, path)?;
+    writer.write_u32::<LittleEndian>(0)?;
+    
+    header.write_metadata(writer, ctx, header_offset)?;
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little)]
+pub struct MaterialColors {
+    pub emission_float: Vec4,
+    pub ambient_float: Vec4,
+    pub diffuse_float: Vec4,
+    pub specular0_float: Vec4,
+    pub specular1_float: Vec4,
+    pub constant0_float: Vec4,
+    pub constant1_float: Vec4,
+    pub constant2_float: Vec4,
+    pub constant3_float: Vec4,
+    pub constant4_float: Vec4,
+    pub constant5_float: Vec4,
+    
+    pub emission: RgbaColor,
+    pub ambient: RgbaColor,
+    pub diffuse: RgbaColor,
+    pub specular0: RgbaColor,
+    pub specular1: RgbaColor,
+    pub constant0: RgbaColor,
+    pub constant1: RgbaColor,
+    pub constant2: RgbaColor,
+    pub constant3: RgbaColor,
+    pub constant4: RgbaColor,
+    pub constant5: RgbaColor,
+    
+    pub command_cache: u32,
+}
+
+impl Default for MaterialColors {
+    /// No emission and specular light, white everywhere else.
+    fn default() -> Self {
+        let black = Vec4::new(0.0, 0.0, 0.0, 1.0);
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        let black_bytes = RgbaColor { r: 0, g: 0, b: 0, a: 255 };
+        let white_bytes = RgbaColor { r: 255, g: 255, b: 255, a: 255 };
+        
+        Self {
+            emission_float: black,
+            ambient_float: white,
+            diffuse_float: white,
+            specular0_float: black,
+            specular1_float: black,
+            constant0_float: white,
+            constant1_float: white,
+            constant2_float: white,
+            constant3_float: white,
+            constant4_float: white,
+            constant5_float: white,
+            emission: black_bytes,
+            ambient: white_bytes,
+            diffuse: white_bytes,
+            specular0: black_bytes,
+            specular1: black_bytes,
+            constant0: white_bytes,
+            constant1: white_bytes,
+            constant2: white_bytes,
+            constant3: white_bytes,
+            constant4: white_bytes,
+            constant5: white_bytes,
+            command_cache: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u32)]
+pub enum FaceCulling {
+    FrontFace,
+    BackFace,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Debug, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little)]
+pub struct Rasterization {
+    /// Bit 0 enables the polygon offset
+    pub flags: u32,
+    pub face_culling: FaceCulling,
+    pub polygon_offset_unit: f32,
+    pub commands: [u32; 3],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u32)]
+pub enum BlendMode {
+    None,
+    Blend,
+    BlendSeparate,
+    LogicOp,
+}
+
+/// Depth test, blending and stencil test. The commands are the PICA commands
+/// setting them up, the flags and modes describe them for editors.
+#[derive(Clone, Debug, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little)]
+pub struct FragmentOperation {
+    /// Bit 0 enables the depth test, bit 1 depth writes
+    pub depth_flags: u32,
+    pub depth_commands: [u32; 4],
+    
+    pub blend_mode: BlendMode,
+    pub blend_color: Vec4,
+    pub blend_commands: [u32; 6],
+    
+    pub stencil_commands: [u32; 4],
+}
+
+/// Where the texture coordinates of a texture unit come from and how they are transformed.
+#[derive(Clone, Debug, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little)]
+pub struct TextureCoordinator {
+    pub source_coordinate_index: i32,
+    /// 0 maps by UV coordinates, 1 to 3 are camera, cube and sphere environment maps
+    pub mapping_type: u32,
+    pub reference_camera_index: i32,
+    pub transform_type: u32,
+    
+    pub scale: [f32; 2],
+    pub rotation: f32,
+    pub translation: [f32; 2],
+    
+    pub flags: u32,
+    #[brw(repr = SerializableMatrix<3, 4>)]
+    pub transform: Matrix3x4<f32>,
+}
+
+impl TextureCoordinator {
+    /// Untransformed UV set `source_coordinate_index`.
+    pub fn new(source_coordinate_index: i32) -> Self {
+        Self {
+            source_coordinate_index,
+            mapping_type: 0,
+            reference_camera_index: 0,
+            transform_type: 0,
+            scale: [1.0, 1.0],
+            rotation: 0.0,
+            translation: [0.0, 0.0],
+            flags: 0,
+            transform: Matrix3x4::identity(),
+        }
+    }
+}
+
+/// Binds a texture and its sampler settings to a texture unit.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextureMapper {
+    pub dynamic_allocator: u32,
+    pub texture: Option<TextureReference>,
+    pub sampler: Option<TextureSampler>,
+    
+    pub commands: [u32; 14],
+    pub commands_length: u32,
+}
+
+impl TextureMapper {
+    /// Maps `texture_name` with linear filtering. The PICA commands are left empty like in `Material::new`.
+    pub fn new(texture_name: &str) -> Self {
+        Self {
+            dynamic_allocator: 0,
+            texture: Some(TextureReference::new(texture_name)),
+            sampler: Some(TextureSampler {
+                min_filter: 1,
+                border_color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+                lod_bias: 0.0,
+            }),
+            commands: [0; 14],
+            commands_length: 14 * 4,
+        }
+    }
+    
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        
+        if discriminant != 0x80000000 {
+            return Err(unsupported!("Texture mapper of type {:#x}", discriminant));
+        }
+        
+        let dynamic_allocator = reader.read_u32::<LittleEndian>()?;
+        let texture = read_pointed(reader, TextureReference::from_reader)?;
+        let sampler = read_pointed(reader, TextureSampler::from_reader)?;
+        let commands = <[u32; 14]>::read_le(reader)?;
+        let commands_length = reader.read_u32::<LittleEndian>()?;
+        
+        Ok(Self {
+            dynamic_allocator,
+            texture,
+            sampler,
+            commands,
+            commands_length,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        let mapper_offset = Pointer::try_from(&writer)?;
+        
+        writer.write_u32::<LittleEndian>(0x80000000)?;
+        writer.write_u32::<LittleEndian>(self.dynamic_allocator)?;
+        
+        let texture_pointer_location = write_pointer_placeholder(writer)?;
+        let sampler_pointer_location = write_pointer_placeholder(writer)?;
+        self.commands.write_le(writer)?;
+        writer.write_u32::<LittleEndian>(self.commands_length)?;
+        
+        if let Some(texture) = &self.texture {
+            write_relative_pointer_here(writer, texture_pointer_location)?;
+            texture.to_writer(writer, ctx)?;
+        }
+        
+        if let Some(sampler) = &self.sampler {
+            write_relative_pointer_here(writer, sampler_pointer_location)?;
+            sampler.to_writer(writer, mapper_offset)?;
+        }
+        
+        Ok(())
+    }
+}
+
+/// Reference to a texture of the texture dict by its name.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextureReference {
+    pub cgfx_object_header: CgfxObjectHeader,
+    pub path: Option<String>,
+}
+
+impl TextureReference {
+    pub fn new(path: &str) -> Self {
+        Self {
+            cgfx_object_header: CgfxObjectHeader {
+                magic: "TXOB".to_string(),
+                revision: 0x5000000,
+                name: None,
+                metadata: None,
+            },
+            path: Some(path.to_string()),
+        }
+    }
+    
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        
+        if discriminant != 0x20000004 {
+            return Err(malformed!("Expected texture reference discriminant 0x20000004, found {:#x}", discriminant));
+        }
+        
+        let cgfx_object_header = CgfxObjectHeader::read(reader)?;
+        let path = brw_read_string(reader, Endian::Little, ())?;
+        read_runtime_link(reader, "Texture reference", &path)?;
+        
+        Ok(Self {
+            cgfx_object_header,
+            path,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        write_reference(writer, ctx, 0x20000004, &self.cgfx_object_header, self.path.as_deref())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextureSampler {
+    pub min_filter: u32,
+    pub border_color: Vec4,
+    pub lod_bias: f32,
+}
+
+impl TextureSampler {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        
+        if discriminant != 0x80000000 {
+            return Err(unsupported!("Texture sampler of type {:#x}", discriminant));
+        }
+        
+        // pointer back to the texture mapper owning the sampler
+        reader.read_i32::<LittleEndian>()?;
+        
+        Ok(Self {
+            min_filter: reader.read_u32::<LittleEndian>()?,
+            border_color: Vec4::read(reader)?,
+            lod_bias: reader.read_f32::<LittleEndian>()?,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, mapper_offset: Pointer) -> Result<()> {
+        writer.write_u32::<LittleEndian>(0x80000000)?;
+        
+        let owner_location = Pointer::try_from(&writer)?;
+        writer.write_i32::<LittleEndian>(i32::try_from(i64::from(mapper_offset) - i64::from(owner_location))?)?;
+        
+        writer.write_u32::<LittleEndian>(self.min_filter)?;
+        self.border_color.write(writer)?;
+        writer.write_f32::<LittleEndian>(self.lod_bias)?;
+        
+        Ok(())
+    }
+}
+
+/// Reference to a shader of the shader dict by its name, usually "DefaultShader".
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShaderReference {
+    pub cgfx_object_header: CgfxObjectHeader,
+    pub path: Option<String>,
+}
+
+impl ShaderReference {
+    pub fn new(path: &str) -> Self {
+        Self {
+            cgfx_object_header: CgfxObjectHeader {
+                magic: "SHDR".to_string(),
+                revision: 0x5000000,
+                name: None,
+                metadata: None,
+            },
+            path: Some(path.to_string()),
+        }
+    }
+    
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        
+        if discriminant != 0x80000001 {
+            return Err(malformed!("Expected shader reference discriminant 0x80000001, found {:#x}", discriminant));
+        }
+        
+        let cgfx_object_header = CgfxObjectHeader::read(reader)?;
+        let path = brw_read_string(reader, Endian::Little, ())?;
+        read_runtime_link(reader, "Shader reference", &path)?;
+        
+        Ok(Self {
+            cgfx_object_header,
+            path,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        write_reference(writer, ctx, 0x80000001, &self.cgfx_object_header, self.path.as_deref())
+    }
+}
+
+/// Fragment lighting, texture combiners and alpha test of a material.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FragmentShader {
+    pub buffer_color: Vec4,
+    pub lighting: FragmentLighting,
+    pub lighting_luts: Option<FragmentLightingLuts>,
+    pub texture_combiners: [TextureCombiner; 6],
+    pub alpha_test: AlphaTest,
+    pub commands: [u32; 6],
+}
+
+impl FragmentShader {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let buffer_color = Vec4::read(reader)?;
+        let lighting = FragmentLighting::read(reader)?;
+        let lighting_luts = read_pointed(reader, FragmentLightingLuts::from_reader)?;
+        let texture_combiners = <[TextureCombiner; 6]>::read(reader)?;
+        let alpha_test = AlphaTest::read(reader)?;
+        let commands = <[u32; 6]>::read_le(reader)?;
+        
+        Ok(Self {
+            buffer_color,
+            lighting,
+            lighting_luts,
+            texture_combiners,
+            alpha_test,
+            commands,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.buffer_color.write(writer)?;
+        self.lighting.write(writer)?;
+        
+        let lut_pointer_location = write_pointer_placeholder(writer)?;
+        self.texture_combiners.write(writer)?;
+        self.alpha_test.write(writer)?;
+        self.commands.write_le(writer)?;
+        
+        if let Some(lighting_luts) = &self.lighting_luts {
+            write_relative_pointer_here(writer, lut_pointer_location)?;
+            lighting_luts.to_writer(writer, ctx)?;
+        }
+        
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little)]
+pub struct FragmentLighting {
+    pub flags: u32,
+    pub layer_config: u32,
+    pub fresnel_selector: u32,
+    pub bump_texture: u32,
+    pub bump_mode: u32,
+    pub bump_renormalize: u32,
+}
+
+/// One stage of the texture combiner ("TexEnv") pipeline.
+#[derive(Clone, Debug, Default, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little)]
+pub struct TextureCombiner {
+    /// Which of the constant colors of the material the stage uses
+    pub constant: u32,
+    pub commands: [u32; 6],
+}
+
+#[derive(Clone, Debug, Default, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little)]
+pub struct AlphaTest {
+    pub flags: u32,
+    pub commands: [u32; 2],
+}
+
+/// Lookup tables for the fragment lighting, stored in the luts dict of the container.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FragmentLightingLuts {
+    pub reflectance_r: Option<LightingLut>,
+    pub reflectance_g: Option<LightingLut>,
+    pub reflectance_b: Option<LightingLut>,
+    pub distribution0: Option<LightingLut>,
+    pub distribution1: Option<LightingLut>,
+    pub fresnel: Option<LightingLut>,
+}
+
+impl FragmentLightingLuts {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            reflectance_r: read_pointed(reader, LightingLut::from_reader)?,
+            reflectance_g: read_pointed(reader, LightingLut::from_reader)?,
+            reflectance_b: read_pointed(reader, LightingLut::from_reader)?,
+            distribution0: read_pointed(reader, LightingLut::from_reader)?,
+            distribution1: read_pointed(reader, LightingLut::from_reader)?,
+            fresnel: read_pointed(reader, LightingLut::from_reader)?,
+        })
+    }
+    
+    fn luts(&self) -> [&Option<LightingLut>; 6] {
+        [&self.reflectance_r, &self.reflectance_g, &self.reflectance_b, &self.distribution0, &self.distribution1, &self.fresnel]
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        let pointer_locations: Vec<Pointer> = (0..6)
+            .map(|_| write_pointer_placeholder(writer))
+            .collect::<Result<_>>()?;
+        
+        for (lut, pointer_location) in self.luts().into_iter().zip(pointer_locations) {
+            if let Some(lut) = lut {
+                write_relative_pointer_here(writer, pointer_location)?;
+                lut.to_writer(writer, ctx)?;
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightingLut {
+    pub input: u32,
+    pub scale: u32,
+    pub sampler: Option<LutReference>,
+}
+
+impl LightingLut {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Ok(Self {
+            input: reader.read_u32::<LittleEndian>()?,
+            scale: reader.read_u32::<LittleEndian>()?,
+            sampler: read_pointed(reader, LutReference::from_reader)?,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        writer.write_u32::<LittleEndian>(self.input)?;
+        writer.write_u32::<LittleEndian>(self.scale)?;
+        
+        let sampler_pointer_location = write_pointer_placeholder(writer)?;
+        
+        if let Some(sampler) = &self.sampler {
+            write_relative_pointer_here(writer, sampler_pointer_location)?;
+            sampler.to_writer(writer, ctx)?;
+        }
+        
+        Ok(())
+    }
+}
+
+/// Reference to a lookup table, by the name of its table set in the luts dict and its own name.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LutReference {
+    pub discriminant: u32,
+    pub table_set_name: Option<String>,
+    pub table_name: Option<String>,
+}
+
+impl LutReference {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        let table_set_name = brw_read_string(reader, Endian::Little, ())?;
+        let table_name = brw_read_string(reader, Endian::Little, ())?;
+        read_runtime_link(reader, "Lookup table reference", &table_name)?;
+        
+        Ok(Self {
+            discriminant,
+            table_set_name,
+            table_name,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        writer.write_u32::<LittleEndian>(self.discriminant)?;
+        write_string_pointer(writer, ctx, self.table_set_name.as_deref())?;
+        write_string_pointer(writer, ctx, self.table_name.as_deref())?;
+        writer.write_u32::<LittleEndian>(0)?;
+        
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use binrw::{BinRead, BinWrite, Endian};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::malformed,
+    scoped_reader_pos,
+    util::{math::Vec4, pointer::Pointer},
+    Result,
+};
+
+use super::{
+    bcres::{CgfxCollectionValue, WriteContext},
+    util::{
+        brw_read_string, check_count, write_list_placeholder, write_pointer_list, write_relative_pointer_here,
+        write_string_pointer,
+    },
+};
+
+/// A single named entry of the user data ("metadata") dict every CGFX object can carry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CgfxMetadata {
+    pub name: Option<String>,
+    pub values: MetadataValues,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MetadataValues {
+    Float(Vec<f32>),
+    Integer(Vec<i32>),
+    String(MetadataStringFormat, Vec<String>),
+    Color(Vec<Vec4>),
+}
+
+impl MetadataValues {
+    fn discriminant(&self) -> u32 {
+        match self {
+            MetadataValues::Float(_) => 0x80000000,
+            MetadataValues::Integer(_) => 0x20000000,
+            MetadataValues::String(_, _) => 0x10000000,
+            MetadataValues::Color(_) => 0x08000000,
+        }
+    }
+    
+    fn value_type(&self) -> u32 {
+        match self {
+            MetadataValues::Float(_) => 0,
+            MetadataValues::Integer(_) => 1,
+            MetadataValues::String(_, _) => 2,
+            MetadataValues::Color(_) => 4,
+        }
+    }
+    
+    pub fn len(&self) -> usize {
+        match self {
+            MetadataValues::Float(values) => values.len(),
+            MetadataValues::Integer(values) => values.len(),
+            MetadataValues::String(_, values) => values.len(),
+            MetadataValues::Color(values) => values.len(),
+        }
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// ASCII and UTF-8 strings are stored in the string section like all other strings,
+/// UTF-16 strings are stored next to the metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u32)]
+pub enum MetadataStringFormat {
+    Ascii,
+    Utf8,
+    Utf16LittleEndian,
+    Utf16BigEndian,
+}
+
+impl MetadataStringFormat {
+    fn is_utf16(self) -> bool {
+        matches!(self, MetadataStringFormat::Utf16LittleEndian | MetadataStringFormat::Utf16BigEndian)
+    }
+    
+    /// Reads the null terminated string a relative pointer points to.
+    fn read_string<R: Read + Seek>(self, reader: &mut R) -> Result<String> {
+        if !self.is_utf16() {
+            return Ok(brw_read_string(reader, Endian::Little, ())?.unwrap_or_default());
+        }
+        
+        let Some(string_ptr) = Pointer::read_relative(reader)? else {
+            return Ok(String::new());
+        };
+        
+        scoped_reader_pos!(reader);
+        reader.seek(SeekFrom::Start(string_ptr.into()))?;
+        
+        let mut units = Vec::new();
+        
+        loop {
+            let unit = match self {
+                MetadataStringFormat::Utf16BigEndian => reader.read_u16::<BigEndian>()?,
+                _ => reader.read_u16::<LittleEndian>()?,
+            };
+            
+            if unit == 0 {
+                break;
+            }
+            
+            units.push(unit);
+        }
+        
+        String::from_utf16(&units).map_err(|_| malformed!("Metadata string at {:#x} isn't valid UTF-16", u64::from(string_ptr)))
+    }
+    
+    fn write_utf16(self, writer: &mut Cursor<&mut Vec<u8>>, string: &str) -> Result<()> {
+        for unit in string.encode_utf16().chain([0]) {
+            match self {
+                MetadataStringFormat::Utf16BigEndian => writer.write_u16::<BigEndian>(unit)?,
+                _ => writer.write_u16::<LittleEndian>(unit)?,
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+fn read_value_list<R: Read + Seek, T>(reader: &mut R, mut read_value: impl FnMut(&mut R) -> Result<T>) -> Result<Vec<T>> {
+    let count = reader.read_u32::<LittleEndian>()?;
+    let list_ptr = Pointer::read_relative(reader)?;
+    
+    let values = if let Some(list_ptr) = list_ptr {
+        scoped_reader_pos!(reader);
+        reader.seek(SeekFrom::Start(list_ptr.into()))?;
+        check_count(reader, count, 1)?;
+        
+        (0..count)
+            .map(|_| read_value(reader))
+            .collect::<Result<Vec<T>>>()?
+    } else {
+        Vec::new()
+    };
+    
+    Ok(values)
+}
+
+impl CgfxMetadata {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        let name = brw_read_string(reader, Endian::Little, ())?;
+        
+        // redundant with the discriminant
+        let _value_type = reader.read_u32::<LittleEndian>()?;
+        
+        let values = match discriminant {
+            0x80000000 => MetadataValues::Float(read_value_list(reader, |reader| Ok(reader.read_f32::<LittleEndian>()?))?),
+            0x20000000 => MetadataValues::Integer(read_value_list(reader, |reader| Ok(reader.read_i32::<LittleEndian>()?))?),
+            0x10000000 => {
+                let format = MetadataStringFormat::read(reader)?;
+                let strings = read_value_list(reader, |reader| format.read_string(reader))?;
+                
+                MetadataValues::String(format, strings)
+            },
+            0x08000000 => MetadataValues::Color(read_value_list(reader, |reader| Ok(Vec4::read(reader)?))?),
+            _ => return Err(malformed!("Invalid metadata type discriminant {:x}", discriminant)),
+        };
+        
+        Ok(Self {
+            name,
+            values,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        writer.write_u32::<LittleEndian>(self.values.discriminant())?;
+        write_string_pointer(writer, ctx, self.name.as_deref())?;
+        
+        writer.write_u32::<LittleEndian>(self.values.value_type())?;
+        
+        if let MetadataValues::String(format, _) = &self.values {
+            format.write(writer)?;
+        }
+        
+        // the values are written right after the metadata, patch the pointer later
+        let list_pointer_location = write_list_placeholder(writer, self.values.len())?;
+        
+        match &self.values {
+            MetadataValues::Float(values) => {
+                write_relative_pointer_here(writer, list_pointer_location)?;
+                
+                for value in values {
+                    writer.write_f32::<LittleEndian>(*value)?;
+                }
+            },
+            MetadataValues::Integer(values) => {
+                write_relative_pointer_here(writer, list_pointer_location)?;
+                
+                for value in values {
+                    writer.write_i32::<LittleEndian>(*value)?;
+                }
+            },
+            MetadataValues::String(format, values) if format.is_utf16() => {
+                write_pointer_list(writer, ctx, list_pointer_location, values,
+                    |value, writer, _| format.write_utf16(writer, value))?;
+                
+                // keep whatever follows aligned
+                while !writer.position().is_multiple_of(4) {
+                    writer.write_u8(0)?;
+                }
+            },
+            MetadataValues::String(_, values) => {
+                write_relative_pointer_here(writer, list_pointer_location)?;
+                
+                for value in values {
+                    write_string_pointer(writer, ctx, Some(value))?;
+                }
+            },
+            MetadataValues::Color(values) => {
+                write_relative_pointer_here(writer, list_pointer_location)?;
+                
+                for value in values {
+                    value.write(writer)?;
+                }
+            },
+        }
+        
+        Ok(())
+    }
+}
+
+impl CgfxCollectionValue for CgfxMetadata {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
@@ -1,6 +1,13 @@
+pub mod animation;
 pub mod bcres;
+pub mod blob;
+pub mod emitter;
 pub mod image_codec;
+pub mod material;
+pub mod metadata;
 pub mod model;
+pub mod skeleton;
 pub mod texture;
+pub mod vertex;
 
 mod util;
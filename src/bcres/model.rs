@@ -1,31 +1,35 @@
 use std::{
-    io::{Cursor, Seek, SeekFrom},
+    io::{Cursor, Read, Seek, SeekFrom},
     ops::{Deref, DerefMut},
-    slice::from_raw_parts,
 };
 
-use anyhow::{anyhow, Result};
-use binrw::{BinRead, BinWrite};
-use byteorder::{LittleEndian, ReadBytesExt};
+use binrw::{BinRead, BinWrite, Endian};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    error::{invalid_input, malformed, unsupported},
     scoped_reader_pos,
     util::{
-        math::{Matrix3x3, SerializableMatrix, Vec3, Vec4},
+        math::{Matrix3x3, SerializableMatrix, Vec3},
         pointer::Pointer,
     },
+    Result,
 };
 
 use super::{
     bcres::{CgfxCollectionValue, CgfxDict, WriteContext},
-    image_codec::RgbaColor,
+    material::Material,
+    skeleton::CgfxSkeleton,
     util::{
-        read_inline_list, read_pointer_list, read_pointer_list_magic, CgfxNodeHeader,
-        CgfxObjectHeader, CgfxTransform,
+        brw_read_string, brw_write_zero, check_count, read_inline_list, read_pointer_list, read_pointer_list_magic,
+        write_image_section_list, write_inline_list, write_list_placeholder, write_pointer_list,
+        write_relative_pointer_here, write_string_pointer,
+        CgfxNodeHeader, CgfxObjectHeader, CgfxTransform,
     },
 };
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CgfxModelCommon {
     // header stuff
     pub cgfx_object_header: CgfxObjectHeader,
@@ -36,28 +40,26 @@ pub struct CgfxModelCommon {
     pub meshes: Option<Vec<Mesh>>,
     pub materials: Option<CgfxDict<Material>>,
     pub shapes: Option<Vec<Shape>>,
-    pub mesh_node_visibilities: Option<CgfxDict<()>>, // TODO: implement
+    pub mesh_node_visibilities: Option<CgfxDict<MeshNodeVisibility>>,
     
     pub flags: u32,
     pub face_culling: u32,
     pub layer_id: u32,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CgfxModel {
     Standard(CgfxModelCommon),
-    Skeletal(CgfxModelCommon, ()),
+    Skeletal(CgfxModelCommon, CgfxSkeleton),
 }
 
 impl CgfxModel {
-    pub fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let discriminant = reader.read_u32::<LittleEndian>()?;
         let cgfx_object_header = CgfxObjectHeader::read(reader)?;
         let cgfx_node_header = CgfxNodeHeader::read(reader)?;
         let transform_node_header = CgfxTransform::read(reader)?;
         
-        // TODO: anim groups in node header
-        
         // meshes
         let meshes: Option<Vec<Mesh>> = read_pointer_list(reader)?;
         
@@ -67,10 +69,13 @@ impl CgfxModel {
         
         let materials = if let Some(material_ptr) = material_ptr {
             scoped_reader_pos!(reader);
-            reader.set_position(reader.position() + u64::from(material_ptr) - 4);
+            reader.seek(SeekFrom::Current(i64::from(material_ptr) - 4))?;
             let dict: CgfxDict<Material> = CgfxDict::from_reader(reader)?;
             
-            assert!(dict.values_count == material_count);
+            if dict.values_count != material_count {
+                return Err(malformed!("Model has {} materials but its dict has {}", material_count, dict.values_count));
+            }
+            
             Some(dict)
         } else {
             None
@@ -85,10 +90,13 @@ impl CgfxModel {
         
         let mesh_node_visibilities = if let Some(mesh_node_visibility_ptr) = mesh_node_visibility_ptr {
             scoped_reader_pos!(reader);
-            reader.set_position(reader.position() + u64::from(mesh_node_visibility_ptr) - 4);
-            let dict: CgfxDict<()> = CgfxDict::from_reader(reader)?;
+            reader.seek(SeekFrom::Current(i64::from(mesh_node_visibility_ptr) - 4))?;
+            let dict: CgfxDict<MeshNodeVisibility> = CgfxDict::from_reader(reader)?;
+            
+            if dict.values_count != mesh_node_visibility_count {
+                return Err(malformed!("Model has {} mesh node visibilities but its dict has {}", mesh_node_visibility_count, dict.values_count));
+            }
             
-            assert!(dict.values_count == mesh_node_visibility_count);
             Some(dict)
         } else {
             None
@@ -98,6 +106,17 @@ impl CgfxModel {
         let face_culling = reader.read_u32::<LittleEndian>()?;
         let layer_id = reader.read_u32::<LittleEndian>()?;
         
+        let skeleton = if discriminant == 0x40000092 {
+            let skeleton_ptr = Pointer::read_relative(reader)?
+                .ok_or_else(|| malformed!("Skeletal model {:?} has no skeleton", cgfx_object_header.name))?;
+            
+            scoped_reader_pos!(reader);
+            reader.seek(SeekFrom::Start(skeleton_ptr.into()))?;
+            Some(CgfxSkeleton::from_reader(reader)?)
+        } else {
+            None
+        };
+        
         let common = CgfxModelCommon {
             cgfx_object_header,
             cgfx_node_header,
@@ -111,15 +130,89 @@ impl CgfxModel {
             layer_id,
         };
         
-        let model = match discriminant {
-            0x40000012 => CgfxModel::Standard(common),
-            0x40000092 => CgfxModel::Skeletal(common, ()),
-            _ => return Err(anyhow!("Invalid model type discriminant {:x}", discriminant)),
+        let model = match (discriminant, skeleton) {
+            (0x40000012, _) => CgfxModel::Standard(common),
+            (0x40000092, Some(skeleton)) => CgfxModel::Skeletal(common, skeleton),
+            _ => return Err(malformed!("Invalid model type discriminant {:x}", discriminant)),
         };
         
         Ok(model)
     }
 
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        let model_offset = Pointer::try_from(&writer)?;
+        
+        let discriminant = match self {
+            CgfxModel::Standard(_) => 0x40000012,
+            CgfxModel::Skeletal(_, _) => 0x40000092,
+        };
+        
+        writer.write_u32::<LittleEndian>(discriminant)?;
+        
+        let common = self.common();
+        
+        let header_offset = Pointer::try_from(&writer)?;
+        
+        if let Some(name) = &common.cgfx_object_header.name {
+            ctx.add_string(name)?;
+            ctx.add_string_reference(header_offset + 8, name.clone());
+        }
+        
+        common.cgfx_object_header.write(writer)?;
+        
+        let node_header_offset = Pointer::try_from(&writer)?;
+        common.cgfx_node_header.write(writer)?;
+        common.transform_node_header.write(writer)?;
+        
+        // all lists are written after the model, patch pointers later
+        let mesh_pointer_location = write_list_placeholder(writer, common.meshes.as_ref().map_or(0, Vec::len))?;
+        let material_pointer_location = write_list_placeholder(writer,
+            common.materials.as_ref().map_or(0, |materials| materials.values_count as usize))?;
+        let shape_pointer_location = write_list_placeholder(writer, common.shapes.as_ref().map_or(0, Vec::len))?;
+        let mesh_node_visibility_pointer_location = write_list_placeholder(writer,
+            common.mesh_node_visibilities.as_ref().map_or(0, |visibilities| visibilities.values_count as usize))?;
+        
+        writer.write_u32::<LittleEndian>(common.flags)?;
+        writer.write_u32::<LittleEndian>(common.face_culling)?;
+        writer.write_u32::<LittleEndian>(common.layer_id)?;
+        
+        let skeleton_pointer_location = Pointer::try_from(&writer)?;
+        
+        if let CgfxModel::Skeletal(_, _) = self {
+            writer.write_u32::<LittleEndian>(0)?;
+        }
+        
+        if let Some(meshes) = &common.meshes {
+            write_pointer_list(writer, ctx, mesh_pointer_location, meshes,
+                |mesh, writer, ctx| mesh.to_writer(writer, ctx, model_offset))?;
+        }
+        
+        if let Some(materials) = &common.materials {
+            write_relative_pointer_here(writer, material_pointer_location)?;
+            materials.to_writer(writer, ctx)?;
+        }
+        
+        if let Some(shapes) = &common.shapes {
+            write_pointer_list(writer, ctx, shape_pointer_location, shapes,
+                |shape, writer, ctx| shape.to_writer(writer, ctx))?;
+        }
+        
+        if let Some(mesh_node_visibilities) = &common.mesh_node_visibilities {
+            write_relative_pointer_here(writer, mesh_node_visibility_pointer_location)?;
+            mesh_node_visibilities.to_writer(writer, ctx)?;
+        }
+        
+        if let CgfxModel::Skeletal(_, skeleton) = self {
+            write_relative_pointer_here(writer, skeleton_pointer_location)?;
+            skeleton.to_writer(writer, ctx)?;
+        }
+        
+        common.cgfx_object_header.write_metadata(writer, ctx, header_offset)?;
+        common.cgfx_node_header.write_anim_groups(writer, ctx, node_header_offset)?;
+        
+        Ok(())
+    }
+    
     pub fn common(&self) -> &CgfxModelCommon {
         match self {
             CgfxModel::Standard(common) => common,
@@ -133,19 +226,122 @@ impl CgfxModel {
             CgfxModel::Skeletal(common, _) => common,
         }
     }
+    
+    pub fn skeleton(&self) -> Option<&CgfxSkeleton> {
+        match self {
+            CgfxModel::Standard(_) => None,
+            CgfxModel::Skeletal(_, skeleton) => Some(skeleton),
+        }
+    }
+    
+    pub fn skeleton_mut(&mut self) -> Option<&mut CgfxSkeleton> {
+        match self {
+            CgfxModel::Standard(_) => None,
+            CgfxModel::Skeletal(_, skeleton) => Some(skeleton),
+        }
+    }
+    
+    /// Recomputes the matrices of the model and its bones from their scale, rotation and
+    /// translation. Bones are relative to the model, while the model is always placed at the
+    /// root, as the children of scene nodes aren't resolved (see `CgfxNodeHeader`).
+    pub fn recompute_transforms(&mut self) -> Result<()> {
+        self.common_mut().transform_node_header.recompute(None);
+        
+        if let Some(skeleton) = self.skeleton_mut() {
+            skeleton.recompute_transforms()?;
+        }
+        
+        Ok(())
+    }
 }
 
 impl CgfxCollectionValue for CgfxModel {
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         Self::from_reader(reader)
     }
 
-    fn write_dict_value(&self, _writer: &mut Cursor<&mut Vec<u8>>, _ctx: &mut WriteContext) -> Result<()> {
-        todo!()
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
+
+impl CgfxModelCommon {
+    /// The mesh node `mesh` belongs to, if it belongs to any.
+    pub fn mesh_node_visibility(&self, mesh: &Mesh) -> Option<&MeshNodeVisibility> {
+        self.mesh_node_visibilities.as_ref()?.values().nth(usize::from(mesh.mesh_node_index))
+    }
+    
+    pub fn find_mesh_node_visibility_mut(&mut self, name: &str) -> Option<&mut MeshNodeVisibility> {
+        self.mesh_node_visibilities.as_mut()?.get_mut(name)
+    }
+    
+    /// Shows or hides all meshes belonging to the mesh node `name`.
+    pub fn set_mesh_node_visible(&mut self, name: &str, visible: bool) -> Result<()> {
+        let visibility = self.find_mesh_node_visibility_mut(name)
+            .ok_or_else(|| invalid_input!("Model has no mesh node called {:?}", name))?;
+        
+        visibility.visible = visible;
+        Ok(())
+    }
+    
+    pub fn meshes_in_node<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Mesh> + 'a {
+        self.meshes.iter().flatten()
+            .filter(move |mesh| self.mesh_node_visibility(mesh).is_some_and(|visibility| visibility.name.as_deref() == Some(name)))
+    }
+    
+    /// Whether `mesh` gets rendered, taking the visibility of its mesh node into account.
+    pub fn is_mesh_visible(&self, mesh: &Mesh) -> bool {
+        mesh.visible != 0 && self.mesh_node_visibility(mesh).is_none_or(|visibility| visibility.visible)
+    }
+    
+    /// Refits the bounding boxes of all shapes, see `Shape::recompute_bounding_box`.
+    pub fn recompute_bounding_boxes(&mut self, fit: BoundingBoxFit) -> Result<()> {
+        for shape in self.shapes.iter_mut().flatten() {
+            shape.recompute_bounding_box(fit)?;
+        }
+        
+        Ok(())
+    }
+}
+
+/// Visibility of a named group of meshes ("mesh node"), so that parts
+/// of a model can be shown or hidden together, e.g. by an animation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MeshNodeVisibility {
+    pub name: Option<String>,
+    pub visible: bool,
+}
+
+impl MeshNodeVisibility {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let name = brw_read_string(reader, Endian::Little, ())?;
+        let visible = reader.read_u32::<LittleEndian>()? != 0;
+        
+        Ok(Self {
+            name,
+            visible,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        write_string_pointer(writer, ctx, self.name.as_deref())?;
+        writer.write_u32::<LittleEndian>(self.visible.into())?;
+        
+        Ok(())
+    }
+}
+
+impl CgfxCollectionValue for MeshNodeVisibility {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::from_reader(reader)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
     }
 }
 
-#[derive(Clone, Debug, PartialEq, BinRead, BinWrite)]
+#[derive(Clone, Debug, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little, magic = 0x01000000u32)]
 pub struct Mesh {
     // object header
@@ -163,53 +359,58 @@ pub struct Mesh {
     pub primitive_index: u32,
     
     // runtime initialized data
-    // ...
-}
-
-#[derive(Clone, Debug, PartialEq, BinRead, BinWrite)]
-#[brw(little, magic = 0x8000000u32)]
-pub struct Material {
-    pub cgfx_object_header: CgfxObjectHeader,
+    runtime_data: [u32; 18],
     
-    // material stuff
-    pub flags: u32,
-    pub tex_coord_config: u32,
-    pub render_layer: u32,
-    pub colors: MaterialColors,
-    // ...
+    #[br(parse_with = brw_read_string)]
+    #[bw(write_with = brw_write_zero)]
+    pub mesh_node_name: Option<String>,
+    
+    render_key_cache: u64,
+    command_alloc: u32,
 }
 
-#[derive(Clone, Debug, PartialEq, BinRead, BinWrite)]
-#[brw(little)]
-pub struct MaterialColors {
-    pub emission_float: Vec4,
-    pub ambient_float: Vec4,
-    pub diffuse_float: Vec4,
-    pub specular0_float: Vec4,
-    pub specular1_float: Vec4,
-    pub constant0_float: Vec4,
-    pub constant1_float: Vec4,
-    pub constant2_float: Vec4,
-    pub constant3_float: Vec4,
-    pub constant4_float: Vec4,
-    pub constant5_float: Vec4,
-    
-    pub emission: RgbaColor,
-    pub ambient: RgbaColor,
-    pub diffuse: RgbaColor,
-    pub specular0: RgbaColor,
-    pub specular1: RgbaColor,
-    pub constant0: RgbaColor,
-    pub constant1: RgbaColor,
-    pub constant2: RgbaColor,
-    pub constant3: RgbaColor,
-    pub constant4: RgbaColor,
-    pub constant5: RgbaColor,
-    
-    pub command_cache: u32,
+impl Mesh {
+    /// Unlike the derived `BinWrite` implementation, this also writes the
+    /// name, metadata and a pointer back to the model at `model_offset`.
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext, model_offset: Pointer) -> Result<()> {
+        writer.write_u32::<LittleEndian>(0x01000000)?;
+        
+        let header_offset = Pointer::try_from(&writer)?;
+        
+        if let Some(name) = &self.cgfx_object_header.name {
+            ctx.add_string(name)?;
+            ctx.add_string_reference(header_offset + 8, name.clone());
+        }
+        
+        self.cgfx_object_header.write(writer)?;
+        writer.write_u32::<LittleEndian>(self.shape_index)?;
+        writer.write_u32::<LittleEndian>(self.material_index)?;
+        
+        let parent_location = Pointer::try_from(&writer)?;
+        let parent_ptr = if self.parent_ptr != 0 {
+            i32::try_from(i64::from(model_offset) - i64::from(parent_location))?
+        } else {
+            0
+        };
+        
+        writer.write_i32::<LittleEndian>(parent_ptr)?;
+        writer.write_u8(self.visible)?;
+        writer.write_u8(self.render_priority)?;
+        writer.write_u16::<LittleEndian>(self.mesh_node_index)?;
+        writer.write_u32::<LittleEndian>(self.primitive_index)?;
+        
+        self.runtime_data.write_le(writer)?;
+        write_string_pointer(writer, ctx, self.mesh_node_name.as_deref())?;
+        writer.write_u64::<LittleEndian>(self.render_key_cache)?;
+        writer.write_u32::<LittleEndian>(self.command_alloc)?;
+        
+        self.cgfx_object_header.write_metadata(writer, ctx, header_offset)?;
+        
+        Ok(())
+    }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Shape {
     // object header
     pub cgfx_object_header: CgfxObjectHeader,
@@ -223,12 +424,16 @@ pub struct Shape {
     pub base_address: u32,
     pub vertex_buffers: Option<Vec<VertexBuffer>>,
     
-    // TODO: blend shape
+    pub blend_shape: Option<BlendShape>,
 }
 
 impl Shape {
-    pub fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
-        assert!(reader.read_u32::<LittleEndian>()? == 0x10000001);
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let magic = reader.read_u32::<LittleEndian>()?;
+        
+        if magic != 0x10000001 {
+            return Err(malformed!("Expected shape magic 0x10000001, found {:#x}", magic));
+        }
         
         let cgfx_object_header = CgfxObjectHeader::read(reader)?;
         let flags = reader.read_u32::<LittleEndian>()?;
@@ -243,12 +448,23 @@ impl Shape {
         };
         
         let position_offset = Vec3::read(reader)?;
-        assert!(position_offset == Vec3::default());
+        if position_offset != Vec3::default() {
+            return Err(unsupported!("Shapes with a position offset of {:?}", position_offset));
+        }
         
         let sub_meshes: Option<Vec<SubMesh>> = read_pointer_list(reader)?;
         let base_address = reader.read_u32::<LittleEndian>()?;
         let vertex_buffers: Option<Vec<VertexBuffer>> = read_pointer_list(reader)?;
         
+        let blend_shape_ptr = Pointer::read_relative(reader)?;
+        let blend_shape = if let Some(blend_shape_ptr) = blend_shape_ptr {
+            scoped_reader_pos!(reader);
+            reader.seek(SeekFrom::Start(blend_shape_ptr.into()))?;
+            Some(BlendShape::from_reader(reader)?)
+        } else {
+            None
+        };
+        
         Ok(Self {
             cgfx_object_header,
             flags,
@@ -257,25 +473,167 @@ impl Shape {
             sub_meshes,
             base_address,
             vertex_buffers,
+            blend_shape,
         })
     }
     
-    pub fn to_writer(&self, _writer: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
-        todo!()
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        writer.write_u32::<LittleEndian>(0x10000001)?;
+        
+        let header_offset = Pointer::try_from(&writer)?;
+        
+        if let Some(name) = &self.cgfx_object_header.name {
+            ctx.add_string(name)?;
+            ctx.add_string_reference(header_offset + 8, name.clone());
+        }
+        
+        self.cgfx_object_header.write(writer)?;
+        writer.write_u32::<LittleEndian>(self.flags)?;
+        
+        let bounding_box_pointer_location = Pointer::try_from(&writer)?;
+        writer.write_u32::<LittleEndian>(0)?;
+        
+        self.position_offset.write(writer)?;
+        
+        let sub_mesh_pointer_location = write_list_placeholder(writer, self.sub_meshes.as_ref().map_or(0, Vec::len))?;
+        writer.write_u32::<LittleEndian>(self.base_address)?;
+        let vertex_buffer_pointer_location = write_list_placeholder(writer, self.vertex_buffers.as_ref().map_or(0, Vec::len))?;
+        
+        let blend_shape_pointer_location = Pointer::try_from(&writer)?;
+        writer.write_u32::<LittleEndian>(0)?;
+        
+        if let Some(bounding_box) = &self.bounding_box {
+            write_relative_pointer_here(writer, bounding_box_pointer_location)?;
+            bounding_box.write(writer)?;
+        }
+        
+        if let Some(sub_meshes) = &self.sub_meshes {
+            write_pointer_list(writer, ctx, sub_mesh_pointer_location, sub_meshes, SubMesh::to_writer)?;
+        }
+        
+        if let Some(vertex_buffers) = &self.vertex_buffers {
+            write_pointer_list(writer, ctx, vertex_buffer_pointer_location, vertex_buffers, VertexBuffer::to_writer)?;
+        }
+        
+        if let Some(blend_shape) = &self.blend_shape {
+            write_relative_pointer_here(writer, blend_shape_pointer_location)?;
+            blend_shape.to_writer(writer, ctx)?;
+        }
+        
+        self.cgfx_object_header.write_metadata(writer, ctx, header_offset)?;
+        
+        Ok(())
+    }
+    
+    /// Decodes `attribute` from the vertex buffers holding the base geometry,
+    /// i.e. skipping the ones that belong to blend shape targets.
+    pub fn decode_attribute(&self, attribute: AttributeName) -> Result<Option<Vec<Vec<f32>>>> {
+        let Some(vertex_buffers) = &self.vertex_buffers else {
+            return Ok(None);
+        };
+        
+        for (i, vertex_buffer) in vertex_buffers.iter().enumerate() {
+            if self.blend_shape.as_ref().is_some_and(|blend_shape| blend_shape.is_target_buffer(i)) {
+                continue;
+            }
+            
+            if let Some(values) = vertex_buffer.decode_attribute(attribute)? {
+                return Ok(Some(values));
+            }
+        }
+        
+        Ok(None)
+    }
+    
+    /// Decodes `attribute` (usually the position or normal) of blend shape target `target_index`.
+    pub fn decode_blend_shape_target(&self, target_index: usize, attribute: AttributeName) -> Result<Option<Vec<Vec<f32>>>> {
+        let target = self.blend_shape.as_ref()
+            .and_then(|blend_shape| blend_shape.targets.as_ref())
+            .and_then(|targets| targets.get(target_index))
+            .ok_or_else(|| invalid_input!("Shape {:?} has no blend shape target {}", self.cgfx_object_header.name, target_index))?;
+        
+        let vertex_buffer = self.vertex_buffers.as_ref()
+            .and_then(|vertex_buffers| vertex_buffers.get(target.vertex_buffer_index as usize))
+            .ok_or_else(|| malformed!("Blend shape target {} references missing vertex buffer {}", target_index, target.vertex_buffer_index))?;
+        
+        vertex_buffer.decode_attribute(attribute)
     }
 }
 
 impl CgfxCollectionValue for Shape {
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         Self::from_reader(reader)
     }
 
-    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, _: &mut WriteContext) -> Result<()> {
-        self.to_writer(writer)
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
+
+/// Alternative versions ("targets") of a shape's geometry which can be blended
+/// with the base geometry, used for things like facial animations.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlendShape {
+    pub targets: Option<Vec<BlendShapeTarget>>,
+    /// The attributes every target provides
+    pub attributes: Option<Vec<AttributeName>>,
+    pub blend_type: BlendShapeType,
+}
+
+impl BlendShape {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let targets: Option<Vec<BlendShapeTarget>> = read_inline_list(reader)?;
+        let attributes: Option<Vec<AttributeName>> = read_inline_list(reader)?;
+        let blend_type = BlendShapeType::read(reader)?;
+        
+        Ok(Self {
+            targets,
+            attributes,
+            blend_type,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        let target_pointer_location = write_list_placeholder(writer, self.targets.as_ref().map_or(0, Vec::len))?;
+        let attribute_pointer_location = write_list_placeholder(writer, self.attributes.as_ref().map_or(0, Vec::len))?;
+        self.blend_type.write(writer)?;
+        
+        if let Some(targets) = &self.targets {
+            write_inline_list(writer, ctx, target_pointer_location, targets)?;
+        }
+        
+        if let Some(attributes) = &self.attributes {
+            write_inline_list(writer, ctx, attribute_pointer_location, attributes)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Whether the vertex buffer at `vertex_buffer_index` holds the data of a target
+    /// instead of the base geometry.
+    pub fn is_target_buffer(&self, vertex_buffer_index: usize) -> bool {
+        self.targets.iter().flatten()
+            .any(|target| target.vertex_buffer_index as usize == vertex_buffer_index)
     }
 }
 
-#[derive(Clone, Debug, PartialEq, BinRead, BinWrite)]
+#[derive(Clone, Debug, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little)]
+pub struct BlendShapeTarget {
+    /// Index into the vertex buffers of the shape
+    pub vertex_buffer_index: u32,
+    pub weight: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(repr = u32, little)]
+pub enum BlendShapeType {
+    None,
+    Position,
+    PositionAndNormal,
+}
+
+#[derive(Clone, Debug, PartialEq, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little)]
 pub struct BoundingBox {
     pub flags: u32,
@@ -286,7 +644,104 @@ pub struct BoundingBox {
     pub size: Vec3,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite)]
+impl BoundingBox {
+    /// The smallest box aligned to the axes containing all of `positions`.
+    pub fn axis_aligned(flags: u32, positions: &[na::Vec3]) -> Option<Self> {
+        let first = positions.first()?;
+        
+        let (min, max) = positions.iter()
+            .fold((*first, *first), |(min, max), position| (min.inf(position), max.sup(position)));
+        
+        let center = (min + max) / 2.0;
+        let size = max - min;
+        
+        Some(Self {
+            flags,
+            center: Vec3::new(center.x, center.y, center.z),
+            orientation: Matrix3x3::identity(),
+            size: Vec3::new(size.x, size.y, size.z),
+        })
+    }
+    
+    /// A box aligned to the principal axes of `positions`, which fits elongated or rotated
+    /// geometry a lot tighter than `axis_aligned`. Falls back to the axis aligned box if
+    /// that one happens to be smaller, so the result is never worse.
+    pub fn oriented(flags: u32, positions: &[na::Vec3]) -> Option<Self> {
+        let axis_aligned = Self::axis_aligned(flags, positions)?;
+        
+        let mean = positions.iter().sum::<na::Vec3>() / positions.len() as f32;
+        let covariance = positions.iter()
+            .map(|position| {
+                let offset = position - mean;
+                offset * offset.transpose()
+            })
+            .sum::<na::Mat3>() / positions.len() as f32;
+        
+        // the eigenvectors of the covariance matrix are the principal axes,
+        // flip one if needed so the orientation stays a proper rotation
+        let mut axes = covariance.symmetric_eigen().eigenvectors;
+        
+        if axes.determinant() < 0.0 {
+            axes.set_column(2, &-axes.column(2));
+        }
+        
+        if axes.iter().any(|value| !value.is_finite()) {
+            return Some(axis_aligned);
+        }
+        
+        let first = axes.tr_mul(&positions[0]);
+        let (min, max) = positions.iter()
+            .map(|position| axes.tr_mul(position))
+            .fold((first, first), |(min, max), local| (min.inf(&local), max.sup(&local)));
+        
+        let center = axes * ((min + max) / 2.0);
+        let size = max - min;
+        
+        let oriented = Self {
+            flags,
+            center: Vec3::new(center.x, center.y, center.z),
+            orientation: axes,
+            size: Vec3::new(size.x, size.y, size.z),
+        };
+        
+        if oriented.volume() < axis_aligned.volume() {
+            Some(oriented)
+        } else {
+            Some(axis_aligned)
+        }
+    }
+    
+    pub fn fit(flags: u32, positions: &[na::Vec3], fit: BoundingBoxFit) -> Option<Self> {
+        match fit {
+            BoundingBoxFit::AxisAligned => Self::axis_aligned(flags, positions),
+            BoundingBoxFit::Oriented => Self::oriented(flags, positions),
+        }
+    }
+    
+    pub fn volume(&self) -> f32 {
+        self.size.x * self.size.y * self.size.z
+    }
+    
+    /// Whether `position` lies inside of this box, allowing it to be off by `tolerance`.
+    pub fn contains(&self, position: &na::Vec3, tolerance: f32) -> bool {
+        let local = self.orientation.tr_mul(&(position - self.center.to_na()));
+        let half_size = self.size.to_na() / 2.0;
+        
+        local.iter().zip(half_size.iter())
+            .all(|(value, half_size)| value.abs() <= half_size + tolerance)
+    }
+}
+
+/// How a shape's bounding box gets fitted around its vertices.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BoundingBoxFit {
+    #[default]
+    AxisAligned,
+    /// Rotated along the principal axes of the vertices
+    Oriented,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(repr = u32, little)]
 pub enum SubMeshSkinning {
     None,
@@ -294,7 +749,7 @@ pub enum SubMeshSkinning {
     Smooth,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SubMesh {
     pub bone_indices: Option<Vec<u32>>,
     pub skinning: SubMeshSkinning,
@@ -302,17 +757,17 @@ pub struct SubMesh {
 }
 
 impl SubMesh {
-    pub fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let bone_index_count = reader.read_u32::<LittleEndian>()?;
         let bone_index_ptr = Pointer::read_relative(reader)?;
         
         let bone_indices = if let Some(bone_index_ptr) = bone_index_ptr {
             scoped_reader_pos!(reader);
             
-            let mut bone_indices = Vec::new();
-            bone_indices.resize(bone_index_count as usize, 0);
-            
             reader.seek(SeekFrom::Start(bone_index_ptr.into()))?;
+            check_count(reader, bone_index_count, 4)?;
+            
+            let mut bone_indices = vec![0; bone_index_count as usize];
             reader.read_u32_into::<LittleEndian>(&mut bone_indices)?;
             Some(bone_indices)
         } else {
@@ -329,22 +784,34 @@ impl SubMesh {
         })
     }
     
-    pub fn to_writer(&self, _writer: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
-        todo!()
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        let bone_index_pointer_location = write_list_placeholder(writer, self.bone_indices.as_ref().map_or(0, Vec::len))?;
+        self.skinning.write(writer)?;
+        let face_pointer_location = write_list_placeholder(writer, self.faces.as_ref().map_or(0, Vec::len))?;
+        
+        if let Some(bone_indices) = &self.bone_indices {
+            write_inline_list(writer, ctx, bone_index_pointer_location, bone_indices)?;
+        }
+        
+        if let Some(faces) = &self.faces {
+            write_pointer_list(writer, ctx, face_pointer_location, faces, Face::to_writer)?;
+        }
+        
+        Ok(())
     }
 }
 
 impl CgfxCollectionValue for SubMesh {
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         Self::from_reader(reader)
     }
 
-    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, _: &mut WriteContext) -> Result<()> {
-        self.to_writer(writer)
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Face {
     pub face_descriptors: Option<Vec<FaceDescriptor>>,
     pub buffer_objs: Option<Vec<u32>>,
@@ -353,7 +820,7 @@ pub struct Face {
 }
 
 impl Face {
-    pub fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let face_descriptors: Option<Vec<FaceDescriptor>> = read_pointer_list(reader)?;
         let buffer_objs: Option<Vec<u32>> = read_inline_list(reader)?;
         let flags = reader.read_u32::<LittleEndian>()?;
@@ -367,40 +834,73 @@ impl Face {
         })
     }
     
-    pub fn to_writer(&self, _: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
-        todo!()
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        let face_descriptor_pointer_location = write_list_placeholder(writer, self.face_descriptors.as_ref().map_or(0, Vec::len))?;
+        let buffer_obj_pointer_location = write_list_placeholder(writer, self.buffer_objs.as_ref().map_or(0, Vec::len))?;
+        writer.write_u32::<LittleEndian>(self.flags)?;
+        writer.write_u32::<LittleEndian>(self.command_alloc)?;
+        
+        if let Some(face_descriptors) = &self.face_descriptors {
+            write_pointer_list(writer, ctx, face_descriptor_pointer_location, face_descriptors, FaceDescriptor::to_writer)?;
+        }
+        
+        if let Some(buffer_objs) = &self.buffer_objs {
+            write_inline_list(writer, ctx, buffer_obj_pointer_location, buffer_objs)?;
+        }
+        
+        Ok(())
     }
 }
 
 impl CgfxCollectionValue for Face {
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         Self::from_reader(reader)
     }
 
-    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, _: &mut WriteContext) -> Result<()> {
-        self.to_writer(writer)
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
+#[brw(little, repr = u8)]
+pub enum PrimitiveMode {
+    Triangles,
+    TriangleStrip,
+    TriangleFan,
+    GeometryPrimitive,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FaceDescriptor {
     pub format: GlDataType,
-    pub primitive_mode: u8, // TODO: make this an enum
+    pub primitive_mode: PrimitiveMode,
     pub visible: u8,
     
-    pub indices: Option<Vec<u16>>, // TODO: implement speial case for format == Short or UShort
+    /// Stored as either 8-bit or 16-bit integers, depending on `format`
+    pub indices: Option<Vec<u16>>,
     
-    // more fields
+    pub buffer_obj: u32,
+    pub location_flag: u32,
+    pub command_cache: u32,
+    pub command_cache_size: u32,
+    pub location_ptr: u32,
+    pub memory_area: u32,
     
+    /// Kept as it was read, bounding box recomputation only touches `Shape::bounding_box`
+    /// since that's the one used for culling.
     pub bounding_volume: u32,
 }
 
 impl FaceDescriptor {
-    pub fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let format = GlDataType::read(reader)?;
-        assert!(format.byte_size() == 1 || format.byte_size() == 2);
         
-        let primitive_mode = reader.read_u8()?;
+        if !matches!(format, GlDataType::Byte | GlDataType::UByte | GlDataType::Short | GlDataType::UShort) {
+            return Err(malformed!("Invalid index format {:?}", format));
+        }
+        
+        let primitive_mode = PrimitiveMode::read(reader)?;
         
         let visible = reader.read_u8()?;
         
@@ -411,15 +911,15 @@ impl FaceDescriptor {
         let indices = if let Some(raw_buffer) = raw_buffer {
             let indices: Vec<u16> = match format.byte_size() {
                 1 => raw_buffer.iter().map(|i| *i as u16).collect(),
-                2 => {
-                    assert!(raw_buffer.len() % 2 == 0);
-                    
-                    unsafe {
-                        let raw_buffer_pointer = (&raw_buffer[0] as *const u8) as *const u16;
-                        from_raw_parts(raw_buffer_pointer, raw_buffer.len() / 2).to_owned()
+                _ => {
+                    if raw_buffer.len() % 2 != 0 {
+                        return Err(malformed!("Index buffer of {} bytes can't contain 16-bit indices", raw_buffer.len()));
                     }
+                    
+                    raw_buffer.chunks_exact(2)
+                        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                        .collect()
                 },
-                _ => panic!("Invalid byte size"),
             };
             
             Some(indices)
@@ -427,9 +927,12 @@ impl FaceDescriptor {
             None
         };
         
-        // skip 6 32-bit integers (fields aren't relevant here)
-        // TODO: they will be necessary for serializing though
-        reader.seek(SeekFrom::Current(6 * 4))?;
+        let buffer_obj = reader.read_u32::<LittleEndian>()?;
+        let location_flag = reader.read_u32::<LittleEndian>()?;
+        let command_cache = reader.read_u32::<LittleEndian>()?;
+        let command_cache_size = reader.read_u32::<LittleEndian>()?;
+        let location_ptr = reader.read_u32::<LittleEndian>()?;
+        let memory_area = reader.read_u32::<LittleEndian>()?;
         
         let bounding_volume = reader.read_u32::<LittleEndian>()?;
         
@@ -438,33 +941,96 @@ impl FaceDescriptor {
             primitive_mode,
             visible,
             indices,
+            buffer_obj,
+            location_flag,
+            command_cache,
+            command_cache_size,
+            location_ptr,
+            memory_area,
             bounding_volume,
         })
     }
     
-    pub fn to_writer(&self, _: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
-        todo!()
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.format.write(writer)?;
+        self.primitive_mode.write(writer)?;
+        writer.write_u8(self.visible)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        
+        let raw_buffer: Option<Vec<u8>> = match &self.indices {
+            Some(indices) if self.format.byte_size() == 1 => Some(indices.iter()
+                .map(|index| u8::try_from(*index)
+                    .map_err(|_| invalid_input!("Index {} doesn't fit into index format {:?}", index, self.format)))
+                .collect::<Result<Vec<u8>>>()?),
+            Some(indices) => Some(indices.iter().flat_map(|index| index.to_le_bytes()).collect()),
+            None => None,
+        };
+        
+        write_image_section_list(writer, ctx, raw_buffer.as_deref())?;
+        
+        writer.write_u32::<LittleEndian>(self.buffer_obj)?;
+        writer.write_u32::<LittleEndian>(self.location_flag)?;
+        writer.write_u32::<LittleEndian>(self.command_cache)?;
+        writer.write_u32::<LittleEndian>(self.command_cache_size)?;
+        writer.write_u32::<LittleEndian>(self.location_ptr)?;
+        writer.write_u32::<LittleEndian>(self.memory_area)?;
+        
+        writer.write_u32::<LittleEndian>(self.bounding_volume)?;
+        
+        Ok(())
+    }
+    
+    /// The indices of this face descriptor as a list of triangles,
+    /// with triangle strips and fans converted accordingly.
+    pub fn triangles(&self) -> Result<Vec<[u16; 3]>> {
+        let indices: &[u16] = self.indices.as_deref().unwrap_or_default();
+        
+        let triangles = match self.primitive_mode {
+            PrimitiveMode::Triangles => indices.chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect(),
+            PrimitiveMode::TriangleStrip => indices.windows(3)
+                .enumerate()
+                // every other triangle of a strip has flipped winding order
+                .map(|(i, triangle)| if i % 2 == 0 {
+                    [triangle[0], triangle[1], triangle[2]]
+                } else {
+                    [triangle[1], triangle[0], triangle[2]]
+                })
+                // degenerate triangles are only used to connect multiple strips
+                .filter(|[a, b, c]| a != b && b != c && a != c)
+                .collect(),
+            PrimitiveMode::TriangleFan => match indices.split_first() {
+                Some((center, rest)) => rest.windows(2)
+                    .map(|edge| [*center, edge[0], edge[1]])
+                    .collect(),
+                None => Vec::new(),
+            },
+            PrimitiveMode::GeometryPrimitive => return Err(unsupported!("Geometry shader primitives can't be converted to triangles")),
+        };
+        
+        Ok(triangles)
     }
 }
 
 impl CgfxCollectionValue for FaceDescriptor {
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         Self::from_reader(reader)
     }
 
-    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, _: &mut WriteContext) -> Result<()> {
-        self.to_writer(writer)
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, BinRead, BinWrite)]
+#[derive(Clone, Debug, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little)]
 pub struct VertexBufferCommon {
     pub attribute_name: AttributeName,
     pub vertex_buffer_type: VertexBufferType,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, BinRead, BinWrite)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little, repr = u32)]
 pub enum AttributeName {
     Position,
@@ -491,7 +1057,7 @@ pub enum AttributeName {
     Interleave,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, BinRead, BinWrite)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little, repr = u32)]
 pub enum GlDataType {
     Byte = 0x1400,
@@ -502,6 +1068,9 @@ pub enum GlDataType {
     Fixed = 0x140C,
 }
 
+/// `GlDataType::Fixed` values are signed 16.16 fixed point numbers
+const FIXED_POINT_ONE: f32 = 65536.0;
+
 impl GlDataType {
     pub fn byte_size(self) -> u32 {
         match self {
@@ -510,12 +1079,56 @@ impl GlDataType {
             GlDataType::Short => 2,
             GlDataType::UShort => 2,
             GlDataType::Float => 4,
-            GlDataType::Fixed => todo!(), // wtf is Fixed?
+            GlDataType::Fixed => 4,
+        }
+    }
+    
+    /// The largest value an integer type can hold, `None` for types which store fractions themselves.
+    pub fn max_value(self) -> Option<f32> {
+        match self {
+            GlDataType::Byte => Some(i8::MAX.into()),
+            GlDataType::UByte => Some(u8::MAX.into()),
+            GlDataType::Short => Some(i16::MAX.into()),
+            GlDataType::UShort => Some(u16::MAX.into()),
+            GlDataType::Float | GlDataType::Fixed => None,
         }
     }
+    
+    /// Reads a single vertex component of this type, without applying any scale.
+    fn read_component<R: Read + Seek>(self, reader: &mut R) -> Result<f32> {
+        let value = match self {
+            GlDataType::Byte => reader.read_i8()?.into(),
+            GlDataType::UByte => reader.read_u8()?.into(),
+            GlDataType::Short => reader.read_i16::<LittleEndian>()?.into(),
+            GlDataType::UShort => reader.read_u16::<LittleEndian>()?.into(),
+            GlDataType::Float => reader.read_f32::<LittleEndian>()?,
+            GlDataType::Fixed => reader.read_i32::<LittleEndian>()? as f32 / FIXED_POINT_ONE,
+        };
+        
+        Ok(value)
+    }
+    
+    /// Writes a single vertex component of this type, `value` needs to be unscaled already.
+    fn write_component(self, writer: &mut Cursor<&mut [u8]>, value: f32) -> Result<()> {
+        fn to_integer<T: TryFrom<i64>>(value: f32) -> Result<T> {
+            let rounded = value.round() as i64;
+            T::try_from(rounded).map_err(|_| invalid_input!("Vertex component {} is out of range", value))
+        }
+        
+        match self {
+            GlDataType::Byte => writer.write_i8(to_integer(value)?)?,
+            GlDataType::UByte => writer.write_u8(to_integer(value)?)?,
+            GlDataType::Short => writer.write_i16::<LittleEndian>(to_integer(value)?)?,
+            GlDataType::UShort => writer.write_u16::<LittleEndian>(to_integer(value)?)?,
+            GlDataType::Float => writer.write_f32::<LittleEndian>(value)?,
+            GlDataType::Fixed => writer.write_i32::<LittleEndian>(to_integer(value * FIXED_POINT_ONE)?)?,
+        }
+        
+        Ok(())
+    }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, BinRead, BinWrite)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little, repr = u32)]
 pub enum VertexBufferType {
     // TODO: is this necessary? this seems redundant
@@ -524,7 +1137,7 @@ pub enum VertexBufferType {
     Interleaved,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum VertexBuffer {
     Attribute(VertexBufferAttribute),
     Interleaved(VertexBufferInterleaved),
@@ -532,41 +1145,94 @@ pub enum VertexBuffer {
 }
 
 impl VertexBuffer {
-    fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let discriminant = reader.read_u32::<LittleEndian>()?;
         
         let vertex_buffer = match discriminant {
             0x40000001 => Self::Attribute(VertexBufferAttribute::from_reader(reader)?),
             0x40000002 => Self::Interleaved(VertexBufferInterleaved::from_reader(reader)?),
             0x80000000 => Self::Fixed(VertexBufferFixed::from_reader(reader)?),
-            _ => return Err(anyhow!("Invalid model type discriminant {:x}", discriminant)),
+            _ => return Err(malformed!("Invalid model type discriminant {:x}", discriminant)),
         };
         
         Ok(vertex_buffer)
     }
     
-    fn to_writer(&self, _writer: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
-        todo!()
+    fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        match self {
+            VertexBuffer::Attribute(attribute) => {
+                writer.write_u32::<LittleEndian>(0x40000001)?;
+                attribute.to_writer(writer, ctx)
+            },
+            VertexBuffer::Interleaved(interleaved) => {
+                writer.write_u32::<LittleEndian>(0x40000002)?;
+                interleaved.to_writer(writer, ctx)
+            },
+            VertexBuffer::Fixed(fixed) => {
+                writer.write_u32::<LittleEndian>(0x80000000)?;
+                fixed.to_writer(writer, ctx)
+            },
+        }
+    }
+    
+    /// Decodes `attribute` if this vertex buffer contains it, returning one value with
+    /// `elements` components per vertex. Fixed attributes aren't streams, so they are skipped.
+    pub fn decode_attribute(&self, attribute: AttributeName) -> Result<Option<Vec<Vec<f32>>>> {
+        match self {
+            VertexBuffer::Attribute(vertex_attribute) => {
+                if vertex_attribute.attribute_name != attribute {
+                    return Ok(None);
+                }
+                
+                let raw_bytes: &[u8] = vertex_attribute.raw_bytes.as_deref().unwrap_or_default();
+                let stride = vertex_attribute.byte_size();
+                
+                Ok(Some(vertex_attribute.decode(raw_bytes, stride, 0)?))
+            },
+            VertexBuffer::Interleaved(interleaved) => {
+                let attributes: &[VertexBufferAttribute] = interleaved.attributes.as_deref().unwrap_or_default();
+                let raw_bytes: &[u8] = interleaved.raw_bytes.as_deref().unwrap_or_default();
+                
+                let Some(attr) = attributes.iter().find(|attr| attr.attribute_name == attribute) else {
+                    return Ok(None);
+                };
+                
+                let stride = interleaved.vertex_stride as usize;
+                let offset = attr.offset as usize;
+                
+                if !raw_bytes.is_empty() && (stride == 0 || !raw_bytes.len().is_multiple_of(stride)) {
+                    return Err(malformed!("Interleaved vertex buffer of {} bytes doesn't divide into vertices of {} bytes", raw_bytes.len(), stride));
+                }
+                
+                if offset + attr.byte_size() > stride {
+                    return Err(malformed!("{:?} at offset {} with {} bytes doesn't fit into a vertex of {} bytes", attr.attribute_name, offset, attr.byte_size(), stride));
+                }
+                
+                Ok(Some(attr.decode(raw_bytes, stride, offset)?))
+            },
+            VertexBuffer::Fixed(_) => Ok(None),
+        }
     }
 }
 
 impl CgfxCollectionValue for VertexBuffer {
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         Self::from_reader(reader)
     }
 
-    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, _: &mut WriteContext) -> Result<()> {
-        self.to_writer(writer)
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct VertexBufferAttribute {
     pub vertex_buffer_common: VertexBufferCommon,
     
     pub buffer_obj: u32,
     pub location_flag: u32,
     
+    #[serde(with = "super::blob::option")]
     pub raw_bytes: Option<Vec<u8>>,
     
     pub location_ptr: u32,
@@ -579,7 +1245,7 @@ pub struct VertexBufferAttribute {
 }
 
 impl VertexBufferAttribute {
-    fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let vertex_buffer_common = VertexBufferCommon::read(reader)?;
         let buffer_obj = reader.read_u32::<LittleEndian>()?;
         let location_flag = reader.read_u32::<LittleEndian>()?;
@@ -591,6 +1257,11 @@ impl VertexBufferAttribute {
         
         let format = GlDataType::read(reader)?;
         let elements = reader.read_u32::<LittleEndian>()?;
+        
+        if !(1..=4).contains(&elements) {
+            return Err(malformed!("Vertex attributes have 1 to 4 elements, found {}", elements));
+        }
+        
         let scale = reader.read_f32::<LittleEndian>()?;
         let offset = reader.read_u32::<LittleEndian>()?;
         
@@ -608,18 +1279,78 @@ impl VertexBufferAttribute {
         })
     }
     
-    fn to_writer(&self, _writer: &mut Cursor<&mut Vec<u8>>) -> Result<()> {
-        todo!()
+    fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.vertex_buffer_common.write(writer)?;
+        writer.write_u32::<LittleEndian>(self.buffer_obj)?;
+        writer.write_u32::<LittleEndian>(self.location_flag)?;
+        
+        write_image_section_list(writer, ctx, self.raw_bytes.as_deref())?;
+        
+        writer.write_u32::<LittleEndian>(self.location_ptr)?;
+        writer.write_u32::<LittleEndian>(self.memory_area)?;
+        
+        self.format.write(writer)?;
+        writer.write_u32::<LittleEndian>(self.elements)?;
+        writer.write_f32::<LittleEndian>(self.scale)?;
+        writer.write_u32::<LittleEndian>(self.offset)?;
+        
+        Ok(())
+    }
+    
+    /// Size of this attribute in bytes for a single vertex.
+    pub fn byte_size(&self) -> usize {
+        (self.format.byte_size() * self.elements) as usize
+    }
+    
+    /// Decodes this attribute out of a buffer of vertices that are `stride` bytes large,
+    /// where the attribute starts `offset` bytes into each vertex.
+    pub fn decode(&self, raw_bytes: &[u8], stride: usize, offset: usize) -> Result<Vec<Vec<f32>>> {
+        if stride == 0 {
+            return Ok(Vec::new());
+        }
+        
+        let vertex_count = raw_bytes.len() / stride;
+        let mut reader = Cursor::new(raw_bytes);
+        
+        (0..vertex_count)
+            .map(|i| {
+                reader.set_position((i * stride + offset) as u64);
+                
+                (0..self.elements)
+                    .map(|_| Ok(self.format.read_component(&mut reader)? * self.scale))
+                    .collect::<Result<Vec<f32>>>()
+            })
+            .collect()
+    }
+    
+    /// Counterpart of `decode`, encodes `values` into `raw_bytes` (which has to be large enough already)
+    /// after dividing them by `scale`. Fails if a value doesn't fit into `format`.
+    pub fn encode(&self, values: &[Vec<f32>], raw_bytes: &mut [u8], stride: usize, offset: usize) -> Result<()> {
+        let mut writer = Cursor::new(raw_bytes);
+        
+        for (i, value) in values.iter().enumerate() {
+            if value.len() != self.elements as usize {
+                return Err(invalid_input!("Expected {} components for {:?}, got {}", self.elements, self.attribute_name, value.len()));
+            }
+            
+            writer.set_position((i * stride + offset) as u64);
+            
+            for component in value {
+                self.format.write_component(&mut writer, component / self.scale)?;
+            }
+        }
+        
+        Ok(())
     }
 }
 
 impl CgfxCollectionValue for VertexBufferAttribute {
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         Self::from_reader(reader)
     }
 
-    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, _: &mut WriteContext) -> Result<()> {
-        self.to_writer(writer)
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
     }
 }
 
@@ -637,13 +1368,14 @@ impl DerefMut for VertexBufferAttribute {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct VertexBufferInterleaved {
     pub vertex_buffer_common: VertexBufferCommon,
     
     pub buffer_obj: u32,
     pub location_flag: u32,
     
+    #[serde(with = "super::blob::option")]
     pub raw_bytes: Option<Vec<u8>>,
     
     pub location_ptr: u32,
@@ -654,7 +1386,7 @@ pub struct VertexBufferInterleaved {
 }
 
 impl VertexBufferInterleaved {
-    fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let vertex_buffer_common = VertexBufferCommon::read(reader)?;
         let buffer_obj = reader.read_u32::<LittleEndian>()?;
         let location_flag = reader.read_u32::<LittleEndian>()?;
@@ -678,9 +1410,32 @@ impl VertexBufferInterleaved {
             attributes,
         })
     }
+    
+    fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.vertex_buffer_common.write(writer)?;
+        writer.write_u32::<LittleEndian>(self.buffer_obj)?;
+        writer.write_u32::<LittleEndian>(self.location_flag)?;
+        
+        write_image_section_list(writer, ctx, self.raw_bytes.as_deref())?;
+        
+        writer.write_u32::<LittleEndian>(self.location_ptr)?;
+        writer.write_u32::<LittleEndian>(self.memory_area)?;
+        
+        writer.write_u32::<LittleEndian>(self.vertex_stride)?;
+        let attribute_pointer_location = write_list_placeholder(writer, self.attributes.as_ref().map_or(0, Vec::len))?;
+        
+        if let Some(attributes) = &self.attributes {
+            write_pointer_list(writer, ctx, attribute_pointer_location, attributes, |attribute, writer, ctx| {
+                writer.write_u32::<LittleEndian>(0x40000001)?;
+                attribute.to_writer(writer, ctx)
+            })?;
+        }
+        
+        Ok(())
+    }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct VertexBufferFixed {
     pub vertex_buffer_common: VertexBufferCommon,
     
@@ -691,10 +1446,15 @@ pub struct VertexBufferFixed {
 }
 
 impl VertexBufferFixed {
-    pub fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let vertex_buffer_common = VertexBufferCommon::read(reader)?;
         let format = GlDataType::read(reader)?;
         let elements = reader.read_u32::<LittleEndian>()?;
+        
+        if !(1..=4).contains(&elements) {
+            return Err(malformed!("Vertex attributes have 1 to 4 elements, found {}", elements));
+        }
+        
         let scale = reader.read_f32::<LittleEndian>()?;
         let vector: Option<Vec<f32>> = read_inline_list(reader)?;
 
@@ -706,5 +1466,19 @@ impl VertexBufferFixed {
             vector,
         })
     }
+    
+    fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.vertex_buffer_common.write(writer)?;
+        self.format.write(writer)?;
+        writer.write_u32::<LittleEndian>(self.elements)?;
+        writer.write_f32::<LittleEndian>(self.scale)?;
+        
+        let vector_pointer_location = write_list_placeholder(writer, self.vector.as_ref().map_or(0, Vec::len))?;
+        
+        if let Some(vector) = &self.vector {
+            write_inline_list(writer, ctx, vector_pointer_location, vector)?;
+        }
+        
+        Ok(())
+    }
 }
-
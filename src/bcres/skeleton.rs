@@ -0,0 +1,377 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use binrw::{BinRead, BinWrite, Endian};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use na::Matrix3x4;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{invalid_input, malformed},
+    scoped_reader_pos,
+    util::{math::invert_transform, pointer::Pointer},
+    write_at_pointer, Result,
+};
+
+use super::{
+    bcres::{CgfxCollectionValue, CgfxDict, WriteContext},
+    metadata::CgfxMetadata,
+    util::{
+        brw_read_string, write_list_placeholder, write_relative_pointer_here, write_string_pointer,
+        CgfxObjectHeader, CgfxTransform,
+    },
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CgfxSkeleton {
+    pub cgfx_object_header: CgfxObjectHeader,
+    
+    pub bones: Option<CgfxDict<CgfxBone>>,
+    /// Index of the root bone in `bones`
+    pub root_bone: Option<u32>,
+    pub scaling_rule: u32,
+    pub flags: u32,
+}
+
+/// A single bone of a skeleton. Bones reference each other by their position in the
+/// bones dict of the skeleton (not counting the root node of the dict).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CgfxBone {
+    pub name: Option<String>,
+    pub flags: u32,
+    pub index: u32,
+    pub parent_index: i32,
+    
+    pub parent: Option<u32>,
+    pub child: Option<u32>,
+    pub previous_sibling: Option<u32>,
+    pub next_sibling: Option<u32>,
+    
+    pub transform: CgfxTransform,
+    pub inverse_world_transform: Matrix3x4<f32>,
+    pub billboard_mode: u32,
+    
+    pub metadata: Option<CgfxDict<CgfxMetadata>>,
+}
+
+/// Offsets of the parent, child, previous sibling and next sibling of a bone.
+type BoneLinks = [Option<Pointer>; 4];
+
+/// Reads a relative pointer which, unlike most others, can point backwards.
+fn read_signed_relative_pointer<R: Read + Seek>(reader: &mut R) -> Result<Option<Pointer>> {
+    let reader_pos = i64::try_from(reader.stream_position()?)?;
+    let value = reader.read_i32::<LittleEndian>()?;
+    
+    if value != 0 {
+        Ok(Some(Pointer::from(u32::try_from(reader_pos + i64::from(value))?)))
+    } else {
+        Ok(None)
+    }
+}
+
+fn write_signed_relative_pointer(writer: &mut Cursor<&mut Vec<u8>>, location: Pointer, target: Pointer) -> Result<()> {
+    let relative_offset = i32::try_from(i64::from(u32::from(target)) - i64::from(u32::from(location)))?;
+    write_at_pointer(writer, location, relative_offset as u32)
+}
+
+impl CgfxSkeleton {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let discriminant = reader.read_u32::<LittleEndian>()?;
+        
+        if discriminant != 0x02000000 {
+            return Err(malformed!("Invalid skeleton type discriminant {:x}", discriminant));
+        }
+        
+        let cgfx_object_header = CgfxObjectHeader::read(reader)?;
+        
+        // bones
+        let bone_count = reader.read_u32::<LittleEndian>()?;
+        let bones_ptr = Pointer::read_relative(reader)?;
+        let mut bone_offsets: Vec<(Pointer, BoneLinks)> = Vec::new();
+        
+        let mut bones = if let Some(bones_ptr) = bones_ptr {
+            scoped_reader_pos!(reader);
+            reader.seek(SeekFrom::Start(bones_ptr.into()))?;
+            
+            let dict = CgfxDict::from_reader_with(reader, |reader| {
+                let offset = Pointer::try_from(reader.stream_position()?)?;
+                let (bone, links) = CgfxBone::read_with_links(reader)?;
+                
+                bone_offsets.push((offset, links));
+                Ok(bone)
+            })?;
+            
+            if dict.values_count != bone_count {
+                return Err(malformed!("Skeleton {:?} has {} bones, expected {}", cgfx_object_header.name, dict.values_count, bone_count));
+            }
+            
+            Some(dict)
+        } else {
+            None
+        };
+        
+        let root_bone_ptr = read_signed_relative_pointer(reader)?;
+        let scaling_rule = reader.read_u32::<LittleEndian>()?;
+        let flags = reader.read_u32::<LittleEndian>()?;
+        
+        // resolve bone pointers to indices
+        let bone_index = |pointer: Option<Pointer>| -> Result<Option<u32>> {
+            pointer
+                .map(|pointer| bone_offsets.iter()
+                    .position(|(offset, _)| *offset == pointer)
+                    .map(|index| index as u32)
+                    .ok_or_else(|| malformed!("Skeleton {:?} references a bone outside of its bones dict", cgfx_object_header.name)))
+                .transpose()
+        };
+        
+        let root_bone = bone_index(root_bone_ptr)?;
+        
+        if let Some(bones) = &mut bones {
+            let bone_values = bones.nodes.iter_mut().filter_map(|node| node.value.as_mut());
+            
+            for (bone, (_, [parent, child, previous_sibling, next_sibling])) in bone_values.zip(&bone_offsets) {
+                bone.parent = bone_index(*parent)?;
+                bone.child = bone_index(*child)?;
+                bone.previous_sibling = bone_index(*previous_sibling)?;
+                bone.next_sibling = bone_index(*next_sibling)?;
+            }
+        }
+        
+        Ok(Self {
+            cgfx_object_header,
+            bones,
+            root_bone,
+            scaling_rule,
+            flags,
+        })
+    }
+    
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        writer.write_u32::<LittleEndian>(0x02000000)?;
+        
+        let header_offset = Pointer::try_from(&writer)?;
+        
+        if let Some(name) = &self.cgfx_object_header.name {
+            ctx.add_string(name)?;
+            ctx.add_string_reference(header_offset + 8, name.clone());
+        }
+        
+        self.cgfx_object_header.write(writer)?;
+        
+        let bones_pointer_location = write_list_placeholder(writer,
+            self.bones.as_ref().map_or(0, |bones| bones.values_count as usize))?;
+        
+        let root_bone_pointer_location = Pointer::try_from(&writer)?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_u32::<LittleEndian>(self.scaling_rule)?;
+        writer.write_u32::<LittleEndian>(self.flags)?;
+        
+        if let Some(bones) = &self.bones {
+            write_relative_pointer_here(writer, bones_pointer_location)?;
+            let bone_offsets = bones.to_writer_with_offsets(writer, ctx)?;
+            
+            // bones point to each other, which is only possible once all of them are written
+            let bone_offset = |index: Option<u32>| -> Result<Option<Pointer>> {
+                index
+                    .map(|index| bone_offsets.get(index as usize).copied()
+                        .ok_or_else(|| malformed!("Bone index {} is out of bounds", index)))
+                    .transpose()
+            };
+            
+            let bone_values = bones.nodes.iter().filter_map(|node| node.value.as_ref());
+            
+            for (bone, offset) in bone_values.zip(&bone_offsets) {
+                let links = [bone.parent, bone.child, bone.previous_sibling, bone.next_sibling];
+                
+                for (i, link) in links.into_iter().enumerate() {
+                    if let Some(target) = bone_offset(link)? {
+                        let location = *offset + 16 + 4 * i as u32;
+                        write_signed_relative_pointer(writer, location, target)?;
+                    }
+                }
+            }
+            
+            if let Some(root_bone) = bone_offset(self.root_bone)? {
+                write_signed_relative_pointer(writer, root_bone_pointer_location, root_bone)?;
+            }
+        }
+        
+        self.cgfx_object_header.write_metadata(writer, ctx, header_offset)?;
+        
+        Ok(())
+    }
+    
+    /// The bones of this skeleton in the order bones reference each other in.
+    pub fn bone_list(&self) -> Vec<&CgfxBone> {
+        self.bones.iter().flat_map(CgfxDict::values).collect()
+    }
+    
+    pub fn bone(&self, index: u32) -> Option<&CgfxBone> {
+        self.bone_list().get(index as usize).copied()
+    }
+    
+    pub fn bone_mut(&mut self, index: u32) -> Option<&mut CgfxBone> {
+        self.bones.iter_mut().flat_map(CgfxDict::values_mut).nth(index as usize)
+    }
+    
+    /// Indices of the bones directly below the bone at `index`, or of the
+    /// bones without a parent if `index` is `None`.
+    pub fn children(&self, index: Option<u32>) -> Vec<u32> {
+        self.bone_list().iter()
+            .enumerate()
+            .filter(|(_, bone)| bone.parent == index)
+            .map(|(i, _)| i as u32)
+            .collect()
+    }
+    
+    /// Indices of all bones, ordered so that every bone comes after its parent.
+    pub fn traverse(&self) -> Result<Vec<u32>> {
+        let bone_count = self.bone_list().len();
+        let mut order = Vec::with_capacity(bone_count);
+        let mut stack = self.children(None);
+        stack.reverse();
+        
+        // every bone has a single parent, so a bone can only be unreachable, not visited twice
+        while let Some(index) = stack.pop() {
+            order.push(index);
+            stack.extend(self.children(Some(index)).into_iter().rev());
+        }
+        
+        if order.len() != bone_count {
+            return Err(malformed!("Skeleton {:?} has {} bones, but only {} can be reached from its root bones, the others are linked in a cycle",
+                self.cgfx_object_header.name, bone_count, order.len()));
+        }
+        
+        Ok(order)
+    }
+    
+    /// Recomputes the local and world transforms of all bones after editing their
+    /// scale, rotation or translation. Inverse world transforms are left alone,
+    /// as they describe the pose the vertices were bound to.
+    pub fn recompute_transforms(&mut self) -> Result<()> {
+        for index in self.traverse()? {
+            let parent_world_transform = self.bone(index)
+                .and_then(|bone| bone.parent)
+                .and_then(|parent| self.bone(parent))
+                .map(|parent| parent.transform.world_transform);
+            
+            if let Some(bone) = self.bone_mut(index) {
+                bone.transform.recompute(parent_world_transform.as_ref());
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Binds the vertices to the current pose, by making the inverse world
+    /// transforms of all bones match their world transforms again.
+    pub fn recompute_inverse_world_transforms(&mut self) -> Result<()> {
+        for bone in self.bones.iter_mut().flat_map(CgfxDict::values_mut) {
+            bone.inverse_world_transform = invert_transform(&bone.transform.world_transform)
+                .ok_or_else(|| invalid_input!("World transform of bone {:?} can't be inverted", bone.name))?;
+        }
+        
+        Ok(())
+    }
+}
+
+impl CgfxBone {
+    /// Reads a bone, returning the offsets of the bones it links to separately,
+    /// because they can only be resolved once the whole skeleton is read.
+    fn read_with_links<R: Read + Seek>(reader: &mut R) -> Result<(Self, BoneLinks)> {
+        let name = brw_read_string(reader, Endian::Little, ())?;
+        let flags = reader.read_u32::<LittleEndian>()?;
+        let index = reader.read_u32::<LittleEndian>()?;
+        let parent_index = reader.read_i32::<LittleEndian>()?;
+        
+        let links = [
+            read_signed_relative_pointer(reader)?,
+            read_signed_relative_pointer(reader)?,
+            read_signed_relative_pointer(reader)?,
+            read_signed_relative_pointer(reader)?,
+        ];
+        
+        let transform = CgfxTransform::read(reader)?;
+        
+        let inverse_world_transform_numbers = (0..12)
+            .map(|_| reader.read_f32::<LittleEndian>())
+            .collect::<Result<Vec<f32>, _>>()?;
+        let inverse_world_transform = Matrix3x4::from_row_slice(&inverse_world_transform_numbers);
+        
+        let billboard_mode = reader.read_u32::<LittleEndian>()?;
+        
+        let metadata_count = reader.read_u32::<LittleEndian>()?;
+        let metadata_ptr = Pointer::read_relative(reader)?;
+        
+        let metadata = if let Some(metadata_ptr) = metadata_ptr {
+            scoped_reader_pos!(reader);
+            reader.seek(SeekFrom::Start(metadata_ptr.into()))?;
+            
+            let dict = CgfxDict::from_reader_with(reader, CgfxMetadata::from_reader)?;
+            
+            if dict.values_count != metadata_count {
+                return Err(malformed!("Metadata of bone {:?} has {} entries, expected {}", name, dict.values_count, metadata_count));
+            }
+            
+            Some(dict)
+        } else {
+            None
+        };
+        
+        let bone = Self {
+            name,
+            flags,
+            index,
+            parent_index,
+            parent: None,
+            child: None,
+            previous_sibling: None,
+            next_sibling: None,
+            transform,
+            inverse_world_transform,
+            billboard_mode,
+            metadata,
+        };
+        
+        Ok((bone, links))
+    }
+    
+    /// Writes the bone without the links to other bones, which are patched in by the skeleton.
+    pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        write_string_pointer(writer, ctx, self.name.as_deref())?;
+        writer.write_u32::<LittleEndian>(self.flags)?;
+        writer.write_u32::<LittleEndian>(self.index)?;
+        writer.write_i32::<LittleEndian>(self.parent_index)?;
+        
+        for _ in 0..4 {
+            writer.write_u32::<LittleEndian>(0)?;
+        }
+        
+        self.transform.write(writer)?;
+        
+        for value in self.inverse_world_transform.transpose().iter() {
+            writer.write_f32::<LittleEndian>(*value)?;
+        }
+        
+        writer.write_u32::<LittleEndian>(self.billboard_mode)?;
+        
+        let metadata_pointer_location = write_list_placeholder(writer,
+            self.metadata.as_ref().map_or(0, |metadata| metadata.values_count as usize))?;
+        
+        if let Some(metadata) = &self.metadata {
+            write_relative_pointer_here(writer, metadata_pointer_location)?;
+            metadata.to_writer(writer, ctx)?;
+        }
+        
+        Ok(())
+    }
+}
+
+impl CgfxCollectionValue for CgfxBone {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Ok(Self::read_with_links(reader)?.0)
+    }
+    
+    fn write_dict_value(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
+        self.to_writer(writer, ctx)
+    }
+}
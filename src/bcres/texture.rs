@@ -3,15 +3,20 @@ use std::{
     io::{Cursor, Read, Seek, SeekFrom},
 };
 
-use anyhow::{Error, Result};
 use binrw::{BinRead, BinWrite};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize};
 
-use crate::{scoped_reader_pos, util::pointer::Pointer};
+use crate::{
+    error::{malformed, unsupported},
+    scoped_reader_pos,
+    util::pointer::Pointer,
+    Error, Result,
+};
 
 use super::{
     bcres::{CgfxCollectionValue, WriteContext},
+    image_codec::{decode_swizzled_buffer, encode_swizzled_buffer, to_png, RgbaColor},
     util::{brw_relative_pointer, CgfxObjectHeader},
 };
 
@@ -53,9 +58,29 @@ impl PicaTextureFormat {
             PicaTextureFormat::ETC1A4 => 8,
         }
     }
+    
+    /// The values of `CgfxTextureCommon::gl_format` and `gl_type` for this format.
+    pub fn gl_format_and_type(&self) -> (u32, u32) {
+        match self {
+            PicaTextureFormat::RGBA8 => (0x6752, 0x1401),
+            PicaTextureFormat::RGB8 => (0x6754, 0x1401),
+            PicaTextureFormat::RGBA5551 => (0x6752, 0x8034),
+            PicaTextureFormat::RGB565 => (0x6754, 0x8363),
+            PicaTextureFormat::RGBA4 => (0x6752, 0x8033),
+            PicaTextureFormat::LA8 => (0x6758, 0x1401),
+            PicaTextureFormat::HiLo8 => (0x6759, 0x1401),
+            PicaTextureFormat::L8 => (0x6757, 0x1401),
+            PicaTextureFormat::A8 => (0x6756, 0x1401),
+            PicaTextureFormat::LA4 => (0x6758, 0x6760),
+            PicaTextureFormat::L4 => (0x6757, 0x6761),
+            PicaTextureFormat::A4 => (0x6756, 0x6761),
+            PicaTextureFormat::ETC1 => (0x675A, 0),
+            PicaTextureFormat::ETC1A4 => (0x675B, 0),
+        }
+    }
 }
 
-#[derive(Clone, PartialEq, Eq, BinRead, BinWrite)]
+#[derive(Clone, PartialEq, Eq, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little)]
 #[br(assert(location_ptr == 0, "ImageData has location_ptr {}", location_ptr))]
 pub struct ImageData {
@@ -63,11 +88,13 @@ pub struct ImageData {
     pub width: u32,
     
     #[brw(ignore)]
+    #[serde(with = "super::blob")]
     pub image_bytes: Vec<u8>,
     
     buffer_length: u32,
     #[br(parse_with = brw_relative_pointer)]
     #[bw(map = |_| 0u32)]
+    #[serde(skip)]
     buffer_pointer: Option<Pointer>,
     
     pub dynamic_alloc: u32,
@@ -92,7 +119,7 @@ impl Debug for ImageData {
     }
 }
 
-#[derive(Debug, Clone, BinRead, BinWrite, PartialEq)]
+#[derive(Debug, Clone, BinRead, BinWrite, PartialEq, Serialize, Deserialize)]
 #[brw(little)]
 pub struct CgfxTextureCommon {
     // cgfx object header
@@ -109,13 +136,13 @@ pub struct CgfxTextureCommon {
     pub texture_format: PicaTextureFormat,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CgfxTexture {
     Cube(CgfxTextureCommon, Vec<ImageData>),
     Image(CgfxTextureCommon, Option<ImageData>),
 }
 
-fn image_data(reader: &mut Cursor<&[u8]>) -> Result<Option<ImageData>> {
+fn image_data<R: Read + Seek>(reader: &mut R) -> Result<Option<ImageData>> {
     let image_data_pointer = Pointer::read(reader)?;
     
     let data = image_data_pointer
@@ -124,10 +151,19 @@ fn image_data(reader: &mut Cursor<&[u8]>) -> Result<Option<ImageData>> {
             reader.seek(SeekFrom::Current(i64::from(pointer) - 4))?;
             
             let mut data = ImageData::read(reader)?;
-            reader.set_position(data.buffer_pointer.unwrap().into());
+            let buffer_pointer = data.buffer_pointer
+                .ok_or_else(|| malformed!("Image data without an image buffer"))?;
+            reader.seek(SeekFrom::Start(buffer_pointer.into()))?;
+            
+            // read through `take` so a broken length can't allocate gigabytes up front
+            let mut image_bytes: Vec<u8> = Vec::new();
+            reader.take(data.buffer_length.into()).read_to_end(&mut image_bytes)?;
+            
+            if image_bytes.len() != data.buffer_length as usize {
+                return Err(malformed!("Image buffer of {:#x} bytes ends early at {:#x} bytes",
+                    data.buffer_length, image_bytes.len()));
+            }
             
-            let mut image_bytes: Vec<u8> = vec![0; data.buffer_length.try_into()?];
-            reader.read_exact(&mut image_bytes)?;
             data.image_bytes = image_bytes;
             
             Ok::<ImageData, Error>(data)
@@ -138,7 +174,7 @@ fn image_data(reader: &mut Cursor<&[u8]>) -> Result<Option<ImageData>> {
 }
 
 impl CgfxTexture {
-    pub fn from_reader(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let texture_type_discriminant = reader.read_u32::<LittleEndian>()?;
         
         let common = CgfxTextureCommon::read(reader)?;
@@ -148,19 +184,115 @@ impl CgfxTexture {
                 let mut images = Vec::with_capacity(6);
                 
                 for _ in 0..6 {
-                    images.push(image_data(reader)?.unwrap());
+                    images.push(image_data(reader)?.ok_or_else(|| malformed!("Cube texture is missing one of its faces"))?);
                 }
                 
                 images
             }),
             0x20000011 => CgfxTexture::Image(common, image_data(reader)?),
             
-            _ => return Err(Error::msg(format!("Invalid Texture discriminant {:x}", texture_type_discriminant)))
+            _ => return Err(malformed!("Invalid Texture discriminant {:x}", texture_type_discriminant))
         };
         
         Ok(result)
     }
     
+    /// Creates a new 2D texture without mipmaps from `pixels`, which are given row by row starting at the top.
+    /// Fails for formats which can't be encoded yet, like ETC1.
+    pub fn from_rgba(name: &str, width: u32, height: u32, format: PicaTextureFormat, pixels: &[RgbaColor]) -> Result<Self> {
+        let image_bytes = encode_swizzled_buffer(pixels, format, width, height)?;
+        let (gl_format, gl_type) = format.gl_format_and_type();
+        
+        let common = CgfxTextureCommon {
+            cgfx_object_header: CgfxObjectHeader {
+                magic: "TXOB".to_string(),
+                revision: 0,
+                name: Some(name.to_string()),
+                metadata: None,
+            },
+            height,
+            width,
+            gl_format,
+            gl_type,
+            mipmap_size: 1,
+            texture_obj: 0,
+            location_flag: 0,
+            texture_format: format,
+        };
+        
+        let image = ImageData {
+            height,
+            width,
+            buffer_length: image_bytes.len().try_into()?,
+            image_bytes,
+            buffer_pointer: None,
+            dynamic_alloc: 0,
+            bits_per_pixel: format.get_bpp(),
+            location_ptr: 0,
+            memory_area: 0,
+        };
+        
+        Ok(CgfxTexture::Image(common, Some(image)))
+    }
+    
+    pub fn common(&self) -> &CgfxTextureCommon {
+        match self {
+            CgfxTexture::Cube(common, _) => common,
+            CgfxTexture::Image(common, _) => common,
+        }
+    }
+    
+    /// Replaces the image of this texture, keeping its format if it can be encoded and using RGBA8 otherwise.
+    /// The texture loses its mipmaps, as they aren't generated.
+    pub fn replace_image(&mut self, colors: &[RgbaColor], width: u32, height: u32) -> Result<()> {
+        let CgfxTexture::Image(common, image) = self else {
+            return Err(unsupported!("Only the image of 2D textures can be replaced"));
+        };
+        
+        let mut texture_format = common.texture_format;
+        let image_bytes = match encode_swizzled_buffer(colors, texture_format, width, height) {
+            Ok(image_bytes) => image_bytes,
+            Err(_) => {
+                texture_format = PicaTextureFormat::RGBA8;
+                encode_swizzled_buffer(colors, texture_format, width, height)?
+            },
+        };
+        
+        let (gl_format, gl_type) = texture_format.gl_format_and_type();
+        
+        common.width = width;
+        common.height = height;
+        common.gl_format = gl_format;
+        common.gl_type = gl_type;
+        common.mipmap_size = 1;
+        common.texture_format = texture_format;
+        
+        let image = image.as_mut()
+            .ok_or_else(|| malformed!("Texture {:?} has no image data", common.cgfx_object_header.name))?;
+        
+        image.width = width;
+        image.height = height;
+        image.bits_per_pixel = texture_format.get_bpp();
+        image.buffer_length = image_bytes.len().try_into()?;
+        image.image_bytes = image_bytes;
+        
+        Ok(())
+    }
+    
+    /// Decodes the image of this texture (or the first face of a cube map) into a PNG file.
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let image = match self {
+            CgfxTexture::Cube(_, images) => images.first(),
+            CgfxTexture::Image(_, image) => image.as_ref(),
+        };
+        
+        let image = image.ok_or_else(|| malformed!("Texture {:?} has no image data", self.common().cgfx_object_header.name))?;
+        let CgfxTextureCommon { texture_format, width, height, .. } = *self.common();
+        
+        let decoded = decode_swizzled_buffer(&image.image_bytes, texture_format, width, height)?;
+        to_png(&decoded, width, height)
+    }
+    
     pub fn to_writer(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext) -> Result<()> {
         // write discriminant
         let discriminant: u32 = match self {
@@ -178,7 +310,6 @@ impl CgfxTexture {
         
         let common_offset = Pointer::try_from(&writer)?;
         let name_offset = common_offset + 8;
-        assert!(common.cgfx_object_header.metadata_pointer == None);
         
         if let Some(name) = &common.cgfx_object_header.name {
             ctx.add_string(name)?;
@@ -189,7 +320,7 @@ impl CgfxTexture {
         
         // write texture specific stuff
         match self {
-            CgfxTexture::Cube(_, _images) => todo!(),
+            CgfxTexture::Cube(_, _images) => return Err(unsupported!("Writing cube textures is not implemented yet")),
             CgfxTexture::Image(_, image) => {
                 writer.write_u32::<LittleEndian>(4)?;
                 
@@ -205,6 +336,8 @@ impl CgfxTexture {
             },
         }
         
+        common.cgfx_object_header.write_metadata(writer, ctx, common_offset)?;
+        
         Ok(())
     }
     
@@ -238,7 +371,7 @@ impl CgfxTexture {
 }
 
 impl CgfxCollectionValue for CgfxTexture {
-    fn read_dict_value(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+    fn read_dict_value<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         Self::from_reader(reader)
     }
     
@@ -5,17 +5,29 @@ use std::{
     str::from_utf8,
 };
 
-use anyhow::Result;
 use binrw::{
     meta::{EndianKind, ReadEndian, WriteEndian},
     parser, writer, BinRead, BinResult, BinWrite, Endian,
 };
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use na::Matrix3x4;
+use serde::{Deserialize, Serialize};
 
-use crate::{scoped_reader_pos, util::{math::Vec3, pointer::Pointer}};
+use crate::{
+    error::{invalid_input, malformed, unsupported, ResultExt},
+    scoped_reader_pos,
+    util::{
+        math::{matrix_to_srt, multiply_transforms, srt_to_matrix, Vec3},
+        pointer::Pointer,
+    },
+    write_at_pointer, Error, Result,
+};
 
-use super::bcres::{CgfxCollectionValue, CgfxDict};
+use super::{
+    animation::AnimGroup,
+    bcres::{CgfxCollectionValue, CgfxDict, WriteContext},
+    metadata::CgfxMetadata,
+};
 
 #[allow(path_statements)] // to disable warning on `endian;`
 #[parser(reader, endian)]
@@ -25,15 +37,23 @@ pub fn brw_read_4_byte_string() -> BinResult<String> {
     endian;
     
     let mut bytes: [u8; 4] = [0; 4];
-    reader.read(&mut bytes)?;
+    reader.read_exact(&mut bytes)?;
     
-    Ok(from_utf8(&bytes).unwrap().to_string()) // ughhh error handling is so painful with binrw
+    from_utf8(&bytes)
+        .map(str::to_string)
+        .map_err(|err| binrw::Error::Custom {
+            pos: reader.stream_position().unwrap_or_default() - 4,
+            err: Box::new(Error::from(err)),
+        })
 }
 
 #[writer(writer, endian)]
 pub fn brw_write_4_byte_string(string: &String) -> BinResult<()> {
     let bytes = string.as_bytes();
-    let out = u32::from_le_bytes(bytes.try_into().unwrap()); // unwrap because BinResult is a pain
+    let out = u32::from_le_bytes(bytes.try_into().map_err(|_| binrw::Error::Custom {
+        pos: writer.stream_position().unwrap_or_default(),
+        err: Box::new(invalid_input!("Magic {:?} isn't 4 bytes long", string)),
+    })?);
     
     out.write_options(writer, endian, ())?;
     Ok(())
@@ -43,7 +63,7 @@ fn read_string(read: &mut impl Read) -> Result<String> {
     let mut string_buffer = Vec::new();
     
     loop {
-        let b = read.read_u8().unwrap();
+        let b = read.read_u8()?;
         
         if b != 0 {
             string_buffer.push(b);
@@ -68,7 +88,7 @@ pub fn brw_read_string() -> BinResult<Option<String>> {
     
     let string = read_string(reader)
         .map_err(|err| binrw::Error::Custom {
-            pos: reader.stream_position().unwrap(),
+            pos: reader_pos + pointer,
             err: Box::new(err),
         })?;
     
@@ -92,36 +112,86 @@ pub fn brw_relative_pointer() -> BinResult<Option<Pointer>> {
         return Ok(None);
     }
     
-    Ok(Some(Pointer::from(reader_pos + pointer)))
+    let target = u32::try_from(reader_pos + pointer)
+        .map_err(|err| binrw::Error::Custom {
+            pos: reader_pos,
+            err: Box::new(err),
+        })?;
+    
+    Ok(Some(Pointer::from(target)))
 }
 
-pub fn read_pointer_list<T: CgfxCollectionValue>(reader: &mut Cursor<&[u8]>) -> Result<Option<Vec<T>>> {
+/// Fails if `count` elements of at least `element_size` bytes don't fit in the rest
+/// of the stream, so a count read from a file can't make us allocate huge lists.
+pub fn check_count<R: Read + Seek>(reader: &mut R, count: u32, element_size: u64) -> Result<()> {
+    let position = reader.stream_position()?;
+    let length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(position))?;
+    
+    let remaining = length.saturating_sub(position);
+    
+    if u64::from(count) * element_size > remaining {
+        return Err(malformed!("Count {} at {:#x} doesn't fit in the remaining {} bytes", count, position, remaining));
+    }
+    
+    Ok(())
+}
+
+/// Reads the count and pointer of a pointer list, but only returns the offset of its pointer
+/// table and the offsets of the objects in it, for objects that can't be read on their own.
+pub fn read_pointer_table<R: Read + Seek>(reader: &mut R) -> Result<Option<(u64, Vec<u64>)>> {
+    let count = reader.read_u32::<LittleEndian>()?;
+    let list_ptr = Pointer::read_relative(reader)?;
+    
+    let Some(list_ptr) = list_ptr else {
+        return Ok(None);
+    };
+    
+    scoped_reader_pos!(reader);
+    reader.seek(SeekFrom::Start(list_ptr.into()))?;
+    check_count(reader, count, 4)?;
+    
+    let object_pointers: Vec<Option<Pointer>> = (0..count)
+        .map(|_| Pointer::read_relative(reader))
+        .collect::<Result<Vec<Option<Pointer>>>>()?;
+    
+    Ok(Some((list_ptr.into(), object_pointers.into_iter().flatten().map(u64::from).collect())))
+}
+
+pub fn read_pointer_list<T: CgfxCollectionValue, R: Read + Seek>(reader: &mut R) -> Result<Option<Vec<T>>> {
     read_pointer_list_magic(reader, None)
 }
 
-pub fn read_pointer_list_magic<T: CgfxCollectionValue>(reader: &mut Cursor<&[u8]>, magic: Option<u32>) -> Result<Option<Vec<T>>> {
+pub fn read_pointer_list_magic<T: CgfxCollectionValue, R: Read + Seek>(reader: &mut R, magic: Option<u32>) -> Result<Option<Vec<T>>> {
     let count = reader.read_u32::<LittleEndian>()?;
     let list_ptr = Pointer::read_relative(reader)?;
     
     let values: Option<Vec<T>> = if let Some(list_ptr) = list_ptr {
         scoped_reader_pos!(reader);
-        let mut values: Vec<T> = Vec::with_capacity(count as usize);
+        let mut values: Vec<T> = Vec::new();
         
         reader.seek(SeekFrom::Start(list_ptr.into()))?;
+        check_count(reader, count, 4)?;
         
         let object_pointers: Vec<Option<Pointer>> = (0..count)
             .map(|_| Pointer::read_relative(reader))
             .collect::<Result<Vec<Option<Pointer>>>>()?;
         
-        for object_pointer in object_pointers {
+        for (i, object_pointer) in object_pointers.into_iter().enumerate() {
             if let Some(object_pointer) = object_pointer {
-                reader.seek(SeekFrom::Start(object_pointer.into()))?;
+                let offset = u64::from(object_pointer);
+                
+                reader.seek(SeekFrom::Start(offset))?;
                 
                 if let Some(magic) = magic {
-                    assert!(reader.read_u32::<LittleEndian>()? == magic);
+                    let actual = reader.read_u32::<LittleEndian>()?;
+                    
+                    if actual != magic {
+                        return Err(malformed!("Expected magic {:#x}, found {:#x}", magic, actual)).at(offset, &format!("[{}]", i));
+                    }
                 }
                 
-                values.push(T::read_dict_value(reader)?);
+                values.push(T::read_dict_value(reader).at(offset, &format!("[{}]", i))?);
             }
         }
         
@@ -133,7 +203,7 @@ pub fn read_pointer_list_magic<T: CgfxCollectionValue>(reader: &mut Cursor<&[u8]
     Ok(values)
 }
 
-pub fn read_inline_list<T: CgfxCollectionValue>(reader: &mut Cursor<&[u8]>) -> Result<Option<Vec<T>>> {
+pub fn read_inline_list<T: CgfxCollectionValue, R: Read + Seek>(reader: &mut R) -> Result<Option<Vec<T>>> {
     let count = reader.read_u32::<LittleEndian>()?;
     let list_ptr = Pointer::read(reader)?;
     
@@ -141,6 +211,7 @@ pub fn read_inline_list<T: CgfxCollectionValue>(reader: &mut Cursor<&[u8]>) -> R
         scoped_reader_pos!(reader);
         
         reader.seek(SeekFrom::Current(i64::from(list_ptr) - 4))?;
+        check_count(reader, count, 1)?;
         
         let values: Vec<T> = (0..count)
             .map(|_| T::read_dict_value(reader))
@@ -154,44 +225,291 @@ pub fn read_inline_list<T: CgfxCollectionValue>(reader: &mut Cursor<&[u8]>) -> R
     Ok(values)
 }
 
-#[derive(Debug, Clone, PartialEq, BinRead, BinWrite)]
-// vvv required because brw_write_4_byte_string might panic otherwise
-#[brw(assert(magic.bytes().len() == 4, "Length of magic number {:?} must be 4 bytes", magic))]
-#[br(assert(metadata_pointer == None, "CgfxTexture {:?} has metadata {:?}", name, metadata_pointer))]
-#[brw(little)]
+/// Points the relative pointer at `pointer_location` to the current position of `writer`.
+pub fn write_relative_pointer_here(writer: &mut Cursor<&mut Vec<u8>>, pointer_location: Pointer) -> Result<()> {
+    let current_offset = Pointer::try_from(&writer)?;
+    write_at_pointer(writer, pointer_location, (current_offset - pointer_location).into())
+}
+
+/// Writes the count of a list and a zero placeholder for its pointer,
+/// returns the location of the pointer so it can be patched later.
+pub fn write_list_placeholder(writer: &mut Cursor<&mut Vec<u8>>, count: usize) -> Result<Pointer> {
+    writer.write_u32::<LittleEndian>(count.try_into()?)?;
+    
+    let pointer_location = Pointer::try_from(&writer)?;
+    writer.write_u32::<LittleEndian>(0)?;
+    
+    Ok(pointer_location)
+}
+
+/// Counterpart of `read_inline_list`. Writes `values` at the current position
+/// and points `pointer_location` to them.
+pub fn write_inline_list<T: CgfxCollectionValue>(writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext, pointer_location: Pointer, values: &[T]) -> Result<()> {
+    write_relative_pointer_here(writer, pointer_location)?;
+    
+    for value in values {
+        value.write_dict_value(writer, ctx)?;
+    }
+    
+    Ok(())
+}
+
+/// Writes an inline byte list (count and pointer) whose contents are
+/// stored in the image section, like raw vertex and index buffers.
+pub fn write_image_section_list(writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext, bytes: Option<&[u8]>) -> Result<()> {
+    writer.write_u32::<LittleEndian>(bytes.map_or(0, <[u8]>::len).try_into()?)?;
+    
+    if let Some(bytes) = bytes {
+        ctx.add_image_reference_to_current_end(Pointer::try_from(&writer)?)?;
+        ctx.append_to_image_section(bytes)?;
+    }
+    
+    writer.write_u32::<LittleEndian>(0)?;
+    Ok(())
+}
+
+/// Counterpart of `read_pointer_list`. Writes the table of object pointers at the current
+/// position, followed by the objects themselves, and points `pointer_location` to the table.
+pub fn write_pointer_list<T>(
+    writer: &mut Cursor<&mut Vec<u8>>,
+    ctx: &mut WriteContext,
+    pointer_location: Pointer,
+    values: &[T],
+    mut write_value: impl FnMut(&T, &mut Cursor<&mut Vec<u8>>, &mut WriteContext) -> Result<()>,
+) -> Result<()> {
+    write_relative_pointer_here(writer, pointer_location)?;
+    
+    let table_location = Pointer::try_from(&writer)?;
+    
+    for _ in values {
+        writer.write_u32::<LittleEndian>(0)?;
+    }
+    
+    for (i, value) in values.iter().enumerate() {
+        write_relative_pointer_here(writer, table_location + u32::try_from(i * 4)?)?;
+        write_value(value, writer, ctx)?;
+    }
+    
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CgfxObjectHeader {
-    #[br(parse_with = brw_read_4_byte_string)]
-    #[bw(write_with = brw_write_4_byte_string)]
     pub magic: String,
     pub revision: u32,
     
-    #[br(parse_with = brw_read_string)]
-    #[bw(write_with = brw_write_zero)]
     pub name: Option<String>,
-    pub metadata_count: u32,
+    pub metadata: Option<CgfxDict<CgfxMetadata>>,
+}
+
+impl CgfxObjectHeader {
+    /// Writes the metadata dict (if there is any) at the current position of `writer`
+    /// and points the metadata pointer of this header, written at `header_offset`, to it.
+    pub fn write_metadata(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext, header_offset: Pointer) -> Result<()> {
+        if let Some(metadata) = &self.metadata {
+            let metadata_pointer_location = header_offset + 16;
+            let current_offset = Pointer::try_from(&writer)?;
+            
+            write_at_pointer(writer, metadata_pointer_location, (current_offset - metadata_pointer_location).into())?;
+            metadata.to_writer(writer, ctx)?;
+        }
+        
+        Ok(())
+    }
+}
+
+impl BinRead for CgfxObjectHeader {
+    type Args<'a> = ();
+    
+    fn read_options<R: Read + Seek>(reader: &mut R, endian: Endian, _args: Self::Args<'_>) -> BinResult<Self> {
+        let magic = brw_read_4_byte_string(reader, endian, ())?;
+        let revision = u32::read_options(reader, endian, ())?;
+        
+        let name = brw_read_string(reader, endian, ())?;
+        
+        let metadata_count = u32::read_options(reader, endian, ())?;
+        let metadata_pointer_pos = reader.stream_position()?;
+        let metadata_pointer = brw_relative_pointer(reader, endian, ())?;
+        
+        let metadata = if let Some(metadata_pointer) = metadata_pointer {
+            reader.seek(SeekFrom::Start(metadata_pointer.into()))?;
+            
+            let dict = CgfxDict::from_reader_with(reader, CgfxMetadata::from_reader)
+                .map_err(|err| binrw::Error::Custom {
+                    pos: metadata_pointer_pos,
+                    err: Box::new(err),
+                })?;
+            
+            if dict.values_count != metadata_count {
+                return Err(binrw::Error::AssertFail {
+                    pos: metadata_pointer_pos,
+                    message: format!("Metadata of {:?} has {} entries, expected {}", name, dict.values_count, metadata_count),
+                });
+            }
+            
+            reader.seek(SeekFrom::Start(metadata_pointer_pos + 4))?;
+            Some(dict)
+        } else {
+            None
+        };
+        
+        Ok(Self {
+            magic,
+            revision,
+            name,
+            metadata,
+        })
+    }
+}
+
+impl ReadEndian for CgfxObjectHeader {
+    const ENDIAN: EndianKind = EndianKind::Endian(Endian::Little);
+}
+
+impl BinWrite for CgfxObjectHeader {
+    type Args<'a> = ();
     
-    #[br(map = |x: u32| Pointer::new(x))]
-    #[bw(map = |x: &Option<Pointer>| x.map_or(0, |ptr| ptr.0))]
-    pub metadata_pointer: Option<Pointer>,
+    fn write_options<W: Write + Seek>(&self, writer: &mut W, endian: Endian, _args: Self::Args<'_>) -> BinResult<()> {
+        // required because brw_write_4_byte_string might panic otherwise
+        if self.magic.len() != 4 {
+            return Err(binrw::Error::AssertFail {
+                pos: writer.stream_position()?,
+                message: format!("Length of magic number {:?} must be 4 bytes", self.magic),
+            });
+        }
+        
+        brw_write_4_byte_string(&self.magic, writer, endian, ())?;
+        self.revision.write_options(writer, endian, ())?;
+        
+        // name and metadata pointers are patched in later
+        brw_write_zero(&self.name, writer, endian, ())?;
+        self.metadata.as_ref().map_or(0, |metadata| metadata.values_count).write_options(writer, endian, ())?;
+        0u32.write_options(writer, endian, ())?;
+        
+        Ok(())
+    }
+}
+
+impl WriteEndian for CgfxObjectHeader {
+    const ENDIAN: EndianKind = EndianKind::Endian(Endian::Little);
 }
 
-#[derive(Debug, Clone, PartialEq, BinRead, BinWrite)]
-#[brw(little)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CgfxNodeHeader {
     pub branch_visible: u32,
     pub is_branch_visible: u32,
     
+    /// Children of a node are linked up by the game at runtime, so `children_pointer` is never
+    /// followed and both fields are kept as they were read. This means there is no hierarchy
+    /// between models, cameras or lights: each of them is treated as a root node. Hierarchies
+    /// stored in the file (i.e. between bones) are resolved by their owners, see `CgfxSkeleton::children`.
     pub child_count: u32,
     pub children_pointer: Option<Pointer>,
     
-    #[brw(ignore)]
-    pub anim_groups: CgfxDict<()>,
+    pub anim_groups: Option<CgfxDict<AnimGroup>>,
+}
+
+impl CgfxNodeHeader {
+    /// Writes the anim group dict (if there is any) at the current position of `writer`
+    /// and points the anim group pointer of this header, written at `header_offset`, to it.
+    pub fn write_anim_groups(&self, writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext, header_offset: Pointer) -> Result<()> {
+        if let Some(anim_groups) = &self.anim_groups {
+            let anim_group_pointer_location = header_offset + 20;
+            let current_offset = Pointer::try_from(&writer)?;
+            
+            write_at_pointer(writer, anim_group_pointer_location, (current_offset - anim_group_pointer_location).into())?;
+            anim_groups.to_writer(writer, ctx)?;
+        }
+        
+        Ok(())
+    }
+}
+
+impl BinRead for CgfxNodeHeader {
+    type Args<'a> = ();
+    
+    fn read_options<R: Read + Seek>(reader: &mut R, endian: Endian, _args: Self::Args<'_>) -> BinResult<Self> {
+        let branch_visible = u32::read_options(reader, endian, ())?;
+        let is_branch_visible = u32::read_options(reader, endian, ())?;
+        
+        let child_count = u32::read_options(reader, endian, ())?;
+        let children_pointer = Pointer::new(u32::read_options(reader, endian, ())?);
+        
+        let anim_group_count = u32::read_options(reader, endian, ())?;
+        let anim_group_pointer_pos = reader.stream_position()?;
+        let anim_group_pointer = brw_relative_pointer(reader, endian, ())?;
+        
+        let anim_groups = if let Some(anim_group_pointer) = anim_group_pointer {
+            reader.seek(SeekFrom::Start(anim_group_pointer.into()))?;
+            
+            let dict = CgfxDict::from_reader_with(reader, AnimGroup::from_reader)
+                .map_err(|err| binrw::Error::Custom {
+                    pos: anim_group_pointer_pos,
+                    err: Box::new(err),
+                })?;
+            
+            if dict.values_count != anim_group_count {
+                return Err(binrw::Error::AssertFail {
+                    pos: anim_group_pointer_pos,
+                    message: format!("Node has {} anim groups, expected {}", dict.values_count, anim_group_count),
+                });
+            }
+            
+            reader.seek(SeekFrom::Start(anim_group_pointer_pos + 4))?;
+            Some(dict)
+        } else {
+            None
+        };
+        
+        Ok(Self {
+            branch_visible,
+            is_branch_visible,
+            child_count,
+            children_pointer,
+            anim_groups,
+        })
+    }
+}
+
+impl ReadEndian for CgfxNodeHeader {
+    const ENDIAN: EndianKind = EndianKind::Endian(Endian::Little);
+}
+
+impl BinWrite for CgfxNodeHeader {
+    type Args<'a> = ();
+    
+    fn write_options<W: Write + Seek>(&self, writer: &mut W, endian: Endian, _args: Self::Args<'_>) -> BinResult<()> {
+        self.branch_visible.write_options(writer, endian, ())?;
+        self.is_branch_visible.write_options(writer, endian, ())?;
+        
+        self.child_count.write_options(writer, endian, ())?;
+        self.children_pointer.map_or(0, |pointer| pointer.0).write_options(writer, endian, ())?;
+        
+        // anim group pointer is patched in later
+        self.anim_groups.as_ref().map_or(0, |anim_groups| anim_groups.values_count).write_options(writer, endian, ())?;
+        0u32.write_options(writer, endian, ())?;
+        
+        Ok(())
+    }
+}
+
+impl WriteEndian for CgfxNodeHeader {
+    const ENDIAN: EndianKind = EndianKind::Endian(Endian::Little);
+}
+
+pub fn write_string_pointer(writer: &mut Cursor<&mut Vec<u8>>, ctx: &mut WriteContext, string: Option<&str>) -> Result<()> {
+    let location = Pointer::try_from(&writer)?;
+    writer.write_u32::<LittleEndian>(0)?;
     
-    anim_group_count: u32,
-    anim_group_pointer: Option<Pointer>,
+    if let Some(string) = string {
+        ctx.add_string(string)?;
+        ctx.add_string_reference(location, string.to_string());
+    }
+    
+    Ok(())
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CgfxTransform {
     pub scale: Vec3,
     pub rotation: Vec3,
@@ -201,6 +519,30 @@ pub struct CgfxTransform {
     pub world_transform: Matrix3x4<f32>,
 }
 
+impl CgfxTransform {
+    /// The local transform described by `scale`, `rotation` and `translation`.
+    pub fn srt_matrix(&self) -> Matrix3x4<f32> {
+        srt_to_matrix(&self.scale, &self.rotation, &self.translation)
+    }
+    
+    /// Sets the local transform, along with the scale, rotation and translation it consists of.
+    /// The world transform needs to be recomputed afterwards.
+    pub fn set_local_transform(&mut self, local_transform: Matrix3x4<f32>) {
+        (self.scale, self.rotation, self.translation) = matrix_to_srt(&local_transform);
+        self.local_transform = local_transform;
+    }
+    
+    /// Recomputes both matrices after editing `scale`, `rotation` or `translation`, placing
+    /// this node below a parent with the world transform `parent_world_transform`.
+    pub fn recompute(&mut self, parent_world_transform: Option<&Matrix3x4<f32>>) {
+        self.local_transform = self.srt_matrix();
+        self.world_transform = match parent_world_transform {
+            Some(parent_world_transform) => multiply_transforms(parent_world_transform, &self.local_transform),
+            None => self.local_transform,
+        };
+    }
+}
+
 impl BinRead for CgfxTransform {
     type Args<'a> = ();
 
@@ -231,7 +573,12 @@ impl BinWrite for CgfxTransform {
 
     fn write_options<W: Write + Seek>(&self, writer: &mut W, endian: Endian, _args: Self::Args<'_>) -> BinResult<()> {
         // sorry 1 person trying to use this on a big endian machine (?)
-        assert!(endian == Endian::Little);
+        if endian != Endian::Little {
+            return Err(binrw::Error::Custom {
+                pos: writer.stream_position()?,
+                err: Box::new(unsupported!("CgfxTransform can only be written as little endian")),
+            });
+        }
         
         let vec_numbers: [f32; 9] = [
             self.scale.x,
@@ -245,8 +592,12 @@ impl BinWrite for CgfxTransform {
             self.translation.z,
         ];
         
-        let local_numbers = self.local_transform.data.as_slice();
-        let world_numbers = self.world_transform.data.as_slice();
+        // nalgebra stores matrices column-major, but they are stored row-major in the file
+        let local_transposed = self.local_transform.transpose();
+        let world_transposed = self.world_transform.transpose();
+        
+        let local_numbers = local_transposed.data.as_slice();
+        let world_numbers = world_transposed.data.as_slice();
         
         let vec_bytes: &[u8] = unsafe {
             slice::from_raw_parts(vec_numbers.as_ptr() as *const u8, vec_numbers.len() * 4)
@@ -260,9 +611,9 @@ impl BinWrite for CgfxTransform {
             slice::from_raw_parts(world_numbers.as_ptr() as *const u8, world_numbers.len() * 4)
         };
         
-        writer.write(vec_bytes)?;
-        writer.write(local_bytes)?;
-        writer.write(world_bytes)?;
+        writer.write_all(vec_bytes)?;
+        writer.write_all(local_bytes)?;
+        writer.write_all(world_bytes)?;
         
         Ok(())
     }
@@ -0,0 +1,448 @@
+use na::{Vec2, Vec3, Vec4};
+
+use crate::{
+    error::{invalid_input, malformed},
+    util::math::Matrix3x3,
+    Result,
+};
+
+use super::{
+    model::{
+        AttributeName, BoundingBox, BoundingBoxFit, GlDataType, PrimitiveMode, Shape, SubMesh, SubMeshSkinning,
+        VertexBuffer, VertexBufferAttribute,
+    },
+    skeleton::CgfxSkeleton,
+};
+
+/// The vertex attributes of a shape, decoded from all of its vertex buffers
+/// with their data types converted to floats and scales applied.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VertexStreams {
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub tangents: Option<Vec<Vec3>>,
+    pub colors: Option<Vec<Vec4>>,
+    pub tex_coords: [Option<Vec<Vec2>>; 3],
+    
+    /// Indices into the bone palette of the sub mesh a vertex is used in, unused ones are 0
+    pub bone_indices: Option<Vec<[u32; 4]>>,
+    pub bone_weights: Option<Vec<Vec4>>,
+}
+
+impl VertexStreams {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+    
+    /// The values of `attribute` with `elements` components each. Attributes this doesn't
+    /// contain get a neutral default, e.g. white for colors or the first bone for skinning.
+    pub fn attribute_values(&self, attribute: AttributeName, elements: u32) -> Vec<Vec<f32>> {
+        let values: Vec<[f32; 4]> = match attribute {
+            AttributeName::Position => self.positions.iter().map(|v| [v.x, v.y, v.z, 0.0]).collect(),
+            AttributeName::Normal => match &self.normals {
+                Some(normals) => normals.iter().map(|v| [v.x, v.y, v.z, 0.0]).collect(),
+                None => vec![[0.0, 0.0, 1.0, 0.0]; self.vertex_count()],
+            },
+            AttributeName::Tangent => match &self.tangents {
+                Some(tangents) => tangents.iter().map(|v| [v.x, v.y, v.z, 0.0]).collect(),
+                None => vec![[1.0, 0.0, 0.0, 0.0]; self.vertex_count()],
+            },
+            AttributeName::Color => match &self.colors {
+                Some(colors) => colors.iter().map(|v| [v.x, v.y, v.z, v.w]).collect(),
+                None => vec![[1.0; 4]; self.vertex_count()],
+            },
+            AttributeName::TexCoord0 | AttributeName::TexCoord1 | AttributeName::TexCoord2 => {
+                let index = attribute as usize - AttributeName::TexCoord0 as usize;
+                
+                match &self.tex_coords[index] {
+                    Some(tex_coords) => tex_coords.iter().map(|v| [v.x, v.y, 0.0, 0.0]).collect(),
+                    None => vec![[0.0; 4]; self.vertex_count()],
+                }
+            },
+            AttributeName::BoneIndex => match &self.bone_indices {
+                Some(bone_indices) => bone_indices.iter().map(|v| v.map(|index| index as f32)).collect(),
+                None => vec![[0.0; 4]; self.vertex_count()],
+            },
+            AttributeName::BoneWeight => match &self.bone_weights {
+                Some(bone_weights) => bone_weights.iter().map(|v| [v.x, v.y, v.z, v.w]).collect(),
+                None => vec![[1.0, 0.0, 0.0, 0.0]; self.vertex_count()],
+            },
+            _ => vec![[0.0; 4]; self.vertex_count()],
+        };
+        
+        values.iter()
+            .map(|value| value[..(elements as usize).min(4)].to_vec())
+            .collect()
+    }
+}
+
+/// The bones (indices into the skeleton) influencing a vertex and how much they do.
+/// Unused influences have a weight of 0, the weights of the others add up to 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VertexSkin {
+    pub bones: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+/// Positions and normals of a blend shape target, replacing the ones of the base geometry.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlendShapeTargetStreams {
+    pub weight: f32,
+    pub positions: Option<Vec<Vec3>>,
+    pub normals: Option<Vec<Vec3>>,
+}
+
+fn component(value: &[f32], index: usize, default: f32) -> f32 {
+    value.get(index).copied().unwrap_or(default)
+}
+
+fn to_vec2(values: Vec<Vec<f32>>) -> Vec<Vec2> {
+    values.iter()
+        .map(|value| Vec2::new(component(value, 0, 0.0), component(value, 1, 0.0)))
+        .collect()
+}
+
+fn to_vec3(values: Vec<Vec<f32>>) -> Vec<Vec3> {
+    values.iter()
+        .map(|value| Vec3::new(component(value, 0, 0.0), component(value, 1, 0.0), component(value, 2, 0.0)))
+        .collect()
+}
+
+fn to_vec4(values: Vec<Vec<f32>>, default_w: f32) -> Vec<Vec4> {
+    values.iter()
+        .map(|value| Vec4::new(component(value, 0, 0.0), component(value, 1, 0.0), component(value, 2, 0.0), component(value, 3, default_w)))
+        .collect()
+}
+
+impl Shape {
+    /// The constant value of `attribute` if it is stored as a fixed attribute
+    /// instead of having a value per vertex.
+    pub fn fixed_attribute(&self, attribute: AttributeName) -> Option<Vec<f32>> {
+        self.vertex_buffers.iter().flatten()
+            .find_map(|vertex_buffer| match vertex_buffer {
+                VertexBuffer::Fixed(fixed) if fixed.vertex_buffer_common.attribute_name == attribute => Some(fixed),
+                _ => None,
+            })
+            .map(|fixed| fixed.vector.iter().flatten()
+                .map(|component| component * fixed.scale)
+                .collect())
+    }
+    
+    /// Like `decode_attribute`, but fixed attributes get repeated for every vertex.
+    fn decode_stream(&self, attribute: AttributeName, vertex_count: usize) -> Result<Option<Vec<Vec<f32>>>> {
+        if let Some(values) = self.decode_attribute(attribute)? {
+            if values.len() != vertex_count {
+                return Err(malformed!("Shape {:?} has {} values for {:?} but {} vertices",
+                    self.cgfx_object_header.name, values.len(), attribute, vertex_count));
+            }
+            
+            return Ok(Some(values));
+        }
+        
+        Ok(self.fixed_attribute(attribute).map(|value| vec![value; vertex_count]))
+    }
+    
+    /// Decodes all vertex buffers of this shape into one stream per attribute.
+    pub fn vertex_streams(&self) -> Result<VertexStreams> {
+        let positions = self.decode_attribute(AttributeName::Position)?
+            .ok_or_else(|| malformed!("Shape {:?} has no vertex positions", self.cgfx_object_header.name))?;
+        let vertex_count = positions.len();
+        
+        let bone_indices = self.decode_stream(AttributeName::BoneIndex, vertex_count)?
+            .map(|values| values.iter()
+                .map(|value| [0, 1, 2, 3].map(|i| component(value, i, 0.0) as u32))
+                .collect());
+        
+        Ok(VertexStreams {
+            positions: to_vec3(positions),
+            normals: self.decode_stream(AttributeName::Normal, vertex_count)?.map(to_vec3),
+            tangents: self.decode_stream(AttributeName::Tangent, vertex_count)?.map(to_vec3),
+            colors: self.decode_stream(AttributeName::Color, vertex_count)?.map(|values| to_vec4(values, 1.0)),
+            tex_coords: [
+                self.decode_stream(AttributeName::TexCoord0, vertex_count)?.map(to_vec2),
+                self.decode_stream(AttributeName::TexCoord1, vertex_count)?.map(to_vec2),
+                self.decode_stream(AttributeName::TexCoord2, vertex_count)?.map(to_vec2),
+            ],
+            bone_indices,
+            bone_weights: self.decode_stream(AttributeName::BoneWeight, vertex_count)?.map(|values| to_vec4(values, 0.0)),
+        })
+    }
+    
+    /// Decodes the positions and normals of all blend shape targets.
+    pub fn blend_shape_target_streams(&self) -> Result<Vec<BlendShapeTargetStreams>> {
+        let Some(targets) = self.blend_shape.as_ref().and_then(|blend_shape| blend_shape.targets.as_ref()) else {
+            return Ok(Vec::new());
+        };
+        
+        targets.iter().enumerate()
+            .map(|(i, target)| Ok(BlendShapeTargetStreams {
+                weight: target.weight,
+                positions: self.decode_blend_shape_target(i, AttributeName::Position)?.map(to_vec3),
+                normals: self.decode_blend_shape_target(i, AttributeName::Normal)?.map(to_vec3),
+            }))
+            .collect()
+    }
+    
+    /// The skin of every vertex of this shape, with bone indices resolved through the bone palette
+    /// of the sub mesh using the vertex. Vertices which aren't part of a skinned sub mesh get `None`.
+    pub fn vertex_skins(&self, streams: &VertexStreams) -> Result<Vec<Option<VertexSkin>>> {
+        let mut skins: Vec<Option<VertexSkin>> = vec![None; streams.vertex_count()];
+        
+        for sub_mesh in self.sub_meshes.iter().flatten() {
+            let face_descriptors = sub_mesh.faces.iter().flatten()
+                .flat_map(|face| face.face_descriptors.iter().flatten());
+            
+            for face_descriptor in face_descriptors {
+                for index in face_descriptor.indices.iter().flatten() {
+                    let index = usize::from(*index);
+                    
+                    if index >= skins.len() {
+                        return Err(malformed!("Vertex index {} is out of bounds for shape {:?} with {} vertices",
+                            index, self.cgfx_object_header.name, skins.len()));
+                    }
+                    
+                    if skins[index].is_none() {
+                        skins[index] = sub_mesh.vertex_skin(streams, index)?;
+                    }
+                }
+            }
+        }
+        
+        Ok(skins)
+    }
+    
+    /// Moves the vertices of rigidly skinned sub meshes, which are stored relative to their bone,
+    /// into model space like the vertices of all other sub meshes.
+    pub fn apply_rigid_skins(&self, streams: &mut VertexStreams, skeleton: &CgfxSkeleton) -> Result<()> {
+        let bones = skeleton.bone_list();
+        let mut transformed = vec![false; streams.vertex_count()];
+        
+        for sub_mesh in self.sub_meshes.iter().flatten().filter(|sub_mesh| sub_mesh.skinning == SubMeshSkinning::Rigid) {
+            let indices = sub_mesh.faces.iter().flatten()
+                .flat_map(|face| face.face_descriptors.iter().flatten())
+                .flat_map(|face_descriptor| face_descriptor.indices.iter().flatten());
+            
+            for index in indices {
+                let index = usize::from(*index);
+                
+                if transformed.get(index) != Some(&false) {
+                    continue;
+                }
+                
+                let Some(skin) = sub_mesh.vertex_skin(streams, index)? else {
+                    continue;
+                };
+                
+                let bone = bones.iter().find(|bone| bone.index == skin.bones[0])
+                    .ok_or_else(|| malformed!("Shape {:?} references missing bone {}", self.cgfx_object_header.name, skin.bones[0]))?;
+                let world_transform = &bone.transform.world_transform;
+                let rotation = world_transform.fixed_view::<3, 3>(0, 0);
+                
+                streams.positions[index] = rotation * streams.positions[index] + world_transform.column(3);
+                
+                if let Some(normals) = &mut streams.normals {
+                    normals[index] = (rotation * normals[index]).normalize();
+                }
+                
+                transformed[index] = true;
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+impl VertexBufferAttribute {
+    /// Adjusts `scale` so that `values` can be stored in `format`. Geometry gets the highest
+    /// precision possible, while attributes like colors or bone indices only change their
+    /// scale if the values wouldn't fit otherwise, as their original scale usually matters.
+    pub fn fit_scale(&mut self, values: &[Vec<f32>]) {
+        let Some(max_value) = self.format.max_value() else {
+            return;
+        };
+        
+        let max_abs = values.iter().flatten().fold(0.0f32, |max, value| max.max(value.abs()));
+        
+        if max_abs == 0.0 {
+            return;
+        }
+        
+        let is_geometry = matches!(self.attribute_name, AttributeName::Position | AttributeName::Normal | AttributeName::Tangent
+            | AttributeName::TexCoord0 | AttributeName::TexCoord1 | AttributeName::TexCoord2);
+        
+        if is_geometry || self.scale == 0.0 || max_abs / self.scale > max_value {
+            self.scale = max_abs / max_value;
+        }
+    }
+    
+    /// Fits the scale to `values` and encodes them into `raw_bytes`.
+    fn encode_fitted(&mut self, values: &[Vec<f32>], raw_bytes: &mut [u8], stride: usize, offset: usize) -> Result<()> {
+        self.fit_scale(values);
+        self.encode(values, raw_bytes, stride, offset)
+    }
+}
+
+impl Shape {
+    /// Replaces the vertices and faces of this shape, keeping the layout of its vertex buffers
+    /// (attributes, data types, strides and offsets) so the game can still render it.
+    /// 
+    /// All triangles end up in the first face of the first sub mesh, so its skinning and bone palette
+    /// are kept. Blend shapes are removed, as their targets refer to the old vertices.
+    pub fn replace_geometry(&mut self, streams: &VertexStreams, triangles: &[[u16; 3]]) -> Result<()> {
+        let vertex_count = streams.vertex_count();
+        
+        if vertex_count > 0x10000 {
+            return Err(invalid_input!("Shape {:?} can't have more than 65536 vertices, got {}", self.cgfx_object_header.name, vertex_count));
+        }
+        
+        if let Some(index) = triangles.iter().flatten().find(|index| usize::from(**index) >= vertex_count) {
+            return Err(invalid_input!("Vertex index {} is out of bounds for {} vertices", index, vertex_count));
+        }
+        
+        // blend shapes
+        if let Some(blend_shape) = self.blend_shape.take() {
+            if let Some(vertex_buffers) = &mut self.vertex_buffers {
+                let mut i = 0;
+                
+                vertex_buffers.retain(|_| {
+                    i += 1;
+                    !blend_shape.is_target_buffer(i - 1)
+                });
+            }
+        }
+        
+        // vertex buffers
+        for vertex_buffer in self.vertex_buffers.iter_mut().flatten() {
+            match vertex_buffer {
+                VertexBuffer::Attribute(attribute) => {
+                    let stride = attribute.byte_size();
+                    let values = streams.attribute_values(attribute.attribute_name, attribute.elements);
+                    let mut raw_bytes = vec![0; vertex_count * stride];
+                    
+                    attribute.encode_fitted(&values, &mut raw_bytes, stride, 0)?;
+                    attribute.raw_bytes = Some(raw_bytes);
+                },
+                VertexBuffer::Interleaved(interleaved) => {
+                    // padding between the attributes stays zeroed
+                    let stride = interleaved.vertex_stride as usize;
+                    let mut raw_bytes = vec![0; vertex_count * stride];
+                    
+                    for attribute in interleaved.attributes.iter_mut().flatten() {
+                        let offset = attribute.offset as usize;
+                        
+                        if offset + attribute.byte_size() > stride {
+                            return Err(malformed!("{:?} at offset {} doesn't fit into a vertex of {} bytes",
+                                attribute.attribute_name, offset, stride));
+                        }
+                        
+                        let values = streams.attribute_values(attribute.attribute_name, attribute.elements);
+                        attribute.encode_fitted(&values, &mut raw_bytes, stride, offset)?;
+                    }
+                    
+                    interleaved.raw_bytes = Some(raw_bytes);
+                },
+                VertexBuffer::Fixed(_) => {},
+            }
+        }
+        
+        // faces
+        let sub_meshes = self.sub_meshes.as_mut()
+            .ok_or_else(|| malformed!("Shape {:?} has no sub meshes", self.cgfx_object_header.name))?;
+        sub_meshes.truncate(1);
+        
+        let faces = sub_meshes.first_mut().and_then(|sub_mesh| sub_mesh.faces.as_mut())
+            .ok_or_else(|| malformed!("Shape {:?} has no faces", self.cgfx_object_header.name))?;
+        faces.truncate(1);
+        
+        let face_descriptors = faces.first_mut().and_then(|face| face.face_descriptors.as_mut())
+            .ok_or_else(|| malformed!("Shape {:?} has no face descriptors", self.cgfx_object_header.name))?;
+        face_descriptors.truncate(1);
+        
+        let face_descriptor = face_descriptors.first_mut()
+            .ok_or_else(|| malformed!("Shape {:?} has no face descriptors", self.cgfx_object_header.name))?;
+        
+        face_descriptor.format = if vertex_count <= 0x100 { GlDataType::UByte } else { GlDataType::UShort };
+        face_descriptor.primitive_mode = PrimitiveMode::Triangles;
+        face_descriptor.indices = Some(triangles.iter().flatten().copied().collect());
+        
+        // bounding box, staying oriented if it was before
+        let fit = match &self.bounding_box {
+            Some(bounding_box) if bounding_box.orientation != Matrix3x3::identity() => BoundingBoxFit::Oriented,
+            _ => BoundingBoxFit::AxisAligned,
+        };
+        self.fit_bounding_box(&streams.positions, fit);
+        
+        Ok(())
+    }
+    
+    /// Refits the bounding box to the current vertices, including the ones of blend shape
+    /// targets, so the game doesn't cull the shape based on outdated geometry.
+    /// Shapes without vertex positions lose their bounding box.
+    pub fn recompute_bounding_box(&mut self, fit: BoundingBoxFit) -> Result<()> {
+        let mut positions = self.decode_attribute(AttributeName::Position)?.map_or_else(Vec::new, to_vec3);
+        
+        for target in self.blend_shape_target_streams()? {
+            positions.extend(target.positions.into_iter().flatten());
+        }
+        
+        self.fit_bounding_box(&positions, fit);
+        Ok(())
+    }
+    
+    fn fit_bounding_box(&mut self, positions: &[Vec3], fit: BoundingBoxFit) {
+        let flags = self.bounding_box.as_ref().map_or(0, |bounding_box| bounding_box.flags);
+        self.bounding_box = BoundingBox::fit(flags, positions, fit);
+    }
+}
+
+impl SubMesh {
+    /// Resolves an index into the bone palette of this sub mesh to an index into the skeleton.
+    pub fn resolve_bone(&self, palette_index: u32) -> Result<u32> {
+        self.bone_indices.as_ref()
+            .and_then(|bone_indices| bone_indices.get(palette_index as usize))
+            .copied()
+            .ok_or_else(|| malformed!("Bone palette index {} is out of bounds", palette_index))
+    }
+    
+    /// The skin of the vertex at `vertex_index` when it is used by this sub mesh.
+    /// Rigid skinning always results in a single bone with full weight.
+    pub fn vertex_skin(&self, streams: &VertexStreams, vertex_index: usize) -> Result<Option<VertexSkin>> {
+        if self.skinning == SubMeshSkinning::None {
+            return Ok(None);
+        }
+        
+        let palette_indices = streams.bone_indices.as_ref()
+            .and_then(|bone_indices| bone_indices.get(vertex_index))
+            .ok_or_else(|| malformed!("Skinned vertex {} has no bone indices", vertex_index))?;
+        
+        if self.skinning == SubMeshSkinning::Rigid {
+            return Ok(Some(VertexSkin {
+                bones: [self.resolve_bone(palette_indices[0])?, 0, 0, 0],
+                weights: [1.0, 0.0, 0.0, 0.0],
+            }));
+        }
+        
+        let weights = streams.bone_weights.as_ref()
+            .and_then(|bone_weights| bone_weights.get(vertex_index))
+            .copied()
+            .unwrap_or(Vec4::x());
+        
+        let mut skin = VertexSkin::default();
+        let weight_sum: f32 = weights.iter().filter(|weight| **weight > 0.0).sum();
+        
+        for i in 0..4 {
+            if weights[i] > 0.0 {
+                skin.bones[i] = self.resolve_bone(palette_indices[i])?;
+                skin.weights[i] = weights[i] / weight_sum;
+            }
+        }
+        
+        // a vertex without any weights would collapse to the origin, so just attach it to the first bone instead
+        if weight_sum <= 0.0 {
+            skin.bones[0] = self.resolve_bone(palette_indices[0])?;
+            skin.weights[0] = 1.0;
+        }
+        
+        Ok(Some(skin))
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    convert::Infallible,
+    io,
+    num::TryFromIntError,
+    result,
+    str::Utf8Error,
+    string::FromUtf8Error,
+};
+
+use thiserror::Error;
+
+pub type Result<T, E = Error> = result::Result<T, E>;
+
+/// Everything that can go wrong while reading, editing or writing files with this crate.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Reading or writing failed, which includes files ending too early
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    
+    /// The data doesn't follow the format, e.g. an unknown discriminant or a count that doesn't match
+    #[error("{0}")]
+    Malformed(String),
+    
+    /// Valid data this crate can't handle yet, e.g. cube map textures
+    #[error("{0}")]
+    Unsupported(String),
+    
+    /// Arguments that don't fit, e.g. an image with the wrong size for its format
+    #[error("{0}")]
+    InvalidInput(String),
+    
+    /// `source` happened while reading the structure at `path` (like `models/Mario/shapes[2]`),
+    /// `offset` is the closest known position in the file
+    #[error("{source} (at offset {offset:#x}{})", describe_path(.path))]
+    At {
+        offset: u64,
+        path: String,
+        source: Box<Error>,
+    },
+}
+
+fn describe_path(path: &str) -> String {
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!(" in {}", path)
+    }
+}
+
+impl Error {
+    /// Marks this error as having happened inside the structure `segment` starting at `offset`.
+    /// Errors that already know where they happened keep their more precise offset.
+    pub fn at(self, offset: u64, segment: &str) -> Self {
+        match self {
+            Error::At { offset, path, source } => {
+                let path = if path.is_empty() {
+                    segment.to_string()
+                } else if path.starts_with('[') {
+                    format!("{}{}", segment, path)
+                } else {
+                    format!("{}/{}", segment, path)
+                };
+                
+                Error::At { offset, path, source }
+            },
+            error => Error::At {
+                offset,
+                path: segment.to_string(),
+                source: Box::new(error),
+            },
+        }
+    }
+    
+    /// The position in the file this happened at, if it is known.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Error::At { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+    
+    /// The structure this happened in, if it is known.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Error::At { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+    
+    /// The error without the information where it happened.
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::At { source, .. } => source.root_cause(),
+            error => error,
+        }
+    }
+}
+
+pub(crate) trait ResultExt<T> {
+    /// See `Error::at`.
+    fn at(self, offset: u64, segment: &str) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for result::Result<T, E> {
+    fn at(self, offset: u64, segment: &str) -> Result<T> {
+        self.map_err(|error| error.into().at(offset, segment))
+    }
+}
+
+impl From<binrw::Error> for Error {
+    fn from(error: binrw::Error) -> Self {
+        match error {
+            binrw::Error::Io(error) => Error::Io(error),
+            // the frames only name binrw's generated code, the error inside is what matters
+            binrw::Error::Backtrace(backtrace) => Error::from(*backtrace.error),
+            binrw::Error::Custom { pos, err } => match err.downcast::<Error>() {
+                Ok(error) => (*error).at(pos, ""),
+                Err(err) => Error::Malformed(format!("{:?}", err)).at(pos, ""),
+            },
+            error => {
+                let offset = match &error {
+                    binrw::Error::BadMagic { pos, .. }
+                    | binrw::Error::AssertFail { pos, .. }
+                    | binrw::Error::NoVariantMatch { pos }
+                    | binrw::Error::EnumErrors { pos, .. } => Some(*pos),
+                    _ => None,
+                };
+                
+                let malformed = Error::Malformed(error.to_string());
+                
+                match offset {
+                    Some(offset) => malformed.at(offset, ""),
+                    None => malformed,
+                }
+            },
+        }
+    }
+}
+
+impl From<Infallible> for Error {
+    fn from(error: Infallible) -> Self {
+        match error {}
+    }
+}
+
+impl From<TryFromIntError> for Error {
+    fn from(error: TryFromIntError) -> Self {
+        Error::Malformed(format!("Number out of range: {}", error))
+    }
+}
+
+impl From<Utf8Error> for Error {
+    fn from(error: Utf8Error) -> Self {
+        Error::Malformed(format!("Invalid UTF-8 string: {}", error))
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(error: FromUtf8Error) -> Self {
+        Error::Malformed(format!("Invalid UTF-8 string: {}", error))
+    }
+}
+
+impl From<png::DecodingError> for Error {
+    fn from(error: png::DecodingError) -> Self {
+        match error {
+            png::DecodingError::IoError(error) => Error::Io(error),
+            error => Error::InvalidInput(format!("Invalid PNG file: {}", error)),
+        }
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(error: png::EncodingError) -> Self {
+        match error {
+            png::EncodingError::IoError(error) => Error::Io(error),
+            error => Error::InvalidInput(format!("Can't encode PNG file: {}", error)),
+        }
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(error: serde_yaml::Error) -> Self {
+        Error::InvalidInput(format!("Invalid YAML: {}", error))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::InvalidInput(format!("Invalid JSON: {}", error))
+    }
+}
+
+macro_rules! malformed {
+    ($($arg:tt)*) => {
+        $crate::Error::Malformed(format!($($arg)*))
+    };
+}
+
+macro_rules! unsupported {
+    ($($arg:tt)*) => {
+        $crate::Error::Unsupported(format!($($arg)*))
+    };
+}
+
+macro_rules! invalid_input {
+    ($($arg:tt)*) => {
+        $crate::Error::InvalidInput(format!($($arg)*))
+    };
+}
+
+pub(crate) use {invalid_input, malformed, unsupported};
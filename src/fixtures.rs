@@ -0,0 +1,820 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use nw_tex::{
+    bcres::{
+        bcres::{CgfxContainer, CgfxDict},
+        emitter::CgfxEmitter,
+        image_codec::RgbaColor,
+        material::{Material, MaterialColors},
+        model::{
+            AttributeName, CgfxModel, CgfxModelCommon, GlDataType, Shape, SubMeshSkinning, VertexBuffer,
+            VertexBufferCommon, VertexBufferFixed, VertexBufferType,
+        },
+        skeleton::CgfxSkeleton,
+        texture::PicaTextureFormat,
+    },
+    util::{blz::blz_encode, math::Vec4},
+    ArchiveRegistry, RegistryItem,
+};
+
+/// Every texture format, in the order of their discriminants.
+pub const TEXTURE_FORMATS: [PicaTextureFormat; 14] = [
+    PicaTextureFormat::RGBA8,
+    PicaTextureFormat::RGB8,
+    PicaTextureFormat::RGBA5551,
+    PicaTextureFormat::RGB565,
+    PicaTextureFormat::RGBA4,
+    PicaTextureFormat::LA8,
+    PicaTextureFormat::HiLo8,
+    PicaTextureFormat::L8,
+    PicaTextureFormat::A8,
+    PicaTextureFormat::LA4,
+    PicaTextureFormat::L4,
+    PicaTextureFormat::A4,
+    PicaTextureFormat::ETC1,
+    PicaTextureFormat::ETC1A4,
+];
+
+/// A model with a single triangle, written in the text format of `CgfxContainer::to_yaml`
+/// with the vertex buffer embedded as base64. `model_container` adds its white material.
+const TRIANGLE_MODEL: &str = r#"
+cgfx_object_header: { magic: CMDL, revision: 150994944, name: triangle, metadata: null }
+cgfx_node_header: { branch_visible: 1, is_branch_visible: 1, child_count: 0, children_pointer: null, anim_groups: null }
+transform_node_header:
+  scale: { x: 1.0, y: 1.0, z: 1.0 }
+  rotation: { x: 0.0, y: 0.0, z: 0.0 }
+  translation: { x: 0.0, y: 0.0, z: 0.0 }
+  local_transform: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0]
+  world_transform: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0]
+meshes:
+- cgfx_object_header: { magic: SOBJ, revision: 0, name: null, metadata: null }
+  shape_index: 0
+  material_index: 0
+  parent_ptr: 0
+  visible: 1
+  render_priority: 0
+  mesh_node_index: 0
+  primitive_index: 0
+  runtime_data: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+  mesh_node_name: null
+  render_key_cache: 0
+  command_alloc: 0
+shapes:
+- cgfx_object_header: { magic: SOBJ, revision: 0, name: null, metadata: null }
+  flags: 0
+  bounding_box:
+    flags: 0
+    center: { x: 0.5, y: 0.5, z: 0.0 }
+    orientation: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]
+    size: { x: 1.0, y: 1.0, z: 0.0 }
+  position_offset: { x: 0.0, y: 0.0, z: 0.0 }
+  sub_meshes:
+  - bone_indices: null
+    skinning: None
+    faces:
+    - face_descriptors:
+      - format: UByte
+        primitive_mode: Triangles
+        visible: 1
+        indices: [0, 1, 2]
+        buffer_obj: 0
+        location_flag: 0
+        command_cache: 0
+        command_cache_size: 0
+        location_ptr: 0
+        memory_area: 0
+        bounding_volume: 0
+      buffer_objs: [0]
+      flags: 0
+      command_alloc: 0
+  base_address: 0
+  vertex_buffers:
+  - !Interleaved
+    vertex_buffer_common: { attribute_name: Interleave, vertex_buffer_type: Interleaved }
+    buffer_obj: 0
+    location_flag: 0
+    raw_bytes: !base64 AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA
+    location_ptr: 0
+    memory_area: 0
+    vertex_stride: 12
+    attributes:
+    - vertex_buffer_common: { attribute_name: Position, vertex_buffer_type: None }
+      buffer_obj: 0
+      location_flag: 0
+      raw_bytes: null
+      location_ptr: 0
+      memory_area: 0
+      format: Float
+      elements: 3
+      scale: 1.0
+      offset: 0
+  - !Fixed
+    vertex_buffer_common: { attribute_name: Color, vertex_buffer_type: Fixed }
+    format: Float
+    elements: 4
+    scale: 1.0
+    vector: [1.0, 0.5, 0.25, 1.0]
+  blend_shape: null
+mesh_node_visibilities: null
+flags: 1
+face_culling: 1
+layer_id: 0
+"#;
+
+/// A skeleton with a single bone, which is 5 units above the origin.
+const LIFTED_SKELETON: &str = r#"
+cgfx_object_header: { magic: "SKM ", revision: 0, name: null, metadata: null }
+bones: null
+root_bone: 0
+scaling_rule: 0
+flags: 0
+"#;
+
+const LIFTED_BONE: &str = r#"
+name: root
+flags: 0
+index: 0
+parent_index: -1
+parent: null
+child: null
+previous_sibling: null
+next_sibling: null
+transform:
+  scale: { x: 1.0, y: 1.0, z: 1.0 }
+  rotation: { x: 0.0, y: 0.0, z: 0.0 }
+  translation: { x: 0.0, y: 5.0, z: 0.0 }
+  local_transform: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 5.0, 0.0]
+  world_transform: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 5.0, 0.0]
+inverse_world_transform: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, -5.0, 0.0]
+billboard_mode: 0
+metadata: null
+"#;
+
+/// A particle emitter using every initializer and updater type whose layout is known.
+const SPARKLE_EMITTER: &str = r#"
+discriminant: 1073741826
+cgfx_object_header: { magic: PEMT, revision: 0, name: sparkle, metadata: null }
+cgfx_node_header: { branch_visible: 1, is_branch_visible: 1, child_count: 0, children_pointer: null, anim_groups: null }
+transform_node_header:
+  scale: { x: 1.0, y: 1.0, z: 1.0 }
+  rotation: { x: 0.0, y: 0.0, z: 0.0 }
+  translation: { x: 0.0, y: 2.0, z: 0.0 }
+  local_transform: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 2.0, 0.0]
+  world_transform: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 2.0, 0.0]
+particle_set_path: sparkle_set
+emitter_type: Constant
+parameters:
+  emission_start: 0.0
+  emission_end: 60.0
+  emission_interval: 2.0
+  emission_interval_random: 0.5
+  emission_ratio: 1.0
+  life_span: 30.0
+  life_span_random: 5.0
+  direction: { x: 0.0, y: 1.0, z: 0.0 }
+  dispersion_angle: 45.0
+  initial_velocity: 0.5
+  initial_velocity_random: 0.1
+  directional_velocity: 0.0
+  inherit_parent_velocity: 0
+shape: !Cylinder { radius: 2.0, height: 1.0, inner_radius_ratio: 0.5 }
+initializers:
+- !SingleValue { target: Scale, value: { x: 1.0, y: 1.0, z: 1.0 } }
+- !RandomValue { target: Rotation, value: { x: 0.0, y: 0.0, z: 0.0 }, random: { x: 0.0, y: 0.0, z: 3.14 } }
+- !Color { target: Color, color: { r: 255, g: 240, b: 128, a: 255 } }
+updaters:
+- !ConstantRate { target: Rotation, rate: { x: 0.0, y: 0.0, z: 0.1 } }
+- !Gravity { target: Velocity, direction: { x: 0.0, y: -1.0, z: 0.0 }, strength: 0.01 }
+- !Spin { target: Translation, axis: { x: 0.0, y: 1.0, z: 0.0 }, speed: 0.05 }
+- !Attraction { target: Velocity, position: { x: 0.0, y: 4.0, z: 0.0 }, strength: 0.2 }
+- !LinearInterpolation { target: Alpha, start: { x: 1.0, y: 0.0, z: 0.0 }, end: { x: 0.0, y: 0.0, z: 0.0 } }
+"#;
+
+/// Some stripes, which compress about as well as real textures do.
+fn image_bytes(length: u32) -> impl Iterator<Item = u8> {
+    (0..length).map(|i| ((i / 8 % 5) * 0x33) as u8)
+}
+
+/// A bcres with a single `width`x`height` texture, laid out exactly like the ones of the
+/// texture archives. Written by hand so it doesn't depend on the writer being correct.
+pub fn texture_bcres(name: &str, format: PicaTextureFormat, width: u32, height: u32) -> Vec<u8> {
+    let image_length = width * height * format.get_bpp() / 8;
+    let (gl_format, gl_type) = format.gl_format_and_type();
+    
+    // header
+    let mut buffer = b"CGFX".to_vec();
+    buffer.extend(0xFEFFu16.to_le_bytes());
+    buffer.extend(0x14u16.to_le_bytes());
+    put(&mut buffer, &[0x5000000, 0, 2]);
+    buffer.extend(b"DATA");
+    put(&mut buffer, &[0]);
+    
+    // dict references, only the textures are used
+    let texture_dict_offset = 0x1C + 16 * 8;
+    
+    for i in 0..16 {
+        if i == 1 {
+            put(&mut buffer, &[1, texture_dict_offset - (0x1C + 8 + 4)]);
+        } else {
+            put(&mut buffer, &[0, 0]);
+        }
+    }
+    
+    // texture dict
+    let node_offset = texture_dict_offset + 12 + 16;
+    let texture_offset = node_offset + 16;
+    let image_data_offset = texture_offset + 4 + 0x14 + 0x20 + 4;
+    let string_offset = image_data_offset + 0x20;
+    let image_offset = (string_offset + name.len() as u32 + 1 + 8).next_multiple_of(0x80);
+    
+    buffer.extend(b"DICT");
+    put(&mut buffer, &[0x2C, 1]);
+    put(&mut buffer, &[0xFFFFFFFF, 1, 0, 0]);
+    put(&mut buffer, &[(name.len() as u32 * 8) - 2, 1 << 16, string_offset - (node_offset + 8), texture_offset - (node_offset + 12)]);
+    
+    // texture, its object header and its image data
+    put(&mut buffer, &[0x20000011]);
+    buffer.extend(b"TXOB");
+    put(&mut buffer, &[0, string_offset - (texture_offset + 12), 0, 0]);
+    put(&mut buffer, &[height, width, gl_format, gl_type, 1, 0, 0, format as u32]);
+    put(&mut buffer, &[4]);
+    put(&mut buffer, &[height, width, image_length, image_offset - (image_data_offset + 12), 0, format.get_bpp(), 0, 0]);
+    
+    buffer.extend(name.as_bytes());
+    buffer.push(0);
+    
+    // image section, the DATA section includes the padding before it
+    buffer.resize(image_offset as usize - 8, 0);
+    let content_length = buffer.len() as u32 - 0x14;
+    buffer.extend(b"IMAG");
+    put(&mut buffer, &[image_length + 8]);
+    buffer.extend(image_bytes(image_length));
+    
+    let file_length = buffer.len() as u32;
+    buffer[0x0C..0x10].copy_from_slice(&file_length.to_le_bytes());
+    buffer[0x18..0x1C].copy_from_slice(&content_length.to_le_bytes());
+    buffer
+}
+
+/// An 8x8 texture bcres whose texture carries metadata of every type, laid out exactly
+/// like the writer lays it out. Written by hand so it doesn't depend on the writer either.
+pub fn metadata_texture_bcres() -> Vec<u8> {
+    let format = PicaTextureFormat::RGBA8;
+    let (gl_format, gl_type) = format.gl_format_and_type();
+    let image_length = 8 * 8 * format.get_bpp() / 8;
+    let mut strings = Vec::new();
+    
+    // header, the lengths are filled in at the end
+    let mut buffer = b"CGFX".to_vec();
+    buffer.extend(0xFEFFu16.to_le_bytes());
+    buffer.extend(0x14u16.to_le_bytes());
+    put(&mut buffer, &[0x5000000, 0, 2]);
+    buffer.extend(b"DATA");
+    put(&mut buffer, &[0]);
+    
+    // dict references, only the textures are used
+    put(&mut buffer, &[0, 0, 1]);
+    let texture_dict_location = put_placeholder(&mut buffer);
+    
+    for _ in 2..16 {
+        put(&mut buffer, &[0, 0]);
+    }
+    
+    point_here(&mut buffer, texture_dict_location);
+    let texture_location = put_dict(&mut buffer, &mut strings, "tex");
+    
+    // texture and its image data, followed by the metadata
+    point_here(&mut buffer, texture_location);
+    put(&mut buffer, &[0x20000011]);
+    buffer.extend(b"TXOB");
+    put(&mut buffer, &[0]);
+    strings.push((put_placeholder(&mut buffer), "tex"));
+    put(&mut buffer, &[5]);
+    let metadata_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[8, 8, gl_format, gl_type, 1, 0, 0, format as u32]);
+    put(&mut buffer, &[4, 8, 8, image_length]);
+    let image_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[0, format.get_bpp(), 0, 0]);
+    
+    // metadata dict along with the patricia tree of its names
+    point_here(&mut buffer, metadata_location);
+    buffer.extend(b"DICT");
+    put(&mut buffer, &[0x6C, 5]);
+    put(&mut buffer, &[0xFFFFFFFF, 1, 0, 0]);
+    
+    let nodes = [("scale", 0x26, 5, 2), ("count", 0x24, 3, 4), ("label", 0x23, 2, 3), ("title", 0x22, 1, 4), ("tint", 0x1E, 0, 5)];
+    let mut value_locations = Vec::new();
+    
+    for (name, reference_bit, left, right) in nodes {
+        put(&mut buffer, &[reference_bit, left | right << 16]);
+        strings.push((put_placeholder(&mut buffer), name));
+        value_locations.push(put_placeholder(&mut buffer));
+    }
+    
+    // every metadata is directly followed by its values
+    point_here(&mut buffer, value_locations[0]);
+    put(&mut buffer, &[0x80000000]);
+    strings.push((put_placeholder(&mut buffer), "scale"));
+    put(&mut buffer, &[0, 1, 4]);
+    put_floats(&mut buffer, &[0.5]);
+    
+    point_here(&mut buffer, value_locations[1]);
+    put(&mut buffer, &[0x20000000]);
+    strings.push((put_placeholder(&mut buffer), "count"));
+    put(&mut buffer, &[1, 2, 4, 3, -1i32 as u32]);
+    
+    point_here(&mut buffer, value_locations[2]);
+    put(&mut buffer, &[0x10000000]);
+    strings.push((put_placeholder(&mut buffer), "label"));
+    put(&mut buffer, &[2, 0, 1, 4]);
+    strings.push((put_placeholder(&mut buffer), "sticker"));
+    
+    // UTF-16 strings aren't in the string section, but after a list of pointers to them
+    point_here(&mut buffer, value_locations[3]);
+    put(&mut buffer, &[0x10000000]);
+    strings.push((put_placeholder(&mut buffer), "title"));
+    put(&mut buffer, &[2, 2, 1, 4, 4]);
+    
+    for unit in "ペーパー".encode_utf16().chain([0]) {
+        buffer.extend(unit.to_le_bytes());
+    }
+    
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+    
+    point_here(&mut buffer, value_locations[4]);
+    put(&mut buffer, &[0x08000000]);
+    strings.push((put_placeholder(&mut buffer), "tint"));
+    put(&mut buffer, &[4, 1, 4]);
+    put_floats(&mut buffer, &[1.0, 0.5, 0.25, 1.0]);
+    
+    put_strings(&mut buffer, strings);
+    
+    // image section, the DATA section includes the padding before it
+    let image_offset = (buffer.len() + 8).next_multiple_of(0x80);
+    buffer.resize(image_offset - 8, 0);
+    let content_length = buffer.len() as u32 - 0x14;
+    buffer.extend(b"IMAG");
+    put(&mut buffer, &[image_length + 8]);
+    buffer.extend(image_bytes(image_length));
+    
+    let image_pointer = (image_offset - image_location) as u32;
+    buffer[image_location..image_location + 4].copy_from_slice(&image_pointer.to_le_bytes());
+    
+    let file_length = buffer.len() as u32;
+    buffer[0x0C..0x10].copy_from_slice(&file_length.to_le_bytes());
+    buffer[0x18..0x1C].copy_from_slice(&content_length.to_le_bytes());
+    buffer
+}
+
+/// Appends little endian `values` to `buffer`.
+fn put(buffer: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        buffer.extend(value.to_le_bytes());
+    }
+}
+
+fn put_floats(buffer: &mut Vec<u8>, values: &[f32]) {
+    for value in values {
+        buffer.extend(value.to_le_bytes());
+    }
+}
+
+/// Points the relative pointer at `location` to the current end of `buffer`.
+fn point_here(buffer: &mut [u8], location: usize) {
+    let pointer = (buffer.len() - location) as u32;
+    buffer[location..location + 4].copy_from_slice(&pointer.to_le_bytes());
+}
+
+/// Appends a null pointer to be filled in later and returns its location.
+fn put_placeholder(buffer: &mut Vec<u8>) -> usize {
+    buffer.extend([0; 4]);
+    buffer.len() - 4
+}
+
+/// Appends `strings` (the pointer locations along with the strings) to `buffer`
+/// and points their pointers to them. Every string is only stored once.
+fn put_strings(buffer: &mut Vec<u8>, strings: Vec<(usize, &str)>) {
+    let mut offsets: HashMap<&str, usize> = HashMap::new();
+    
+    for (location, string) in strings {
+        let offset = *offsets.entry(string).or_insert_with(|| {
+            buffer.extend(string.as_bytes());
+            buffer.push(0);
+            buffer.len() - string.len() - 1
+        });
+        
+        let pointer = (offset - location) as u32;
+        buffer[location..location + 4].copy_from_slice(&pointer.to_le_bytes());
+    }
+}
+
+/// Appends a material called `name` using `texture_name` as its only texture, laid out like the
+/// materials of the game. The pointers of its strings are added to `strings`.
+fn put_material<'a>(buffer: &mut Vec<u8>, strings: &mut Vec<(usize, &'a str)>, name: &'a str, texture_name: &'a str) {
+    put(buffer, &[0x8000000]);
+    buffer.extend(b"MTOB");
+    put(buffer, &[0x6000000]);
+    strings.push((put_placeholder(buffer), name));
+    put(buffer, &[0, 0]);
+    
+    // flags, texture coordinate config, render layer
+    put(buffer, &[0, 0, 1]);
+    
+    // colors, first as floats and then as bytes
+    for i in 0..11 {
+        put_floats(buffer, &[1.0, 1.0 - i as f32 / 16.0, 0.5, 1.0]);
+    }
+    
+    for i in 0..11 {
+        buffer.extend([255, 255 - i * 16, 128, 255]);
+    }
+    
+    put(buffer, &[0]);
+    
+    // rasterization, culling back faces
+    put(buffer, &[0, 1]);
+    put_floats(buffer, &[0.0]);
+    put(buffer, &[0x1, 0x40, 0x1004_0000]);
+    
+    // fragment operation: depth test and writes, no blending, no stencil test
+    put(buffer, &[3, 0x41, 0x1010_0107, 0x3, 0x1012_0126]);
+    put(buffer, &[0]);
+    put_floats(buffer, &[0.0, 0.0, 0.0, 0.0]);
+    put(buffer, &[0, 0, 0, 0, 0, 0]);
+    put(buffer, &[0, 0, 0, 0]);
+    
+    // texture coordinators, only the first one is used
+    put(buffer, &[1]);
+    
+    for i in 0..3 {
+        put(buffer, &[i, 0, u32::MAX, 0]);
+        put_floats(buffer, &[1.0, 1.0, 0.0, 0.0, 0.0]);
+        put(buffer, &[0]);
+        put_floats(buffer, &[1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+    }
+    
+    // texture mappers, procedural texture mapper, shader and fragment shader
+    let texture_mapper_location = put_placeholder(buffer);
+    put(buffer, &[0, 0, 0]);
+    let shader_location = put_placeholder(buffer);
+    let fragment_shader_location = put_placeholder(buffer);
+    
+    // shader program description, shader parameters, light set and fog
+    put(buffer, &[0, 0, 0, 0, 0]);
+    
+    // hashes and unique id
+    put(buffer, &(0..13).map(|i| 0x1000 + i).collect::<Vec<u32>>());
+    put(buffer, &[42]);
+    
+    // texture mapper
+    point_here(buffer, texture_mapper_location);
+    let texture_mapper_offset = buffer.len();
+    put(buffer, &[0x80000000, 0]);
+    let texture_location = put_placeholder(buffer);
+    let sampler_location = put_placeholder(buffer);
+    put(buffer, &(0..14).map(|i| 0x0100_0080 + i).collect::<Vec<u32>>());
+    put(buffer, &[14 * 4]);
+    
+    point_here(buffer, texture_location);
+    put(buffer, &[0x20000004]);
+    buffer.extend(b"TXOB");
+    put(buffer, &[0x5000000, 0, 0, 0]);
+    strings.push((put_placeholder(buffer), texture_name));
+    put(buffer, &[0]);
+    
+    point_here(buffer, sampler_location);
+    put(buffer, &[0x80000000, (texture_mapper_offset as i32 - buffer.len() as i32) as u32, 1]);
+    put_floats(buffer, &[0.0, 0.0, 0.0, 1.0, 0.0]);
+    
+    // shader reference
+    point_here(buffer, shader_location);
+    put(buffer, &[0x80000001]);
+    buffer.extend(b"SHDR");
+    put(buffer, &[0x5000000, 0, 0, 0]);
+    strings.push((put_placeholder(buffer), "DefaultShader"));
+    put(buffer, &[0]);
+    
+    // fragment shader: buffer color, lighting, no lookup tables, texture combiners and alpha test
+    point_here(buffer, fragment_shader_location);
+    put_floats(buffer, &[0.0, 0.0, 0.0, 1.0]);
+    put(buffer, &[0, 0, 0, 0, 0, 0]);
+    put(buffer, &[0]);
+    
+    for i in 0..6 {
+        put(buffer, &[i, 0x0000_00C0 + i, 0x1000_0000, 0x0000_0E00, 0, 0xFF00_0000, 0]);
+    }
+    
+    put(buffer, &[0, 0x10, 0x000F_0104]);
+    put(buffer, &[0; 6]);
+}
+
+/// A material laid out like the ones of the game, see `put_material`.
+pub fn material_bytes(name: &str, texture_name: &str) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let mut strings = Vec::new();
+    
+    put_material(&mut buffer, &mut strings, name, texture_name);
+    put_strings(&mut buffer, strings);
+    buffer
+}
+
+/// Appends a dict with the single value `name` and returns the location of the pointer to its value.
+fn put_dict<'a>(buffer: &mut Vec<u8>, strings: &mut Vec<(usize, &'a str)>, name: &'a str) -> usize {
+    // a single node is told apart from the root by the highest bit of the name
+    let reference_bit = name.len() as u32 * 8 - 1 - name.as_bytes()[0].leading_zeros();
+    
+    buffer.extend(b"DICT");
+    put(buffer, &[0x2C, 1]);
+    put(buffer, &[0xFFFFFFFF, 1, 0, 0]);
+    put(buffer, &[reference_bit, 1 << 16]);
+    strings.push((put_placeholder(buffer), name));
+    put_placeholder(buffer)
+}
+
+/// Appends a list of a single pointer, pointed to by the list pointer at `location`,
+/// and points it to the current end of `buffer`.
+fn put_single_pointer_list(buffer: &mut Vec<u8>, location: usize) {
+    point_here(buffer, location);
+    let pointer_location = put_placeholder(buffer);
+    point_here(buffer, pointer_location);
+}
+
+/// A bcres with a model called `name`, which is a triangle with the material `material_name`
+/// using `texture_name`. Laid out like the models of the game with the vertices and indices in
+/// the image section, and written by hand so it doesn't depend on the writer being correct.
+pub fn model_bcres(name: &str, material_name: &str, texture_name: &str) -> Vec<u8> {
+    let mut strings = Vec::new();
+    
+    // header, the lengths are filled in at the end
+    let mut buffer = b"CGFX".to_vec();
+    buffer.extend(0xFEFFu16.to_le_bytes());
+    buffer.extend(0x14u16.to_le_bytes());
+    put(&mut buffer, &[0x5000000, 0, 2]);
+    buffer.extend(b"DATA");
+    put(&mut buffer, &[0]);
+    
+    // dict references, only the models are used
+    put(&mut buffer, &[1]);
+    let model_dict_location = put_placeholder(&mut buffer);
+    
+    for _ in 1..16 {
+        put(&mut buffer, &[0, 0]);
+    }
+    
+    point_here(&mut buffer, model_dict_location);
+    let model_location = put_dict(&mut buffer, &mut strings, name);
+    
+    // model, its node and its transform
+    point_here(&mut buffer, model_location);
+    let model_offset = buffer.len();
+    put(&mut buffer, &[0x40000012]);
+    buffer.extend(b"CMDL");
+    put(&mut buffer, &[0x9000000]);
+    strings.push((put_placeholder(&mut buffer), name));
+    put(&mut buffer, &[0, 0]);
+    put(&mut buffer, &[1, 1, 0, 0, 0, 0]);
+    put_floats(&mut buffer, &[1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    
+    for _ in 0..2 {
+        put_floats(&mut buffer, &[1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+    }
+    
+    // meshes, materials, shapes, no mesh node visibilities, then flags, face culling and layer
+    put(&mut buffer, &[1]);
+    let mesh_list_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[1]);
+    let material_dict_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[1]);
+    let shape_list_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[0, 0]);
+    put(&mut buffer, &[1, 1, 0]);
+    
+    // mesh, pointing back to the model and named after its mesh node
+    put_single_pointer_list(&mut buffer, mesh_list_location);
+    put(&mut buffer, &[0x01000000]);
+    buffer.extend(b"SOBJ");
+    put(&mut buffer, &[0, 0, 0, 0]);
+    put(&mut buffer, &[0, 0]);
+    let parent_pointer = model_offset as i32 - buffer.len() as i32;
+    put(&mut buffer, &[parent_pointer as u32]);
+    put(&mut buffer, &[1, 0]);
+    put(&mut buffer, &[0; 18]);
+    strings.push((put_placeholder(&mut buffer), name));
+    put(&mut buffer, &[0, 0, 0]);
+    
+    // materials
+    point_here(&mut buffer, material_dict_location);
+    let material_location = put_dict(&mut buffer, &mut strings, material_name);
+    point_here(&mut buffer, material_location);
+    put_material(&mut buffer, &mut strings, material_name, texture_name);
+    
+    // shape, with its bounding box, a sub mesh and the vertex buffers
+    put_single_pointer_list(&mut buffer, shape_list_location);
+    put(&mut buffer, &[0x10000001]);
+    buffer.extend(b"SOBJ");
+    put(&mut buffer, &[0, 0, 0, 0]);
+    put(&mut buffer, &[0]);
+    let bounding_box_location = put_placeholder(&mut buffer);
+    put_floats(&mut buffer, &[0.0, 0.0, 0.0]);
+    put(&mut buffer, &[1]);
+    let sub_mesh_list_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[0, 2]);
+    let vertex_buffer_list_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[0]);
+    
+    point_here(&mut buffer, bounding_box_location);
+    put(&mut buffer, &[0]);
+    put_floats(&mut buffer, &[0.5, 0.5, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0]);
+    
+    // sub mesh without skinning, with a single face and face descriptor
+    put_single_pointer_list(&mut buffer, sub_mesh_list_location);
+    put(&mut buffer, &[0, 0, 0, 1]);
+    let face_list_location = put_placeholder(&mut buffer);
+    
+    put_single_pointer_list(&mut buffer, face_list_location);
+    put(&mut buffer, &[1]);
+    let face_descriptor_list_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[1]);
+    let buffer_obj_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[0, 0]);
+    point_here(&mut buffer, buffer_obj_location);
+    put(&mut buffer, &[0]);
+    
+    put_single_pointer_list(&mut buffer, face_descriptor_list_location);
+    put(&mut buffer, &[0x1401]);
+    buffer.extend([0, 1, 0, 0]);
+    put(&mut buffer, &[3]);
+    let index_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[0; 7]);
+    
+    // interleaved positions and a fixed color
+    point_here(&mut buffer, vertex_buffer_list_location);
+    let interleaved_location = put_placeholder(&mut buffer);
+    let fixed_location = put_placeholder(&mut buffer);
+    
+    point_here(&mut buffer, interleaved_location);
+    put(&mut buffer, &[0x40000002, 21, 2, 0, 0, 36]);
+    let vertex_location = put_placeholder(&mut buffer);
+    put(&mut buffer, &[0, 0, 12, 1]);
+    let attribute_list_location = put_placeholder(&mut buffer);
+    
+    put_single_pointer_list(&mut buffer, attribute_list_location);
+    put(&mut buffer, &[0x40000001, 0, 0, 0, 0, 0, 0, 0, 0, 0x1406, 3]);
+    put_floats(&mut buffer, &[1.0]);
+    put(&mut buffer, &[0]);
+    
+    point_here(&mut buffer, fixed_location);
+    put(&mut buffer, &[0x80000000, 3, 1, 0x1406, 4]);
+    put_floats(&mut buffer, &[1.0]);
+    put(&mut buffer, &[4]);
+    let vector_location = put_placeholder(&mut buffer);
+    point_here(&mut buffer, vector_location);
+    put_floats(&mut buffer, &[1.0, 0.5, 0.25, 1.0]);
+    
+    put_strings(&mut buffer, strings);
+    
+    // image section with the indices and vertices, the DATA section includes the padding before it
+    buffer.resize((buffer.len() + 8).next_multiple_of(0x80) - 8, 0);
+    let content_length = buffer.len() as u32 - 0x14;
+    buffer.extend(b"IMAG");
+    put(&mut buffer, &[8 + 4 + 36]);
+    
+    point_here(&mut buffer, index_location);
+    buffer.extend([0, 1, 2, 0]);
+    point_here(&mut buffer, vertex_location);
+    put_floats(&mut buffer, &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+    
+    let file_length = buffer.len() as u32;
+    buffer[0x0C..0x10].copy_from_slice(&file_length.to_le_bytes());
+    buffer[0x18..0x1C].copy_from_slice(&content_length.to_le_bytes());
+    buffer
+}
+
+/// A container with a white triangle model and textures of the given formats.
+pub fn model_container(formats: &[PicaTextureFormat]) -> Result<CgfxContainer> {
+    let mut common: CgfxModelCommon = serde_yaml::from_str(TRIANGLE_MODEL)?;
+    let white = Material {
+        colors: MaterialColors {
+            emission_float: Vec4::new(1.0, 1.0, 1.0, 1.0),
+            emission: RgbaColor { r: 255, g: 255, b: 255, a: 255 },
+            ..MaterialColors::default()
+        },
+        ..Material::new("white")
+    };
+    
+    common.materials = Some(CgfxDict::from_values("DICT", vec![("white".to_string(), white)])?);
+    
+    let model = CgfxModel::Standard(common);
+    let mut builder = CgfxContainer::builder().model(model);
+    
+    for &format in formats {
+        let name = texture_id(format);
+        let texture = CgfxContainer::new(&texture_bcres(&name, format, 8, 16))?
+            .textures.and_then(|mut textures| textures.remove(&name).ok().flatten())
+            .expect("Texture fixture contains its texture");
+        
+        builder = builder.texture(texture);
+    }
+    
+    Ok(builder.build()?)
+}
+
+/// `model_container` with the triangle rigidly skinned to a bone 5 units above the origin.
+pub fn skeletal_model_container(formats: &[PicaTextureFormat]) -> Result<CgfxContainer> {
+    let mut gfx = model_container(formats)?;
+    let models = gfx.models.as_mut().expect("Model container has models");
+    let mut common = match models.remove("triangle")?.expect("Model container has the triangle") {
+        CgfxModel::Standard(common) | CgfxModel::Skeletal(common, _) => common,
+    };
+    
+    let shape = &mut common.shapes.as_mut().expect("Triangle has a shape")[0];
+    
+    for sub_mesh in shape.sub_meshes.iter_mut().flatten() {
+        sub_mesh.skinning = SubMeshSkinning::Rigid;
+        sub_mesh.bone_indices = Some(vec![0]);
+    }
+    
+    shape.vertex_buffers.get_or_insert_with(Vec::new).push(VertexBuffer::Fixed(VertexBufferFixed {
+        vertex_buffer_common: VertexBufferCommon {
+            attribute_name: AttributeName::BoneIndex,
+            vertex_buffer_type: VertexBufferType::Fixed,
+        },
+        format: GlDataType::UByte,
+        elements: 1,
+        scale: 1.0,
+        vector: Some(vec![0.0]),
+    }));
+    
+    let mut skeleton: CgfxSkeleton = serde_yaml::from_str(LIFTED_SKELETON)?;
+    skeleton.bones = Some(CgfxDict::from_values("DICT", vec![("root".to_string(), serde_yaml::from_str(LIFTED_BONE)?)])?);
+    
+    models.insert("triangle", CgfxModel::Skeletal(common, skeleton))?;
+    gfx.update_header()?;
+    Ok(gfx)
+}
+
+/// The shape of the triangle model with 4 bytes of padding in front of and after every position.
+pub fn padded_triangle_shape() -> Result<Shape> {
+    let common: CgfxModelCommon = serde_yaml::from_str(TRIANGLE_MODEL)?;
+    let mut shape = common.shapes.expect("Triangle has a shape").remove(0);
+    
+    let Some(VertexBuffer::Interleaved(interleaved)) = shape.vertex_buffers.as_mut().and_then(|buffers| buffers.first_mut()) else {
+        panic!("Triangle has no interleaved vertex buffer");
+    };
+    
+    let mut raw_bytes = Vec::new();
+    
+    for position in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+        raw_bytes.extend([0xAA; 4]);
+        raw_bytes.extend(position.iter().flat_map(|value| value.to_le_bytes()));
+        raw_bytes.extend([0xAA; 4]);
+    }
+    
+    interleaved.raw_bytes = Some(raw_bytes);
+    interleaved.vertex_stride = 20;
+    interleaved.attributes.as_mut().expect("Triangle has interleaved attributes")[0].offset = 4;
+    Ok(shape)
+}
+
+pub fn sparkle_emitter() -> Result<CgfxEmitter> {
+    Ok(serde_yaml::from_str(SPARKLE_EMITTER)?)
+}
+
+/// Id of the texture of `format` in `texture_archive`.
+pub fn texture_id(format: PicaTextureFormat) -> String {
+    format!("tex_{:?}", format).to_lowercase()
+}
+
+/// Writes a texture archive `{name}.bin` with its registry `{name}_info.bin` into `dir`,
+/// containing one compressed texture per format. Returns the path of the archive.
+pub fn texture_archive(dir: &Path, name: &str, formats: &[PicaTextureFormat]) -> Result<PathBuf> {
+    let mut archive = Vec::new();
+    let mut items = Vec::new();
+    
+    for (i, &format) in formats.iter().enumerate() {
+        let id = texture_id(format);
+        let mut bcres = texture_bcres(&id, format, 8 << (i % 3), 8 << (i % 2));
+        let compressed = blz_encode(&mut bcres)?;
+        
+        items.push(RegistryItem {
+            id,
+            is_readonly: None,
+            image_format: None,
+            file_offset: archive.len().try_into()?,
+            field_0x8: 0,
+            byte_length: compressed.len().try_into()?,
+        });
+        archive.extend(compressed);
+    }
+    
+    let archive_path = dir.join(format!("{}.bin", name));
+    fs::write(&archive_path, archive)?;
+    fs::write(dir.join(format!("{}_info.bin", name)), ArchiveRegistry { items }.to_buffer()?)?;
+    
+    Ok(archive_path)
+}
@@ -0,0 +1,1484 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    f32::consts::TAU,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use byteorder::{LittleEndian, ReadBytesExt};
+use gltf_json::{
+    accessor::{ComponentType, GenericComponentType, Type},
+    animation::{Interpolation, Property, Sampler},
+    buffer::{Target, View},
+    material::{AlphaMode, PbrBaseColorFactor, PbrMetallicRoughness, StrengthFactor},
+    mesh::{Mode, MorphTarget, Primitive, Semantic},
+    root::Get,
+    scene::UnitQuaternion,
+    texture::Info,
+    validation::{Checked, USize64},
+    Accessor, Buffer, Image, Index, Material, Mesh, Node, Root, Scene, Skin, Texture, Value,
+};
+use na::{Matrix3x4, Matrix4, Quaternion, Rotation3, UnitQuaternion as NaUnitQuaternion, Vec2, Vec3, Vec4};
+use nw_tex::{
+    bcres::{
+        animation::{
+            AnimCurve, AnimElement, AnimInterpolation, AnimKeyFrame, AnimLoopMode, AnimTransform, AnimValue,
+            CgfxAnimation,
+        },
+        bcres::{CgfxCollectionValue, CgfxContainer, CgfxDict},
+        image_codec::{from_png, RgbaColor},
+        model::{AttributeName, CgfxModel, MeshNodeVisibility, Shape, SubMeshSkinning, VertexBuffer},
+        skeleton::CgfxSkeleton,
+        texture::{CgfxTexture, PicaTextureFormat},
+        vertex::VertexStreams,
+    },
+    util::math::{self, EulerOrder},
+};
+
+/// All files making up an exported model.
+pub struct GltfExport {
+    pub gltf: String,
+    /// Vertex data referenced by the .gltf file
+    pub bin: Vec<u8>,
+    /// Textures referenced by the .gltf file as file name and PNG file content
+    pub textures: Vec<(String, Vec<u8>)>,
+}
+
+/// Collects the binary buffer of the exported model while creating the accessors pointing into it.
+struct BufferBuilder<'a> {
+    root: &'a mut Root,
+    bin: Vec<u8>,
+}
+
+impl BufferBuilder<'_> {
+    fn push_accessor(&mut self, bytes: &[u8], count: usize, component_type: ComponentType, type_: Type,
+            target: Option<Target>, min_max: Option<(Value, Value)>) -> Index<Accessor> {
+        // all accessors need to be aligned to their component size, 4 covers all of them
+        self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+        
+        let view = self.root.push(View {
+            buffer: Index::new(0),
+            byte_length: USize64::from(bytes.len()),
+            byte_offset: Some(USize64::from(self.bin.len())),
+            byte_stride: None,
+            name: None,
+            target: target.map(Checked::Valid),
+            extensions: None,
+            extras: Default::default(),
+        });
+        
+        self.bin.extend_from_slice(bytes);
+        
+        let (min, max) = min_max.unzip();
+        
+        self.root.push(Accessor {
+            buffer_view: Some(view),
+            byte_offset: None,
+            count: USize64::from(count),
+            component_type: Checked::Valid(GenericComponentType(component_type)),
+            type_: Checked::Valid(type_),
+            min,
+            max,
+            name: None,
+            normalized: false,
+            sparse: None,
+            extensions: None,
+            extras: Default::default(),
+        })
+    }
+    
+    fn push_floats<const N: usize>(&mut self, values: &[[f32; N]], type_: Type, target: Option<Target>, with_bounds: bool) -> Index<Accessor> {
+        let bytes: Vec<u8> = values.iter().flatten().flat_map(|value| value.to_le_bytes()).collect();
+        
+        let min_max = with_bounds.then(|| {
+            let mut min = [f32::INFINITY; N];
+            let mut max = [f32::NEG_INFINITY; N];
+            
+            for value in values {
+                for i in 0..N {
+                    min[i] = min[i].min(value[i]);
+                    max[i] = max[i].max(value[i]);
+                }
+            }
+            
+            (Value::from(min.to_vec()), Value::from(max.to_vec()))
+        });
+        
+        self.push_accessor(&bytes, values.len(), ComponentType::F32, type_, target, min_max)
+    }
+}
+
+fn vec3_array(value: &Vec3) -> [f32; 3] {
+    [value.x, value.y, value.z]
+}
+
+/// CGFX stores rotations as XYZ euler angles in radians.
+fn euler_to_quaternion(rotation: &math::Vec3) -> UnitQuaternion {
+    let quaternion = math::euler_to_quaternion(rotation, EulerOrder::XYZ);
+    UnitQuaternion([quaternion.i, quaternion.j, quaternion.k, quaternion.w])
+}
+
+fn to_matrix4(matrix: &Matrix3x4<f32>) -> Matrix4<f32> {
+    matrix.insert_row(3, 0.0).map_with_location(|row, col, value| if row == 3 && col == 3 { 1.0 } else { value })
+}
+
+fn transform_node(name: Option<String>, scale: &math::Vec3, rotation: &math::Vec3, translation: &math::Vec3) -> Node {
+    Node {
+        name,
+        scale: Some([scale.x, scale.y, scale.z]),
+        rotation: Some(euler_to_quaternion(rotation)),
+        translation: Some([translation.x, translation.y, translation.z]),
+        ..Default::default()
+    }
+}
+
+struct ExportedSkeleton {
+    skin: Index<Skin>,
+    root_bones: Vec<Index<Node>>,
+    /// The joint index in the skin for every bone index
+    joint_indices: HashMap<u32, u16>,
+}
+
+/// Adds a node for every bone as well as a skin using all of them as joints.
+fn export_skeleton(buffer: &mut BufferBuilder, skeleton: &CgfxSkeleton) -> Result<ExportedSkeleton> {
+    let bones = skeleton.bone_list();
+    
+    let joints: Vec<Index<Node>> = bones.iter()
+        .map(|bone| buffer.root.push(transform_node(bone.name.clone(),
+            &bone.transform.scale, &bone.transform.rotation, &bone.transform.translation)))
+        .collect();
+    
+    let mut root_bones = Vec::new();
+    
+    for (i, bone) in bones.iter().enumerate() {
+        match bone.parent {
+            Some(parent) => {
+                let parent_node = joints.get(parent as usize)
+                    .ok_or_else(|| anyhow!("Bone {:?} has invalid parent {}", bone.name, parent))?;
+                
+                buffer.root.nodes[parent_node.value()].children.get_or_insert_with(Vec::new).push(joints[i]);
+            },
+            None => root_bones.push(joints[i]),
+        }
+    }
+    
+    let inverse_bind_matrices: Vec<[f32; 16]> = bones.iter()
+        .map(|bone| to_matrix4(&bone.inverse_world_transform).as_slice().try_into().unwrap())
+        .collect();
+    let inverse_bind_matrices = buffer.push_floats(&inverse_bind_matrices, Type::Mat4, None, false);
+    
+    let skin = buffer.root.push(Skin {
+        extensions: None,
+        extras: Default::default(),
+        inverse_bind_matrices: Some(inverse_bind_matrices),
+        joints,
+        name: skeleton.cgfx_object_header.name.clone(),
+        skeleton: root_bones.first().copied(),
+    });
+    
+    let joint_indices = bones.iter().enumerate()
+        .map(|(i, bone)| Ok((bone.index, u16::try_from(i)?)))
+        .collect::<Result<HashMap<u32, u16>>>()?;
+    
+    Ok(ExportedSkeleton {
+        skin,
+        root_bones,
+        joint_indices,
+    })
+}
+
+/// Exports a single shape as a primitive with all of its vertex attributes and blend shape targets.
+fn export_shape(buffer: &mut BufferBuilder, shape: &Shape, skeleton: Option<&CgfxSkeleton>,
+        joint_indices: &HashMap<u32, u16>, material: Option<Index<Material>>) -> Result<(Primitive, Vec<f32>)> {
+    let streams = shape.vertex_streams()?;
+    let skins = shape.vertex_skins(&streams)?;
+    let is_skinned = skins.iter().any(Option::is_some);
+    
+    // vertices of rigidly skinned sub meshes are relative to their bone,
+    // unlike glTF which expects every vertex to be in model space
+    let mut model_space = streams.clone();
+    
+    if let Some(skeleton) = skeleton {
+        shape.apply_rigid_skins(&mut model_space, skeleton)?;
+    }
+    
+    let VertexStreams { positions, normals, .. } = model_space;
+    
+    let mut attributes = BTreeMap::new();
+    
+    let position_values: Vec<[f32; 3]> = positions.iter().map(vec3_array).collect();
+    attributes.insert(Checked::Valid(Semantic::Positions),
+        buffer.push_floats(&position_values, Type::Vec3, Some(Target::ArrayBuffer), true));
+    
+    if let Some(normals) = &normals {
+        let values: Vec<[f32; 3]> = normals.iter().map(vec3_array).collect();
+        attributes.insert(Checked::Valid(Semantic::Normals), buffer.push_floats(&values, Type::Vec3, Some(Target::ArrayBuffer), false));
+    }
+    
+    if let Some(tangents) = &streams.tangents {
+        // glTF tangents have a fourth component for the handedness of the bitangent
+        let values: Vec<[f32; 4]> = tangents.iter().map(|tangent| [tangent.x, tangent.y, tangent.z, 1.0]).collect();
+        attributes.insert(Checked::Valid(Semantic::Tangents), buffer.push_floats(&values, Type::Vec4, Some(Target::ArrayBuffer), false));
+    }
+    
+    if let Some(colors) = &streams.colors {
+        let values: Vec<[f32; 4]> = colors.iter().map(|color| [color.x, color.y, color.z, color.w]).collect();
+        attributes.insert(Checked::Valid(Semantic::Colors(0)), buffer.push_floats(&values, Type::Vec4, Some(Target::ArrayBuffer), false));
+    }
+    
+    for (i, tex_coords) in streams.tex_coords.iter().enumerate() {
+        if let Some(tex_coords) = tex_coords {
+            // glTF has the origin of texture coordinates in the top left, CGFX in the bottom left
+            let values: Vec<[f32; 2]> = tex_coords.iter().map(|uv| [uv.x, 1.0 - uv.y]).collect();
+            attributes.insert(Checked::Valid(Semantic::TexCoords(i as u32)),
+                buffer.push_floats(&values, Type::Vec2, Some(Target::ArrayBuffer), false));
+        }
+    }
+    
+    if is_skinned {
+        let mut joints: Vec<u8> = Vec::with_capacity(skins.len() * 8);
+        let mut weights: Vec<[f32; 4]> = Vec::with_capacity(skins.len());
+        
+        for skin in &skins {
+            // vertices which aren't used by any skinned sub mesh still need a valid skin
+            let skin = skin.unwrap_or_default();
+            
+            for (bone, weight) in skin.bones.iter().zip(skin.weights) {
+                let joint = if weight > 0.0 {
+                    *joint_indices.get(bone).ok_or_else(|| anyhow!("Vertex is skinned to missing bone {}", bone))?
+                } else {
+                    0
+                };
+                
+                joints.extend_from_slice(&joint.to_le_bytes());
+            }
+            
+            weights.push(if skin.weights == [0.0; 4] { [1.0, 0.0, 0.0, 0.0] } else { skin.weights });
+        }
+        
+        attributes.insert(Checked::Valid(Semantic::Joints(0)),
+            buffer.push_accessor(&joints, skins.len(), ComponentType::U16, Type::Vec4, Some(Target::ArrayBuffer), None));
+        attributes.insert(Checked::Valid(Semantic::Weights(0)),
+            buffer.push_floats(&weights, Type::Vec4, Some(Target::ArrayBuffer), false));
+    }
+    
+    // blend shapes, glTF stores the difference to the base geometry instead of the absolute values
+    let mut targets = Vec::new();
+    let mut target_weights = Vec::new();
+    
+    for target in shape.blend_shape_target_streams()? {
+        let target_positions = target.positions.map(|target_positions| {
+            let values: Vec<[f32; 3]> = target_positions.iter().zip(&streams.positions)
+                .map(|(target, base)| vec3_array(&(target - base)))
+                .collect();
+            buffer.push_floats(&values, Type::Vec3, None, true)
+        });
+        
+        let target_normals = target.normals.zip(streams.normals.as_ref()).map(|(target_normals, base_normals)| {
+            let values: Vec<[f32; 3]> = target_normals.iter().zip(base_normals)
+                .map(|(target, base)| vec3_array(&(target - base)))
+                .collect();
+            buffer.push_floats(&values, Type::Vec3, None, false)
+        });
+        
+        targets.push(MorphTarget {
+            positions: target_positions,
+            normals: target_normals,
+            tangents: None,
+        });
+        target_weights.push(target.weight);
+    }
+    
+    // indices
+    let face_descriptors = shape.sub_meshes.iter().flatten()
+        .flat_map(|sub_mesh| sub_mesh.faces.iter().flatten())
+        .flat_map(|face| face.face_descriptors.iter().flatten());
+    
+    let mut indices: Vec<u8> = Vec::new();
+    let mut index_count = 0;
+    
+    for face_descriptor in face_descriptors {
+        for index in face_descriptor.triangles()?.iter().flatten() {
+            indices.extend_from_slice(&index.to_le_bytes());
+            index_count += 1;
+        }
+    }
+    
+    let indices = buffer.push_accessor(&indices, index_count, ComponentType::U16, Type::Scalar, Some(Target::ElementArrayBuffer), None);
+    
+    let primitive = Primitive {
+        attributes,
+        extensions: None,
+        extras: Default::default(),
+        indices: Some(indices),
+        material,
+        mode: Checked::Valid(Mode::Triangles),
+        targets: (!targets.is_empty()).then_some(targets),
+    };
+    
+    Ok((primitive, target_weights))
+}
+
+fn export_material(root: &mut Root, name: &str, material: &nw_tex::bcres::material::Material, texture: Option<Index<Texture>>) -> Index<Material> {
+    let diffuse: &Vec4 = &material.colors.diffuse_float.to_na();
+    let emission = &material.colors.emission_float;
+    let tex_coord = u32::try_from(material.texture_coordinators[0].source_coordinate_index).unwrap_or(0);
+    
+    root.push(Material {
+        name: Some(name.to_string()),
+        alpha_mode: Checked::Valid(if diffuse.w < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque }),
+        pbr_metallic_roughness: PbrMetallicRoughness {
+            base_color_factor: PbrBaseColorFactor([diffuse.x, diffuse.y, diffuse.z, diffuse.w]),
+            base_color_texture: texture.map(|index| Info {
+                index,
+                tex_coord,
+                extensions: None,
+                extras: Default::default(),
+            }),
+            metallic_factor: StrengthFactor(0.0),
+            ..Default::default()
+        },
+        emissive_factor: gltf_json::material::EmissiveFactor([emission.x, emission.y, emission.z]),
+        ..Default::default()
+    })
+}
+
+/// Exports `model` into a .gltf file, a binary buffer called `bin_file_name` with its vertex data
+/// and PNG versions of the textures its materials use, which have to be taken from `textures`.
+/// Skeletal models also get their bones as nodes and a skin.
+pub fn export_bcres_to_gltf(model: &CgfxModel, textures: Option<&CgfxDict<CgfxTexture>>, bin_file_name: &str) -> Result<GltfExport> {
+    let common = model.common();
+    let shapes: &[Shape] = common.shapes.as_deref().unwrap_or_default();
+    
+    let mut root = Root::default();
+    root.asset.generator = Some(format!("nw-tex {}", env!("CARGO_PKG_VERSION")));
+    
+    let mut buffer = BufferBuilder {
+        root: &mut root,
+        bin: Vec::new(),
+    };
+    
+    let transform = &common.transform_node_header;
+    let model_node = transform_node(common.cgfx_object_header.name.clone(), &transform.scale, &transform.rotation, &transform.translation);
+    let mut model_children = Vec::new();
+    
+    // skeleton
+    let skeleton = model.skeleton();
+    
+    let (skin, joint_indices) = match skeleton {
+        Some(skeleton) => {
+            let exported = export_skeleton(&mut buffer, skeleton)?;
+            
+            model_children.extend(exported.root_bones);
+            (Some(exported.skin), exported.joint_indices)
+        },
+        None => (None, HashMap::new()),
+    };
+    
+    // materials
+    let mut exported_textures: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut gltf_textures: HashMap<&str, Index<Texture>> = HashMap::new();
+    let mut materials: Vec<Index<Material>> = Vec::new();
+    
+    let material_nodes = common.materials.iter()
+        .flat_map(CgfxDict::iter);
+    
+    for (i, (name, material)) in material_nodes.enumerate() {
+        let name = name.map_or_else(|| format!("material{}", i), str::to_string);
+        
+        // textures which aren't part of this file, e.g. because they are shared between models, are left out
+        let cgfx_texture = material.base_texture_name()
+            .and_then(|texture_name| Some((texture_name, textures?.get(texture_name)?)));
+        
+        let texture = match cgfx_texture {
+            Some((texture_name, cgfx_texture)) => match gltf_textures.get(texture_name) {
+                Some(texture) => Some(*texture),
+                None => {
+                    let file_name = format!("{}.png", texture_name);
+                    exported_textures.push((file_name.clone(), cgfx_texture.to_png()?));
+                    
+                    let image = buffer.root.push(Image {
+                        buffer_view: None,
+                        mime_type: None,
+                        name: Some(texture_name.to_string()),
+                        uri: Some(file_name),
+                        extensions: None,
+                        extras: Default::default(),
+                    });
+                    
+                    let texture = buffer.root.push(Texture {
+                        name: Some(texture_name.to_string()),
+                        sampler: None,
+                        source: image,
+                        extensions: None,
+                        extras: Default::default(),
+                    });
+                    
+                    gltf_textures.insert(texture_name, texture);
+                    Some(texture)
+                },
+            },
+            None => None,
+        };
+        
+        materials.push(export_material(buffer.root, &name, material, texture));
+    }
+    
+    // geometry
+    let mesh_list: Vec<(Option<String>, usize, Option<Index<Material>>)> = match &common.meshes {
+        Some(meshes) => meshes.iter().enumerate()
+            .map(|(i, mesh)| (
+                Some(mesh.cgfx_object_header.name.clone().unwrap_or_else(|| format!("mesh{}", i))),
+                mesh.shape_index as usize,
+                materials.get(mesh.material_index as usize).copied(),
+            ))
+            .collect(),
+        None => (0..shapes.len()).map(|i| (Some(format!("mesh{}", i)), i, None)).collect(),
+    };
+    
+    for (name, shape_index, material) in mesh_list {
+        let shape = shapes.get(shape_index)
+            .ok_or_else(|| anyhow!("Mesh {:?} references missing shape {}", name, shape_index))?;
+        
+        let (primitive, weights) = export_shape(&mut buffer, shape, skeleton, &joint_indices, material)?;
+        let is_skinned = primitive.attributes.contains_key(&Checked::Valid(Semantic::Joints(0)));
+        
+        let mesh = buffer.root.push(Mesh {
+            extensions: None,
+            extras: Default::default(),
+            name: name.clone(),
+            primitives: vec![primitive],
+            weights: (!weights.is_empty()).then_some(weights),
+        });
+        
+        model_children.push(buffer.root.push(Node {
+            name,
+            mesh: Some(mesh),
+            skin: if is_skinned { skin } else { None },
+            ..Default::default()
+        }));
+    }
+    
+    let bin = buffer.bin;
+    
+    root.push(Buffer {
+        byte_length: USize64::from(bin.len()),
+        name: None,
+        uri: Some(bin_file_name.to_string()),
+        extensions: None,
+        extras: Default::default(),
+    });
+    
+    let model_node = root.push(Node {
+        children: (!model_children.is_empty()).then_some(model_children),
+        ..model_node
+    });
+    
+    let scene = root.push(Scene {
+        extensions: None,
+        extras: Default::default(),
+        name: None,
+        nodes: vec![model_node],
+    });
+    root.scene = Some(scene);
+    
+    Ok(GltfExport {
+        gltf: root.to_string_pretty()?,
+        bin,
+        textures: exported_textures,
+    })
+}
+
+const GLB_JSON_CHUNK: u32 = 0x4E4F534A;
+const GLB_BIN_CHUNK: u32 = 0x004E4942;
+
+/// The maximum amount of bones a single sub mesh can use.
+const MAX_BONE_PALETTE_SIZE: usize = 20;
+
+/// A parsed .gltf or .glb file along with the content of all of its buffers.
+struct GltfDocument {
+    root: Root,
+    buffers: Vec<Vec<u8>>,
+    /// Directory which external buffers and images are relative to
+    base_dir: PathBuf,
+}
+
+/// Reads the content of a data URI or of a file relative to `base_dir`.
+fn read_uri(uri: &str, base_dir: &Path) -> Result<Vec<u8>> {
+    match uri.strip_prefix("data:") {
+        Some(data) => {
+            let (_, encoded) = data.split_once(";base64,")
+                .ok_or_else(|| anyhow!("Only base64 encoded data URIs are supported"))?;
+            
+            Ok(BASE64_STANDARD.decode(encoded)?)
+        },
+        None => {
+            let path = base_dir.join(uri.replace("%20", " "));
+            fs::read(&path).map_err(|error| anyhow!("Could not read {:?}: {}", path, error))
+        },
+    }
+}
+
+fn read_component(bytes: &[u8], component_type: ComponentType, normalized: bool) -> f32 {
+    let (value, max_value) = match component_type {
+        ComponentType::I8 => (f32::from(bytes[0] as i8), 127.0),
+        ComponentType::U8 => (f32::from(bytes[0]), 255.0),
+        ComponentType::I16 => (f32::from(i16::from_le_bytes([bytes[0], bytes[1]])), 32767.0),
+        ComponentType::U16 => (f32::from(u16::from_le_bytes([bytes[0], bytes[1]])), 65535.0),
+        ComponentType::U32 => (u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32, u32::MAX as f32),
+        ComponentType::F32 => return f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    };
+    
+    if normalized {
+        (value / max_value).max(-1.0)
+    } else {
+        value
+    }
+}
+
+impl GltfDocument {
+    fn load(bytes: &[u8], base_dir: &Path) -> Result<Self> {
+        let (json, bin_chunk) = if bytes.starts_with(b"glTF") {
+            let mut reader = Cursor::new(bytes);
+            reader.set_position(12);
+            
+            let mut json = None;
+            let mut bin_chunk = None;
+            
+            while (reader.position() as usize) < bytes.len() {
+                let length = reader.read_u32::<LittleEndian>()? as usize;
+                let chunk_type = reader.read_u32::<LittleEndian>()?;
+                
+                let start = reader.position() as usize;
+                let chunk = bytes.get(start..start + length)
+                    .ok_or_else(|| anyhow!("GLB chunk at {:#x} is out of bounds", start))?;
+                
+                match chunk_type {
+                    GLB_JSON_CHUNK => json = Some(chunk),
+                    GLB_BIN_CHUNK => bin_chunk = Some(chunk),
+                    _ => {},
+                }
+                
+                reader.set_position((start + length) as u64);
+            }
+            
+            (json.ok_or_else(|| anyhow!("GLB file has no JSON chunk"))?, bin_chunk)
+        } else {
+            (bytes, None)
+        };
+        
+        let root: Root = serde_json::from_slice(json)?;
+        
+        let buffers = root.buffers.iter()
+            .map(|buffer| match &buffer.uri {
+                Some(uri) => read_uri(uri, base_dir),
+                None => bin_chunk.map(<[u8]>::to_vec)
+                    .ok_or_else(|| anyhow!("Buffer {:?} has neither a URI nor a GLB chunk", buffer.name)),
+            })
+            .collect::<Result<Vec<Vec<u8>>>>()?;
+        
+        Ok(Self {
+            root,
+            buffers,
+            base_dir: base_dir.to_owned(),
+        })
+    }
+    
+    fn get<T>(&self, index: Index<T>) -> Result<&T>
+    where
+        Root: Get<T>,
+    {
+        self.root.get(index)
+            .ok_or_else(|| anyhow!("Index {} is out of bounds", index.value()))
+    }
+    
+    fn view_bytes(&self, view: &View) -> Result<&[u8]> {
+        let start = view.byte_offset.map_or(0, |offset| offset.0 as usize);
+        let end = start + view.byte_length.0 as usize;
+        
+        self.buffers.get(view.buffer.value())
+            .and_then(|buffer| buffer.get(start..end))
+            .ok_or_else(|| anyhow!("Buffer view {:?} is out of bounds", view.name))
+    }
+    
+    /// Reads every element of an accessor as floats, mapping normalized integers to 0..1 or -1..1.
+    fn read_accessor(&self, index: Index<Accessor>) -> Result<Vec<Vec<f32>>> {
+        let accessor = self.get(index)?;
+        
+        if accessor.sparse.is_some() {
+            return Err(anyhow!("Accessor {} is sparse, which is not supported", index.value()));
+        }
+        
+        let (Checked::Valid(GenericComponentType(component_type)), Checked::Valid(type_)) = (accessor.component_type, accessor.type_) else {
+            return Err(anyhow!("Accessor {} has an invalid type", index.value()));
+        };
+        
+        let count = accessor.count.0 as usize;
+        let components = type_.multiplicity();
+        
+        // accessors without a buffer view are all zeros
+        let Some(view) = accessor.buffer_view else {
+            return Ok(vec![vec![0.0; components]; count]);
+        };
+        
+        let view = self.get(view)?;
+        let bytes = self.view_bytes(view)?;
+        
+        let component_size = component_type.size();
+        let stride = view.byte_stride.map_or(components * component_size, |stride| stride.0);
+        let offset = accessor.byte_offset.map_or(0, |offset| offset.0 as usize);
+        
+        (0..count)
+            .map(|i| (0..components)
+                .map(|j| {
+                    let start = offset + i * stride + j * component_size;
+                    let component = bytes.get(start..start + component_size)
+                        .ok_or_else(|| anyhow!("Accessor {} is out of bounds", index.value()))?;
+                    
+                    Ok(read_component(component, component_type, accessor.normalized))
+                })
+                .collect())
+            .collect()
+    }
+    
+    fn image_bytes(&self, image: &Image) -> Result<Vec<u8>> {
+        match (&image.uri, image.buffer_view) {
+            (Some(uri), _) => read_uri(uri, &self.base_dir),
+            (None, Some(view)) => Ok(self.view_bytes(self.get(view)?)?.to_vec()),
+            (None, None) => Err(anyhow!("Image {:?} has no data", image.name)),
+        }
+    }
+    
+    /// The parent of every node, if it has one.
+    fn node_parents(&self) -> Vec<Option<usize>> {
+        let mut parents = vec![None; self.root.nodes.len()];
+        
+        for (i, node) in self.root.nodes.iter().enumerate() {
+            for child in node.children.iter().flatten() {
+                if let Some(parent) = parents.get_mut(child.value()) {
+                    *parent = Some(i);
+                }
+            }
+        }
+        
+        parents
+    }
+}
+
+fn component(values: &[f32], index: usize) -> f32 {
+    values.get(index).copied().unwrap_or_default()
+}
+
+fn to_vec3(values: &[f32]) -> Vec3 {
+    Vec3::new(component(values, 0), component(values, 1), component(values, 2))
+}
+
+fn to_matrix3x4(matrix: &Matrix4<f32>) -> Matrix3x4<f32> {
+    matrix.fixed_view::<3, 4>(0, 0).into_owned()
+}
+
+/// Scale, rotation and translation of a node, taken apart from its matrix if it has one.
+fn node_srt(node: &Node) -> (Vec3, NaUnitQuaternion<f32>, Vec3) {
+    match node.matrix {
+        Some(matrix) => {
+            let matrix = Matrix4::from_column_slice(&matrix);
+            let linear = matrix.fixed_view::<3, 3>(0, 0);
+            let scale = Vec3::new(linear.column(0).norm(), linear.column(1).norm(), linear.column(2).norm());
+            let rotation = Rotation3::from_matrix(&linear.into_owned().map_with_location(|_, col, value| value / scale[col]));
+            
+            (scale, NaUnitQuaternion::from_rotation_matrix(&rotation), matrix.fixed_view::<3, 1>(0, 3).into_owned())
+        },
+        None => {
+            let [x, y, z, w] = node.rotation.unwrap_or_default().0;
+            
+            (
+                node.scale.map_or(Vec3::new(1.0, 1.0, 1.0), Vec3::from),
+                NaUnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z)),
+                node.translation.map_or(Vec3::zeros(), Vec3::from),
+            )
+        },
+    }
+}
+
+fn node_matrix(node: &Node) -> Matrix4<f32> {
+    match node.matrix {
+        Some(matrix) => Matrix4::from_column_slice(&matrix),
+        None => {
+            let (scale, rotation, translation) = node_srt(node);
+            Matrix4::new_translation(&translation) * rotation.to_homogeneous() * Matrix4::new_nonuniform_scaling(&scale)
+        },
+    }
+}
+
+/// A glTF primitive converted to what a CGFX shape needs.
+struct ImportedPrimitive {
+    name: String,
+    streams: VertexStreams,
+    triangles: Vec<[u16; 3]>,
+    /// Skin joints used by this primitive, the bone indices of `streams` index into it
+    bone_palette: Option<Vec<u32>>,
+    material: Option<usize>,
+}
+
+/// Converts a primitive, moving its vertices into model space by `world_transform`
+/// unless it is skinned, as the vertices of skinned primitives already are in model space.
+fn import_primitive(document: &GltfDocument, name: String, primitive: &Primitive, world_transform: Option<&Matrix4<f32>>) -> Result<ImportedPrimitive> {
+    if primitive.mode != Checked::Valid(Mode::Triangles) {
+        return Err(anyhow!("Primitive of {:?} does not consist of triangles", name));
+    }
+    
+    let attribute = |semantic: Semantic| {
+        primitive.attributes.get(&Checked::Valid(semantic))
+            .map(|accessor| document.read_accessor(*accessor))
+            .transpose()
+    };
+    
+    let positions = attribute(Semantic::Positions)?
+        .ok_or_else(|| anyhow!("Primitive of {:?} has no positions", name))?;
+    
+    let mut streams = VertexStreams {
+        positions: positions.iter().map(|value| to_vec3(value)).collect(),
+        normals: attribute(Semantic::Normals)?
+            .map(|normals| normals.iter().map(|value| to_vec3(value)).collect()),
+        tangents: attribute(Semantic::Tangents)?
+            .map(|tangents| tangents.iter().map(|value| to_vec3(value)).collect()),
+        colors: attribute(Semantic::Colors(0))?
+            .map(|colors| colors.iter()
+                .map(|value| Vec4::new(component(value, 0), component(value, 1), component(value, 2), *value.get(3).unwrap_or(&1.0)))
+                .collect()),
+        ..Default::default()
+    };
+    
+    for (i, tex_coords) in streams.tex_coords.iter_mut().enumerate() {
+        // glTF has the origin of texture coordinates in the top left, CGFX in the bottom left
+        *tex_coords = attribute(Semantic::TexCoords(i as u32))?
+            .map(|values| values.iter().map(|value| Vec2::new(component(value, 0), 1.0 - component(value, 1))).collect());
+    }
+    
+    if let Some(world_transform) = world_transform {
+        let normal_matrix = world_transform.fixed_view::<3, 3>(0, 0).try_inverse().unwrap_or_default().transpose();
+        
+        for position in &mut streams.positions {
+            *position = world_transform.transform_point(&(*position).into()).coords;
+        }
+        
+        for normal in streams.normals.iter_mut().flatten() {
+            *normal = (normal_matrix * *normal).normalize();
+        }
+        
+        for tangent in streams.tangents.iter_mut().flatten() {
+            *tangent = world_transform.transform_vector(tangent).normalize();
+        }
+    }
+    
+    // skinning, every vertex gets its joints as indices into the bone palette of the primitive
+    let bone_palette = match (attribute(Semantic::Joints(0))?, attribute(Semantic::Weights(0))?) {
+        (Some(joints), Some(weights)) => {
+            let mut bone_palette: Vec<u32> = Vec::new();
+            let mut bone_indices = Vec::with_capacity(joints.len());
+            
+            for (vertex_joints, vertex_weights) in joints.iter().zip(&weights) {
+                let mut indices = [0; 4];
+                
+                for (i, index) in indices.iter_mut().enumerate() {
+                    if component(vertex_weights, i) <= 0.0 {
+                        continue;
+                    }
+                    
+                    let joint = component(vertex_joints, i) as u32;
+                    
+                    *index = match bone_palette.iter().position(|bone| *bone == joint) {
+                        Some(palette_index) => palette_index as u32,
+                        None => {
+                            bone_palette.push(joint);
+                            bone_palette.len() as u32 - 1
+                        },
+                    };
+                }
+                
+                bone_indices.push(indices);
+            }
+            
+            if bone_palette.len() > MAX_BONE_PALETTE_SIZE {
+                return Err(anyhow!("Primitive of {:?} uses {} bones, but at most {} are supported, try splitting it up",
+                    name, bone_palette.len(), MAX_BONE_PALETTE_SIZE));
+            }
+            
+            streams.bone_indices = Some(bone_indices);
+            streams.bone_weights = Some(weights.iter()
+                .map(|value| Vec4::new(component(value, 0), component(value, 1), component(value, 2), component(value, 3)))
+                .collect());
+            
+            Some(bone_palette)
+        },
+        _ => None,
+    };
+    
+    // triangles
+    let indices: Vec<u32> = match primitive.indices {
+        Some(indices) => document.read_accessor(indices)?.iter().map(|value| component(value, 0) as u32).collect(),
+        None => (0..streams.vertex_count() as u32).collect(),
+    };
+    
+    let triangles = indices.chunks_exact(3)
+        .map(|triangle| Ok([u16::try_from(triangle[0])?, u16::try_from(triangle[1])?, u16::try_from(triangle[2])?]))
+        .collect::<Result<Vec<[u16; 3]>>>()?;
+    
+    Ok(ImportedPrimitive {
+        name,
+        streams,
+        triangles,
+        bone_palette,
+        material: primitive.material.map(|material| material.value()),
+    })
+}
+
+/// The attributes of a shape which have a value for each vertex.
+fn shape_attributes(shape: &Shape) -> Vec<AttributeName> {
+    shape.vertex_buffers.iter().flatten()
+        .flat_map(|vertex_buffer| match vertex_buffer {
+            VertexBuffer::Attribute(attribute) => vec![attribute.attribute_name],
+            VertexBuffer::Interleaved(interleaved) => interleaved.attributes.iter().flatten()
+                .map(|attribute| attribute.attribute_name)
+                .collect(),
+            VertexBuffer::Fixed(_) => Vec::new(),
+        })
+        .collect()
+}
+
+/// Picks the shape whose vertex layout fits `primitive` best, as there is no way
+/// to create a shape from scratch which the game is guaranteed to be able to render.
+fn find_template_shape<'a>(shapes: &'a [Shape], primitive: &ImportedPrimitive) -> Result<&'a Shape> {
+    let score = |shape: &Shape| {
+        let attributes = shape_attributes(shape);
+        let has = |attribute: AttributeName| attributes.contains(&attribute);
+        let is_smooth_skinned = shape.sub_meshes.iter().flatten().next()
+            .is_some_and(|sub_mesh| sub_mesh.skinning == SubMeshSkinning::Smooth);
+        
+        let matches = [
+            is_smooth_skinned == primitive.bone_palette.is_some(),
+            has(AttributeName::Normal) == primitive.streams.normals.is_some(),
+            has(AttributeName::Tangent) == primitive.streams.tangents.is_some(),
+            has(AttributeName::Color) == primitive.streams.colors.is_some(),
+            has(AttributeName::TexCoord0) == primitive.streams.tex_coords[0].is_some(),
+            has(AttributeName::TexCoord1) == primitive.streams.tex_coords[1].is_some(),
+            has(AttributeName::TexCoord2) == primitive.streams.tex_coords[2].is_some(),
+        ];
+        
+        // skinning is more important than all other attributes combined
+        matches.iter().enumerate()
+            .filter(|(_, matches)| **matches)
+            .map(|(i, _)| if i == 0 { matches.len() } else { 1 })
+            .sum::<usize>()
+    };
+    
+    let shape = shapes.iter().max_by_key(|shape| score(shape))
+        .ok_or_else(|| anyhow!("Template model has no shapes"))?;
+    
+    if primitive.bone_palette.is_some() {
+        let attributes = shape_attributes(shape);
+        
+        if !attributes.contains(&AttributeName::BoneIndex) || !attributes.contains(&AttributeName::BoneWeight) {
+            return Err(anyhow!("Template model has no skinned shape to import {:?} with", primitive.name));
+        }
+    }
+    
+    Ok(shape)
+}
+
+/// Builds a skeleton with a bone for every joint of `skin`, keeping their order so vertices
+/// can refer to bones by their joint index.
+fn import_skeleton(document: &GltfDocument, skin: &Skin, template: &CgfxSkeleton) -> Result<CgfxSkeleton> {
+    let template_bone = template.bone_list().first().copied().cloned()
+        .ok_or_else(|| anyhow!("Template skeleton {:?} has no bones", template.cgfx_object_header.name))?;
+    
+    let node_parents = document.node_parents();
+    let joint_index = |node: usize| skin.joints.iter().position(|joint| joint.value() == node);
+    
+    // the closest ancestor of every joint which is also a joint
+    let joint_parents: Vec<Option<usize>> = skin.joints.iter()
+        .map(|joint| {
+            let mut node = joint.value();
+            
+            while let Some(parent) = node_parents.get(node).copied().flatten() {
+                if let Some(parent_joint) = joint_index(parent) {
+                    return Some(parent_joint);
+                }
+                
+                node = parent;
+            }
+            
+            None
+        })
+        .collect();
+    
+    let local_transforms: Vec<Matrix4<f32>> = skin.joints.iter()
+        .map(|joint| Ok(node_matrix(document.get(*joint)?)))
+        .collect::<Result<_>>()?;
+    
+    let world_transform = |joint: usize| {
+        let mut transform = local_transforms[joint];
+        let mut parent = joint_parents[joint];
+        
+        while let Some(parent_joint) = parent {
+            transform = local_transforms[parent_joint] * transform;
+            parent = joint_parents[parent_joint];
+        }
+        
+        transform
+    };
+    
+    let inverse_bind_matrices = skin.inverse_bind_matrices
+        .map(|accessor| document.read_accessor(accessor))
+        .transpose()?;
+    
+    let mut bones = Vec::with_capacity(skin.joints.len());
+    
+    for (i, joint) in skin.joints.iter().enumerate() {
+        let node = document.get(*joint)?;
+        let name = node.name.clone().unwrap_or_else(|| format!("bone{}", i));
+        
+        let parent = joint_parents[i];
+        let siblings: Vec<usize> = (0..skin.joints.len()).filter(|other| joint_parents[*other] == parent).collect();
+        let sibling_position = siblings.iter().position(|sibling| *sibling == i).unwrap();
+        
+        let (scale, rotation, translation) = node_srt(node);
+        let world = world_transform(i);
+        
+        let inverse_world_transform = match &inverse_bind_matrices {
+            Some(matrices) => {
+                let values = matrices.get(i)
+                    .ok_or_else(|| anyhow!("Skin {:?} has no inverse bind matrix for joint {}", skin.name, i))?;
+                Matrix4::from_column_slice(values)
+            },
+            None => world.try_inverse().unwrap_or_else(Matrix4::identity),
+        };
+        
+        let mut bone = template_bone.clone();
+        bone.name = Some(name.clone());
+        bone.index = i as u32;
+        bone.parent_index = parent.map_or(-1, |parent| parent as i32);
+        bone.parent = parent.map(|parent| parent as u32);
+        bone.child = (0..skin.joints.len()).find(|child| joint_parents[*child] == Some(i)).map(|child| child as u32);
+        bone.previous_sibling = sibling_position.checked_sub(1).map(|position| siblings[position] as u32);
+        bone.next_sibling = siblings.get(sibling_position + 1).map(|sibling| *sibling as u32);
+        bone.transform.scale = math::Vec3::new(scale.x, scale.y, scale.z);
+        bone.transform.rotation = math::quaternion_to_euler(&rotation, EulerOrder::XYZ);
+        bone.transform.translation = math::Vec3::new(translation.x, translation.y, translation.z);
+        bone.transform.local_transform = to_matrix3x4(&local_transforms[i]);
+        bone.transform.world_transform = to_matrix3x4(&world);
+        bone.inverse_world_transform = to_matrix3x4(&inverse_world_transform);
+        bone.metadata = None;
+        
+        bones.push((name, bone));
+    }
+    
+    let magic_number = template.bones.as_ref().map_or("DICT", |bones| &bones.magic_number);
+    
+    Ok(CgfxSkeleton {
+        bones: Some(CgfxDict::from_values(magic_number, bones)?),
+        root_bone: joint_parents.iter().position(Option::is_none).map(|root| root as u32),
+        ..template.clone()
+    })
+}
+
+fn import_material(material: &Material, name: &str, texture_name: Option<&str>,
+        template: &nw_tex::bcres::material::Material) -> Result<nw_tex::bcres::material::Material> {
+    let [r, g, b, a] = material.pbr_metallic_roughness.base_color_factor.0;
+    let [emission_r, emission_g, emission_b] = material.emissive_factor.0;
+    let to_u8 = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    
+    let mut imported = template.clone();
+    imported.cgfx_object_header.name = Some(name.to_string());
+    
+    let colors = &mut imported.colors;
+    colors.diffuse_float = math::Vec4::new(r, g, b, a);
+    colors.diffuse = RgbaColor { r: to_u8(r), g: to_u8(g), b: to_u8(b), a: to_u8(a) };
+    colors.emission_float = math::Vec4::new(emission_r, emission_g, emission_b, colors.emission_float.w);
+    colors.emission = RgbaColor { r: to_u8(emission_r), g: to_u8(emission_g), b: to_u8(emission_b), a: colors.emission.a };
+    
+    imported.set_texture(0, texture_name)?;
+    
+    if let Some(texture_info) = &material.pbr_metallic_roughness.base_color_texture {
+        imported.texture_coordinators[0].source_coordinate_index = i32::try_from(texture_info.tex_coord)?;
+    }
+    
+    Ok(imported)
+}
+
+fn import_texture(document: &GltfDocument, texture: &Texture, name: &str) -> Result<CgfxTexture> {
+    let image = document.get(texture.source)?;
+    let (colors, width, height) = from_png(&document.image_bytes(image)?)
+        .map_err(|error| anyhow!("Could not decode texture {:?}, only PNG is supported: {}", name, error))?;
+    
+    Ok(CgfxTexture::from_rgba(name, width, height, PicaTextureFormat::RGBA8, &colors)?)
+}
+
+/// Adds `values` to `dict`, replacing the values which have the same name.
+fn merge_into_dict<T: CgfxCollectionValue>(dict: Option<CgfxDict<T>>, values: Vec<(String, T)>) -> Result<CgfxDict<T>> {
+    let mut dict = dict.unwrap_or_else(|| CgfxDict::new("DICT"));
+    
+    for (name, value) in values {
+        dict.insert(&name, value)?;
+    }
+    
+    Ok(dict)
+}
+
+/// Collects every node with a mesh below `node` along with its world transform.
+fn collect_mesh_nodes(document: &GltfDocument, node: Index<Node>, parent_transform: &Matrix4<f32>,
+        mesh_nodes: &mut Vec<(Index<Node>, Matrix4<f32>)>) -> Result<()> {
+    let gltf_node = document.get(node)?;
+    let world_transform = parent_transform * node_matrix(gltf_node);
+    
+    if gltf_node.mesh.is_some() {
+        mesh_nodes.push((node, world_transform));
+    }
+    
+    for child in gltf_node.children.iter().flatten() {
+        collect_mesh_nodes(document, *child, &world_transform, mesh_nodes)?;
+    }
+    
+    Ok(())
+}
+
+/// Imports the meshes, materials and skin of a .gltf or .glb file into `gfx` as a model called
+/// `model_name`, replacing the model with the same name if there is one. Files referenced by the
+/// glTF file are searched for in `base_dir`.
+/// 
+/// Shapes, materials and bones are cloned from the model with the same name or the first model
+/// of `gfx` and then filled in, so the imported model keeps settings the game expects. This means
+/// `gfx` needs to contain a model already (a skeletal one for skinned glTF files), as there are
+/// too many fields whose meaning isn't known to build one from scratch. Base color textures of
+/// materials are added to the textures of `gfx` as RGBA8 under the name of their material.
+/// 
+/// Note that `gfx` can only be written afterwards if it has none of the dicts that aren't parsed
+/// yet, like cameras or lights.
+pub fn import_gltf_to_bcres(gfx: &mut CgfxContainer, gltf: &[u8], base_dir: &Path, model_name: &str) -> Result<()> {
+    let document = GltfDocument::load(gltf, base_dir)?;
+    
+    let template_model = gfx.models.iter()
+        .flat_map(CgfxDict::iter)
+        .min_by_key(|(name, _)| *name != Some(model_name))
+        .map(|(_, model)| model.clone())
+        .ok_or_else(|| anyhow!("There is no model to use as a template for {:?}, the file needs to contain one already", model_name))?;
+    
+    let template_common = template_model.common();
+    let template_shapes: &[Shape] = template_common.shapes.as_deref().unwrap_or_default();
+    
+    let template_mesh = template_common.meshes.iter().flatten().next()
+        .ok_or_else(|| anyhow!("Template model has no meshes"))?;
+    let template_material = template_common.materials.iter()
+        .flat_map(CgfxDict::values)
+        .next()
+        .ok_or_else(|| anyhow!("Template model has no materials"))?;
+    
+    // nodes with meshes
+    let scene = document.root.scene
+        .or_else(|| (!document.root.scenes.is_empty()).then(|| Index::new(0)));
+    
+    let root_nodes: Vec<Index<Node>> = match scene {
+        Some(scene) => document.get(scene)?.nodes.clone(),
+        None => document.node_parents().iter().enumerate()
+            .filter(|(_, parent)| parent.is_none())
+            .map(|(i, _)| Index::new(i as u32))
+            .collect(),
+    };
+    
+    let mut mesh_nodes = Vec::new();
+    
+    for node in root_nodes {
+        collect_mesh_nodes(&document, node, &Matrix4::identity(), &mut mesh_nodes)?;
+    }
+    
+    // skeleton
+    let mut skins = mesh_nodes.iter().filter_map(|(node, _)| document.root.get(*node)?.skin);
+    let skin = skins.next();
+    
+    if skins.any(|other| Some(other) != skin) {
+        return Err(anyhow!("Meshes with different skins can't be imported into a single model"));
+    }
+    
+    let skeleton = match skin {
+        Some(skin) => {
+            let CgfxModel::Skeletal(_, template_skeleton) = &template_model else {
+                return Err(anyhow!("Importing a skinned model requires a skeletal model as a template"));
+            };
+            
+            Some(import_skeleton(&document, document.get(skin)?, template_skeleton)?)
+        },
+        None => None,
+    };
+    
+    // primitives
+    let mut primitives: Vec<ImportedPrimitive> = Vec::new();
+    
+    for (node_index, world_transform) in &mesh_nodes {
+        let node = document.get(*node_index)?;
+        let Some(mesh_index) = node.mesh else {
+            continue;
+        };
+        
+        let mesh = document.get(mesh_index)?;
+        let mesh_name = mesh.name.clone().or_else(|| node.name.clone())
+            .unwrap_or_else(|| format!("mesh{}", mesh_index.value()));
+        
+        for (i, primitive) in mesh.primitives.iter().enumerate() {
+            let mut name = if mesh.primitives.len() > 1 { format!("{}_{}", mesh_name, i) } else { mesh_name.clone() };
+            
+            // meshes used by multiple nodes need unique names
+            if primitives.iter().any(|other| other.name == name) {
+                name = format!("{}_{}", name, primitives.len());
+            }
+            
+            let world_transform = node.skin.is_none().then_some(world_transform);
+            primitives.push(import_primitive(&document, name, primitive, world_transform)?);
+        }
+    }
+    
+    // materials and textures
+    let mut materials = Vec::new();
+    let mut textures = Vec::new();
+    
+    for (i, material) in document.root.materials.iter().enumerate() {
+        let name = material.name.clone().unwrap_or_else(|| format!("material{}", i));
+        let texture_info = &material.pbr_metallic_roughness.base_color_texture;
+        
+        if let Some(texture_info) = texture_info {
+            textures.push((name.clone(), import_texture(&document, document.get(texture_info.index)?, &name)?));
+        }
+        
+        let texture_name = texture_info.as_ref().map(|_| name.as_str());
+        materials.push((name.clone(), import_material(material, &name, texture_name, template_material)?));
+    }
+    
+    let default_material = materials.len();
+    
+    if primitives.iter().any(|primitive| primitive.material.is_none()) {
+        let mut material = template_material.clone();
+        material.cgfx_object_header.name = Some("default".to_string());
+        
+        materials.push(("default".to_string(), material));
+    }
+    
+    // shapes and meshes
+    let mut shapes = Vec::with_capacity(primitives.len());
+    let mut meshes = Vec::with_capacity(primitives.len());
+    
+    for (i, primitive) in primitives.iter().enumerate() {
+        let mut shape = find_template_shape(template_shapes, primitive)?.clone();
+        shape.cgfx_object_header.name = None;
+        shape.replace_geometry(&primitive.streams, &primitive.triangles)?;
+        
+        if let Some(sub_mesh) = shape.sub_meshes.iter_mut().flatten().next() {
+            match &primitive.bone_palette {
+                Some(bone_palette) => {
+                    sub_mesh.skinning = SubMeshSkinning::Smooth;
+                    sub_mesh.bone_indices = Some(bone_palette.clone());
+                },
+                None if sub_mesh.skinning != SubMeshSkinning::None => {
+                    sub_mesh.skinning = SubMeshSkinning::None;
+                    sub_mesh.bone_indices = Some(vec![0]);
+                },
+                None => {},
+            }
+        }
+        
+        let mut mesh = template_mesh.clone();
+        mesh.cgfx_object_header.name = Some(primitive.name.clone());
+        mesh.shape_index = i as u32;
+        mesh.material_index = primitive.material.unwrap_or(default_material) as u32;
+        mesh.mesh_node_index = u16::try_from(i)?;
+        
+        shapes.push(shape);
+        meshes.push(mesh);
+    }
+    
+    let mut common = template_common.clone();
+    common.cgfx_object_header.name = Some(model_name.to_string());
+    common.shapes = Some(shapes);
+    common.meshes = Some(meshes);
+    common.materials = Some(merge_into_dict(None, materials)?);
+    
+    if common.mesh_node_visibilities.is_some() {
+        let visibilities = primitives.iter()
+            .map(|primitive| (primitive.name.clone(), MeshNodeVisibility {
+                name: Some(primitive.name.clone()),
+                visible: true,
+            }))
+            .collect();
+        
+        common.mesh_node_visibilities = Some(merge_into_dict(None, visibilities)?);
+    }
+    
+    let model = match skeleton {
+        Some(skeleton) => CgfxModel::Skeletal(common, skeleton),
+        None => CgfxModel::Standard(common),
+    };
+    
+    gfx.models = Some(merge_into_dict(gfx.models.take(), vec![(model_name.to_string(), model)])?);
+    
+    if !textures.is_empty() {
+        gfx.textures = Some(merge_into_dict(gfx.textures.take(), textures)?);
+    }
+    
+    Ok(())
+}
+
+/// How glTF animations get converted to CGFX animation curves.
+pub struct AnimationImportOptions {
+    /// Frames per second, used to convert the seconds of glTF key frames to CGFX frames
+    pub frame_rate: f32,
+    /// Whether to create a key frame on every frame instead of taking over the key frames of the glTF file
+    pub resample: bool,
+    /// How far the reduced curves may deviate from the original key frames
+    pub tolerance: f32,
+}
+
+/// The key frames of a glTF animation sampler.
+struct SampledChannel {
+    times: Vec<f32>,
+    values: Vec<Vec<f32>>,
+    /// In and out tangents of every key frame, only used by cubic spline interpolation
+    tangents: Vec<(Vec<f32>, Vec<f32>)>,
+    interpolation: Interpolation,
+}
+
+fn to_quaternion(values: &[f32]) -> NaUnitQuaternion<f32> {
+    NaUnitQuaternion::from_quaternion(Quaternion::new(component(values, 3), component(values, 0), component(values, 1), component(values, 2)))
+}
+
+impl SampledChannel {
+    fn load(document: &GltfDocument, sampler: &Sampler) -> Result<Self> {
+        let Checked::Valid(interpolation) = sampler.interpolation else {
+            return Err(anyhow!("Animation sampler has an invalid interpolation"));
+        };
+        
+        let times: Vec<f32> = document.read_accessor(sampler.input)?.iter().map(|value| component(value, 0)).collect();
+        let output = document.read_accessor(sampler.output)?;
+        
+        // cubic splines store in tangent, value and out tangent of every key frame
+        let (values, tangents) = if interpolation == Interpolation::CubicSpline {
+            output.chunks_exact(3)
+                .map(|key_frame| (key_frame[1].clone(), (key_frame[0].clone(), key_frame[2].clone())))
+                .unzip()
+        } else {
+            (output, Vec::new())
+        };
+        
+        if values.len() != times.len() {
+            return Err(anyhow!("Animation sampler has {} key frames but {} values", times.len(), values.len()));
+        }
+        
+        Ok(Self {
+            times,
+            values,
+            tangents,
+            interpolation,
+        })
+    }
+    
+    /// The value at `time` in seconds, rotations get interpolated as quaternions.
+    fn value_at(&self, time: f32, is_rotation: bool) -> Vec<f32> {
+        let next = self.times.partition_point(|key_time| *key_time <= time);
+        
+        let (left, right) = match next.checked_sub(1) {
+            Some(left) if next < self.times.len() => (left, next),
+            Some(left) => return self.values[left].clone(),
+            None => return self.values.first().cloned().unwrap_or_default(),
+        };
+        
+        let duration = self.times[right] - self.times[left];
+        let t = (time - self.times[left]) / duration;
+        let (a, b) = (&self.values[left], &self.values[right]);
+        
+        match self.interpolation {
+            Interpolation::Step => a.clone(),
+            Interpolation::Linear if is_rotation => {
+                let rotation = to_quaternion(a).slerp(&to_quaternion(b), t);
+                vec![rotation.i, rotation.j, rotation.k, rotation.w]
+            },
+            Interpolation::Linear => a.iter().zip(b).map(|(a, b)| a + (b - a) * t).collect(),
+            Interpolation::CubicSpline => {
+                let (t2, t3) = (t * t, t * t * t);
+                let (_, out_tangent) = &self.tangents[left];
+                let (in_tangent, _) = &self.tangents[right];
+                
+                let value: Vec<f32> = (0..a.len())
+                    .map(|i| (2.0 * t3 - 3.0 * t2 + 1.0) * a[i]
+                        + (t3 - 2.0 * t2 + t) * duration * component(out_tangent, i)
+                        + (-2.0 * t3 + 3.0 * t2) * component(b, i)
+                        + (t3 - t2) * duration * component(in_tangent, i))
+                    .collect();
+                
+                if is_rotation {
+                    let rotation = to_quaternion(&value);
+                    vec![rotation.i, rotation.j, rotation.k, rotation.w]
+                } else {
+                    value
+                }
+            },
+        }
+    }
+}
+
+/// Converts a translation, rotation or scale channel into one CGFX curve per component.
+/// Rotations become XYZ euler angles, which can't be interpolated like quaternions,
+/// so resampling gives more accurate results for them.
+fn import_channel(channel: &SampledChannel, is_rotation: bool, options: &AnimationImportOptions) -> [Option<AnimValue>; 3] {
+    // cubic spline tangents of quaternions can't be converted to euler angles
+    let resample = options.resample || (is_rotation && channel.interpolation == Interpolation::CubicSpline);
+    
+    let times: Vec<f32> = if resample {
+        let frame_count = (channel.times.last().copied().unwrap_or_default() * options.frame_rate).ceil() as u32;
+        (0..=frame_count).map(|frame| frame as f32 / options.frame_rate).collect()
+    } else {
+        channel.times.clone()
+    };
+    
+    let interpolation = match channel.interpolation {
+        Interpolation::Step => AnimInterpolation::Step,
+        Interpolation::CubicSpline if !resample => AnimInterpolation::Hermite,
+        _ => AnimInterpolation::Linear,
+    };
+    
+    let mut values: Vec<[f32; 3]> = times.iter()
+        .map(|time| {
+            let value = channel.value_at(*time, is_rotation);
+            
+            if is_rotation {
+                let rotation = math::quaternion_to_euler(&to_quaternion(&value), EulerOrder::XYZ);
+                [rotation.x, rotation.y, rotation.z]
+            } else {
+                [component(&value, 0), component(&value, 1), component(&value, 2)]
+            }
+        })
+        .collect();
+    
+    // keep angles from jumping by a full turn between key frames, which would spin the bone around
+    if is_rotation {
+        for i in 1..values.len() {
+            let previous = values[i - 1];
+            
+            for (angle, previous) in values[i].iter_mut().zip(previous) {
+                *angle += ((previous - *angle) / TAU).round() * TAU;
+            }
+        }
+    }
+    
+    [0, 1, 2].map(|axis| {
+        let key_frames = times.iter().zip(&values).enumerate()
+            .map(|(i, (time, value))| {
+                // glTF tangents are per second, CGFX slopes per frame
+                let (in_slope, out_slope) = match interpolation {
+                    AnimInterpolation::Hermite => {
+                        let (in_tangent, out_tangent) = &channel.tangents[i];
+                        (component(in_tangent, axis) / options.frame_rate, component(out_tangent, axis) / options.frame_rate)
+                    },
+                    _ => (0.0, 0.0),
+                };
+                
+                AnimKeyFrame {
+                    frame: time * options.frame_rate,
+                    value: value[axis],
+                    in_slope,
+                    out_slope,
+                }
+            })
+            .collect();
+        
+        let mut curve = AnimCurve::new(interpolation, key_frames);
+        curve.reduce_key_frames(options.tolerance);
+        
+        Some(match curve.constant_value(options.tolerance) {
+            Some(value) => AnimValue::Constant(value),
+            None => AnimValue::Curve(curve),
+        })
+    })
+}
+
+/// Imports every animation of a .gltf or .glb file into the skeletal animations of `gfx`, replacing
+/// animations with the same name, and returns their names. Animations without a name are named after
+/// `default_name`. Every channel animates the bone named like the node it targets.
+pub fn import_gltf_animations(gfx: &mut CgfxContainer, gltf: &[u8], base_dir: &Path, default_name: &str,
+        options: &AnimationImportOptions) -> Result<Vec<String>> {
+    let document = GltfDocument::load(gltf, base_dir)?;
+    let mut animations = Vec::new();
+    
+    for (i, animation) in document.root.animations.iter().enumerate() {
+        let name = match &animation.name {
+            Some(name) => name.clone(),
+            None if document.root.animations.len() == 1 => default_name.to_string(),
+            None => format!("{}_{}", default_name, i),
+        };
+        
+        let mut bones: Vec<(String, AnimTransform)> = Vec::new();
+        let mut end_time: f32 = 0.0;
+        
+        for channel in &animation.channels {
+            // morph target weights have no equivalent in skeletal animations
+            let Checked::Valid(property) = channel.target.path else {
+                continue;
+            };
+            
+            if property == Property::MorphTargetWeights {
+                continue;
+            }
+            
+            let Some(bone_name) = &document.get(channel.target.node)?.name else {
+                continue;
+            };
+            
+            let sampler = animation.samplers.get(channel.sampler.value())
+                .ok_or_else(|| anyhow!("Animation {:?} references missing sampler {}", name, channel.sampler.value()))?;
+            let sampled = SampledChannel::load(&document, sampler)?;
+            
+            let Some(last_time) = sampled.times.last() else {
+                continue;
+            };
+            
+            end_time = end_time.max(*last_time);
+            
+            let values = import_channel(&sampled, property == Property::Rotation, options);
+            
+            let transform = match bones.iter().position(|(other, _)| other == bone_name) {
+                Some(index) => &mut bones[index].1,
+                None => {
+                    bones.push((bone_name.clone(), AnimTransform::default()));
+                    &mut bones.last_mut().unwrap().1
+                },
+            };
+            
+            match property {
+                Property::Translation => transform.translation = values,
+                Property::Rotation => transform.rotation = values,
+                Property::Scale => transform.scale = values,
+                Property::MorphTargetWeights => {},
+            }
+        }
+        
+        let elements = bones.into_iter()
+            .map(|(bone_name, transform)| (bone_name.clone(), AnimElement {
+                name: Some(bone_name),
+                transform,
+            }))
+            .collect();
+        
+        let mut cgfx_animation = CgfxAnimation::new_skeletal(&name, end_time * options.frame_rate, AnimLoopMode::Loop);
+        cgfx_animation.elements = Some(CgfxDict::from_values("DICT", elements)?);
+        
+        animations.push((name, cgfx_animation));
+    }
+    
+    if animations.is_empty() {
+        return Err(anyhow!("The glTF file does not contain any animations"));
+    }
+    
+    let names = animations.iter().map(|(name, _)| name.clone()).collect();
+    gfx.skeletal_animations = Some(merge_into_dict(gfx.skeletal_animations.take(), animations)?);
+    
+    Ok(names)
+}
@@ -0,0 +1,310 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, Result};
+use nw_tex::{
+    bcres::{
+        bcres::{CgfxContainer, CgfxDict},
+        model::{AttributeName, CgfxModel, Shape},
+        texture::{CgfxTexture, PicaTextureFormat},
+    },
+    util::blz::blz_decode,
+    ArchiveRegistry,
+};
+use serde::Serialize;
+
+/// What the `inspect` method found inside of a file.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Inspection {
+    Container {
+        file_length: u32,
+        dicts: Vec<DictInfo>,
+    },
+    Archive {
+        entries: Vec<ArchiveEntryInfo>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct DictInfo {
+    pub name: &'static str,
+    pub entries: Vec<EntryInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EntryInfo {
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub details: EntryDetails,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EntryDetails {
+    Model(ModelInfo),
+    Texture(TextureInfo),
+    SkeletalAnimation {
+        frame_count: f32,
+        animated_bones: usize,
+    },
+    /// Dicts whose values aren't read yet, like cameras or lights
+    Other,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelInfo {
+    pub skeletal: bool,
+    pub bones: usize,
+    pub meshes: usize,
+    pub shapes: Vec<ShapeInfo>,
+    pub materials: Vec<Option<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShapeInfo {
+    /// `None` if the positions can't be decoded
+    pub vertices: Option<usize>,
+    pub triangles: usize,
+    pub vertex_buffers: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TextureInfo {
+    pub width: u32,
+    pub height: u32,
+    pub format: PicaTextureFormat,
+    pub mipmaps: u32,
+    /// 1 for 2D textures, 6 for cube maps
+    pub faces: usize,
+    pub byte_length: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveEntryInfo {
+    pub id: String,
+    pub file_offset: u32,
+    pub byte_length: u32,
+    pub decompressed_length: Option<usize>,
+    /// The format noted down in the registry, which should match the one of the texture
+    pub registry_format: Option<PicaTextureFormat>,
+    pub texture: Option<TextureInfo>,
+    /// Why the entry couldn't be decompressed or parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn dict_info<T>(name: &'static str, dict: &Option<CgfxDict<T>>, details: impl Fn(&T) -> Result<EntryDetails>) -> Result<Option<DictInfo>>
+where
+    T: nw_tex::bcres::bcres::CgfxCollectionValue,
+{
+    let Some(dict) = dict else {
+        return Ok(None);
+    };
+    
+    let entries = dict.iter()
+        .map(|(entry_name, value)| Ok(EntryInfo {
+            name: entry_name.map(str::to_string),
+            details: details(value)?,
+        }))
+        .collect::<Result<Vec<_>>>()?;
+    
+    Ok(Some(DictInfo { name, entries }))
+}
+
+fn texture_info(texture: &CgfxTexture) -> TextureInfo {
+    let common = texture.common();
+    
+    TextureInfo {
+        width: common.width,
+        height: common.height,
+        format: common.texture_format,
+        mipmaps: common.mipmap_size,
+        faces: match texture {
+            CgfxTexture::Cube(_, images) => images.len(),
+            CgfxTexture::Image(_, _) => 1,
+        },
+        byte_length: texture.size(),
+    }
+}
+
+fn shape_info(shape: &Shape) -> Result<ShapeInfo> {
+    let vertices = shape.decode_attribute(AttributeName::Position).ok().flatten()
+        .map(|positions| positions.len());
+    
+    let mut triangles = 0;
+    
+    for face_descriptor in shape.sub_meshes.iter().flatten()
+        .flat_map(|sub_mesh| sub_mesh.faces.iter().flatten())
+        .flat_map(|face| face.face_descriptors.iter().flatten())
+    {
+        triangles += face_descriptor.triangles()?.len();
+    }
+    
+    Ok(ShapeInfo {
+        vertices,
+        triangles,
+        vertex_buffers: shape.vertex_buffers.as_ref().map_or(0, Vec::len),
+    })
+}
+
+fn model_info(model: &CgfxModel) -> Result<ModelInfo> {
+    let common = model.common();
+    
+    Ok(ModelInfo {
+        skeletal: model.skeleton().is_some(),
+        bones: model.skeleton().map_or(0, |skeleton| skeleton.bone_list().len()),
+        meshes: common.meshes.as_ref().map_or(0, Vec::len),
+        shapes: common.shapes.iter().flatten().map(shape_info).collect::<Result<_>>()?,
+        materials: common.materials.iter()
+            .flat_map(|materials| materials.iter())
+            .map(|(name, _)| name.map(str::to_string))
+            .collect(),
+    })
+}
+
+pub fn inspect_container(gfx: &CgfxContainer) -> Result<Inspection> {
+    let other = |_: &()| Ok(EntryDetails::Other);
+    
+    let dicts = [
+        dict_info("models", &gfx.models, |model| Ok(EntryDetails::Model(model_info(model)?)))?,
+        dict_info("textures", &gfx.textures, |texture| Ok(EntryDetails::Texture(texture_info(texture))))?,
+        dict_info("luts", &gfx.luts, other)?,
+        dict_info("materials", &gfx.materials, other)?,
+        dict_info("shaders", &gfx.shaders, other)?,
+        dict_info("cameras", &gfx.cameras, other)?,
+        dict_info("lights", &gfx.lights, other)?,
+        dict_info("fogs", &gfx.fogs, other)?,
+        dict_info("scenes", &gfx.scenes, other)?,
+        dict_info("skeletal_animations", &gfx.skeletal_animations, |animation| Ok(EntryDetails::SkeletalAnimation {
+            frame_count: animation.frame_count,
+            animated_bones: animation.elements.as_ref().map_or(0, CgfxDict::len),
+        }))?,
+        dict_info("material_animations", &gfx.material_animations, other)?,
+        dict_info("visibility_animations", &gfx.visibility_animations, other)?,
+        dict_info("camera_animations", &gfx.camera_animations, other)?,
+        dict_info("light_animations", &gfx.light_animations, other)?,
+        dict_info("fog_animations", &gfx.fog_animations, other)?,
+        dict_info("emitters", &gfx.emitters, |_| Ok(EntryDetails::Other))?,
+    ];
+    
+    Ok(Inspection::Container {
+        file_length: gfx.header.file_length,
+        dicts: dicts.into_iter().flatten().collect(),
+    })
+}
+
+fn archive_entry_texture(compressed: &[u8]) -> Result<(usize, Option<TextureInfo>)> {
+    let decompressed = blz_decode(compressed)?;
+    let gfx = CgfxContainer::new(&decompressed)?;
+    let texture = gfx.textures.iter().flat_map(|textures| textures.values()).next();
+    
+    Ok((decompressed.len(), texture.map(texture_info)))
+}
+
+/// Lists the entries of a texture archive. Entries which can't be read are reported
+/// instead of failing, so one broken texture doesn't hide the rest.
+pub fn inspect_archive(archive: &[u8], registry: &ArchiveRegistry) -> Inspection {
+    let entries = registry.items.iter()
+        .map(|item| {
+            let start = item.file_offset as usize;
+            let end = start + item.byte_length as usize;
+            
+            let result = archive.get(start..end)
+                .ok_or_else(|| anyhow!("Entry lies outside of the archive"))
+                .and_then(archive_entry_texture);
+            
+            let (decompressed_length, texture, error) = match result {
+                Ok((decompressed_length, texture)) => (Some(decompressed_length), texture, None),
+                Err(error) => (None, None, Some(error.to_string())),
+            };
+            
+            ArchiveEntryInfo {
+                id: item.id.clone(),
+                file_offset: item.file_offset,
+                byte_length: item.byte_length,
+                decompressed_length,
+                registry_format: item.image_format,
+                texture,
+                error,
+            }
+        })
+        .collect();
+    
+    Inspection::Archive { entries }
+}
+
+fn describe_texture(texture: &TextureInfo) -> String {
+    let faces = if texture.faces == 1 { String::new() } else { format!(", {} faces", texture.faces) };
+    
+    format!("{}x{} {:?}, {} mipmap(s){}, {:#x} bytes",
+        texture.width, texture.height, texture.format, texture.mipmaps, faces, texture.byte_length)
+}
+
+impl Inspection {
+    /// An indented tree for reading in the terminal.
+    pub fn to_text(&self) -> Result<String> {
+        let mut out = String::new();
+        
+        match self {
+            Inspection::Container { file_length, dicts } => {
+                writeln!(out, "CGFX container, {:#x} bytes", file_length)?;
+                
+                for dict in dicts {
+                    writeln!(out, "  {} ({})", dict.name, dict.entries.len())?;
+                    
+                    for entry in &dict.entries {
+                        let name = entry.name.as_deref().unwrap_or("<unnamed>");
+                        
+                        match &entry.details {
+                            EntryDetails::Model(model) => {
+                                let kind = if model.skeletal { format!("skeletal, {} bones", model.bones) } else { "standard".to_string() };
+                                writeln!(out, "    {}: {} model, {} mesh(es)", name, kind, model.meshes)?;
+                                
+                                writeln!(out, "      shapes ({})", model.shapes.len())?;
+                                for (i, shape) in model.shapes.iter().enumerate() {
+                                    let vertices = shape.vertices.map_or("?".to_string(), |vertices| vertices.to_string());
+                                    writeln!(out, "        [{}]: {} vertices, {} triangles, {} vertex buffer(s)",
+                                        i, vertices, shape.triangles, shape.vertex_buffers)?;
+                                }
+                                
+                                writeln!(out, "      materials ({})", model.materials.len())?;
+                                for material in &model.materials {
+                                    writeln!(out, "        {}", material.as_deref().unwrap_or("<unnamed>"))?;
+                                }
+                            },
+                            EntryDetails::Texture(texture) => writeln!(out, "    {}: {}", name, describe_texture(texture))?,
+                            EntryDetails::SkeletalAnimation { frame_count, animated_bones } =>
+                                writeln!(out, "    {}: {} frames, {} animated bone(s)", name, frame_count, animated_bones)?,
+                            EntryDetails::Other => writeln!(out, "    {}", name)?,
+                        }
+                    }
+                }
+            },
+            Inspection::Archive { entries } => {
+                writeln!(out, "Texture archive, {} entries", entries.len())?;
+                
+                for entry in entries {
+                    write!(out, "  {}: offset {:#x}, {:#x} bytes", entry.id, entry.file_offset, entry.byte_length)?;
+                    
+                    if let Some(decompressed_length) = entry.decompressed_length {
+                        write!(out, " ({:#x} decompressed)", decompressed_length)?;
+                    }
+                    
+                    match (&entry.texture, &entry.error) {
+                        (_, Some(error)) => writeln!(out, ", unreadable: {}", error)?,
+                        (Some(texture), None) => writeln!(out, ", {}", describe_texture(texture))?,
+                        (None, None) => writeln!(out, ", no texture")?,
+                    }
+                    
+                    if let (Some(registry_format), Some(texture)) = (entry.registry_format, &entry.texture) {
+                        if registry_format != texture.format {
+                            writeln!(out, "    registry says {:?} instead", registry_format)?;
+                        }
+                    }
+                }
+            },
+        }
+        
+        Ok(out)
+    }
+}
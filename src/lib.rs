@@ -3,17 +3,22 @@ use std::{
     str::from_utf8,
 };
 
-use anyhow::Result;
 use bcres::texture::PicaTextureFormat;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize};
 use util::pointer::Pointer;
 
 pub mod bcres;
+pub mod error;
 pub mod util;
 
+pub use error::{Error, Result};
+
+use error::malformed;
+
 fn get_string(bytes: &[u8], start: Pointer) -> Result<String> {
-	let bytes_slice = &bytes[start.into()..];
+	let bytes_slice = bytes.get(usize::try_from(start)?..)
+		.ok_or_else(|| malformed!("String pointer {:?} points outside of the file", start))?;
 	let null_position_from_start = bytes_slice.iter().position(|&x| x == 0x0);
 	
 	let string = if let Some(null_position_from_start) = null_position_from_start {
@@ -27,7 +32,7 @@ fn get_string(bytes: &[u8], start: Pointer) -> Result<String> {
 
 pub fn get_4_byte_string(reader: &mut impl Read) -> Result<String> {
 	let mut bytes: [u8; 4] = [0; 4];
-	reader.read(&mut bytes)?;
+	reader.read_exact(&mut bytes)?;
 	
 	Ok(from_utf8(&bytes)?.to_string())
 }
@@ -43,15 +48,6 @@ pub fn write_at_pointer<W: Write + Seek>(writer: &mut W, pointer: Pointer, value
 	Ok(())
 }
 
-#[macro_export]
-macro_rules! assert_matching {
-	($writer:ident, $base_option:ident) => {
-		if let Some(base) = $base_option {
-            assert!(&***$writer.get_ref() == &base[..$writer.get_ref().len()], "Not matching");
-        }
-	};
-}
-
 pub struct ReaderGuard<'a, R: Read + Seek> {
     pub reader: &'a mut R,
     start_pos: u64,
@@ -116,8 +112,8 @@ impl RegistryItem {
 		})
 	}
 	
-	pub fn write(&self, writer: &mut impl Write, write_string: &mut impl FnMut(&str) -> Pointer) -> Result<()> {
-		let id_pointer = write_string(&self.id);
+	pub fn write(&self, writer: &mut impl Write, write_string: &mut impl FnMut(&str) -> Result<Pointer>) -> Result<()> {
+		let id_pointer = write_string(&self.id)?;
 		id_pointer.write(writer)?;
 		
 		writer.write_u32::<LittleEndian>(self.file_offset)?;
@@ -139,6 +135,10 @@ impl ArchiveRegistry {
 		let item_count = cursor.read_u32::<LittleEndian>()?;
 		let mut items = Vec::default();
 		
+		if u64::from(item_count) * 16 + 4 > buffer.len() as u64 {
+			return Err(malformed!("Registry has {} items, which don't fit into {:#x} bytes", item_count, buffer.len()));
+		}
+
 		let string_section_offset = 4 + item_count * 16;
 		let get_string = |ptr| get_string(buffer, ptr + string_section_offset);
 		
@@ -154,15 +154,15 @@ impl ArchiveRegistry {
 		let mut string_buffer: Vec<u8> = Vec::new();
 		
 		let mut write_string = |string: &str| {
-			let current_offset: Pointer = string_buffer.len().into();
+			let current_offset = Pointer::try_from(string_buffer.len())?;
 			
 			string_buffer.extend(string.bytes());
 			string_buffer.extend([0].iter());
 			
-			current_offset
+			Ok(current_offset)
 		};
 		
-		main_buffer.write_u32::<LittleEndian>(self.items.len().try_into().unwrap())?;
+		main_buffer.write_u32::<LittleEndian>(self.items.len().try_into()?)?;
 		
 		for item in &self.items {
 			item.write(&mut main_buffer, &mut write_string)?;
@@ -1,12 +1,15 @@
 use std::{
     ffi::OsStr,
-    fs, panic,
+    fs::{self, File},
+    io::BufReader,
+    panic,
     path::{Path, PathBuf},
 };
 
 use anyhow::{anyhow, Error, Result};
 use clap::{ArgAction, Parser, ValueEnum};
 use compression_cache::{CachedFile, CompressionCache};
+use gltf::AnimationImportOptions;
 use nw_tex::{
     bcres::{
         bcres::CgfxContainer,
@@ -18,10 +21,14 @@ use nw_tex::{
 };
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
+#[cfg(test)]
+mod fixtures;
 #[cfg(test)]
 mod tests;
 
 mod compression_cache;
+mod gltf;
+mod inspect;
 mod wavefront;
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -30,6 +37,21 @@ enum Method {
     Extract,
     /// TODO: Takes in your modified kersti file and builds it into the original game file
     Rebuild,
+    /// Takes in a .bcres file and exports every model inside of it, together with its textures
+    ExportModels,
+    /// Takes in a .bcres file and replaces the geometry of its meshes with the objects of the same name
+    /// in the model file, or imports a .gltf/.glb model file as a whole model
+    ImportModels,
+    /// Takes in a .bcres file and adds the animations of the .gltf/.glb model file to its skeletal animations
+    ImportAnimations,
+    /// Takes in a .bcres/.bcrez file or a 'XXX_xx.bin' texture archive and prints what it contains
+    Inspect,
+}
+
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
+enum ModelFormat {
+    Obj,
+    Gltf,
 }
 
 #[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
@@ -94,6 +116,44 @@ struct Args {
     #[arg(short, long, verbatim_doc_comment)]
     asset_format: Option<AssetFormat>,
     
+    /// The file format models get exported in when the method is 'export-models'.
+    /// 
+    /// .obj (default) is the Wavefront OBJ format, which is supported by pretty much every 3D program.
+    /// Every model gets an .obj file and a .mtl file with its materials, textures are exported as .png.
+    /// 
+    /// .gltf is glTF 2.0, which also keeps the skeleton, all texture coordinates and blend shapes.
+    /// Every model gets a .gltf file and a .bin file with its vertex data, textures are exported as .png.
+    #[arg(short, long, verbatim_doc_comment)]
+    model_format: Option<ModelFormat>,
+    
+    /// The model file to import when the method is 'import-models'.
+    /// Objects of an .obj file replace the meshes of the same name, so every object needs to be called
+    /// like the mesh it replaces, which is already the case for exported models.
+    /// A .gltf or .glb file becomes a model named after the file, replacing the model of that name if there is one.
+    /// The .bcres file has to contain a model already, as the imported one takes over its settings.
+    #[arg(long, verbatim_doc_comment)]
+    model_file: Option<String>,
+    
+    /// Frames per second of imported animations when the method is 'import-animations'. Defaults to 30.
+    #[arg(long, verbatim_doc_comment)]
+    frame_rate: Option<f32>,
+    
+    /// When the method is 'import-animations' and this flag is set, animations get a key frame on every frame
+    /// instead of keeping the key frames of the model file. This makes rotations more accurate, as they are
+    /// interpolated differently in CGFX.
+    #[arg(long, verbatim_doc_comment)]
+    resample: bool,
+    
+    /// How far imported animation curves may deviate from the original when removing unnecessary key frames.
+    /// Defaults to 0.001, set it to 0 to keep every key frame.
+    #[arg(long, verbatim_doc_comment)]
+    key_tolerance: Option<f32>,
+    
+    /// When the method is 'inspect' and this flag is set, the structure of the input gets printed
+    /// as JSON instead of an indented tree.
+    #[arg(long, verbatim_doc_comment)]
+    json: bool,
+    
     /// Print app version
     #[arg(short, long, action = ArgAction::Version)]
     version: Option<bool>,
@@ -133,33 +193,14 @@ fn get_input_sibling_path(input: &Path, old_file_ending: &str, new_file_ending:
 fn bcres_buffer_into_png(bcres_buffer: &[u8], id: &str) -> Result<(Vec<u8>, PicaTextureFormat)> {
     let gfx = CgfxContainer::new(bcres_buffer)?;
     
-    assert!(gfx.textures.is_some(), "Texture archive bcres file has to contain a texture section");
-    
-    let textures = gfx.textures.as_ref().unwrap();
-    let texture_node: &nw_tex::bcres::bcres::CgfxNode<CgfxTexture> = textures.nodes.iter()
-        .find(|node| node.value.is_some())
-        .expect("Texture archive bcres file has to contain at least one texture");
+    let texture = gfx.textures.iter().flat_map(|textures| textures.values()).next()
+        .ok_or_else(|| anyhow!("Texture archive file {:?} has to contain at least one texture", id))?;
     
-    let (common, image) = match texture_node.value.as_ref().unwrap() {
-        CgfxTexture::Image(common, image) => (common, image.as_ref().unwrap()),
-        other => panic!("Unsupported texture type {:?}, expected Image", other),
+    let (common, image) = match texture {
+        CgfxTexture::Image(common, Some(image)) => (common, image),
+        other => return Err(anyhow!("Unsupported texture {:?} in {:?}, expected an image", other.common().cgfx_object_header.name, id)),
     };
     
-    // debug
-    let recreation = CgfxContainer::from_single_texture(
-        id.to_string(),
-        textures.nodes[1].reference_bit,
-        texture_node.value.as_ref().unwrap().clone());
-    let texturesA = gfx.textures.as_ref().unwrap();
-    let texturesB = recreation.textures.as_ref().unwrap();
-    
-    let serialized = recreation.to_buffer_debug(None)?;
-    fs::write("testing/serialized/".to_string() + id + ".bcres", &serialized)?;
-    
-    if serialized != &bcres_buffer[..gfx.header.file_length as usize] {
-        println!("Aaaa {}", id);
-    }
-    
     let CgfxTextureCommon { texture_format, width, height, .. } = *common;
     let decoded = decode_swizzled_buffer(&image.image_bytes, texture_format, width, height)?;
     
@@ -230,10 +271,11 @@ file with the same name but ending on '_info.bin' rather than '.bin'", secondary
     };
     
     for item in registry.items.iter_mut() {
-        let start_offset: usize = item.file_offset.try_into().unwrap();
-        let end_offset: usize = (item.file_offset + item.byte_length).try_into().unwrap();
+        let start_offset = item.file_offset as usize;
+        let end_offset = start_offset + item.byte_length as usize;
         
-        let file_content = &input_file_buf[start_offset..end_offset];
+        let file_content = input_file_buf.get(start_offset..end_offset)
+            .ok_or_else(|| anyhow!("Registry entry {:?} lies outside of the archive", item.id))?;
         let filename: String;
         let to_write: Vec<u8>;
         
@@ -266,7 +308,7 @@ file with the same name but ending on '_info.bin' rather than '.bin'", secondary
         }
         
         let file_name = output_dir_name.join(filename + resource_file_extension);
-        // fs::write(file_name, to_write)?;
+        fs::write(file_name, to_write)?;
     }
     
     fs::write(&output_file_name, registry.to_yaml()?)?;
@@ -350,7 +392,7 @@ fn rebuild(input: PathBuf, opt_output: Option<String>, asset_format: AssetFormat
             } else {
                 println!("Encoding {:?}", item.id);
                 match asset_format {
-                    AssetFormat::Bcres => blz_encode(&mut buffer),
+                    AssetFormat::Bcres => Ok(blz_encode(&mut buffer)?),
                     AssetFormat::Png => todo!(),
                     _ => panic!(),
                 }
@@ -383,6 +425,181 @@ fn rebuild(input: PathBuf, opt_output: Option<String>, asset_format: AssetFormat
     Ok(())
 }
 
+fn export_models(input: PathBuf, opt_output: Option<String>, model_format: ModelFormat) -> Result<()> {
+    let output_dir_name = match &opt_output {
+        Some(path) => PathBuf::from(path),
+        None => input.with_extension(""),
+    };
+    
+    let gfx = CgfxContainer::from_reader(&mut BufReader::new(File::open(&input)?))?;
+    
+    let models = gfx.models.as_ref()
+        .ok_or_else(|| anyhow!("File {:?} does not contain any models", input))?;
+    
+    fs::create_dir_all(&output_dir_name)?;
+    
+    for (i, (name, model)) in models.iter().enumerate() {
+        let name = name.map_or_else(|| format!("model{}", i), str::to_string);
+        println!("Exporting {:?}", name);
+        
+        match model_format {
+            ModelFormat::Obj => {
+                let mtl_file_name = name.clone() + ".mtl";
+                let export = wavefront::export_bcres_to_obj(model, gfx.textures.as_ref(), &mtl_file_name)?;
+                
+                fs::write(output_dir_name.join(name + ".obj"), export.obj)?;
+                fs::write(output_dir_name.join(mtl_file_name), export.mtl)?;
+                
+                for (file_name, png) in export.textures {
+                    fs::write(output_dir_name.join(file_name), png)?;
+                }
+            },
+            ModelFormat::Gltf => {
+                let bin_file_name = name.clone() + ".bin";
+                let export = gltf::export_bcres_to_gltf(model, gfx.textures.as_ref(), &bin_file_name)?;
+                
+                fs::write(output_dir_name.join(name + ".gltf"), export.gltf)?;
+                fs::write(output_dir_name.join(bin_file_name), export.bin)?;
+                
+                for (file_name, png) in export.textures {
+                    fs::write(output_dir_name.join(file_name), png)?;
+                }
+            },
+        }
+    }
+    
+    Ok(())
+}
+
+fn import_models(input: PathBuf, opt_output: Option<String>, model_file: &Path) -> Result<()> {
+    let output_file_name = match opt_output {
+        Some(path) => PathBuf::from(path),
+        None => get_input_sibling_path(&input, ".bcres", "_imported.bcres")?,
+    };
+    
+    let mut gfx = CgfxContainer::from_reader(&mut BufReader::new(File::open(&input)?))?;
+    
+    let extension = model_file.extension().and_then(OsStr::to_str).unwrap_or_default().to_lowercase();
+    
+    if extension == "gltf" || extension == "glb" {
+        let model_name = model_file.file_stem().and_then(OsStr::to_str)
+            .ok_or_else(|| anyhow!("Invalid model file name {:?}", model_file))?;
+        let base_dir = model_file.parent().unwrap_or(Path::new("."));
+        
+        gltf::import_gltf_to_bcres(&mut gfx, &fs::read(model_file)?, base_dir, model_name)?;
+        println!("Imported {:?}", model_name);
+        
+        fs::write(output_file_name, gfx.to_buffer()?)?;
+        return Ok(());
+    }
+    
+    let objects = wavefront::import_obj(&fs::read_to_string(model_file)?)?;
+    
+    let models = gfx.models.as_mut()
+        .ok_or_else(|| anyhow!("File {:?} does not contain any models", input))?;
+    
+    for object in objects {
+        let Some(object_name) = &object.name else {
+            println!("Skipping object without a name");
+            continue;
+        };
+        
+        let mut replaced = false;
+        
+        for model in models.values_mut() {
+            let common = model.common_mut();
+            
+            // unnamed meshes get exported as "mesh<index>"
+            let shape_indices: Vec<usize> = common.meshes.iter().flatten().enumerate()
+                .filter(|(i, mesh)| mesh.cgfx_object_header.name.clone().unwrap_or_else(|| format!("mesh{}", i)) == *object_name)
+                .map(|(_, mesh)| mesh.shape_index as usize)
+                .collect();
+            
+            for shape_index in shape_indices {
+                let shape = common.shapes.as_mut()
+                    .and_then(|shapes| shapes.get_mut(shape_index))
+                    .ok_or_else(|| anyhow!("Mesh {:?} references missing shape {}", object_name, shape_index))?;
+                
+                shape.replace_geometry(&object.streams, &object.triangles)?;
+                replaced = true;
+            }
+        }
+        
+        if replaced {
+            println!("Replaced {:?}", object_name);
+        } else {
+            println!("There is no mesh called {:?}, skipping it", object_name);
+        }
+    }
+    
+    fs::write(output_file_name, gfx.to_buffer()?)?;
+    Ok(())
+}
+
+fn import_animations(input: PathBuf, opt_output: Option<String>, model_file: &Path, options: &AnimationImportOptions) -> Result<()> {
+    let output_file_name = match opt_output {
+        Some(path) => PathBuf::from(path),
+        None => get_input_sibling_path(&input, ".bcres", "_imported.bcres")?,
+    };
+    
+    let mut gfx = CgfxContainer::from_reader(&mut BufReader::new(File::open(&input)?))?;
+    
+    let default_name = model_file.file_stem().and_then(OsStr::to_str)
+        .ok_or_else(|| anyhow!("Invalid model file name {:?}", model_file))?;
+    let base_dir = model_file.parent().unwrap_or(Path::new("."));
+    
+    for name in gltf::import_gltf_animations(&mut gfx, &fs::read(model_file)?, base_dir, default_name, options)? {
+        println!("Imported {:?}", name);
+    }
+    
+    fs::write(output_file_name, gfx.to_buffer()?)?;
+    Ok(())
+}
+
+fn inspect(input: PathBuf, opt_output: Option<String>, json: bool) -> Result<()> {
+    let extension = input.extension().and_then(OsStr::to_str).unwrap_or_default().to_lowercase();
+    
+    let inspection = match extension.as_str() {
+        "bcres" | "bcrez" => {
+            let mut buffer = fs::read(&input)?;
+            
+            // .bcrez files are compressed, but they are sometimes stored decompressed already
+            if !buffer.starts_with(b"CGFX") {
+                buffer = blz_decode(&buffer)?;
+            }
+            
+            inspect::inspect_container(&CgfxContainer::new(&buffer)?)?
+        },
+        "bin" => {
+            // accept both files of an archive
+            let (archive_path, registry_path) = if input.to_string_lossy().ends_with("_info.bin") {
+                (get_input_sibling_path(&input, "_info.bin", ".bin")?, input.clone())
+            } else {
+                (input.clone(), get_input_sibling_path(&input, ".bin", "_info.bin")?)
+            };
+            
+            let registry = ArchiveRegistry::new(&fs::read(&registry_path)
+                .map_err(|error| anyhow!("Could not open {:?}, the archive's registry: {}", registry_path, error))?)?;
+            
+            inspect::inspect_archive(&fs::read(&archive_path)?, &registry)
+        },
+        _ => return Err(anyhow!("Can't inspect {:?}, expected a .bcres, .bcrez or .bin file", input)),
+    };
+    
+    let text = if json {
+        serde_json::to_string_pretty(&inspection)? + "\n"
+    } else {
+        inspection.to_text()?
+    };
+    
+    match opt_output {
+        Some(path) => fs::write(path, text)?,
+        None => print!("{}", text),
+    }
+    
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
     
@@ -401,5 +618,25 @@ fn main() -> Result<()> {
     match args.method {
         Method::Extract => extract(input, output, args.clean, asset_format),
         Method::Rebuild => rebuild(input, output, asset_format),
+        Method::ExportModels => export_models(input, output, args.model_format.unwrap_or(ModelFormat::Obj)),
+        Method::Inspect => inspect(input, output, args.json),
+        Method::ImportModels => {
+            let model_file = args.model_file
+                .ok_or_else(|| anyhow!("Importing models requires a --model-file"))?;
+            
+            import_models(input, output, Path::new(&model_file))
+        },
+        Method::ImportAnimations => {
+            let model_file = args.model_file
+                .ok_or_else(|| anyhow!("Importing animations requires a --model-file"))?;
+            
+            let options = AnimationImportOptions {
+                frame_rate: args.frame_rate.unwrap_or(30.0),
+                resample: args.resample,
+                tolerance: args.key_tolerance.unwrap_or(0.001),
+            };
+            
+            import_animations(input, output, Path::new(&model_file), &options)
+        },
     }
 }
@@ -1,19 +1,98 @@
-use std::fs;
+use std::{
+    fs,
+    io::{BufReader, Cursor, Seek, Write},
+    path::Path,
+};
 
 use anyhow::Result;
-use nw_tex::bcres::bcres::CgfxContainer;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use binrw::BinWrite;
+use byteorder::{LittleEndian, WriteBytesExt};
+use gltf_json::{mesh::Semantic, validation::Checked};
+use na::Vec4;
+use nw_tex::bcres::{
+    animation::{AnimCurve, AnimGroupMember, AnimInterpolation, AnimKeyFrame, AnimValue, CgfxAnimation},
+    bcres::{CgfxContainer, CgfxDict, WriteContext},
+    emitter::{CgfxEmitter, ParticleInitializer, ParticleStream, ParticleUpdater},
+    image_codec::{decode_swizzled_buffer, encode_swizzled_buffer, from_png, RgbaColor, ENCODABLE_FORMATS},
+    material::{FaceCulling, Material},
+    metadata::{MetadataStringFormat, MetadataValues},
+    model::{
+        AttributeName, BlendShape, BlendShapeTarget, BlendShapeType, BoundingBox, CgfxModel, FaceDescriptor,
+        GlDataType, MeshNodeVisibility, PrimitiveMode, Shape, SubMesh, SubMeshSkinning, VertexBuffer,
+        VertexBufferAttribute, VertexBufferCommon, VertexBufferFixed, VertexBufferInterleaved, VertexBufferType,
+    },
+    skeleton::CgfxSkeleton,
+    texture::{CgfxTexture, PicaTextureFormat},
+    vertex::VertexStreams,
+};
+use nw_tex::util::blz::{blz_decode, blz_encode};
+use nw_tex::util::math::{
+    euler_to_quaternion, invert_transform, matrix_to_srt, multiply_transforms, quaternion_to_euler, srt_to_matrix,
+    EulerOrder, Vec3,
+};
+use nw_tex::util::pointer::Pointer;
+use nw_tex::{ArchiveRegistry, Error};
+use proptest::{collection::vec, prelude::*};
 
-use crate::{extract, AssetFormat};
+use crate::{
+    extract,
+    fixtures::{material_bytes, metadata_texture_bcres, model_bcres, model_container, padded_triangle_shape, skeletal_model_container, sparkle_emitter, texture_archive, texture_bcres, texture_id, TEXTURE_FORMATS},
+    gltf::{export_bcres_to_gltf, import_gltf_animations, import_gltf_to_bcres, AnimationImportOptions},
+    inspect::{inspect_archive, inspect_container, EntryDetails, Inspection},
+    rebuild,
+    wavefront::{export_bcres_to_obj, import_obj},
+    AssetFormat,
+};
 
 #[test]
 fn extract_texture_archives() -> Result<()> {
-    for item_result in fs::read_dir("testing/archives")? {
-        let item = item_result?;
-        let file_name = item.file_name().to_str().unwrap().to_string();
-        
-        if file_name.ends_with(".bin") && !file_name.ends_with("_info.bin") {
-            println!("Extracting {}", file_name);
-            extract(item.path(), None, true, AssetFormat::Bcres)?;
+    let dir = tempfile::tempdir()?;
+    let archive = texture_archive(dir.path(), "texture_US", &TEXTURE_FORMATS)?;
+    let registry = dir.path().join("texture_US_info.bin");
+    let original_archive = fs::read(&archive)?;
+    let original_registry = fs::read(&registry)?;
+    
+    extract(archive.clone(), None, false, AssetFormat::Bcres)?;
+    
+    for format in TEXTURE_FORMATS {
+        let extracted = fs::read(dir.path().join("texture_US_tex").join(texture_id(format) + ".bcres"))?;
+        let gfx = CgfxContainer::new(&extracted)?;
+        assert_eq!(gfx.textures.unwrap().values().next().unwrap().common().texture_format, format);
+    }
+    
+    // nothing changed, so everything gets taken from the compression cache
+    fs::remove_file(&archive)?;
+    fs::remove_file(&registry)?;
+    rebuild(dir.path().join("texture_US_tex.yaml"), None, AssetFormat::Bcres)?;
+    
+    assert!(fs::read(&archive)? == original_archive, "Rebuilt archive does not match the original");
+    assert!(fs::read(&registry)? == original_registry, "Rebuilt registry does not match the original");
+    
+    // RGB8 and HiLo8 can't be decoded yet
+    let decodable_formats: Vec<PicaTextureFormat> = TEXTURE_FORMATS.into_iter()
+        .filter(|format| !matches!(format, PicaTextureFormat::RGB8 | PicaTextureFormat::HiLo8))
+        .collect();
+    let png_archive = texture_archive(dir.path(), "texture_JP", &decodable_formats)?;
+    
+    extract(png_archive, None, false, AssetFormat::Png)?;
+    
+    for format in decodable_formats {
+        let prefix = if ENCODABLE_FORMATS.contains(&format) { "" } else { "READONLY_" };
+        let png = fs::read(dir.path().join("texture_JP_tex").join(prefix.to_owned() + &texture_id(format) + ".png"))?;
+        from_png(&png)?;
+    }
+    
+    // archives from the game, if there are any
+    if Path::new("testing/archives").is_dir() {
+        for item_result in fs::read_dir("testing/archives")? {
+            let item = item_result?;
+            let file_name = item.file_name().to_str().unwrap().to_string();
+            
+            if file_name.ends_with(".bin") && !file_name.ends_with("_info.bin") {
+                println!("Extracting {}", file_name);
+                extract(item.path(), None, true, AssetFormat::Bcres)?;
+            }
         }
     }
     Ok(())
@@ -21,6 +100,20 @@ fn extract_texture_archives() -> Result<()> {
 
 #[test]
 fn reencode_bcres_files() -> Result<()> {
+    for format in TEXTURE_FORMATS {
+        for (name, width, height) in [("t", 8, 8), ("texture", 16, 64), ("some_longer_texture_name", 128, 8)] {
+            let content = texture_bcres(name, format, width, height);
+            let reencoded = CgfxContainer::new(&content)?.to_buffer_verified()?;
+            
+            assert!(content == reencoded, "Texture {} ({:?}) does not match its original when reencoded", name, format);
+        }
+    }
+    
+    // files from the game, if there are any
+    if !Path::new("testing/bcres").is_dir() {
+        return Ok(());
+    }
+    
     for item_result in fs::read_dir("testing/bcres")? {
         let item = item_result?;
         let file_name = item.file_name().to_str().unwrap().to_string();
@@ -36,12 +129,1359 @@ fn reencode_bcres_files() -> Result<()> {
         let trimmed_content = &content[0..gfx.header.file_length as usize];
         
         println!("Saving {:?}", file_name);
-        let reencoded = gfx.to_buffer()?;
+        let reencoded = gfx.to_buffer_verified()?;
         
-        assert!(reencoded.len() == gfx.header.file_length as usize, "Length of file {} does not match", file_name);
         assert!(trimmed_content == &reencoded, "File {} does not match its original when reencoded", file_name);
+        
+        let blob_dir = tempfile::tempdir()?;
+        let yaml = gfx.to_yaml(blob_dir.path())?;
+        let from_yaml = CgfxContainer::from_yaml(&yaml, blob_dir.path())?;
+        
+        assert!(trimmed_content == from_yaml.to_buffer_verified()?, "File {} does not match its original when dumped to YAML", file_name);
     }
     
     println!("Done!");
     Ok(())
 }
+
+#[test]
+fn author_texture_bcres() -> Result<()> {
+    let (name, width, height) = ("authored", 16, 32);
+    
+    for format in TEXTURE_FORMATS {
+        let content = texture_bcres(name, format, width, height);
+        let container = CgfxContainer::new(&content)?;
+        
+        let Some(CgfxTexture::Image(_, Some(image))) = container.textures.as_ref().and_then(|textures| textures.get(name)) else {
+            panic!("Texture {:?} ({:?}) has no image", name, format);
+        };
+        
+        // formats which can't even be decoded can't be authored either
+        let Ok(pixels) = decode_swizzled_buffer(&image.image_bytes, format, width, height) else {
+            continue;
+        };
+        
+        match CgfxTexture::from_rgba(name, width, height, format, &pixels) {
+            Ok(texture) => {
+                let authored = CgfxContainer::builder().texture(texture).build()?;
+                
+                assert_eq!(authored.header, container.header, "Header of authored {:?} texture is wrong", format);
+                assert!(authored.to_buffer()? == content, "Authored {:?} texture does not match the fixture", format);
+            },
+            Err(error) => assert!(matches!(error.root_cause(), Error::Unsupported(_)),
+                "Authoring a {:?} texture failed with {}", format, error),
+        }
+    }
+    
+    let mut unnamed = CgfxTexture::from_rgba(name, 8, 8, PicaTextureFormat::RGBA8, &[RgbaColor { r: 0, g: 0, b: 0, a: 0 }; 64])?;
+    unnamed.metadata_mut().cgfx_object_header.name = None;
+    
+    assert!(CgfxContainer::builder().texture(unnamed).build().is_err());
+    assert!(CgfxTexture::from_rgba(name, 8, 8, PicaTextureFormat::RGBA8, &[]).is_err());
+    
+    Ok(())
+}
+
+#[test]
+fn compute_header_lengths() -> Result<()> {
+    let content = texture_bcres("tex", PicaTextureFormat::L8, 16, 16);
+    let mut gfx = CgfxContainer::new(&content)?;
+    
+    // a second texture grows every section
+    let texture = gfx.textures.as_ref().unwrap().get("tex").unwrap().clone();
+    gfx.textures.as_mut().unwrap().insert("copy", texture.clone())?;
+    
+    assert!(gfx.to_buffer_verified().is_err(), "Lengths of the original header were accepted after editing");
+    CgfxContainer::new(&gfx.to_buffer()?)?.to_buffer_verified()?;
+    
+    // a name that doesn't fit into the padding of the original layout anymore
+    let long_name = "a_texture_name_which_is_much_longer_than_the_ones_from_the_game".repeat(3);
+    let single = CgfxContainer::from_single_texture(long_name, texture)?;
+    let written = single.to_buffer_verified()?;
+    
+    assert_eq!(single.header.file_length as usize, written.len());
+    assert_eq!(CgfxContainer::new(&written)?.header, single.header);
+    
+    // the DATA section ends where the IMAG section starts
+    let imag_offset = (single.header.header_length as u32 + single.header.content_length) as usize;
+    assert_eq!(&written[imag_offset..imag_offset + 4], b"IMAG");
+    assert_eq!(u32::from_le_bytes(written[imag_offset + 4..imag_offset + 8].try_into()?) as usize, written.len() - imag_offset);
+    assert_eq!(single.header.sections_count, 2);
+    
+    // without any images there's only the DATA section
+    let emitter_only = CgfxContainer::builder().emitter(sparkle_emitter()?).build()?;
+    let written = emitter_only.to_buffer_verified()?;
+    
+    assert_eq!(emitter_only.header.sections_count, 1);
+    assert_eq!(written.len(), (emitter_only.header.header_length as u32 + emitter_only.header.content_length) as usize);
+    assert_eq!(CgfxContainer::new(&written)?.header, emitter_only.header);
+    
+    // writing against the original reports where they differ
+    let original = CgfxContainer::new(&content)?;
+    assert!(original.to_buffer_debug(Some(&content))? == content);
+    
+    let mut changed = content.clone();
+    changed[0x30] ^= 0xFF;
+    
+    let mismatch = original.to_buffer_debug(Some(&changed)).unwrap_err();
+    assert_eq!(mismatch.offset(), Some(0x30));
+    
+    Ok(())
+}
+
+#[test]
+fn read_containers_from_files() -> Result<()> {
+    let mut gfx = skeletal_model_container(&[PicaTextureFormat::RGBA8, PicaTextureFormat::L4])?;
+    gfx.emitters = Some(CgfxDict::from_values("DICT", vec![("sparkle".to_string(), sparkle_emitter()?)])?);
+    gfx.update_header()?;
+    
+    let content = gfx.to_buffer()?;
+    let mut file = tempfile::tempfile()?;
+    file.write_all(&content)?;
+    
+    // read directly from the file as well as buffered, both starting at the header
+    file.rewind()?;
+    let from_file = CgfxContainer::from_reader(&mut file)?;
+    
+    file.rewind()?;
+    let from_buffered_file = CgfxContainer::from_reader(&mut BufReader::new(&mut file))?;
+    
+    let from_buffer = CgfxContainer::new(&content)?;
+    assert_eq!(from_file, from_buffer);
+    assert_eq!(from_buffered_file, from_buffer);
+    assert!(from_buffered_file.to_buffer()? == content);
+    
+    // a file that ends early fails like a truncated buffer does
+    file.set_len(content.len() as u64 / 2)?;
+    file.rewind()?;
+    assert!(CgfxContainer::from_reader(&mut BufReader::new(&mut file)).is_err());
+    Ok(())
+}
+
+#[test]
+fn triangulate_face_descriptors() -> Result<()> {
+    let mut face_descriptor = FaceDescriptor {
+        format: GlDataType::UByte,
+        primitive_mode: PrimitiveMode::TriangleStrip,
+        visible: 1,
+        indices: Some(vec![0, 1, 2, 3, 3, 4, 4, 5, 6]),
+        buffer_obj: 0,
+        location_flag: 0,
+        command_cache: 0,
+        command_cache_size: 0,
+        location_ptr: 0,
+        memory_area: 0,
+        bounding_volume: 0,
+    };
+    
+    assert_eq!(face_descriptor.triangles()?, vec![[0, 1, 2], [2, 1, 3], [4, 5, 6]]);
+    
+    face_descriptor.primitive_mode = PrimitiveMode::TriangleFan;
+    face_descriptor.indices = Some(vec![0, 1, 2, 3]);
+    
+    assert_eq!(face_descriptor.triangles()?, vec![[0, 1, 2], [0, 2, 3]]);
+    Ok(())
+}
+
+#[test]
+fn encode_fixed_point_vertices() -> Result<()> {
+    let attribute = VertexBufferAttribute {
+        vertex_buffer_common: VertexBufferCommon {
+            attribute_name: AttributeName::Position,
+            vertex_buffer_type: VertexBufferType::None,
+        },
+        buffer_obj: 0,
+        location_flag: 0,
+        raw_bytes: None,
+        location_ptr: 0,
+        memory_area: 0,
+        format: GlDataType::Fixed,
+        elements: 3,
+        scale: 0.5,
+        offset: 0,
+    };
+    
+    let positions = vec![vec![1.0, -2.5, 0.0], vec![1000.25, 0.125, -0.5]];
+    let mut raw_bytes = vec![0u8; positions.len() * attribute.byte_size()];
+    
+    attribute.encode(&positions, &mut raw_bytes, attribute.byte_size(), 0)?;
+    assert_eq!(&raw_bytes[0..4], &0x20000i32.to_le_bytes());
+    
+    assert_eq!(attribute.decode(&raw_bytes, attribute.byte_size(), 0)?, positions);
+    Ok(())
+}
+
+#[test]
+fn decode_padded_interleaved_vertices() -> Result<()> {
+    fn interleaved(shape: &mut Shape) -> &mut VertexBufferInterleaved {
+        match shape.vertex_buffers.as_mut().and_then(|buffers| buffers.first_mut()) {
+            Some(VertexBuffer::Interleaved(interleaved)) => interleaved,
+            _ => panic!("Shape has no interleaved vertex buffer"),
+        }
+    }
+    
+    let mut shape = padded_triangle_shape()?;
+    
+    let streams = shape.vertex_streams()?;
+    let positions: Vec<[f32; 3]> = streams.positions.iter().map(|position| [position.x, position.y, position.z]).collect();
+    assert_eq!(positions, [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+    
+    // a stride the buffer doesn't divide into
+    interleaved(&mut shape).vertex_stride = 16;
+    
+    let wrong_stride = shape.decode_attribute(AttributeName::Position).unwrap_err();
+    assert!(matches!(wrong_stride.root_cause(), Error::Malformed(_)));
+    
+    // positions reaching past the end of a vertex
+    interleaved(&mut shape).vertex_stride = 20;
+    interleaved(&mut shape).attributes.as_mut().unwrap()[0].offset = 12;
+    
+    let wrong_offset = shape.decode_attribute(AttributeName::Position).unwrap_err();
+    assert!(matches!(wrong_offset.root_cause(), Error::Malformed(_)));
+    Ok(())
+}
+
+#[test]
+fn resolve_vertex_skins() -> Result<()> {
+    let mut sub_mesh = SubMesh {
+        bone_indices: Some(vec![7, 3, 12]),
+        skinning: SubMeshSkinning::Smooth,
+        faces: None,
+    };
+    
+    let streams = VertexStreams {
+        bone_indices: Some(vec![[2, 0, 1, 0]]),
+        bone_weights: Some(vec![Vec4::new(0.5, 0.25, 0.0, 0.0)]),
+        ..Default::default()
+    };
+    
+    let skin = sub_mesh.vertex_skin(&streams, 0)?.unwrap();
+    assert_eq!(skin.bones, [12, 7, 0, 0]);
+    assert_eq!(skin.weights, [2.0 / 3.0, 1.0 / 3.0, 0.0, 0.0]);
+    
+    sub_mesh.skinning = SubMeshSkinning::Rigid;
+    
+    let skin = sub_mesh.vertex_skin(&streams, 0)?.unwrap();
+    assert_eq!(skin.bones, [12, 0, 0, 0]);
+    assert_eq!(skin.weights, [1.0, 0.0, 0.0, 0.0]);
+    Ok(())
+}
+
+#[test]
+fn write_blend_shapes() -> Result<()> {
+    let mut gfx = model_container(&[])?;
+    let model = gfx.models.as_mut().and_then(|models| models.get_mut("triangle")).unwrap();
+    let shape = &mut model.common_mut().shapes.as_mut().unwrap()[0];
+    
+    // the target is a copy of the base geometry, which gets moved up when blended in
+    let vertex_buffers = shape.vertex_buffers.as_mut().unwrap();
+    vertex_buffers.push(vertex_buffers[0].clone());
+    
+    shape.blend_shape = Some(BlendShape {
+        targets: Some(vec![BlendShapeTarget { vertex_buffer_index: 2, weight: 0.5 }]),
+        attributes: Some(vec![AttributeName::Position]),
+        blend_type: BlendShapeType::Position,
+    });
+    
+    let shape = shape.clone();
+    gfx.update_header()?;
+    
+    let content = gfx.to_buffer_verified()?;
+    let written = CgfxContainer::new(&content)?;
+    let written_shape = &written.models.as_ref().and_then(|models| models.get("triangle")).unwrap()
+        .common().shapes.as_ref().unwrap()[0];
+    
+    assert_eq!(written_shape, &shape);
+    assert!(written_shape.blend_shape.as_ref().unwrap().is_target_buffer(2));
+    assert!(!written_shape.blend_shape.as_ref().unwrap().is_target_buffer(0));
+    assert!(written.to_buffer()? == content, "Shape with blend shape changed when written again");
+    Ok(())
+}
+
+#[test]
+fn toggle_mesh_node_visibility() -> Result<()> {
+    let mut gfx = model_container(&[])?;
+    let model = gfx.models.as_mut().and_then(|models| models.get_mut("triangle")).unwrap();
+    
+    let visibility = MeshNodeVisibility { name: Some("body".to_string()), visible: true };
+    model.common_mut().mesh_node_visibilities = Some(CgfxDict::from_values("DICT", vec![("body".to_string(), visibility)])?);
+    
+    let common = model.common_mut();
+    assert_eq!(common.meshes_in_node("body").count(), 1);
+    assert!(common.set_mesh_node_visible("head", false).is_err());
+    
+    common.set_mesh_node_visible("body", false)?;
+    gfx.update_header()?;
+    
+    let content = gfx.to_buffer_verified()?;
+    let mut written = CgfxContainer::new(&content)?;
+    let common = written.models.as_mut().and_then(|models| models.get_mut("triangle")).unwrap().common_mut();
+    let mesh = common.meshes_in_node("body").next().unwrap().clone();
+    
+    assert!(!common.is_mesh_visible(&mesh));
+    
+    // showing it again only changes the visibility
+    common.set_mesh_node_visible("body", true)?;
+    assert!(common.is_mesh_visible(&mesh));
+    
+    let shown = written.to_buffer()?;
+    let differences: Vec<usize> = (0..content.len()).filter(|&i| content[i] != shown[i]).collect();
+    
+    assert_eq!(shown.len(), content.len());
+    assert_eq!(differences.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn write_materials() -> Result<()> {
+    let material = Material::from_reader(&mut Cursor::new(material_bytes("body", "body_tex")))?;
+    
+    assert_eq!(material.cgfx_object_header.name.as_deref(), Some("body"));
+    assert_eq!(material.base_texture_name(), Some("body_tex"));
+    assert_eq!(material.texture_names().collect::<Vec<_>>(), [Some("body_tex"), None, None]);
+    assert_eq!(material.shader.as_ref().and_then(|shader| shader.path.as_deref()), Some("DefaultShader"));
+    assert_eq!(material.rasterization.face_culling, FaceCulling::BackFace);
+    assert_eq!(material.fragment_shader.as_ref().unwrap().texture_combiners[5].constant, 5);
+    assert_eq!(material.hashes[12], 0x100C);
+    assert_eq!(material.unique_id, 42);
+    
+    let mut gfx = model_container(&[])?;
+    let model = gfx.models.as_mut().and_then(|models| models.get_mut("triangle")).unwrap();
+    model.common_mut().materials = Some(CgfxDict::from_values("DICT", vec![("body".to_string(), material.clone())])?);
+    gfx.update_header()?;
+    
+    let content = gfx.to_buffer_verified()?;
+    let written = CgfxContainer::new(&content)?;
+    let written_material = written.models.as_ref().and_then(|models| models.get("triangle")).unwrap()
+        .common().materials.as_ref().and_then(|materials| materials.get("body"));
+    
+    assert_eq!(written_material, Some(&material));
+    assert!(written.to_buffer()? == content, "Model with materials changed when written again");
+    Ok(())
+}
+
+#[test]
+fn write_emitters() -> Result<()> {
+    let emitter = sparkle_emitter()?;
+    let gfx = CgfxContainer::builder().emitter(emitter.clone()).build()?;
+    
+    let content = gfx.to_buffer()?;
+    let written = CgfxContainer::new(&content)?;
+    
+    assert_eq!(written.emitters.as_ref().and_then(|emitters| emitters.get("sparkle")), Some(&emitter));
+    assert!(written.to_buffer_verified()? == content, "Container with emitters changed when written again");
+    
+    // initializers and updaters of unknown types keep their contents
+    let payload: Vec<u8> = (0..20).collect();
+    let mut unknown = emitter.clone();
+    
+    unknown.initializers.get_or_insert_with(Vec::new)
+        .push(ParticleInitializer::Unknown { discriminant: 0x00100008, target: ParticleStream::Rotation, payload: payload.clone() });
+    unknown.updaters.get_or_insert_with(Vec::new)
+        .insert(0, ParticleUpdater::Unknown { discriminant: 0x00200020, target: ParticleStream::Color, payload });
+    
+    let content = CgfxContainer::builder().emitter(unknown.clone()).build()?.to_buffer()?;
+    let written = CgfxContainer::new(&content)?;
+    
+    assert_eq!(written.emitters.as_ref().and_then(|emitters| emitters.get("sparkle")), Some(&unknown));
+    assert!(written.to_buffer()? == content, "Emitter with unknown updaters changed when written again");
+    
+    // the contents of unparsed dicts would get lost
+    let mut with_luts = written.clone();
+    with_luts.luts = Some(CgfxDict::new("DICT"));
+    assert!(with_luts.to_buffer().is_err());
+    
+    Ok(())
+}
+
+#[test]
+fn write_names_containing_other_names() -> Result<()> {
+    let black = [RgbaColor { r: 0, g: 0, b: 0, a: 255 }; 64];
+    let gfx = CgfxContainer::builder()
+        .texture(CgfxTexture::from_rgba("body_eye", 8, 8, PicaTextureFormat::RGBA8, &black)?)
+        .texture(CgfxTexture::from_rgba("body", 8, 8, PicaTextureFormat::RGBA8, &black)?)
+        .build()?;
+    
+    // "body" is a prefix of "body_eye", but it still needs its own string
+    let written = CgfxContainer::new(&gfx.to_buffer_verified()?)?;
+    let textures = written.textures.as_ref().unwrap();
+    let names: Vec<Option<&str>> = textures.iter().map(|(name, _)| name).collect();
+    
+    assert_eq!(names, [Some("body_eye"), Some("body")]);
+    assert_eq!(textures.get("body").map(|texture| texture.common().cgfx_object_header.name.as_deref()), Some(Some("body")));
+    Ok(())
+}
+
+#[test]
+fn write_texture_metadata() -> Result<()> {
+    let bcres = metadata_texture_bcres();
+    let gfx = CgfxContainer::new(&bcres)?;
+    
+    let texture = gfx.textures.as_ref().and_then(|textures| textures.get("tex")).unwrap();
+    let metadata = texture.metadata().cgfx_object_header.metadata.as_ref().unwrap();
+    let values: Vec<(Option<&str>, &MetadataValues)> = metadata.iter().map(|(name, metadata)| (name, &metadata.values)).collect();
+    
+    assert_eq!(values, [
+        (Some("scale"), &MetadataValues::Float(vec![0.5])),
+        (Some("count"), &MetadataValues::Integer(vec![3, -1])),
+        (Some("label"), &MetadataValues::String(MetadataStringFormat::Ascii, vec!["sticker".to_string()])),
+        (Some("title"), &MetadataValues::String(MetadataStringFormat::Utf16LittleEndian, vec!["ペーパー".to_string()])),
+        (Some("tint"), &MetadataValues::Color(vec![nw_tex::util::math::Vec4 { x: 1.0, y: 0.5, z: 0.25, w: 1.0 }])),
+    ]);
+    
+    assert!(gfx.to_buffer_verified()? == bcres, "Texture with metadata changed when written");
+    Ok(())
+}
+
+#[test]
+fn export_obj() -> Result<()> {
+    let mut gfx = skeletal_model_container(&[PicaTextureFormat::RGBA8, PicaTextureFormat::ETC1])?;
+    let etc1 = texture_id(PicaTextureFormat::ETC1);
+    
+    let model = gfx.models.as_mut().and_then(|models| models.get_mut("triangle")).unwrap();
+    let material = model.common_mut().materials.as_mut().and_then(|materials| materials.get_mut("white")).unwrap();
+    material.set_texture(0, Some(&etc1))?;
+    
+    // the texture is found through the texture mapper of the material, not through its name
+    let export = export_bcres_to_obj(model, gfx.textures.as_ref(), "triangle.mtl")?;
+    assert!(export.mtl.contains(&format!("map_Kd {}.png", etc1)));
+    assert_eq!(export.textures.len(), 1);
+    assert_eq!(export.textures[0].0, format!("{}.png", etc1));
+    
+    // the triangle is rigidly skinned to a bone 5 units above the origin
+    let positions: Vec<&str> = export.obj.lines().filter(|line| line.starts_with("v ")).collect();
+    assert_eq!(positions, ["v 0 5 0 1 0.5 0.25", "v 1 5 0 1 0.5 0.25", "v 0 6 0 1 0.5 0.25"]);
+    
+    // textures of other files are left out
+    gfx.textures.as_mut().unwrap().remove(&etc1)?;
+    
+    let model = gfx.models.as_ref().and_then(|models| models.get("triangle")).unwrap();
+    let export = export_bcres_to_obj(model, gfx.textures.as_ref(), "triangle.mtl")?;
+    assert!(!export.mtl.contains("map_Kd"));
+    assert!(export.textures.is_empty());
+    Ok(())
+}
+
+#[test]
+fn export_gltf_uv_sets() -> Result<()> {
+    let mut gfx = model_container(&[PicaTextureFormat::RGBA8])?;
+    let model = gfx.models.as_mut().and_then(|models| models.get_mut("triangle")).unwrap();
+    let vertex_buffers = model.common_mut().shapes.as_mut().unwrap()[0].vertex_buffers.as_mut().unwrap();
+    
+    for (attribute_name, uv) in [(AttributeName::TexCoord0, [0.25, 0.5]), (AttributeName::TexCoord1, [1.0, 0.0])] {
+        vertex_buffers.push(VertexBuffer::Fixed(VertexBufferFixed {
+            vertex_buffer_common: VertexBufferCommon { attribute_name, vertex_buffer_type: VertexBufferType::Fixed },
+            format: GlDataType::Float,
+            elements: 2,
+            scale: 1.0,
+            vector: Some(uv.to_vec()),
+        }));
+    }
+    
+    let material = model.common_mut().materials.as_mut().and_then(|materials| materials.get_mut("white")).unwrap();
+    material.set_texture(0, Some(&texture_id(PicaTextureFormat::RGBA8)))?;
+    material.texture_coordinators[0].source_coordinate_index = 1;
+    
+    let export = export_bcres_to_gltf(model, gfx.textures.as_ref(), "triangle.bin")?;
+    let root: gltf_json::Root = serde_json::from_str(&export.gltf)?;
+    
+    let attributes = &root.meshes[0].primitives[0].attributes;
+    assert!(attributes.contains_key(&Checked::Valid(Semantic::TexCoords(0))));
+    assert!(attributes.contains_key(&Checked::Valid(Semantic::TexCoords(1))));
+    assert!(!attributes.contains_key(&Checked::Valid(Semantic::TexCoords(2))));
+    
+    let base_color_texture = root.materials[0].pbr_metallic_roughness.base_color_texture.as_ref().unwrap();
+    assert_eq!(base_color_texture.tex_coord, 1);
+    Ok(())
+}
+
+#[test]
+fn import_gltf_models() -> Result<()> {
+    let mut gfx = skeletal_model_container(&[PicaTextureFormat::RGBA8])?;
+    let rgba8 = texture_id(PicaTextureFormat::RGBA8);
+    
+    let model = gfx.models.as_mut().and_then(|models| models.get_mut("triangle")).unwrap();
+    let material = model.common_mut().materials.as_mut().and_then(|materials| materials.get_mut("white")).unwrap();
+    material.set_texture(0, Some(&rgba8))?;
+    
+    let dir = tempfile::tempdir()?;
+    let export = export_bcres_to_gltf(model, gfx.textures.as_ref(), "triangle.bin")?;
+    fs::write(dir.path().join("triangle.bin"), &export.bin)?;
+    
+    for (file_name, png) in &export.textures {
+        fs::write(dir.path().join(file_name), png)?;
+    }
+    
+    // the container has no textures, so the texture gets created from scratch
+    let mut imported = skeletal_model_container(&[])?;
+    let model = imported.models.as_mut().and_then(|models| models.get_mut("triangle")).unwrap();
+    let vertex_buffers = model.common_mut().shapes.as_mut().unwrap()[0].vertex_buffers.as_mut().unwrap();
+    
+    // smooth skinning needs a template shape with bone indices and weights for every vertex
+    vertex_buffers.retain(|vertex_buffer| !matches!(vertex_buffer,
+        VertexBuffer::Fixed(fixed) if fixed.vertex_buffer_common.attribute_name == AttributeName::BoneIndex));
+    
+    for (attribute_name, scale) in [(AttributeName::BoneIndex, 1.0), (AttributeName::BoneWeight, 0.01)] {
+        vertex_buffers.push(VertexBuffer::Attribute(VertexBufferAttribute {
+            vertex_buffer_common: VertexBufferCommon { attribute_name, vertex_buffer_type: VertexBufferType::None },
+            buffer_obj: 0,
+            location_flag: 0,
+            raw_bytes: Some(vec![0; 3 * 4]),
+            location_ptr: 0,
+            memory_area: 0,
+            format: GlDataType::UByte,
+            elements: 4,
+            scale,
+            offset: 0,
+        }));
+    }
+    
+    import_gltf_to_bcres(&mut imported, export.gltf.as_bytes(), dir.path(), "imported")?;
+    imported.update_header()?;
+    
+    let written = CgfxContainer::new(&imported.to_buffer_verified()?)?;
+    let (original_common, original_skeleton) = match gfx.models.as_ref().and_then(|models| models.get("triangle")).unwrap() {
+        CgfxModel::Skeletal(common, skeleton) => (common, skeleton),
+        CgfxModel::Standard(_) => panic!("Triangle isn't skeletal"),
+    };
+    let (common, skeleton) = match written.models.as_ref().and_then(|models| models.get("imported")).unwrap() {
+        CgfxModel::Skeletal(common, skeleton) => (common, skeleton),
+        CgfxModel::Standard(_) => panic!("Imported model isn't skeletal"),
+    };
+    
+    // the rigid skin of the triangle becomes a smooth one with the vertices in model space
+    let mut original_streams = original_common.shapes.as_ref().unwrap()[0].vertex_streams()?;
+    original_common.shapes.as_ref().unwrap()[0].apply_rigid_skins(&mut original_streams, original_skeleton)?;
+    
+    let shape = &common.shapes.as_ref().unwrap()[0];
+    let streams = shape.vertex_streams()?;
+    assert_eq!(streams.positions, original_streams.positions);
+    assert_eq!(streams.colors, original_streams.colors);
+    assert_eq!(streams.bone_weights, Some(vec![Vec4::new(1.0, 0.0, 0.0, 0.0); 3]));
+    assert_eq!(shape.sub_meshes.as_ref().unwrap()[0].skinning, SubMeshSkinning::Smooth);
+    
+    let bone_names = |skeleton: &CgfxSkeleton| skeleton.bone_list().iter().map(|bone| bone.name.clone()).collect::<Vec<_>>();
+    assert_eq!(bone_names(skeleton), bone_names(original_skeleton));
+    assert_eq!(skeleton.bone_list()[0].transform.world_transform, original_skeleton.bone_list()[0].transform.world_transform);
+    
+    let texture = written.textures.as_ref().and_then(|textures| textures.get("white")).unwrap();
+    let original_texture = gfx.textures.as_ref().and_then(|textures| textures.get(&rgba8)).unwrap();
+    assert_eq!(texture.common().texture_format, PicaTextureFormat::RGBA8);
+    assert_eq!(texture.to_png()?, original_texture.to_png()?);
+    Ok(())
+}
+
+/// A glTF file animating the node "root" over one second, which moves 3 units up, turns a third
+/// around the diagonal and stays at twice its size.
+fn animated_gltf() -> String {
+    let floats: [f32; 22] = [
+        0.0, 1.0,
+        0.0, 0.0, 0.0, 0.0, 3.0, 0.0,
+        0.0, 0.0, 0.0, 1.0, 0.5, 0.5, 0.5, 0.5,
+        2.0, 2.0, 2.0, 2.0, 2.0, 2.0,
+    ];
+    let bytes: Vec<u8> = floats.iter().flat_map(|value| value.to_le_bytes()).collect();
+    
+    // (offset, count, type) of the times and the translations, rotations and scales
+    let accessors = [(0, 2, "SCALAR"), (8, 2, "VEC3"), (32, 2, "VEC4"), (64, 2, "VEC3")]
+        .map(|(offset, count, accessor_type)| serde_json::json!({
+            "bufferView": 0,
+            "byteOffset": offset,
+            "componentType": 5126,
+            "count": count,
+            "type": accessor_type,
+        }));
+    let samplers = [1, 2, 3]
+        .map(|output| serde_json::json!({ "input": 0, "output": output, "interpolation": "LINEAR" }));
+    let channels = ["translation", "rotation", "scale"].iter().enumerate()
+        .map(|(i, path)| serde_json::json!({ "sampler": i, "target": { "node": 0, "path": path } }))
+        .collect::<Vec<_>>();
+    
+    serde_json::json!({
+        "asset": { "version": "2.0" },
+        "nodes": [{ "name": "root" }],
+        "buffers": [{
+            "byteLength": bytes.len(),
+            "uri": format!("data:application/octet-stream;base64,{}", BASE64_STANDARD.encode(&bytes)),
+        }],
+        "bufferViews": [{ "buffer": 0, "byteLength": bytes.len() }],
+        "accessors": accessors,
+        "animations": [{ "name": "grow", "samplers": samplers, "channels": channels }],
+    }).to_string()
+}
+
+#[test]
+fn import_gltf_animation_channels() -> Result<()> {
+    let import = |frame_rate: f32, resample: bool| -> Result<CgfxAnimation> {
+        let mut gfx = skeletal_model_container(&[])?;
+        let options = AnimationImportOptions { frame_rate, resample, tolerance: 0.001 };
+        
+        let names = import_gltf_animations(&mut gfx, animated_gltf().as_bytes(), Path::new("."), "default", &options)?;
+        assert_eq!(names, ["grow"]);
+        
+        Ok(gfx.skeletal_animations.and_then(|mut animations| animations.remove("grow").ok().flatten()).unwrap())
+    };
+    let key_frames = |value: &Option<AnimValue>| match value {
+        Some(AnimValue::Curve(curve)) => curve.key_frames.clone(),
+        Some(AnimValue::Constant(value)) => vec![AnimKeyFrame { frame: 0.0, value: *value, in_slope: 0.0, out_slope: 0.0 }],
+        None => panic!("Component isn't animated"),
+    };
+    
+    let animation = import(30.0, false)?;
+    assert_eq!(animation.frame_count, 30.0);
+    
+    let transform = &animation.elements.as_ref().and_then(|elements| elements.get("root")).unwrap().transform;
+    assert_eq!(transform.scale, [Some(AnimValue::Constant(2.0)), Some(AnimValue::Constant(2.0)), Some(AnimValue::Constant(2.0))]);
+    assert_eq!(transform.translation[0], Some(AnimValue::Constant(0.0)));
+    
+    let Some(AnimValue::Curve(translation_y)) = &transform.translation[1] else {
+        panic!("Translation isn't animated");
+    };
+    assert_eq!(translation_y.interpolation, AnimInterpolation::Linear);
+    assert_eq!(translation_y.key_frames.iter().map(|key_frame| (key_frame.frame, key_frame.value)).collect::<Vec<_>>(), [(0.0, 0.0), (30.0, 3.0)]);
+    
+    // the key frames of the glTF file are taken over, while resampling adds some in between
+    let rotations = transform.rotation.each_ref().map(key_frames);
+    assert!(rotations.iter().all(|key_frames| key_frames.len() <= 2));
+    
+    let resampled = import(10.0, true)?;
+    assert_eq!(resampled.frame_count, 10.0);
+    
+    let resampled_transform = &resampled.elements.as_ref().and_then(|elements| elements.get("root")).unwrap().transform;
+    let resampled_rotations = resampled_transform.rotation.each_ref().map(key_frames);
+    assert!(resampled_rotations.iter().any(|key_frames| key_frames.len() > 2));
+    
+    for (key_frames, resampled_key_frames) in rotations.iter().zip(&resampled_rotations) {
+        assert!((key_frames.last().unwrap().value - resampled_key_frames.last().unwrap().value).abs() < 1e-4);
+        assert!(resampled_key_frames.iter().all(|key_frame| key_frame.frame.fract() == 0.0 && key_frame.frame <= 10.0));
+    }
+    Ok(())
+}
+
+#[test]
+fn import_obj_geometry() -> Result<()> {
+    let obj = "
+o quad
+v 0 0 0
+v 2 0 0
+v 2 1 -4
+v 0 1 -4
+vt 0 0
+vt 1 1
+f 1/1 2/1 3/1 4/2
+f 1/1 3/1 4/1
+";
+
+    let objects = import_obj(obj)?;
+    assert_eq!(objects.len(), 1);
+    
+    let quad = &objects[0];
+    assert_eq!(quad.name.as_deref(), Some("quad"));
+    // vertex 4 is used with two different texture coordinates
+    assert_eq!(quad.streams.vertex_count(), 5);
+    assert_eq!(quad.triangles, vec![[0, 1, 2], [0, 2, 3], [0, 2, 4]]);
+    
+    let mut attribute = VertexBufferAttribute {
+        vertex_buffer_common: VertexBufferCommon {
+            attribute_name: AttributeName::Position,
+            vertex_buffer_type: VertexBufferType::None,
+        },
+        buffer_obj: 0,
+        location_flag: 0,
+        raw_bytes: None,
+        location_ptr: 0,
+        memory_area: 0,
+        format: GlDataType::Byte,
+        elements: 3,
+        scale: 1.0,
+        offset: 0,
+    };
+    
+    let positions = quad.streams.attribute_values(AttributeName::Position, 3);
+    let mut raw_bytes = vec![0u8; positions.len() * attribute.byte_size()];
+    
+    attribute.fit_scale(&positions);
+    attribute.encode(&positions, &mut raw_bytes, attribute.byte_size(), 0)?;
+    assert_eq!(attribute.scale, 4.0 / 127.0);
+    
+    for (decoded, original) in attribute.decode(&raw_bytes, attribute.byte_size(), 0)?.iter().zip(&positions) {
+        for (a, b) in decoded.iter().zip(original) {
+            assert!((a - b).abs() <= attribute.scale / 2.0);
+        }
+    }
+    
+    let bounding_box = BoundingBox::axis_aligned(0, &quad.streams.positions).unwrap();
+    assert_eq!((bounding_box.center.x, bounding_box.center.y, bounding_box.center.z), (1.0, 0.5, -2.0));
+    assert_eq!((bounding_box.size.x, bounding_box.size.y, bounding_box.size.z), (2.0, 1.0, 4.0));
+    
+    // interleaved vertices keep their stride, offsets and padding
+    let mut shape = padded_triangle_shape()?;
+    shape.replace_geometry(&quad.streams, &quad.triangles)?;
+    
+    let Some(VertexBuffer::Interleaved(interleaved)) = shape.vertex_buffers.as_ref().and_then(|buffers| buffers.first()) else {
+        panic!("Shape lost its interleaved vertex buffer");
+    };
+    let raw_bytes = interleaved.raw_bytes.as_deref().unwrap();
+    
+    assert_eq!((interleaved.vertex_stride, interleaved.attributes.as_ref().unwrap()[0].offset), (20, 4));
+    assert_eq!(raw_bytes.len(), 5 * 20);
+    assert!(raw_bytes.chunks(20).all(|vertex| vertex[..4] == [0; 4] && vertex[16..] == [0; 4]));
+    assert_eq!(shape.vertex_streams()?.positions, quad.streams.positions);
+    Ok(())
+}
+
+#[test]
+fn fit_oriented_bounding_boxes() {
+    // a long thin rod along the diagonal of the xy plane
+    let positions: Vec<na::Vec3> = (0..=10)
+        .flat_map(|i| {
+            let t = i as f32;
+            [na::Vec3::new(t, t, 0.0), na::Vec3::new(t + 0.1, t - 0.1, 0.5)]
+        })
+        .collect();
+    
+    let axis_aligned = BoundingBox::axis_aligned(0, &positions).unwrap();
+    let oriented = BoundingBox::oriented(0, &positions).unwrap();
+    
+    assert!(oriented.volume() < axis_aligned.volume() / 10.0);
+    assert!((oriented.orientation.determinant() - 1.0).abs() < 1e-4);
+    assert!(positions.iter().all(|position| oriented.contains(position, 1e-4)));
+    assert!(!oriented.contains(&na::Vec3::new(10.0, 0.0, 0.0), 1e-4));
+    
+    // already axis aligned geometry never gets a worse box
+    let cube: Vec<na::Vec3> = (0..8)
+        .map(|i| na::Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32))
+        .collect();
+    assert!(BoundingBox::oriented(0, &cube).unwrap().volume() <= 1.0 + 1e-4);
+    assert!(BoundingBox::oriented(0, &[]).is_none());
+}
+
+#[test]
+fn convert_euler_angles_and_matrices() {
+    let orders = [EulerOrder::XYZ, EulerOrder::XZY, EulerOrder::YXZ, EulerOrder::YZX, EulerOrder::ZXY, EulerOrder::ZYX];
+    let rotation = Vec3::new(0.3, -1.1, 0.7);
+    
+    for order in orders {
+        let quaternion = euler_to_quaternion(&rotation, order);
+        let back = quaternion_to_euler(&quaternion, order);
+        
+        assert!(quaternion.angle_to(&euler_to_quaternion(&back, order)) < 1e-4, "{:?} doesn't round trip", order);
+        assert!((back.to_na() - rotation.to_na()).norm() < 1e-4, "{:?} doesn't round trip", order);
+    }
+    
+    // matches nalgebra's convention, which is the one CGFX uses
+    let expected = na::UnitQuat::from_euler_angles(rotation.x, rotation.y, rotation.z);
+    assert!(euler_to_quaternion(&rotation, EulerOrder::XYZ).angle_to(&expected) < 1e-5);
+    
+    // gimbal lock still describes the same rotation
+    let locked = Vec3::new(0.4, std::f32::consts::FRAC_PI_2, 0.0);
+    let quaternion = euler_to_quaternion(&locked, EulerOrder::XYZ);
+    assert!(quaternion.angle_to(&euler_to_quaternion(&quaternion_to_euler(&quaternion, EulerOrder::XYZ), EulerOrder::XYZ)) < 1e-3);
+    
+    let scale = Vec3::new(2.0, 0.5, 1.5);
+    let translation = Vec3::new(1.0, -2.0, 3.0);
+    let matrix = srt_to_matrix(&scale, &rotation, &translation);
+    let (scale_back, rotation_back, translation_back) = matrix_to_srt(&matrix);
+    
+    assert!((scale_back.to_na() - scale.to_na()).norm() < 1e-4);
+    assert!((rotation_back.to_na() - rotation.to_na()).norm() < 1e-4);
+    assert!((translation_back.to_na() - translation.to_na()).norm() < 1e-4);
+    
+    let inverse = invert_transform(&matrix).unwrap();
+    assert!((multiply_transforms(&matrix, &inverse) - na::Matrix3x4::identity()).norm() < 1e-4);
+}
+
+#[test]
+fn encode_swizzled_textures() -> Result<()> {
+    // multiples of 17 survive being reduced to 4 bits per channel
+    let colors: Vec<RgbaColor> = (0..16 * 8)
+        .map(|i| RgbaColor { r: (i % 16) as u8 * 17, g: (i / 16) as u8 * 17, b: 0x33, a: 0xFF - (i % 4) as u8 * 17 })
+        .collect();
+    
+    for format in [PicaTextureFormat::RGBA8, PicaTextureFormat::RGBA4] {
+        let encoded = encode_swizzled_buffer(&colors, format, 16, 8)?;
+        
+        assert_eq!(encoded.len(), 16 * 8 * format.get_bpp() as usize / 8);
+        assert_eq!(decode_swizzled_buffer(&encoded, format, 16, 8)?, colors);
+    }
+    
+    assert!(encode_swizzled_buffer(&colors, PicaTextureFormat::RGBA8, 8, 16).is_ok());
+    assert!(encode_swizzled_buffer(&colors, PicaTextureFormat::RGBA8, 4, 32).is_err());
+    Ok(())
+}
+
+#[test]
+fn build_dict_tree() -> Result<()> {
+    let dict = CgfxDict::from_values("DICT", vec![("tex".to_string(), 1u32)])?;
+    
+    // same tree as the one of single texture containers
+    assert_eq!(dict.tree_length, 44);
+    assert_eq!((dict.nodes[0].reference_bit, dict.nodes[0].left_node_index, dict.nodes[0].right_node_index), (0xFFFFFFFF, 1, 0));
+    assert_eq!((dict.nodes[1].reference_bit, dict.nodes[1].left_node_index, dict.nodes[1].right_node_index), (22, 0, 1));
+    
+    let values = vec![("a".to_string(), 1u32), ("b".to_string(), 2), ("ab".to_string(), 3), ("a".to_string(), 4)];
+    assert!(CgfxDict::from_values("DICT", values).is_err());
+    Ok(())
+}
+
+#[test]
+fn edit_dict() -> Result<()> {
+    let names = ["Body", "Head", "ArmL", "ArmR", "Arm", "LegL", "LegR", "a", "b", "ab"];
+    let mut dict = CgfxDict::new("DICT");
+    
+    for (i, name) in names.iter().enumerate() {
+        assert_eq!(dict.insert(name, i as u32)?, None);
+    }
+    
+    assert_eq!(dict.insert("Head", 100)?, Some(1));
+    assert_eq!(dict.get("Head"), Some(&100));
+    assert_eq!(dict.get("Hea"), None);
+    
+    *dict.get_mut("Arm").unwrap() += 1;
+    assert_eq!(dict.get("Arm"), Some(&5));
+    
+    dict.rename("ArmL", "ArmLeft")?;
+    assert!(dict.rename("ArmR", "Body").is_err());
+    assert!(dict.rename("Missing", "Other").is_err());
+    
+    assert_eq!(dict.remove("LegL")?, Some(5));
+    assert_eq!(dict.remove("LegL")?, None);
+    
+    // every value can still be found through the tree and the order didn't change
+    let expected = ["Body", "Head", "ArmLeft", "ArmR", "Arm", "LegR", "a", "b", "ab"];
+    assert_eq!(dict.iter().map(|(name, _)| name.unwrap()).collect::<Vec<_>>(), expected);
+    
+    for (i, name) in expected.iter().enumerate() {
+        assert_eq!(dict.index_of(name), Some(i));
+        assert!(dict.get(name).is_some());
+    }
+    
+    assert_eq!(dict.len(), expected.len());
+    assert_eq!(dict.values_count, expected.len() as u32);
+    assert_eq!(dict.tree_length, 0x1C + 0x10 * expected.len() as u32);
+    Ok(())
+}
+
+#[test]
+fn reduce_animation_curves() -> Result<()> {
+    let key_frame = |frame: f32, value: f32| AnimKeyFrame { frame, value, ..Default::default() };
+    
+    // a ramp with a tiny bump, followed by a plateau
+    let mut curve = AnimCurve::new(AnimInterpolation::Linear, vec![
+        key_frame(0.0, 0.0),
+        key_frame(1.0, 1.0),
+        key_frame(2.0, 2.0005),
+        key_frame(3.0, 3.0),
+        key_frame(4.0, 3.0),
+        key_frame(5.0, 3.0),
+    ]);
+    
+    curve.reduce_key_frames(0.001);
+    assert_eq!(curve.key_frames, vec![key_frame(0.0, 0.0), key_frame(3.0, 3.0), key_frame(5.0, 3.0)]);
+    assert_eq!(curve.evaluate(1.5), 1.5);
+    assert_eq!(curve.evaluate(10.0), 3.0);
+    assert_eq!(curve.constant_value(0.001), None);
+    
+    let mut buffer = Vec::new();
+    curve.to_writer(&mut Cursor::new(&mut buffer))?;
+    assert_eq!(buffer.len(), 24 + 3 * 8);
+    assert_eq!(AnimCurve::from_reader(&mut Cursor::new(&buffer[..]))?, curve);
+    
+    let mut step_curve = AnimCurve::new(AnimInterpolation::Step, vec![key_frame(0.0, 1.0), key_frame(2.0, 1.0), key_frame(4.0, 1.0)]);
+    step_curve.reduce_key_frames(0.0);
+    assert_eq!(step_curve.key_frames, vec![key_frame(0.0, 1.0)]);
+    assert_eq!(step_curve.constant_value(0.0), Some(1.0));
+    Ok(())
+}
+
+#[test]
+fn report_malformed_containers() -> Result<()> {
+    // header and dict references of a container, `dicts` are (index, count, relative pointer)
+    let container = |dicts: &[(usize, u32, u32)]| {
+        let mut buffer = b"CGFX".to_vec();
+        buffer.extend(0xFEFFu16.to_le_bytes());
+        buffer.extend(0x14u16.to_le_bytes());
+        
+        for value in [0x5000000u32, 0, 1] {
+            buffer.extend(value.to_le_bytes());
+        }
+        
+        buffer.extend(b"DATA");
+        buffer.extend(0u32.to_le_bytes());
+        
+        for i in 0..16 {
+            let (count, pointer) = dicts.iter()
+                .find(|(index, _, _)| *index == i)
+                .map_or((0, 0), |(_, count, pointer)| (*count, *pointer));
+            
+            buffer.extend(count.to_le_bytes());
+            buffer.extend(pointer.to_le_bytes());
+        }
+        
+        buffer
+    };
+    
+    let truncated = CgfxContainer::new(&container(&[])[..10]).unwrap_err();
+    assert_eq!(truncated.path(), Some("header"));
+    assert!(matches!(truncated.root_cause(), Error::Io(_)));
+    
+    // dict pointing past the end of the file
+    let out_of_bounds = CgfxContainer::new(&container(&[(0, 1, 0x1000)])).unwrap_err();
+    assert_eq!(out_of_bounds.path(), Some("models"));
+    assert_eq!(out_of_bounds.offset(), Some(0x1C + 4 + 0x1000));
+    
+    // empty luts dict, but the container says it has one lut
+    let mut buffer = container(&[(2, 1, 0x100 - (0x1C + 2 * 8 + 4))]);
+    buffer.resize(0x100, 0);
+    buffer.extend(b"DICT");
+    
+    for value in [0x1Cu32, 0, 0xFFFFFFFF, 0, 0, 0] {
+        buffer.extend(value.to_le_bytes());
+    }
+    
+    let count_mismatch = CgfxContainer::new(&buffer).unwrap_err();
+    assert_eq!(count_mismatch.path(), Some("luts"));
+    assert_eq!(count_mismatch.offset(), Some(0x100));
+    assert!(matches!(count_mismatch.root_cause(), Error::Malformed(_)));
+    
+    // sub mesh with more bone indices than the whole file could hold
+    let mut sub_mesh = Vec::new();
+    
+    for value in [0xFFFFFFFFu32, 4, 0, 0, 0] {
+        sub_mesh.extend(value.to_le_bytes());
+    }
+    
+    let huge_count = SubMesh::from_reader(&mut Cursor::new(&sub_mesh)).unwrap_err();
+    assert!(matches!(huge_count.root_cause(), Error::Malformed(_)));
+    
+    // garbage isn't Bottom LZ compressed either
+    assert!(blz_decode(&[0xAB; 64]).is_err());
+    
+    // data which can't be compressed is stored raw, marked by a size increase of 0
+    let noise: Vec<u8> = (0..64u32).map(|i| (i.wrapping_mul(0x9E3779B1) >> 24) as u8).collect();
+    let stored = blz_encode(&mut noise.clone())?;
+    assert_eq!(stored.len(), noise.len() + 4);
+    assert_eq!(blz_decode(&stored)?, noise);
+    assert_eq!(blz_decode(&[0; 12])?, [0; 8]);
+    Ok(())
+}
+
+/// Parses `buffer` as far as possible, decoding textures and shapes too. Any result is fine as long as it doesn't panic.
+fn parse_untrusted(buffer: &[u8]) {
+    let Ok(container) = CgfxContainer::new(buffer) else {
+        return;
+    };
+    
+    for texture in container.textures.iter().flat_map(|textures| textures.values()) {
+        let _ = texture.to_png();
+    }
+    
+    for model in container.models.iter().flat_map(|models| models.values()) {
+        for shape in model.common().shapes.iter().flatten() {
+            let _ = shape.vertex_streams();
+        }
+    }
+}
+
+#[test]
+fn parse_synthetic_texture_bcres() -> Result<()> {
+    let buffer = texture_bcres("tex", PicaTextureFormat::RGBA8, 8, 8);
+    let container = CgfxContainer::new(&buffer)?;
+    
+    let textures = container.textures.as_ref().unwrap();
+    let (name, texture) = textures.iter().next().unwrap();
+    assert_eq!(name, Some("tex"));
+    assert_eq!((texture.common().width, texture.common().height), (8, 8));
+    assert_eq!(texture.size(), 8 * 8 * 4);
+    
+    texture.to_png()?;
+    Ok(())
+}
+
+#[test]
+fn parse_synthetic_model_bcres() -> Result<()> {
+    let container = CgfxContainer::new(&model_bcres("body", "body_mat", "body_tex"))?;
+    let model = container.models.as_ref().and_then(|models| models.get("body")).unwrap();
+    let common = model.common();
+    
+    assert!(model.skeleton().is_none());
+    assert_eq!(common.cgfx_object_header.name.as_deref(), Some("body"));
+    assert_eq!(common.face_culling, 1);
+    
+    let mesh = &common.meshes.as_ref().unwrap()[0];
+    assert_eq!((mesh.shape_index, mesh.material_index, mesh.visible), (0, 0, 1));
+    assert_eq!(mesh.mesh_node_name.as_deref(), Some("body"));
+    
+    let material = common.materials.as_ref().and_then(|materials| materials.get("body_mat")).unwrap();
+    assert_eq!(material.base_texture_name(), Some("body_tex"));
+    assert_eq!(material.rasterization.face_culling, FaceCulling::BackFace);
+    assert_eq!(material.unique_id, 42);
+    
+    let shape = &common.shapes.as_ref().unwrap()[0];
+    assert_eq!(shape.bounding_box.as_ref().unwrap().size, Vec3::new(1.0, 1.0, 0.0));
+    
+    let faces = shape.sub_meshes.as_ref().unwrap()[0].faces.as_ref().unwrap();
+    assert_eq!(faces[0].face_descriptors.as_ref().unwrap()[0].triangles()?, [[0, 1, 2]]);
+    
+    let streams = shape.vertex_streams()?;
+    assert_eq!(streams.positions, [na::Vec3::new(0.0, 0.0, 0.0), na::Vec3::new(1.0, 0.0, 0.0), na::Vec3::new(0.0, 1.0, 0.0)]);
+    assert_eq!(streams.colors.unwrap()[2], Vec4::new(1.0, 0.5, 0.25, 1.0));
+    
+    // the writer keeps everything the hand written file contains, only its padding differs
+    let mut rewritten = container.clone();
+    rewritten.update_header()?;
+    
+    let written = CgfxContainer::new(&rewritten.to_buffer_verified()?)?;
+    let written_common = written.models.as_ref().and_then(|models| models.get("body")).unwrap().common();
+    
+    assert_eq!(written_common.meshes, common.meshes);
+    assert_eq!(written_common.shapes, common.shapes);
+    assert_eq!(written_common.materials.as_ref().and_then(|materials| materials.get("body_mat")), Some(material));
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+    
+    #[test]
+    fn parse_corrupted_bcres(
+        format in prop::sample::select(TEXTURE_FORMATS.to_vec()),
+        mutations in vec((any::<prop::sample::Index>(), any::<u32>()), 1..8),
+    ) {
+        let mut buffer = texture_bcres("texture", format, 16, 8);
+        
+        // overwrite aligned words, which hits counts and pointers far more often than random bytes
+        for (index, value) in mutations {
+            let offset = index.index(buffer.len() / 4) * 4;
+            buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        
+        parse_untrusted(&buffer);
+    }
+    
+    #[test]
+    fn parse_corrupted_model(
+        mutations in vec((any::<prop::sample::Index>(), any::<u32>()), 1..8),
+    ) {
+        let mut buffer = skeletal_model_container(&[PicaTextureFormat::RGBA8])
+            .and_then(|gfx| Ok(gfx.to_buffer()?))
+            .expect("Model fixture can be written");
+        
+        for (index, value) in mutations {
+            let offset = index.index(buffer.len() / 4) * 4;
+            buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        
+        parse_untrusted(&buffer);
+    }
+    
+    #[test]
+    fn parse_truncated_bcres(length in 0..0x300usize) {
+        let buffer = texture_bcres("texture", PicaTextureFormat::RGB565, 8, 8);
+        parse_untrusted(&buffer[..length.min(buffer.len())]);
+    }
+    
+    #[test]
+    fn parse_random_bytes(buffer in vec(any::<u8>(), 0..0x200)) {
+        parse_untrusted(&buffer);
+        let _ = blz_decode(&buffer);
+        let _ = ArchiveRegistry::new(&buffer);
+    }
+    
+    #[test]
+    fn decode_corrupted_blz(
+        data in vec(0..4u8, 0x20..0x400),
+        mutations in vec((any::<prop::sample::Index>(), any::<u8>()), 0..4),
+    ) {
+        let mut encoded = blz_encode(&mut data.clone())?;
+        
+        // data which can't be compressed is stored raw, padded to a multiple of 4 bytes
+        if mutations.is_empty() {
+            let decoded = blz_decode(&encoded)?;
+            prop_assert_eq!(&decoded[..data.len()], &data[..]);
+            prop_assert!(decoded[data.len()..].iter().all(|byte| *byte == 0));
+        }
+        
+        for (index, value) in mutations {
+            let offset = index.index(encoded.len());
+            encoded[offset] = value;
+        }
+        
+        let _ = blz_decode(&encoded);
+    }
+}
+
+/// Words that look like counts, pointers, discriminants or floats, so random
+/// buffers get past the first checks of the parsers more often.
+fn plausible_word() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        4 => 0..8u32,
+        4 => (0..0x80u32).prop_map(|x| x * 4),
+        2 => prop::sample::select(vec![
+            0x40000012, 0x40000092, 0x10000001, 0x20000011, 0x20000009, 0x40000001, 0x40000002,
+            0x80000000, 0x10000000, 0x08000000, 0x02000000, 0x00100001, 0x00200002, 0xFFFFFFFF,
+            0x3F800000, u32::from_le_bytes(*b"DICT"), u32::from_le_bytes(*b"TXOB"),
+        ]),
+        1 => any::<u32>(),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+    
+    #[test]
+    fn parse_plausible_structures(words in vec(plausible_word(), 2..0x100)) {
+        // start every buffer with the discriminant and magic the parser expects
+        let with_prefix = |prefix: &[u32]| -> Vec<u8> {
+            prefix.iter().chain(&words[prefix.len().min(words.len())..])
+                .flat_map(|word| word.to_le_bytes())
+                .collect()
+        };
+        let magic = |magic: &[u8; 4]| u32::from_le_bytes(*magic);
+        
+        let model = if words[0] & 1 == 0 { 0x40000012 } else { 0x40000092 };
+        let _ = CgfxModel::from_reader(&mut Cursor::new(with_prefix(&[model, magic(b"CMDL")])));
+        let _ = CgfxSkeleton::from_reader(&mut Cursor::new(with_prefix(&[0x02000000, magic(b"SOBJ")])));
+        let _ = CgfxTexture::from_reader(&mut Cursor::new(with_prefix(&[0x20000011, magic(b"TXOB")])));
+        let _ = CgfxAnimation::from_reader(&mut Cursor::new(with_prefix(&[magic(b"CANM")])));
+        let _ = CgfxEmitter::from_reader(&mut Cursor::new(with_prefix(&[0x40000000, magic(b"PEMT")])));
+        let _ = CgfxDict::<CgfxModel>::from_reader(&mut Cursor::new(with_prefix(&[magic(b"DICT")])));
+    }
+}
+
+#[test]
+fn dump_containers_to_text() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let gfx = model_container(&TEXTURE_FORMATS)?;
+    let content = gfx.to_buffer()?;
+    let gfx = CgfxContainer::new(&content)?;
+    
+    let model = gfx.models.as_ref().and_then(|models| models.get("triangle")).unwrap();
+    assert!(model.common().materials.as_ref().and_then(|materials| materials.get("white")).is_some());
+    
+    let yaml = gfx.to_yaml(&dir.path().join("yaml"))?;
+    let from_yaml = CgfxContainer::from_yaml(&yaml, &dir.path().join("yaml"))?;
+    
+    // offsets remembered while parsing aren't part of the text, so compare what gets written instead
+    assert!(from_yaml.to_yaml(&dir.path().join("yaml"))? == yaml, "Container changed when dumped to YAML");
+    assert!(from_yaml.to_buffer_verified()? == content, "Container dumped to YAML does not match its original when written");
+    
+    // one file for each texture and the interleaved vertex buffer
+    assert_eq!(fs::read_dir(dir.path().join("yaml"))?.count(), TEXTURE_FORMATS.len() + 1);
+    assert!(!yaml.contains("!base64"));
+    
+    let json = gfx.to_json(&dir.path().join("json"))?;
+    let from_json = CgfxContainer::from_json(&json, &dir.path().join("json"))?;
+    assert!(from_json.to_buffer_verified()? == content, "Container dumped to JSON does not match its original when written");
+    
+    // without a blob directory, blobs are embedded
+    let embedded: CgfxContainer = serde_yaml::from_str(&serde_yaml::to_string(&gfx)?)?;
+    assert!(embedded.to_buffer_verified()? == content, "Container with embedded blobs does not match its original when written");
+    
+    // edits in the text show up in the written file
+    let edited_yaml = yaml.replacen("layer_id: 0", "layer_id: 3", 1);
+    let edited = CgfxContainer::new(&CgfxContainer::from_yaml(&edited_yaml, &dir.path().join("yaml"))?.to_buffer()?)?;
+    assert_eq!(edited.models.unwrap().get("triangle").unwrap().common().layer_id, 3);
+    
+    // blobs can't be taken from outside of the blob directory
+    let escaping_yaml = yaml.replacen("!file 0000.bin", "!file ../0000.bin", 1);
+    assert!(CgfxContainer::from_yaml(&escaping_yaml, &dir.path().join("yaml")).is_err());
+    assert!(CgfxContainer::from_yaml(&yaml, &dir.path().join("missing")).is_err());
+    
+    Ok(())
+}
+
+#[test]
+fn dump_materials_and_emitters_to_text() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let model = CgfxContainer::new(&model_bcres("body", "body_mat", "body_tex"))?
+        .models.and_then(|mut models| models.remove("body").ok().flatten())
+        .expect("Model fixture contains its model");
+    let texture = CgfxTexture::from_rgba("body_tex", 8, 8, PicaTextureFormat::RGBA8, &[RgbaColor { r: 255, g: 0, b: 0, a: 255 }; 64])?;
+    let emitter = sparkle_emitter()?;
+    
+    // the matrices of the fixture are the ones its scale, rotation and translation describe
+    assert_eq!(emitter.transform_node_header.local_transform, emitter.transform_node_header.srt_matrix());
+    
+    let gfx = CgfxContainer::builder().model(model).texture(texture).emitter(emitter.clone()).build()?;
+    let content = gfx.to_buffer_verified()?;
+    let gfx = CgfxContainer::new(&content)?;
+    
+    let yaml = gfx.to_yaml(&dir.path().join("yaml"))?;
+    let from_yaml = CgfxContainer::from_yaml(&yaml, &dir.path().join("yaml"))?;
+    assert!(from_yaml.to_buffer_verified()? == content, "Container with materials and emitters changed when dumped to YAML");
+    
+    let material = from_yaml.models.as_ref().and_then(|models| models.get("body"))
+        .and_then(|model| model.common().materials.as_ref()?.get("body_mat")).unwrap();
+    assert_eq!(material.base_texture_name(), Some("body_tex"));
+    assert_eq!(from_yaml.emitters.as_ref().and_then(|emitters| emitters.get("sparkle")), Some(&emitter));
+    Ok(())
+}
+
+#[test]
+fn inspect_containers_and_archives() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let gfx = CgfxContainer::new(&model_container(&TEXTURE_FORMATS)?.to_buffer()?)?;
+    
+    let Inspection::Container { dicts, .. } = inspect_container(&gfx)? else {
+        panic!("Inspecting a container has to describe a container");
+    };
+    
+    let names: Vec<_> = dicts.iter().map(|dict| dict.name).collect();
+    assert_eq!(names, ["models", "textures"]);
+    
+    let EntryDetails::Model(model) = &dicts[0].entries[0].details else {
+        panic!("Expected a model, got {:?}", dicts[0].entries[0]);
+    };
+    
+    assert_eq!(dicts[0].entries[0].name.as_deref(), Some("triangle"));
+    assert_eq!(model.shapes.len(), 1);
+    assert_eq!(model.shapes[0].vertices, Some(3));
+    assert_eq!(model.shapes[0].triangles, 1);
+    assert_eq!(model.materials, [Some("white".to_string())]);
+    
+    for (entry, format) in dicts[1].entries.iter().zip(TEXTURE_FORMATS) {
+        let EntryDetails::Texture(texture) = &entry.details else {
+            panic!("Expected a texture, got {:?}", entry);
+        };
+        
+        assert_eq!(entry.name, Some(texture_id(format)));
+        assert_eq!((texture.width, texture.height, texture.format), (8, 16, format));
+    }
+    
+    let archive = texture_archive(dir.path(), "texture_US", &TEXTURE_FORMATS)?;
+    let registry = ArchiveRegistry::new(&fs::read(dir.path().join("texture_US_info.bin"))?)?;
+    let inspection = inspect_archive(&fs::read(archive)?, &registry);
+    
+    let Inspection::Archive { entries } = &inspection else {
+        panic!("Inspecting an archive has to describe an archive");
+    };
+    
+    assert_eq!(entries.len(), TEXTURE_FORMATS.len());
+    
+    for (entry, format) in entries.iter().zip(TEXTURE_FORMATS) {
+        assert_eq!(entry.error, None);
+        assert_eq!(entry.texture.as_ref().map(|texture| texture.format), Some(format));
+    }
+    
+    let text = inspection.to_text()?;
+    assert!(text.contains(&texture_id(TEXTURE_FORMATS[0])), "Text does not list the entries:\n{}", text);
+    
+    let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&inspection)?)?;
+    assert_eq!(json["kind"], "archive");
+    assert_eq!(json["entries"].as_array().map(Vec::len), Some(TEXTURE_FORMATS.len()));
+    
+    // entries pointing outside of the archive get reported instead of failing
+    let inspection = inspect_archive(&[], &registry);
+    let Inspection::Archive { entries } = &inspection else { unreachable!() };
+    assert!(entries.iter().all(|entry| entry.error.is_some()));
+    
+    Ok(())
+}
+
+/// An empty standard model whose node header has a single anim group, which is at 0xE0.
+fn anim_group_model(anim_group: &[u32]) -> Result<Vec<u8>> {
+    let object_header = [0x40000012, u32::from_le_bytes(*b"CMDL"), 0x9000000, 0, 0, 0];
+    let node_header = [1, 1, 0, 0, 1, 0xE0 - 0x2C];
+    let lists = [0; 11];
+    let dict = [u32::from_le_bytes(*b"DICT"), 0x2C, 1, 0xFFFFFFFF, 1, 0, 0, 0, 0x10000, 0, 4];
+    
+    let mut bytes = Vec::new();
+    
+    for word in object_header.into_iter().chain(node_header).chain([0; 33]).chain(lists).chain(dict).chain(anim_group.iter().copied()) {
+        bytes.write_u32::<LittleEndian>(word)?;
+    }
+    
+    Ok(bytes)
+}
+
+/// Writes the node header of `model` followed by its anim groups.
+fn write_anim_groups(model: &CgfxModel) -> Result<Vec<u8>> {
+    let node_header = &model.common().cgfx_node_header;
+    
+    let mut out = Vec::new();
+    let mut writer = Cursor::new(&mut out);
+    let mut ctx = WriteContext::new();
+    
+    node_header.write(&mut writer)?;
+    node_header.write_anim_groups(&mut writer, &mut ctx, Pointer::from(0u32))?;
+    
+    Ok(out)
+}
+
+#[test]
+fn write_node_header_anim_groups() -> Result<()> {
+    let bytes = anim_group_model(&[1, 0, 2, 0, 0, 1, 8, 1, 6])?;
+    
+    let model = CgfxModel::from_reader(&mut Cursor::new(&bytes[..]))?;
+    let node_header = &model.common().cgfx_node_header;
+    assert_eq!(node_header.anim_groups.as_ref().map(|anim_groups| anim_groups.values_count), Some(1));
+    
+    // the anim groups are written right after the node header this time
+    let out = write_anim_groups(&model)?;
+    
+    assert_eq!(out[..0x10], bytes[0x18..0x28]);
+    assert_eq!(out[0x10..0x18], [1, 0, 0, 0, 4, 0, 0, 0]);
+    assert_eq!(out[0x18..], bytes[0xE0..]);
+    Ok(())
+}
+
+#[test]
+fn write_model_anim_groups() -> Result<()> {
+    // the group's element animates the third mesh and its dict follows the blend operations
+    let anim_group = [1, 0, 2, 1, 0x14, 1, 8, 1, 6];
+    let elements = [u32::from_le_bytes(*b"DICT"), 0x2C, 1, 0xFFFFFFFF, 1, 0, 0, 0, 0x10000, 0, 4];
+    let element = [0x01000000, 0, 0x2C, 0, 0, 0, 2];
+    
+    let bytes = anim_group_model(&[&anim_group[..], &elements, &element].concat())?;
+    
+    let model = CgfxModel::from_reader(&mut Cursor::new(&bytes[..]))?;
+    let anim_group = model.common().cgfx_node_header.anim_groups.as_ref()
+        .and_then(|anim_groups| anim_groups.nodes[1].value.as_ref())
+        .unwrap();
+    
+    let element = anim_group.elements.as_ref().and_then(|elements| elements.nodes[1].value.as_ref()).unwrap();
+    assert_eq!(element.member, AnimGroupMember::Mesh { mesh_index: 2 });
+    assert_eq!(element.member_offset, 0x2C);
+    assert_eq!(anim_group.blend_operation(element), anim_group.blend_operations.first().copied());
+    
+    let out = write_anim_groups(&model)?;
+    assert_eq!(out[0x18..], bytes[0xE0..]);
+    Ok(())
+}
+
+#[test]
+fn decode_blend_shape_targets() -> Result<()> {
+    // a shape with one vertex whose second vertex buffer is a blend shape target moving it up
+    let shape = [0x10000001, u32::from_le_bytes(*b"SOBJ"), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0xC, 0x10, 0];
+    let vertex_buffer_list = [0x28, 0x64];
+    let blend_shape = [1, 0x10, 1, 0x10, 1];
+    let target = [1, 0.5f32.to_bits()];
+    let attributes = [0];
+    
+    let vertex_buffer = |position: [f32; 3]| {
+        let attribute = [0x40000001, 0, 0, 0, 0, 12, 0x1C, 0, 0, 0x1406, 3, 1f32.to_bits(), 0];
+        attribute.into_iter().chain(position.map(f32::to_bits))
+    };
+    
+    let mut bytes = Vec::new();
+    
+    for word in shape.into_iter().chain(vertex_buffer_list).chain(blend_shape).chain(target).chain(attributes)
+        .chain(vertex_buffer([1.0, 2.0, 3.0])).chain(vertex_buffer([1.0, 4.0, 3.0])) {
+        bytes.write_u32::<LittleEndian>(word)?;
+    }
+    
+    let shape = Shape::from_reader(&mut Cursor::new(&bytes[..]))?;
+    let blend_shape = shape.blend_shape.as_ref().unwrap();
+    
+    assert_eq!(blend_shape.blend_type, BlendShapeType::Position);
+    assert_eq!(blend_shape.targets, Some(vec![BlendShapeTarget { vertex_buffer_index: 1, weight: 0.5 }]));
+    assert_eq!(blend_shape.attributes, Some(vec![AttributeName::Position]));
+    assert!(!blend_shape.is_target_buffer(0) && blend_shape.is_target_buffer(1));
+    
+    // the base geometry skips the target's vertex buffer
+    assert_eq!(shape.decode_attribute(AttributeName::Position)?, Some(vec![vec![1.0, 2.0, 3.0]]));
+    assert_eq!(shape.decode_blend_shape_target(0, AttributeName::Position)?, Some(vec![vec![1.0, 4.0, 3.0]]));
+    assert!(shape.decode_blend_shape_target(1, AttributeName::Position).is_err());
+    Ok(())
+}
@@ -2,10 +2,11 @@
 // based on CUE's DS/GBA Compressors
 use std::io::{self, Cursor, Seek, SeekFrom};
 
-use anyhow::{Error, Result};
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use rayon::iter::{repeat, ParallelIterator};
 
+use crate::{error::malformed, Result};
+
 /// 3-bytes length, 16MB - 1
 const RAW_MAXIM: usize = 0x00FFFFFF;
 
@@ -14,7 +15,9 @@ const BLZ_SHIFT: u32 = 1;
 
 /// bits to check
 /// 
-///     ((((1 << BLZ_SHIFT) - 1) << (8 - BLZ_SHIFT)
+/// ```text
+/// ((((1 << BLZ_SHIFT) - 1) << (8 - BLZ_SHIFT)
+/// ```
 const BLZ_MASK: u32 = 0x80;
 
 /// max number of bytes to not encode
@@ -25,51 +28,65 @@ const BLZ_THRESHOLD_U32: u32 = 2;
 
 /// max lz offset (aka BLZ_N)
 /// 
-///     ((1 << 12) + 2)
+/// ```text
+/// ((1 << 12) + 2)
+/// ```
 const BLZ_MAX_OFFSET: usize = 0x1002;
 
 /// max coded (aka BLZ_F)
 /// 
-///     ((1 << 4) + BLZ_THRESHOLD)
+/// ```text
+/// ((1 << 4) + BLZ_THRESHOLD)
+/// ```
 const BLZ_MAX_CODED: usize = 0x12;
 
 pub fn blz_decode(input_buffer: &[u8]) -> Result<Vec<u8>> {
     if input_buffer.len() % 4 != 0 {
-        return Err(Error::msg("Input buffer has an invalid length (must be multiple of 4)"));
+        return Err(malformed!("Input buffer has an invalid length (must be multiple of 4)"));
+    }
+    
+    // a size increase of 0 means it's stored raw, because it couldn't be compressed
+    if let Some(raw) = input_buffer.strip_suffix(&[0; 4]) {
+        return Ok(raw.to_vec());
     }
     
     if input_buffer.len() < 8 {
-        return Err(Error::msg("Input buffer is too small to be a valid Bottom LZ file"));
+        return Err(malformed!("Input buffer is too small to be a valid Bottom LZ file"));
     }
     
     // extracting basic information
-    let input_buffer_length: u32 = input_buffer.len().try_into().unwrap();
+    let input_buffer_length: u32 = input_buffer.len().try_into()?;
     
     let mut input_buffer_u32: Vec<u32> = repeat(0).take(input_buffer.len() / 4).collect();
     LittleEndian::read_u32_into(input_buffer, &mut input_buffer_u32);
     
     let result_size_increase = input_buffer_u32[input_buffer_u32.len() - 1];
     
-    if result_size_increase == 0 {
-        panic!("Not coded file!");
-    }
-    
     let header_length: u32 = input_buffer[input_buffer.len() - 5].into();
-    assert!(header_length >= 0x08 || header_length <= 0x0B, "Invalid header length");
-    assert!(input_buffer_length > header_length, "Invalid header length");
+    if !(0x08..=0x0B).contains(&header_length) || input_buffer_length <= header_length {
+        return Err(malformed!("Invalid Bottom LZ header length {:#x}", header_length));
+    }
     
     let mut encoded_length = input_buffer_u32[input_buffer_u32.len() - 2] & 0x00FFFFFF;
+    
+    if encoded_length < header_length || encoded_length > input_buffer_length {
+        return Err(malformed!("Invalid Bottom LZ encoded length {:#x}", encoded_length));
+    }
+    
     let unencoded_length = input_buffer_length - encoded_length;
     
     encoded_length -= header_length;
     
-    let encoded_length_usize: usize = encoded_length.try_into().unwrap();
-    let unencoded_length_usize: usize = unencoded_length.try_into().unwrap();
+    let encoded_length_usize: usize = encoded_length.try_into()?;
+    let unencoded_length_usize: usize = unencoded_length.try_into()?;
     
-    let result_size: usize = (input_buffer_length + result_size_increase)
-        .try_into()
-        .unwrap();
-    assert!(result_size <= RAW_MAXIM, "Resulting file too large");
+    let result_size: usize = input_buffer_length.checked_add(result_size_increase)
+        .ok_or_else(|| malformed!("Resulting file too large"))?
+        .try_into()?;
+    
+    if result_size > RAW_MAXIM {
+        return Err(malformed!("Resulting file too large"));
+    }
     
     // start populating result with unencoded area
     let mut result_buffer: Vec<u8> = Vec::with_capacity(result_size);
@@ -91,7 +108,7 @@ pub fn blz_decode(input_buffer: &[u8]) -> Result<Vec<u8>> {
         mask >>= BLZ_SHIFT;
         
         if mask == 0 {
-            if encoded.position() == encoded_length.into() {
+            if encoded.position() == u64::from(encoded_length) {
                 break;
             }
             
@@ -100,13 +117,13 @@ pub fn blz_decode(input_buffer: &[u8]) -> Result<Vec<u8>> {
         }
         
         if flags & mask == 0 {
-            if encoded.position() == encoded_length.into() {
+            if encoded.position() == u64::from(encoded_length) {
                 break;
             }
             
-            result_buffer.push(encoded.read_u8().unwrap());
+            result_buffer.push(encoded.read_u8()?);
         } else {
-            if encoded.position() + 1 == encoded_length.into() {
+            if encoded.position() + 1 == u64::from(encoded_length) {
                 break;
             }
             
@@ -114,19 +131,24 @@ pub fn blz_decode(input_buffer: &[u8]) -> Result<Vec<u8>> {
             let len: usize = (pos >> 12) + BLZ_THRESHOLD + 1;
             
             if result_buffer.len() + len > result_size {
-                panic!("Wrong decoded length");
-                // len = result_size;
+                return Err(malformed!("Wrong decoded length"));
             }
             
             pos = (pos & 0xFFF) + 3;
             
+            if pos > result_buffer.len() {
+                return Err(malformed!("Bottom LZ back reference points before the start of the file"));
+            }
+            
             for _ in 0..len {
                 result_buffer.push(result_buffer[result_buffer.len() - pos]);
             }
         }
     }
     
-    assert!(result_buffer.len() == result_size, "Decompressed byte length doesn't match expected length");
+    if result_buffer.len() != result_size {
+        return Err(malformed!("Decompressed byte length doesn't match expected length"));
+    }
     
     result_buffer[unencoded_length_usize..].reverse();
     
@@ -215,7 +237,7 @@ pub fn blz_encode(input_buffer: &mut [u8]) -> Result<Vec<u8>> {
     let idk = input_length + 4 < ((result_bytes_written + input_bytes_left + 3) & (u32::MAX - 3)) + 8;
     
     if result_bytes_written == 0 || idk {
-        todo!()
+        store_raw(input_buffer)
     } else {
         // convert numbers
         let input_buffer_length: u32 = input_buffer.len().try_into().unwrap();
@@ -237,6 +259,11 @@ pub fn blz_encode(input_buffer: &mut [u8]) -> Result<Vec<u8>> {
             header_length += 1;
         }
         
+        // a size increase of 0 would mark the file as not encoded
+        if size_increase <= header_length {
+            return store_raw(input_buffer);
+        }
+        
         container_buffer.write_u24::<LittleEndian>(result_bytes_written + header_length)?;
         container_buffer.write_u8(header_length.try_into().unwrap())?;
         container_buffer.write_u32::<LittleEndian>(size_increase - header_length)?;
@@ -245,12 +272,24 @@ pub fn blz_encode(input_buffer: &mut [u8]) -> Result<Vec<u8>> {
     }
 }
 
+/// Stores `input_buffer` without compressing it, which is marked by a size increase of 0.
+/// The input gets padded to a multiple of 4 bytes, so decoding it again keeps the padding.
+fn store_raw(input_buffer: &[u8]) -> Result<Vec<u8>> {
+    let mut container_buffer = input_buffer.to_vec();
+    container_buffer.resize(input_buffer.len().next_multiple_of(4), 0);
+    container_buffer.write_u32::<LittleEndian>(0)?;
+    
+    Ok(container_buffer)
+}
+
 /// Searches for biggest occurence of the input cursor's upcoming bytes in the
 /// previously read input bytes.
 ///
 /// Returns slice of search result in the form of
 /// 
-///     (found_length, found_position)
+/// ```text
+/// (found_length, found_position)
+/// ```
 fn search(input: &Cursor<&[u8]>, input_buffer: &[u8], prev_position_result: Option<u32>) -> (u32, Option<u32>) {
     let mut length_result: usize = BLZ_THRESHOLD;
     let mut position_result: Option<u32> = prev_position_result;
@@ -1,9 +1,10 @@
 use std::io::{Read, Seek, Write};
 
 use binrw::{BinRead, BinResult, BinWrite, Endian};
-use na::{ArrayStorage, Const, Matrix, U3};
+use na::{ArrayStorage, Const, Matrix, Matrix3x4, Rotation3, UnitQuaternion, U3};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, PartialEq, Default, BinRead, BinWrite)]
+#[derive(Clone, Copy, Debug, PartialEq, Default, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little)]
 pub struct Vec3 {
     pub x: f32,
@@ -21,7 +22,7 @@ impl Vec3 {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, BinRead, BinWrite)]
+#[derive(Clone, Copy, Debug, PartialEq, Default, BinRead, BinWrite, Serialize, Deserialize)]
 #[brw(little)]
 pub struct Vec4 {
     pub x: f32,
@@ -43,6 +44,121 @@ impl Vec4 {
 // why doesn't this exist by default
 pub type Matrix3x3<T> = Matrix<T, U3, U3, ArrayStorage<T, 3, 3>>;
 
+/// The order in which the rotations around the single axes of euler angles get applied,
+/// e.g. `XYZ` rotates around X first and around Z last. CGFX always uses `XYZ`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EulerOrder {
+    #[default]
+    XYZ,
+    XZY,
+    YXZ,
+    YZX,
+    ZXY,
+    ZYX,
+}
+
+impl EulerOrder {
+    /// The axes in the order they get applied in.
+    fn axes(self) -> [usize; 3] {
+        match self {
+            EulerOrder::XYZ => [0, 1, 2],
+            EulerOrder::XZY => [0, 2, 1],
+            EulerOrder::YXZ => [1, 0, 2],
+            EulerOrder::YZX => [1, 2, 0],
+            EulerOrder::ZXY => [2, 0, 1],
+            EulerOrder::ZYX => [2, 1, 0],
+        }
+    }
+}
+
+pub fn euler_to_quaternion(rotation: &Vec3, order: EulerOrder) -> UnitQuaternion<f32> {
+    let angles = [rotation.x, rotation.y, rotation.z];
+    
+    order.axes().iter().fold(UnitQuaternion::identity(), |quaternion, &axis| {
+        let mut axis_angle = na::Vec3::zeros();
+        axis_angle[axis] = angles[axis];
+        
+        UnitQuaternion::from_scaled_axis(axis_angle) * quaternion
+    })
+}
+
+/// The inverse of `euler_to_quaternion`. In gimbal lock, the last rotation is chosen to be 0.
+pub fn quaternion_to_euler(quaternion: &UnitQuaternion<f32>, order: EulerOrder) -> Vec3 {
+    let [i, j, k] = order.axes();
+    let matrix = quaternion.to_rotation_matrix().into_inner();
+    
+    // cyclic orders (XYZ, YZX, ZXY) have the opposite signs of the others
+    let sign = if (i + 1) % 3 == j { 1.0 } else { -1.0 };
+    
+    let sin_middle = (-sign * matrix[(k, i)]).clamp(-1.0, 1.0);
+    let middle = sin_middle.asin();
+    
+    let (first, last) = if sin_middle.abs() < 0.99999 {
+        (
+            (sign * matrix[(k, j)]).atan2(matrix[(k, k)]),
+            (sign * matrix[(j, i)]).atan2(matrix[(i, i)]),
+        )
+    } else {
+        ((-sign * matrix[(j, k)]).atan2(matrix[(j, j)]), 0.0)
+    };
+    
+    let mut angles = [0.0; 3];
+    angles[i] = first;
+    angles[j] = middle;
+    angles[k] = last;
+    
+    Vec3::new(angles[0], angles[1], angles[2])
+}
+
+/// Builds the affine matrix scaling, then rotating by XYZ euler angles, then translating.
+pub fn srt_to_matrix(scale: &Vec3, rotation: &Vec3, translation: &Vec3) -> Matrix3x4<f32> {
+    let rotation = euler_to_quaternion(rotation, EulerOrder::XYZ).to_rotation_matrix().into_inner();
+    let linear = rotation * na::Mat3::from_diagonal(&scale.to_na());
+    
+    linear.insert_column(3, 0.0).map_with_location(|row, col, value| if col == 3 { translation.to_na()[row] } else { value })
+}
+
+/// Splits an affine matrix back into scale, XYZ euler angles and translation. Shear gets lost,
+/// and a mirroring is expressed as a negative X scale.
+pub fn matrix_to_srt(matrix: &Matrix3x4<f32>) -> (Vec3, Vec3, Vec3) {
+    let linear: na::Mat3 = matrix.fixed_view::<3, 3>(0, 0).into();
+    let translation = matrix.column(3);
+    
+    let mut scale = na::Vec3::new(linear.column(0).norm(), linear.column(1).norm(), linear.column(2).norm());
+    
+    if linear.determinant() < 0.0 {
+        scale.x = -scale.x;
+    }
+    
+    let rotation = if scale.iter().all(|value| value.abs() > f32::EPSILON) {
+        let rotation = Rotation3::from_matrix(&(linear * na::Mat3::from_diagonal(&scale.map(|value| 1.0 / value))));
+        quaternion_to_euler(&UnitQuaternion::from_rotation_matrix(&rotation), EulerOrder::XYZ)
+    } else {
+        Vec3::default()
+    };
+    
+    (
+        Vec3::new(scale.x, scale.y, scale.z),
+        rotation,
+        Vec3::new(translation.x, translation.y, translation.z),
+    )
+}
+
+/// Applies `child` first, then `parent`, treating both as affine matrices.
+pub fn multiply_transforms(parent: &Matrix3x4<f32>, child: &Matrix3x4<f32>) -> Matrix3x4<f32> {
+    let linear = parent.fixed_view::<3, 3>(0, 0) * child;
+    
+    linear.map_with_location(|row, col, value| if col == 3 { value + parent[(row, 3)] } else { value })
+}
+
+/// The inverse of an affine matrix, or `None` if it can't be inverted.
+pub fn invert_transform(matrix: &Matrix3x4<f32>) -> Option<Matrix3x4<f32>> {
+    let inverse_linear = matrix.fixed_view::<3, 3>(0, 0).try_inverse()?;
+    let inverse_translation = -(inverse_linear * matrix.column(3));
+    
+    Some(inverse_linear.insert_column(3, 0.0).map_with_location(|row, col, value| if col == 3 { inverse_translation[row] } else { value }))
+}
+
 // binrw matrix helper
 pub struct SerializableMatrix<const R: usize, const C: usize> {
     data: ArrayStorage<f32, R, C>,
@@ -81,6 +197,8 @@ impl<const R: usize, const C: usize> BinWrite for SerializableMatrix<R, C> {
     type Args<'a> = ();
 
     fn write_options<W: Write + Seek>(&self, writer: &mut W, endian: Endian, _: Self::Args<'_>) -> BinResult<()> {
-        self.data.as_slice().write_options(writer, endian, ())
+        // stored in row-major order, like it is read
+        let matrix = Matrix::<f32, Const<R>, Const<C>, ArrayStorage<f32, R, C>>::from_array_storage(self.data);
+        matrix.transpose().as_slice().write_options(writer, endian, ())
     }
 }
@@ -1,9 +1,11 @@
 // darxoon's small pointer utility v1
 use std::{fmt::Debug, io::{Cursor, Read, Seek, Write}, num::TryFromIntError, ops::{Add, Sub}, result};
 
-use anyhow::Result;
 use binrw::{BinRead, BinWrite};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::malformed, Error, Result};
 
 macro_rules! from_type {
     ($t:ident, $from:ty) => {
@@ -17,7 +19,7 @@ macro_rules! from_type {
             type Output = Self;
         
             fn add(self, rhs: $from) -> Self {
-                $t(self.0 + u32::from(rhs))
+                $t(self.0.wrapping_add(u32::from(rhs)))
             }
         }
         
@@ -25,34 +27,19 @@ macro_rules! from_type {
             type Output = Self;
         
             fn sub(self, rhs: $from) -> Self {
-                $t(self.0 - u32::from(rhs))
+                $t(self.0.wrapping_sub(u32::from(rhs)))
             }
         }
     };
 }
 
-macro_rules! from_type_unwrap {
+macro_rules! try_from_type {
     ($t:ident, $from:ty) => {
-        impl From<$from> for $t {
-            fn from(value: $from) -> Self {
-                Pointer(value.try_into().unwrap())
-            }
-        }
-        
-        impl Add<$from> for $t {
-            type Output = Self;
-        
-            fn add(self, rhs: $from) -> Self {
-                // it's beautiful
-                $t((i32::try_from(self.0).unwrap() + i32::try_from(rhs).unwrap()).try_into().unwrap())
-            }
-        }
-        
-        impl Sub<$from> for $t {
-            type Output = Self;
-        
-            fn sub(self, rhs: $from) -> Self {
-                $t((i32::try_from(self.0).unwrap() - i32::try_from(rhs).unwrap()).try_into().unwrap())
+        impl TryFrom<$from> for $t {
+            type Error = Error;
+            
+            fn try_from(value: $from) -> result::Result<Self, Self::Error> {
+                Ok($t(u32::try_from(value)?))
             }
         }
     };
@@ -68,17 +55,21 @@ macro_rules! into_type {
     };
 }
 
-macro_rules! into_type_unwrap {
+macro_rules! try_into_type {
     ($t:ident, $into:ty) => {
-        impl From<$t> for $into {
-            fn from(value: $t) -> Self {
-                value.0.try_into().unwrap()
+        impl TryFrom<$t> for $into {
+            type Error = Error;
+            
+            fn try_from(value: $t) -> result::Result<Self, Self::Error> {
+                Ok(value.0.try_into()?)
             }
         }
     };
 }
 
-#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, BinRead, BinWrite)]
+/// An offset into a file. Arithmetic with other offsets wraps around like 32 bit offsets do,
+/// conversions from and additions of wider or signed numbers fail instead if they don't fit.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, BinRead, BinWrite, Serialize, Deserialize)]
 pub struct Pointer(pub u32);
 
 impl Pointer {
@@ -105,12 +96,20 @@ impl Pointer {
         let value = reader.read_u32::<LittleEndian>()?;
         
         if value != 0 {
-            Ok(Some(Pointer(value) + reader_pos))
+            Ok(Some(Pointer::try_from(reader_pos)?.checked_add(value.into())?))
         } else {
             Ok(None)
         }
     }
     
+    /// Adds `offset`, failing if the result isn't a valid 32 bit offset.
+    pub fn checked_add(self, offset: i64) -> Result<Pointer> {
+        i64::from(self.0).checked_add(offset)
+            .and_then(|result| u32::try_from(result).ok())
+            .map(Pointer)
+            .ok_or_else(|| malformed!("Offset {:#x} + {:#x} is out of bounds", self.0, offset))
+    }
+    
     pub fn write(&self, writer: &mut impl Write) -> Result<()> {
         writer.write_u32::<LittleEndian>(self.0)?;
         Ok(())
@@ -134,7 +133,7 @@ impl Add<Self> for Pointer {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self {
-        Pointer(self.0 + rhs.0)
+        Pointer(self.0.wrapping_add(rhs.0))
     }
 }
 
@@ -142,7 +141,7 @@ impl Sub<Self> for Pointer {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self {
-        Pointer(self.0 - rhs.0)
+        Pointer(self.0.wrapping_sub(rhs.0))
     }
 }
 
@@ -179,14 +178,14 @@ impl<T> TryFrom<&&mut Cursor<T>> for Pointer {
 
 from_type!(Pointer, u32);
 
-from_type_unwrap!(Pointer, i32);
-from_type_unwrap!(Pointer, u64);
-from_type_unwrap!(Pointer, i64);
-from_type_unwrap!(Pointer, usize);
+try_from_type!(Pointer, i32);
+try_from_type!(Pointer, u64);
+try_from_type!(Pointer, i64);
+try_from_type!(Pointer, usize);
 
 into_type!(Pointer, u32);
 into_type!(Pointer, u64);
 into_type!(Pointer, i64);
 
-into_type_unwrap!(Pointer, i32);
-into_type_unwrap!(Pointer, usize);
+try_into_type!(Pointer, i32);
+try_into_type!(Pointer, usize);
@@ -1,137 +1,383 @@
-use std::{
-    fmt::Write,
-    io::{Cursor, Seek, SeekFrom},
-};
+use std::{collections::HashMap, fmt::Write};
 
-use anyhow::Result;
-use binrw::BinRead;
-use na::Vec3;
-use nw_tex::{
-    bcres::model::{
-        AttributeName, CgfxModelCommon, Face, FaceDescriptor, GlDataType, SubMesh, VertexBuffer,
-        VertexBufferAttribute,
-    },
-    util::math,
+use anyhow::{anyhow, Result};
+use na::{Vec2, Vec3, Vec4};
+use nw_tex::bcres::{
+    bcres::CgfxDict,
+    material::Material,
+    model::{CgfxModel, Shape},
+    skeleton::CgfxSkeleton,
+    texture::CgfxTexture,
+    vertex::VertexStreams,
 };
 
-#[allow(unused)]
-pub fn export_bcres_to_obj(common: &CgfxModelCommon) -> Result<String> {
-    let shapes = common.shapes.as_ref().unwrap();
-    let mut all_vertices: Vec<Vec3> = Vec::new();
-    let mut all_faces: Vec<Vec<[u32; 3]>> = Vec::new();
-    
-    for shape in shapes {
-        let vertex_buffers = shape.vertex_buffers.as_ref().unwrap();
-        let mut current_vertices: Vec<Vec3> = Vec::new();
-        let mut current_faces: Vec<[u32; 3]> = Vec::new();
+/// All files making up an exported model.
+pub struct ObjExport {
+    pub obj: String,
+    pub mtl: String,
+    /// Textures referenced by the .mtl file as file name and PNG file content
+    pub textures: Vec<(String, Vec<u8>)>,
+}
+
+/// Deduplicates lines like `v ...` or `vt ...` so that every distinct value only gets written once.
+#[derive(Default)]
+struct IndexedLines {
+    indices: HashMap<String, usize>,
+    lines: Vec<String>,
+}
+
+impl IndexedLines {
+    /// Returns the (1-based) OBJ index of `line`, adding it if it doesn't exist yet.
+    fn insert(&mut self, line: String) -> usize {
+        if let Some(index) = self.indices.get(&line) {
+            return *index;
+        }
         
-        // collect all vertices
-        for vb in vertex_buffers {
-            match vb {
-                VertexBuffer::Attribute(attribute) => {
-                    if attribute.vertex_buffer_common.attribute_name == AttributeName::Position {
-                        assert!(attribute.format == GlDataType::Float);
-                        let raw_bytes: &[u8] = attribute.raw_bytes.as_ref().unwrap();
-                        let mut reader = Cursor::new(raw_bytes);
-                        
-                        for _ in 0..raw_bytes.len() / attribute.elements as usize {
-                            let pos: Vec3 = math::Vec3::read(&mut reader)?.to_na() * attribute.scale;
-                            
-                            current_vertices.push(pos);
-                            
-                            if !all_vertices.contains(&pos) {
-                                all_vertices.push(pos);
-                            }
-                        }
-                        
-                        todo!();
-                    }
-                },
-                VertexBuffer::Interleaved(interleaved) => {
-                    let attributes: &[VertexBufferAttribute] = interleaved.attributes.as_ref().unwrap();
-                    
-                    // check if this vb contains a position attribute
-                    if attributes.iter().all(|attr| attr.attribute_name != AttributeName::Position) {
-                        continue;
-                    }
-                    
-                    let raw_bytes: &[u8] = interleaved.raw_bytes.as_ref().unwrap();
-                    let mut reader = Cursor::new(raw_bytes);
-                    
-                    let vertex_byte_size: u32 = attributes.iter()
-                        .map(|attr| attr.format.byte_size() * attr.elements)
-                        .sum();
-                    let vertex_count = raw_bytes.len() / vertex_byte_size as usize;
-                    
-                    for _ in 0..vertex_count {
-                        for attr in attributes {
-                            if attr.attribute_name == AttributeName::Position {
-                                assert!(attr.elements == 3 && attr.format == GlDataType::Float);
-                                let pos: Vec3 = math::Vec3::read(&mut reader)?.to_na() * attr.scale;
-                                
-                                current_vertices.push(pos);
-                                
-                                if !all_vertices.contains(&pos) {
-                                    all_vertices.push(pos);
-                                }
-                            } else {
-                                reader.seek(SeekFrom::Current((attr.format.byte_size() * attr.elements) as i64))?;
-                            }
-                        }
-                    }
-                },
-                // it doesn't make sense for Position to be fixed so this is just ignored
-                VertexBuffer::Fixed(_) => (),
+        self.lines.push(line.clone());
+        self.indices.insert(line, self.lines.len());
+        self.lines.len()
+    }
+}
+
+fn write_material(mtl_out: &mut String, name: &str, material: &Material, texture_file_name: Option<&str>) -> Result<()> {
+    let colors = &material.colors;
+    
+    writeln!(mtl_out, "newmtl {}", name)?;
+    writeln!(mtl_out, "Ka {} {} {}", colors.ambient_float.x, colors.ambient_float.y, colors.ambient_float.z)?;
+    writeln!(mtl_out, "Kd {} {} {}", colors.diffuse_float.x, colors.diffuse_float.y, colors.diffuse_float.z)?;
+    writeln!(mtl_out, "Ks {} {} {}", colors.specular0_float.x, colors.specular0_float.y, colors.specular0_float.z)?;
+    writeln!(mtl_out, "Ke {} {} {}", colors.emission_float.x, colors.emission_float.y, colors.emission_float.z)?;
+    writeln!(mtl_out, "d {}", colors.diffuse_float.w)?;
+    
+    if let Some(texture_file_name) = texture_file_name {
+        writeln!(mtl_out, "map_Kd {}", texture_file_name)?;
+    }
+    
+    writeln!(mtl_out)?;
+    Ok(())
+}
+
+/// Writes the faces of `shape` into `obj_out`, adding all vertex attributes to the deduplicated lists.
+/// OBJ only supports a single set of texture coordinates, so only the first one is exported.
+fn write_shape(obj_out: &mut String, shape: &Shape, skeleton: Option<&CgfxSkeleton>,
+        positions: &mut IndexedLines, tex_coords: &mut IndexedLines, normals: &mut IndexedLines) -> Result<()> {
+    let mut streams = shape.vertex_streams()?;
+    
+    // vertices of rigidly skinned sub meshes are relative to their bone
+    if let Some(skeleton) = skeleton {
+        shape.apply_rigid_skins(&mut streams, skeleton)?;
+    }
+    
+    let vertex_indices: Vec<(usize, Option<usize>, Option<usize>)> = (0..streams.vertex_count())
+        .map(|i| {
+            let position = streams.positions[i];
+            
+            let position_line = match &streams.colors {
+                // vertex colors aren't part of the OBJ spec, but most programs support them like this
+                Some(colors) => format!("v {} {} {} {} {} {}", position.x, position.y, position.z, colors[i].x, colors[i].y, colors[i].z),
+                None => format!("v {} {} {}", position.x, position.y, position.z),
+            };
+            
+            let tex_coord_index = streams.tex_coords[0].as_ref()
+                .map(|uvs| tex_coords.insert(format!("vt {} {}", uvs[i].x, uvs[i].y)));
+            let normal_index = streams.normals.as_ref()
+                .map(|vns| normals.insert(format!("vn {} {} {}", vns[i].x, vns[i].y, vns[i].z)));
+            
+            (positions.insert(position_line), tex_coord_index, normal_index)
+        })
+        .collect();
+    
+    let face_descriptors = shape.sub_meshes.iter().flatten()
+        .flat_map(|sub_mesh| sub_mesh.faces.iter().flatten())
+        .flat_map(|face| face.face_descriptors.iter().flatten());
+    
+    for face_descriptor in face_descriptors {
+        for triangle in face_descriptor.triangles()? {
+            write!(obj_out, "f")?;
+            
+            for index in triangle {
+                let (position, tex_coord, normal) = vertex_indices.get(usize::from(index))
+                    .ok_or_else(|| anyhow!("Vertex index {} is out of bounds", index))?;
+                
+                match (tex_coord, normal) {
+                    (Some(tex_coord), Some(normal)) => write!(obj_out, " {}/{}/{}", position, tex_coord, normal)?,
+                    (Some(tex_coord), None) => write!(obj_out, " {}/{}", position, tex_coord)?,
+                    (None, Some(normal)) => write!(obj_out, " {}//{}", position, normal)?,
+                    (None, None) => write!(obj_out, " {}", position)?,
+                }
             }
+            
+            writeln!(obj_out)?;
         }
+    }
+    
+    Ok(())
+}
+
+/// Exports `model` into an .obj file, a .mtl file called `mtl_file_name` with its materials
+/// and PNG versions of the textures the materials use, which have to be taken from `textures`.
+/// Materials whose texture isn't in `textures` are exported without one.
+pub fn export_bcres_to_obj(model: &CgfxModel, textures: Option<&CgfxDict<CgfxTexture>>, mtl_file_name: &str) -> Result<ObjExport> {
+    let common = model.common();
+    let skeleton = model.skeleton();
+    let shapes: &[Shape] = common.shapes.as_deref().unwrap_or_default();
+    
+    let mut positions = IndexedLines::default();
+    let mut tex_coords = IndexedLines::default();
+    let mut normals = IndexedLines::default();
+    let mut faces_out = String::new();
+    
+    // materials
+    let mut mtl_out = String::new();
+    let mut exported_textures: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut material_names: Vec<String> = Vec::new();
+    
+    let material_nodes = common.materials.iter()
+        .flat_map(CgfxDict::iter);
+    
+    for (i, (name, material)) in material_nodes.enumerate() {
+        let name = name.map_or_else(|| format!("material{}", i), str::to_string);
         
-        // collect all faces
-        let sub_meshes: &[SubMesh] = shape.sub_meshes.as_ref().unwrap();
+        // textures which aren't part of this file, e.g. because they are shared between models, are left out
+        let texture = material.base_texture_name()
+            .and_then(|texture_name| Some((texture_name, textures?.get(texture_name)?)));
+        let texture_file_name = texture.map(|(texture_name, _)| format!("{}.png", texture_name));
         
-        for sub_mesh in sub_meshes {
-            let gfx_faces: &[Face] = sub_mesh.faces.as_ref().unwrap();
-            
-            for gfx_face in gfx_faces {
-                let face_descriptors: &[FaceDescriptor] = gfx_face.face_descriptors.as_ref().unwrap();
+        if let (Some((_, texture)), Some(texture_file_name)) = (texture, &texture_file_name) {
+            if exported_textures.iter().all(|(file_name, _)| file_name != texture_file_name) {
+                exported_textures.push((texture_file_name.clone(), texture.to_png()?));
+            }
+        }
+        
+        write_material(&mut mtl_out, &name, material, texture_file_name.as_deref())?;
+        material_names.push(name);
+    }
+    
+    // geometry
+    match &common.meshes {
+        Some(meshes) => {
+            for (i, mesh) in meshes.iter().enumerate() {
+                let shape = shapes.get(mesh.shape_index as usize)
+                    .ok_or_else(|| anyhow!("Mesh {} references missing shape {}", i, mesh.shape_index))?;
                 
-                for face_descriptor in face_descriptors {
-                    let indices: &[u16] = face_descriptor.indices.as_ref().unwrap();
-                    assert!(indices.len() % 3 == 0);
-                    
-                    let mut reader = indices.iter();
-                    
-                    for _ in 0..indices.len() / 3 {
-                        let a: Vec3 = current_vertices[*reader.next().unwrap() as usize];
-                        let a_index = all_vertices.iter().position(|v: &Vec3| *v == a).unwrap();
-                        
-                        let b: Vec3 = current_vertices[*reader.next().unwrap()  as usize];
-                        let b_index = all_vertices.iter().position(|v: &Vec3| *v == b).unwrap();
-                        
-                        let c: Vec3 = current_vertices[*reader.next().unwrap()  as usize];
-                        let c_index = all_vertices.iter().position(|v: &Vec3| *v == c).unwrap();
-                        
-                        current_faces.push([a_index as u32, b_index as u32, c_index as u32]);
-                    }
+                match &mesh.cgfx_object_header.name {
+                    Some(name) => writeln!(faces_out, "\no {}", name)?,
+                    None => writeln!(faces_out, "\no mesh{}", i)?,
+                }
+                
+                if let Some(material_name) = material_names.get(mesh.material_index as usize) {
+                    writeln!(faces_out, "usemtl {}", material_name)?;
                 }
+                
+                write_shape(&mut faces_out, shape, skeleton, &mut positions, &mut tex_coords, &mut normals)?;
+            }
+        },
+        None => {
+            for (i, shape) in shapes.iter().enumerate() {
+                writeln!(faces_out, "\no mesh{}", i)?;
+                write_shape(&mut faces_out, shape, skeleton, &mut positions, &mut tex_coords, &mut normals)?;
             }
+        },
+    }
+    
+    let mut obj_out = String::new();
+    
+    if !material_names.is_empty() {
+        writeln!(obj_out, "mtllib {}", mtl_file_name)?;
+    }
+    
+    for line in positions.lines.iter().chain(&tex_coords.lines).chain(&normals.lines) {
+        writeln!(obj_out, "{}", line)?;
+    }
+    
+    obj_out.push_str(&faces_out);
+    
+    Ok(ObjExport {
+        obj: obj_out,
+        mtl: mtl_out,
+        textures: exported_textures,
+    })
+}
+
+/// A single object (`o ...`) of an imported .obj file.
+pub struct ObjObject {
+    pub name: Option<String>,
+    pub streams: VertexStreams,
+    pub triangles: Vec<[u16; 3]>,
+}
+
+/// Vertex data of an .obj file, which all objects share.
+#[derive(Default)]
+struct ObjVertexData {
+    positions: Vec<Vec3>,
+    colors: Vec<Vec4>,
+    tex_coords: Vec<Vec2>,
+    normals: Vec<Vec3>,
+}
+
+/// Combines the separately indexed positions, texture coordinates and normals of an object's faces into
+/// vertices, because CGFX (like pretty much every other format) only supports a single index per vertex.
+#[derive(Default)]
+struct ObjObjectBuilder {
+    name: Option<String>,
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), u16>,
+    vertex_keys: Vec<(usize, Option<usize>, Option<usize>)>,
+    triangles: Vec<[u16; 3]>,
+}
+
+impl ObjObjectBuilder {
+    fn vertex(&mut self, key: (usize, Option<usize>, Option<usize>)) -> Result<u16> {
+        if let Some(index) = self.vertices.get(&key) {
+            return Ok(*index);
         }
         
-        all_faces.push(current_faces);
+        let index = u16::try_from(self.vertex_keys.len())
+            .map_err(|_| anyhow!("Object {:?} has more than 65536 vertices", self.name))?;
+        
+        self.vertices.insert(key, index);
+        self.vertex_keys.push(key);
+        Ok(index)
     }
     
-    let mut obj_out = String::new();
+    fn build(self, data: &ObjVertexData) -> ObjObject {
+        let has_tex_coords = self.vertex_keys.iter().any(|(_, tex_coord, _)| tex_coord.is_some());
+        let has_normals = self.vertex_keys.iter().any(|(_, _, normal)| normal.is_some());
+        let has_colors = !data.colors.is_empty();
+        
+        let streams = VertexStreams {
+            positions: self.vertex_keys.iter().map(|(position, _, _)| data.positions[*position]).collect(),
+            normals: has_normals.then(|| self.vertex_keys.iter()
+                .map(|(_, _, normal)| normal.map_or(Vec3::z(), |normal| data.normals[normal]))
+                .collect()),
+            colors: has_colors.then(|| self.vertex_keys.iter()
+                .map(|(position, _, _)| data.colors[*position])
+                .collect()),
+            tex_coords: [
+                has_tex_coords.then(|| self.vertex_keys.iter()
+                    .map(|(_, tex_coord, _)| tex_coord.map_or(Vec2::zeros(), |tex_coord| data.tex_coords[tex_coord]))
+                    .collect()),
+                None,
+                None,
+            ],
+            ..Default::default()
+        };
+        
+        ObjObject {
+            name: self.name,
+            streams,
+            triangles: self.triangles,
+        }
+    }
+}
+
+fn parse_floats<const N: usize>(line_number: usize, values: &[&str]) -> Result<[f32; N]> {
+    let mut result = [0.0; N];
     
-    for vertex in &all_vertices {
-        writeln!(obj_out, "v {} {} {}", vertex.x, vertex.y, vertex.z)?;
+    if values.len() < N {
+        return Err(anyhow!("Line {}: expected {} values, got {}", line_number, N, values.len()));
     }
     
-    for (i, current_faces) in all_faces.iter().enumerate() {
-        writeln!(obj_out, "\no mesh{}", i)?;
+    for (i, value) in values.iter().take(N).enumerate() {
+        result[i] = value.parse().map_err(|_| anyhow!("Line {}: invalid number {:?}", line_number, value))?;
+    }
+    
+    Ok(result)
+}
+
+/// Resolves a 1-based (or negative, relative to the end) OBJ index into an index into a list of `count` elements.
+fn resolve_index(line_number: usize, index: &str, count: usize) -> Result<usize> {
+    let index: i64 = index.parse().map_err(|_| anyhow!("Line {}: invalid index {:?}", line_number, index))?;
+    
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(anyhow!("Line {}: index {} is out of bounds", line_number, index));
+    }
+    
+    Ok(resolved as usize)
+}
+
+/// Parses an .obj file into its objects. Polygons get triangulated, materials and
+/// everything else not describing geometry is ignored.
+pub fn import_obj(obj: &str) -> Result<Vec<ObjObject>> {
+    let mut data = ObjVertexData::default();
+    let mut builders: Vec<ObjObjectBuilder> = vec![ObjObjectBuilder::default()];
+    
+    for (i, line) in obj.lines().enumerate() {
+        let line_number = i + 1;
+        let mut parts = line.split_whitespace();
+        
+        let Some(keyword) = parts.next() else {
+            continue;
+        };
         
-        for [a, b, c] in current_faces {
-            writeln!(obj_out, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        let values: Vec<&str> = parts.collect();
+        
+        match keyword {
+            "v" => {
+                let [x, y, z] = parse_floats(line_number, &values)?;
+                data.positions.push(Vec3::new(x, y, z));
+                
+                // vertex colors aren't part of the OBJ spec, but most programs support them like this
+                if values.len() >= 6 {
+                    let [r, g, b] = parse_floats(line_number, &values[3..])?;
+                    data.colors.push(Vec4::new(r, g, b, 1.0));
+                }
+            },
+            "vt" => {
+                let [u, v] = parse_floats(line_number, &values)?;
+                data.tex_coords.push(Vec2::new(u, v));
+            },
+            "vn" => {
+                let [x, y, z] = parse_floats(line_number, &values)?;
+                data.normals.push(Vec3::new(x, y, z));
+            },
+            "o" => {
+                builders.push(ObjObjectBuilder {
+                    name: Some(values.join(" ")),
+                    ..Default::default()
+                });
+            },
+            "f" => {
+                let builder = builders.last_mut().unwrap();
+                
+                let indices = values.iter()
+                    .map(|value| {
+                        let mut indices = value.split('/');
+                        
+                        let position = resolve_index(line_number, indices.next().unwrap_or_default(), data.positions.len())?;
+                        let tex_coord = indices.next()
+                            .filter(|index| !index.is_empty())
+                            .map(|index| resolve_index(line_number, index, data.tex_coords.len()))
+                            .transpose()?;
+                        let normal = indices.next()
+                            .filter(|index| !index.is_empty())
+                            .map(|index| resolve_index(line_number, index, data.normals.len()))
+                            .transpose()?;
+                        
+                        builder.vertex((position, tex_coord, normal))
+                    })
+                    .collect::<Result<Vec<u16>>>()?;
+                
+                if indices.len() < 3 {
+                    return Err(anyhow!("Line {}: faces need at least 3 vertices", line_number));
+                }
+                
+                // triangulate as a fan
+                for i in 1..indices.len() - 1 {
+                    builder.triangles.push([indices[0], indices[i], indices[i + 1]]);
+                }
+            },
+            _ => {},
         }
     }
     
-    Ok(obj_out)
+    if !data.colors.is_empty() && data.colors.len() != data.positions.len() {
+        return Err(anyhow!("Either all or no vertices need to have colors"));
+    }
+    
+    Ok(builders.into_iter()
+        .filter(|builder| !builder.triangles.is_empty())
+        .map(|builder| builder.build(&data))
+        .collect())
 }